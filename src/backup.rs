@@ -0,0 +1,45 @@
+use std::path::{Path, PathBuf};
+
+/// Where to keep a copy of a file from before it was last saved
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BackupStyle {
+    /// `notes.txt~`
+    Tilde,
+    /// `.notes.txt.bak`
+    Hidden,
+}
+
+impl BackupStyle {
+    /// The path of the backup of a file
+    pub fn path(&self, file: &str) -> PathBuf {
+        match self {
+            Self::Tilde => PathBuf::from(format!("{}~", file)),
+            Self::Hidden => hidden_sibling(file, ".bak"),
+        }
+    }
+}
+
+/// What to do after a while without any edits
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Autosave {
+    /// Save the file itself
+    Save,
+    /// Write the buffer to a snapshot file next to the file, like
+    /// `.notes.txt.autosave`, and leave the file alone until it's saved
+    Snapshot,
+}
+
+/// The path of the snapshot that `Autosave::Snapshot` writes for a file
+pub fn snapshot_path(file: &str) -> PathBuf {
+    hidden_sibling(file, ".autosave")
+}
+
+/// A hidden file in the same directory as a file, like `.notes.txt.bak`
+fn hidden_sibling(file: &str, extension: &str) -> PathBuf {
+    let path = Path::new(file);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}{}", name, extension))
+}
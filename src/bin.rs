@@ -1,16 +1,176 @@
 use cursive::event::{Event, Key};
 use cursive::traits::*;
-use cursive::views::{Dialog, EditView, NamedView, OnEventView, TextArea, TextView};
+use cursive::view::ScrollStrategy;
+use cursive::views::{
+    Dialog, EditView, LinearLayout, NamedView, OnEventView, Panel, SelectView, TextArea,
+    TextContent, TextView,
+};
 use cursive::Cursive;
-use editor::views::{CodeArea, DefaultHighlighter, Highlighter};
-use std::fs::read_to_string;
+use editor::clipboard::{Clipboard, Sharing};
+use editor::config::Config;
+use editor::crypt::{Cipher, Encryption};
+use editor::diagnostics::{cargo_check, Severity};
+#[cfg(feature = "lsp")]
+use editor::diff::Hunk;
+#[cfg(feature = "lsp")]
+use editor::edits::TextEdit;
+use editor::encoding::TextEncoding;
+use editor::fuzzy::fuzzy_score;
+#[cfg(feature = "lsp")]
+use editor::json::Json;
+use editor::keymap::Command;
+use editor::languages::{extension, file_dir};
+use editor::locations::{FileLocation, LocationFinder};
+use editor::lock::{lock_holder, FileLock};
+use editor::logs::LogFilter;
+#[cfg(feature = "lsp")]
+use editor::lsp::{project_root, server_for, CodeAction, LanguageServer, Location, Reply};
+use editor::marks::{Marks, SLOTS};
+use editor::notebook::Interpreter;
+use editor::outline::{Entry, Outliner};
+use editor::tags::{find_tags_file, read_tags, Symbol};
+use editor::tasks::{project_tasks, Output, Running, Task};
+use editor::views::{CodeArea, DefaultHighlighter, Highlighter, TerminalView};
+use std::cell::RefCell;
+#[cfg(feature = "lsp")]
+use std::collections::HashMap;
+use std::env::{self, current_dir};
+use std::fs::canonicalize;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+const USAGE: &str = "usage: editor [--readonly] [+LINE] [FILE[:LINE[:COL]]]...\n\
+                     Use - as the file to edit standard input.";
+
+// The title of a buffer that isn't a file yet
+const SCRATCH_TITLE: &str = "[scratch]";
 
 fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) if args.help => {
+            println!("{}", USAGE);
+            return;
+        }
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("editor: {}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
     let mut siv = Cursive::default();
     // The main dialog will just have a textarea.
     // Its size expand automatically with the content.
 
-    siv.load_theme_file("assets/style.toml").unwrap();
+    let config = Config::load().unwrap_or_else(|e| {
+        siv.add_layer(Dialog::info(format!(
+            "Could not load the config file: {}",
+            e
+        )));
+        Config::default()
+    });
+    if let Err(e) = siv.load_theme_file(&config.theme) {
+        let theme = config.theme.display();
+        siv.add_layer(Dialog::info(format!(
+            "Could not load the theme {}: {:?}",
+            theme, e
+        )));
+    }
+    // Autosave checks for idle buffers, and files are checked for changes
+    // on disk, whenever the screen refreshes
+    siv.set_fps(1);
+    let clipboard = match config.clipboard {
+        Sharing::Local => None,
+        sharing => Some(Clipboard::shared(sharing)),
+    };
+    siv.set_user_data(Session {
+        config,
+        clipboard,
+        last_task: None,
+        task: None,
+        run: None,
+        last_filter: String::new(),
+        read_only: args.read_only,
+        buffers: args.files.len().max(1),
+        splits: 0,
+        locks: Vec::new(),
+        jumps: Vec::new(),
+        location: None,
+        #[cfg(feature = "lsp")]
+        language_servers: HashMap::new(),
+    });
+    siv.add_global_callback(Event::CtrlChar('g'), ask_goto_line);
+    siv.add_global_callback(Event::AltChar('s'), ask_save_as);
+    siv.add_global_callback(Event::AltChar('u'), ask_time_travel);
+    siv.add_global_callback(Event::Shift(Key::F4), ask_play_macro);
+    siv.add_global_callback(Event::AltChar('v'), ask_paste_from_history);
+    siv.add_global_callback(Event::AltChar('|'), ask_filter);
+    siv.add_global_callback(Event::CtrlChar('t'), ask_symbol);
+    siv.add_global_callback(Event::AltChar('.'), goto_definition);
+    siv.add_global_callback(Event::AltChar(','), jump_back);
+    siv.add_global_callback(Event::AltChar('g'), ask_matching_lines);
+    siv.add_global_callback(Event::AltChar('t'), ask_task);
+    siv.add_global_callback(Event::Key(Key::F9), run_last_task);
+    siv.add_global_callback(Event::Key(Key::F5), run_file);
+    siv.add_global_callback(Event::Shift(Key::F5), close_output_panel);
+    siv.add_global_callback(Event::AltChar('r'), run_notebook);
+    siv.add_global_callback(Event::Key(Key::F7), check_project);
+    siv.add_global_callback(Event::AltChar('2'), toggle_second_view);
+    siv.add_global_callback(Event::CtrlChar('w'), ask_pane_move);
+    siv.add_global_callback(Event::AltChar('1'), toggle_outline);
+    siv.add_global_callback(Event::AltChar('z'), toggle_zen);
+    siv.add_global_callback(Event::AltChar('`'), toggle_terminal);
+    siv.add_global_callback(Event::Key(Key::F2), |s| goto_next_location(s, true));
+    siv.add_global_callback(Event::Shift(Key::F2), |s| goto_next_location(s, false));
+    // The outline follows edits each time the screen refreshes
+    siv.add_global_callback(Event::Refresh, refresh_outline);
+    #[cfg(feature = "lsp")]
+    {
+        // The language server is told about edits each time the screen refreshes
+        siv.add_global_callback(Event::Refresh, |s| {
+            sync_language_server(s);
+        });
+        siv.add_global_callback(Event::Key(Key::F1), |s| {
+            ask_language_server(s, LanguageServer::hover)
+        });
+        siv.add_global_callback(Event::Key(Key::F12), |s| {
+            ask_language_server(s, LanguageServer::definition)
+        });
+        siv.add_global_callback(Event::Shift(Key::F12), ask_rename);
+        siv.add_global_callback(Event::Shift(Key::F1), |s| {
+            ask_language_server(s, LanguageServer::code_actions)
+        });
+    }
+    siv.add_global_callback(Event::AltChar('n'), |s| switch_buffer(s, true));
+    siv.add_global_callback(Event::AltChar('p'), |s| switch_buffer(s, false));
+    siv.add_global_callback(Event::AltChar('a'), |s| {
+        save_buffers(s, false);
+    });
+    siv.add_global_callback(Event::AltChar('m'), |s| {
+        save_buffers(s, true);
+    });
+    siv.add_global_callback(Event::AltChar('h'), ask_marks);
+    // Terminals send Alt+Shift+1 as Alt and `!`, and so on
+    for (slot, ch) in (1..=SLOTS).zip(&['!', '@', '#', '$']) {
+        siv.add_global_callback(Event::AltChar(*ch), move |s| jump_to_mark(s, slot));
+    }
+
+    // Each file from the command line gets a screen of its own
+    if !args.files.is_empty() {
+        for (i, (file, position)) in args.files.iter().enumerate() {
+            if i > 0 {
+                siv.add_active_screen();
+            }
+            open_path(&mut siv, file, *position);
+        }
+        siv.set_screen(0);
+        siv.run();
+        return;
+    }
 
     // siv.add_layer(
     //     Dialog::new()
@@ -64,16 +224,2255 @@ fn show_popup(s: &mut Cursive, name: &str) {
         // Remove the initial popup
         s.pop_layer();
         // And put a new one instead
-        s.add_layer(open::<DefaultHighlighter>(name));
+        open_path(s, name, None);
+    }
+}
+
+// Open a file, at a line and column if there is one,
+// unless another editor is editing it already
+fn open_path(s: &mut Cursive, name: &str, position: Option<(usize, usize)>) {
+    if name == "-" {
+        open_stdin(s);
+        goto(s, position);
+        return;
+    }
+    match FileLock::acquire(Path::new(name)) {
+        Ok(Some(lock)) => {
+            // The lock is let go of if the file can't be opened
+            if open_unlocked(s, name, position, false) {
+                s.with_user_data(|session: &mut Session| session.locks.push(lock));
+            }
+        }
+        Ok(None) => ask_locked(s, name, position),
+        // Files in directories that can't be written to can't be locked
+        Err(_) => {
+            open_unlocked(s, name, position, false);
+        }
     }
 }
 
-fn open<H>(file: &str) -> Dialog
+// Open a file without checking for other editors. It opens read-only when
+// another editor has it locked, until the lock can be taken. Returns whether
+// the file was opened, or is waiting for the secret to decrypt it.
+fn open_unlocked(
+    s: &mut Cursive,
+    name: &str,
+    position: Option<(usize, usize)>,
+    read_only: bool,
+) -> bool {
+    if let Some(cipher) = Cipher::from_path(name) {
+        // Encrypted files need a secret before they can be opened
+        ask_secret(s, name, cipher, position, read_only);
+        return true;
+    }
+    let area = code_area(s)
+        .with_read_only(read_only || session_read_only(s))
+        .with_locked_elsewhere(read_only);
+    match open::<DefaultHighlighter>(name, area) {
+        Ok(dialog) => {
+            s.add_layer(dialog.with_name("editor").full_screen());
+            update_title(s);
+            goto(s, position);
+            true
+        }
+        Err(e) => {
+            s.add_layer(Dialog::info(format!("Could not open {}: {}", name, e)));
+            false
+        }
+    }
+}
+
+// Ask what to do about a file that another editor has locked
+fn ask_locked(s: &mut Cursive, name: &str, position: Option<(usize, usize)>) {
+    let holder =
+        lock_holder(Path::new(name)).map_or(String::new(), |pid| format!(" (process {})", pid));
+    let (read_only_name, steal_name) = (name.to_string(), name.to_string());
+    s.add_layer(
+        Dialog::text(format!(
+            "{} is already open in another editor{}. \
+             Saving it here could overwrite the changes made there.",
+            name, holder
+        ))
+        .title("File is locked")
+        .button("Open read-only", move |s| {
+            s.pop_layer();
+            open_unlocked(s, &read_only_name, position, true);
+        })
+        .button("Steal lock", move |s| {
+            s.pop_layer();
+            match FileLock::steal(Path::new(&steal_name)) {
+                Ok(lock) => {
+                    s.with_user_data(|session: &mut Session| session.locks.push(lock));
+                }
+                Err(e) => s.add_layer(Dialog::info(format!(
+                    "Could not lock {}: {}",
+                    steal_name, e
+                ))),
+            }
+            open_unlocked(s, &steal_name, position, false);
+        }),
+    );
+}
+
+// Open what was piped into the editor in a scratch buffer,
+// which asks for a file name when it's saved
+fn open_stdin(s: &mut Cursive) {
+    let mut bytes = Vec::new();
+    if let Err(e) = io::stdin().read_to_end(&mut bytes) {
+        s.add_layer(Dialog::info(format!(
+            "Could not read standard input: {}",
+            e
+        )));
+    }
+    let area = code_area::<DefaultHighlighter>(s)
+        .with_content(String::from_utf8_lossy(&bytes))
+        .on_modified(|s, _| update_title(s))
+        .on_save_as(ask_save_as);
+    s.add_layer(
+        Dialog::new()
+            .title(SCRATCH_TITLE)
+            .content(panes(area.with_name("code")))
+            .with_name("editor")
+            .full_screen(),
+    );
+}
+
+// Move the cursor of the open file to a line and column
+fn goto(s: &mut Cursive, position: Option<(usize, usize)>) {
+    if let Some((line, col)) = position {
+        s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            code.goto_position(line, col)
+        });
+    }
+}
+
+// What the editor keeps track of between callbacks
+#[derive(Default)]
+struct Session {
+    config: Config,
+    // The clipboard the code areas share, unless each has its own
+    clipboard: Option<Rc<RefCell<Clipboard>>>,
+    last_task: Option<Task>,
+    // The task whose output is shown, and the file that's running below the
+    // code, which are killed if their output is closed before they finish
+    task: Option<Running>,
+    run: Option<Running>,
+    // The last command text was filtered through, to offer again
+    last_filter: String,
+    // Whether files are opened read-only
+    read_only: bool,
+    // The number of screens with a file open, one for each file
+    buffers: usize,
+    // How many views were split off from the code, to give each a name of its own
+    splits: usize,
+    // The lock files of the open files, which are removed when the editor exits
+    locks: Vec<FileLock>,
+    // The buffers and positions jumped away from to go to a definition,
+    // the most recent last
+    jumps: Vec<(usize, (usize, usize))>,
+    // The place in a file printed in the output that was gone to last
+    location: Option<FileLocation>,
+    // The language servers by their command and project root,
+    // or None for ones that couldn't be started
+    #[cfg(feature = "lsp")]
+    language_servers: HashMap<(String, PathBuf), Option<LanguageServer>>,
+}
+
+// The settings loaded when the editor started
+fn config(s: &mut Cursive) -> Config {
+    s.user_data::<Session>()
+        .map(|session| session.config.clone())
+        .unwrap_or_default()
+}
+
+// Whether files are opened read-only
+fn session_read_only(s: &mut Cursive) -> bool {
+    s.user_data::<Session>()
+        .is_some_and(|session| session.read_only)
+}
+
+// A new code area with the settings the editor started with
+fn code_area<H>(s: &mut Cursive) -> CodeArea<H>
+where
+    H: Highlighter,
+{
+    let read_only = session_read_only(s);
+    let clipboard = s
+        .user_data::<Session>()
+        .and_then(|session| session.clipboard.clone());
+    let area = config(s)
+        .apply(CodeArea::default())
+        .with_read_only(read_only)
+        .on_quit(ask_quit)
+        .on_file_changed(ask_file_changed);
+    #[cfg(feature = "lsp")]
+    let area = area.on_format(format_with_language_server);
+    match clipboard {
+        Some(clipboard) => area.with_clipboard(clipboard),
+        None => area,
+    }
+}
+
+fn open<H>(file: &str, area: CodeArea<H>) -> io::Result<Dialog>
+where
+    H: Highlighter,
+{
+    Ok(Dialog::new().title(file).content(panes(
+        area.open_file(file)?
+            .on_modified(|s, _| update_title(s))
+            .with_name("code"),
+    )))
+}
+
+// The code area of an editor, in a layout with room for an output panel below it
+// and an outline beside it
+fn panes(code: impl View) -> NamedView<LinearLayout> {
+    LinearLayout::horizontal()
+        .child(
+            LinearLayout::vertical()
+                .child(code)
+                .with_name("panes")
+                .full_width(),
+        )
+        .with_name("columns")
+}
+
+// Ask for the passphrase or identity needed to decrypt a file,
+// and open the file once it has been entered.
+fn ask_secret(
+    s: &mut Cursive,
+    file: &str,
+    cipher: Cipher,
+    position: Option<(usize, usize)>,
+    read_only: bool,
+) {
+    let file = file.to_string();
+    let mut input = EditView::new().on_submit(move |s, secret| {
+        let encryption = Encryption::new(cipher, secret);
+        let area = code_area(s)
+            .with_read_only(read_only || session_read_only(s))
+            .with_locked_elsewhere(read_only);
+        match open_encrypted::<DefaultHighlighter>(&file, encryption, area) {
+            Ok(dialog) => {
+                s.pop_layer();
+                s.add_layer(dialog.with_name("editor").full_screen());
+                update_title(s);
+                goto(s, position);
+            }
+            Err(e) => s.add_layer(Dialog::info(format!("Could not decrypt {}: {}", file, e))),
+        }
+    });
+    input.set_secret(cipher.secret_is_hidden());
+
+    s.add_layer(
+        Dialog::new()
+            .title(cipher.secret_name())
+            .content(input.fixed_width(30)),
+    );
+}
+
+fn open_encrypted<H>(file: &str, encryption: Encryption, area: CodeArea<H>) -> io::Result<Dialog>
 where
     H: Highlighter,
 {
-    let contents = read_to_string(file);
-    Dialog::new()
-        .title(file)
-        .content(CodeArea::<H>::default().open_file(file))
+    Ok(Dialog::new().title(file).content(panes(
+        area.open_encrypted(file, encryption)?
+            .on_modified(|s, _| update_title(s))
+            .with_name("code"),
+    )))
+}
+
+// Show the name of the open file in the title of its dialog, with its
+// encoding if it isn't UTF-8, marked with a `*` if there are unsaved changes
+fn update_title(s: &mut Cursive) {
+    let title = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        if code.is_zen() {
+            return String::new();
+        }
+        let mut title = if code.is_scratch() {
+            SCRATCH_TITLE.to_string()
+        } else {
+            code.filename().to_string()
+        };
+        if code.text_encoding() != TextEncoding::default() {
+            title += &format!(" [{}]", code.text_encoding().name());
+        }
+        if code.is_modified() {
+            title += " *";
+        }
+        title
+    });
+    if let Some(title) = title {
+        s.call_on_name("editor", |editor: &mut Dialog| editor.set_title(title));
+    }
+}
+
+// Ask for a line, or a line and column, and move the cursor there
+fn ask_goto_line(s: &mut Cursive) {
+    if s.find_name::<CodeArea<DefaultHighlighter>>("code")
+        .is_none()
+    {
+        return;
+    }
+
+    let input = EditView::new().on_submit(|s, text| match parse_position(text) {
+        Some((line, col)) => {
+            s.pop_layer();
+            s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                code.goto_position(line, col)
+            });
+        }
+        None => s.add_layer(Dialog::info("Enter a line like 120, or 120:15")),
+    });
+    s.add_layer(
+        Dialog::new()
+            .title("Go to line")
+            .content(input.fixed_width(20))
+            .dismiss_button("Cancel"),
+    );
+}
+
+// Ask how many times to play the recorded macro, and play it
+fn ask_play_macro(s: &mut Cursive) {
+    if s.find_name::<CodeArea<DefaultHighlighter>>("code")
+        .is_none()
+    {
+        return;
+    }
+
+    let input = EditView::new()
+        .content("1")
+        .on_submit(|s, text| match text.trim().parse() {
+            Ok(times) => {
+                s.pop_layer();
+                play_macro(s, times);
+            }
+            Err(_) => s.add_layer(Dialog::info("Enter a number of times, like 10")),
+        });
+    s.add_layer(
+        Dialog::new()
+            .title("Play the macro how many times?")
+            .content(input.fixed_width(20))
+            .dismiss_button("Cancel"),
+    );
+}
+
+fn play_macro(s: &mut Cursive, times: usize) {
+    let callback = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.play_macro(times)
+    });
+    if let Some(Some(callback)) = callback {
+        callback(s);
+    }
+    update_title(s);
+}
+
+// Pick an earlier cut or copy to paste
+fn ask_paste_from_history(s: &mut Cursive) {
+    let history = match s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.clipboard_history()
+    }) {
+        Some(history) => history,
+        None => return,
+    };
+    if history.is_empty() {
+        s.add_layer(Dialog::info("Nothing has been cut or copied yet"));
+        return;
+    }
+
+    let mut picker = SelectView::new().on_submit(|s, &index: &usize| {
+        s.pop_layer();
+        let callback = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            code.paste_from_history(index)
+        });
+        if let Some(Some(callback)) = callback {
+            callback(s);
+        }
+        update_title(s);
+    });
+    for (index, text) in history.iter().enumerate() {
+        // Each entry shows its first line with text, and how many more there are
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let first = lines.next().unwrap_or_default().trim();
+        let label = match lines.count() {
+            0 => first.to_string(),
+            more => format!("{}  (+{} lines)", first, more),
+        };
+        picker.add_item(label, index);
+    }
+    s.add_layer(
+        Dialog::new()
+            .title("Paste from history")
+            .content(picker.scrollable())
+            .dismiss_button("Cancel"),
+    );
+}
+
+// Ask for a shell command, and replace the selection, or the whole buffer,
+// with what the command prints when it's given the text
+fn ask_filter(s: &mut Cursive) {
+    if s.find_name::<CodeArea<DefaultHighlighter>>("code")
+        .is_none()
+    {
+        return;
+    }
+    let last_filter = s
+        .user_data::<Session>()
+        .map(|session| session.last_filter.clone())
+        .unwrap_or_default();
+
+    let input = EditView::new().content(last_filter).on_submit(filter);
+    s.add_layer(
+        Dialog::new()
+            .title("Filter through command")
+            .content(input.fixed_width(40))
+            .dismiss_button("Cancel"),
+    );
+}
+
+// Replace the selection, or the whole buffer, with what a command prints
+fn filter(s: &mut Cursive, command: &str) {
+    if command.trim().is_empty() {
+        return;
+    }
+    s.with_user_data(|session: &mut Session| session.last_filter = command.to_string());
+    let filtered = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.filter_selection(command)
+    });
+    match filtered {
+        Some(Ok(())) => {
+            s.pop_layer();
+            update_title(s);
+        }
+        Some(Err(e)) => s.add_layer(Dialog::info(format!("Could not run {}: {}", command, e))),
+        None => {}
+    }
+}
+
+// Ask for a new name to save the file as
+fn ask_save_as(s: &mut Cursive) {
+    let filename = match s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.filename().to_string()
+    }) {
+        Some(filename) => filename,
+        None => return,
+    };
+
+    let input = EditView::new().content(filename).on_submit(|s, file| {
+        let saved = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            code.save_as(file)
+        });
+        match saved {
+            Some(Ok(())) => {
+                s.pop_layer();
+                update_title(s);
+            }
+            Some(Err(e)) => s.add_layer(Dialog::info(format!("Could not save {}: {}", file, e))),
+            None => {}
+        }
+    });
+    s.add_layer(
+        Dialog::new()
+            .title("Save as")
+            .content(input.fixed_width(30))
+            .dismiss_button("Cancel"),
+    );
+}
+
+// Ask for a number of minutes, and put the buffer back how it looked that long
+// ago, or redo the changes made in that long after the time it was put back to
+fn ask_time_travel(s: &mut Cursive) {
+    if s.find_name::<CodeArea<DefaultHighlighter>>("code")
+        .is_none()
+    {
+        return;
+    }
+
+    let travel = |s: &mut Cursive, earlier: bool| {
+        let minutes = s
+            .call_on_name("minutes", |input: &mut EditView| input.get_content())
+            .and_then(|text| text.trim().parse::<f64>().ok())
+            .filter(|minutes| minutes.is_finite() && *minutes >= 0.0);
+        let duration = match minutes {
+            Some(minutes) => Duration::from_secs_f64(minutes * 60.0),
+            None => {
+                s.add_layer(Dialog::info("Enter a number of minutes, like 5 or 0.5"));
+                return;
+            }
+        };
+        let command = if earlier {
+            Command::Earlier(duration)
+        } else {
+            Command::Later(duration)
+        };
+        s.pop_layer();
+        let callback = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            code.run_command(command)
+        });
+        if let Some(Some(callback)) = callback {
+            callback(s);
+        }
+        update_title(s);
+    };
+    s.add_layer(
+        Dialog::new()
+            .title("Minutes to go back or forward")
+            .content(
+                EditView::new()
+                    .content("5")
+                    .with_name("minutes")
+                    .fixed_width(20),
+            )
+            .button("Earlier", move |s| travel(s, true))
+            .button("Later", move |s| travel(s, false))
+            .dismiss_button("Cancel"),
+    );
+}
+
+// The symbols in the project's tags file, or None if it couldn't be read
+fn project_symbols(s: &mut Cursive) -> Option<Vec<Symbol>> {
+    let dir = current_dir().unwrap_or_default();
+    match find_tags_file(&dir).map(|path| read_tags(&path)) {
+        Some(Ok(symbols)) => Some(symbols),
+        Some(Err(e)) => {
+            s.add_layer(Dialog::info(format!("Could not read the tags file: {}", e)));
+            None
+        }
+        None => {
+            s.add_layer(Dialog::info("No tags file found, try running `ctags -R`"));
+            None
+        }
+    }
+}
+
+// Search the symbols in the tags file by name, and jump to the chosen one
+fn ask_symbol(s: &mut Cursive) {
+    let symbols = match project_symbols(s) {
+        Some(symbols) => symbols,
+        None => return,
+    };
+
+    let mut results = SelectView::new().on_submit(jump_to_symbol);
+    show_symbols(&mut results, &symbols, "");
+    let query = EditView::new()
+        .on_edit(move |s, query, _| {
+            s.call_on_name("symbols", |results: &mut SelectView<Symbol>| {
+                show_symbols(results, &symbols, query)
+            });
+        })
+        .on_submit(|s, _| {
+            let selected = s
+                .call_on_name("symbols", |results: &mut SelectView<Symbol>| {
+                    results.selection()
+                })
+                .flatten();
+            if let Some(symbol) = selected {
+                jump_to_symbol(s, &symbol);
+            }
+        });
+
+    s.add_layer(
+        Dialog::new()
+            .title("Go to symbol")
+            .content(
+                LinearLayout::vertical()
+                    .child(query)
+                    .child(results.with_name("symbols").fixed_height(10)),
+            )
+            .dismiss_button("Cancel")
+            .fixed_width(60),
+    );
+}
+
+// Ask for a log level like `warn`, or a pattern, and list the lines that match
+// it along with the stack traces under them. Picking a line goes to it.
+fn ask_matching_lines(s: &mut Cursive) {
+    let lines = match s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.lines().to_vec()
+    }) {
+        Some(lines) => lines,
+        None => return,
+    };
+
+    let results = SelectView::<usize>::new().on_submit(goto_matching_line);
+    let query = EditView::new()
+        .on_edit(move |s, query, _| {
+            s.call_on_name("matching lines", |results: &mut SelectView<usize>| {
+                show_matching_lines(results, &lines, query)
+            });
+        })
+        .on_submit(|s, _| {
+            let selected = s
+                .call_on_name("matching lines", |results: &mut SelectView<usize>| {
+                    results.selection()
+                })
+                .flatten();
+            if let Some(row) = selected {
+                goto_matching_line(s, &row);
+            }
+        });
+
+    s.add_layer(
+        Dialog::new()
+            .title("Show matching lines")
+            .content(
+                LinearLayout::vertical()
+                    .child(query)
+                    .child(results.with_name("matching lines").fixed_height(15)),
+            )
+            .dismiss_button("Cancel")
+            .fixed_width(80),
+    );
+}
+
+// List the lines that pass a filter, with their line numbers
+fn show_matching_lines(results: &mut SelectView<usize>, lines: &[String], filter: &str) {
+    results.clear();
+    // A pattern that's being typed may not be a valid one yet
+    let filter = match LogFilter::parse(filter) {
+        Ok(parsed) if !filter.is_empty() => parsed,
+        _ => return,
+    };
+    for row in filter.filter(lines).into_iter().take(1000) {
+        results.add_item(format!("{:>6}  {}", row + 1, lines[row]), row);
+    }
+}
+
+fn goto_matching_line(s: &mut Cursive, &row: &usize) {
+    s.pop_layer();
+    s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.goto_position(row + 1, 1)
+    });
+}
+
+// List the symbols that match a query, best matches first
+fn show_symbols(results: &mut SelectView<Symbol>, symbols: &[Symbol], query: &str) {
+    let dir = current_dir().unwrap_or_default();
+    let mut matches: Vec<(i64, &Symbol)> = symbols
+        .iter()
+        .filter_map(|symbol| Some((fuzzy_score(query, &symbol.name)?, symbol)))
+        .collect();
+    matches.sort_by_key(|&(score, _)| -score);
+
+    results.clear();
+    for (_, symbol) in matches.into_iter().take(100) {
+        let file = symbol.file.strip_prefix(&dir).unwrap_or(&symbol.file);
+        let label = format!("{}  {}", symbol.name, file.display());
+        results.add_item(label, symbol.clone());
+    }
+}
+
+// Open the file that defines a symbol, and move the cursor to its definition
+fn jump_to_symbol(s: &mut Cursive, symbol: &Symbol) {
+    let line = match symbol.line() {
+        Ok(line) => line,
+        Err(e) => {
+            s.add_layer(Dialog::info(format!(
+                "Could not find {}: {}",
+                symbol.name, e
+            )));
+            return;
+        }
+    };
+
+    let open_file = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        (code.filename().to_string(), code.is_modified())
+    });
+    let is_open = |file: &str| canonicalize(file).ok() == canonicalize(&symbol.file).ok();
+    match open_file {
+        Some((file, _)) if is_open(&file) => {
+            s.pop_layer();
+        }
+        Some((_, true)) => {
+            s.add_layer(Dialog::info(
+                "Save your changes before opening another file",
+            ));
+            return;
+        }
+        _ => {
+            // Replace the search and the file that was open with the symbol's file
+            let dir = current_dir().unwrap_or_default();
+            let file = symbol.file.strip_prefix(&dir).unwrap_or(&symbol.file);
+            let file = file.to_string_lossy();
+            s.pop_layer();
+            s.pop_layer();
+            open_path(s, &file, None);
+        }
+    }
+    s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.goto_line(line)
+    });
+}
+
+// Jump to where the word under the cursor is defined, according to the tags
+// file. The file is shown in its own buffer, opening it if it isn't open yet.
+fn goto_definition(s: &mut Cursive) {
+    let under_cursor = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        let word = code.word_under_cursor();
+        (word, code.cursor_position())
+    });
+    let (name, position) = match under_cursor {
+        Some((Some(name), position)) => (name, position),
+        Some((None, _)) => {
+            s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                code.bell("There's no symbol under the cursor")
+            });
+            return;
+        }
+        None => return,
+    };
+    let symbols = match project_symbols(s) {
+        Some(symbols) => symbols,
+        None => return,
+    };
+    let symbol = match symbols.into_iter().find(|symbol| symbol.name == name) {
+        Some(symbol) => symbol,
+        None => {
+            s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                code.bell(format!("{} isn't in the tags file", name))
+            });
+            return;
+        }
+    };
+    let line = match symbol.line() {
+        Ok(line) => line,
+        Err(e) => {
+            s.add_layer(Dialog::info(format!(
+                "Could not find {}: {}",
+                symbol.name, e
+            )));
+            return;
+        }
+    };
+
+    let from = s.active_screen();
+    show_buffer(s, &symbol.file);
+    s.with_user_data(|session: &mut Session| session.jumps.push((from, position)));
+    s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.goto_line(line)
+    });
+}
+
+// Go back to where the cursor was before the last jump to a definition
+fn jump_back(s: &mut Cursive) {
+    let jump = s
+        .user_data::<Session>()
+        .and_then(|session| session.jumps.pop());
+    let (screen, (line, col)) = match jump {
+        Some(jump) => jump,
+        None => {
+            s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                code.bell("There's nowhere to jump back to")
+            });
+            return;
+        }
+    };
+    s.set_screen(screen);
+    s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.goto_position(line, col)
+    });
+}
+
+// Show the buffer with a file open, opening the file in a new buffer
+// if none of them has it open
+fn show_buffer(s: &mut Cursive, path: &Path) {
+    let target = canonicalize(path).ok();
+    let is_open = |s: &mut Cursive| {
+        s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            canonicalize(&*code.filename()).ok() == target
+        })
+    };
+    if is_open(s) == Some(true) {
+        return;
+    }
+    let buffers = s
+        .user_data::<Session>()
+        .map_or(1, |session| session.buffers.max(1));
+    let active = s.active_screen();
+    for screen in 0..buffers {
+        s.set_screen(screen);
+        if is_open(s) == Some(true) {
+            return;
+        }
+    }
+    s.set_screen(active);
+
+    let dir = current_dir().unwrap_or_default();
+    let file = path.strip_prefix(&dir).unwrap_or(path);
+    s.add_active_screen();
+    s.with_user_data(|session: &mut Session| session.buffers += 1);
+    open_path(s, &file.to_string_lossy(), None);
+}
+
+// Run the selected lines of a script, or the whole script, once it's confirmed,
+// and show what each line printed next to it
+fn run_notebook(s: &mut Cursive) {
+    let script = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        let file = code.filename().to_string();
+        let interpreter = Interpreter::from_path(&file);
+        let (first_line, lines) = code.selected_lines();
+        interpreter.map(|interpreter| (file, interpreter, first_line, lines))
+    });
+    let (file, interpreter, first_line, lines) = match script {
+        Some(Some(script)) => script,
+        Some(None) => {
+            s.add_layer(Dialog::info("Only Python and JavaScript files can be run"));
+            return;
+        }
+        None => return,
+    };
+
+    // The code isn't sandboxed, so it's only run once it's known that's what was meant
+    let count = lines.lines().count();
+    let question = format!(
+        "Run {} line{} of {}?\n\nThe code can read and write anything you can.",
+        count,
+        if count == 1 { "" } else { "s" },
+        file
+    );
+    s.add_layer(
+        Dialog::text(question)
+            .title("Run")
+            .button("Run", move |s| {
+                s.pop_layer();
+                run_script(s, &file, interpreter, first_line, lines.clone());
+            })
+            .dismiss_button("Cancel"),
+    );
+}
+
+// Run some lines of a script in the background, and show what each line
+// printed next to it in the script's buffer
+fn run_script(
+    s: &mut Cursive,
+    file: &str,
+    interpreter: Interpreter,
+    first_line: usize,
+    lines: String,
+) {
+    // A file that isn't on disk yet is known by its name
+    let full_path = |file: &str| canonicalize(file).unwrap_or_else(|_| PathBuf::from(file));
+    let path = full_path(file);
+    // Run it in the background, so a slow script doesn't freeze the editor
+    let sink = s.cb_sink().clone();
+    thread::spawn(move || {
+        let result = interpreter.run(&lines, first_line, Duration::from_secs(10));
+        let _ = sink.send(Box::new(move |s: &mut Cursive| match result {
+            // The output goes to the script's buffer, even if another one is shown now
+            Ok(diagnostics) => {
+                each_buffer(s, |s| {
+                    s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                        if full_path(&code.filename()) == path {
+                            code.set_diagnostics(diagnostics.clone());
+                        }
+                    })
+                });
+            }
+            Err(e) => s.add_layer(Dialog::info(format!("Could not run the script: {}", e))),
+        }));
+    });
+}
+
+// Run `cargo check` on the open file's package, and mark the lines
+// it complains about in every Rust file that's open
+fn check_project(s: &mut Cursive) {
+    let file = match s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.filename().to_string()
+    }) {
+        Some(file) => file,
+        None => return,
+    };
+    let dir = file_dir(Path::new(&file)).to_path_buf();
+
+    // Checking can take a while, so it runs in the background
+    let sink = s.cb_sink().clone();
+    thread::spawn(move || {
+        let result = cargo_check(&dir);
+        let _ = sink.send(Box::new(move |s: &mut Cursive| match result {
+            Ok(diagnostics) => {
+                let count = |severity| {
+                    diagnostics
+                        .iter()
+                        .filter(|(_, d)| d.severity == severity)
+                        .count()
+                };
+                let summary = match (count(Severity::Error), count(Severity::Warning)) {
+                    (0, 0) => String::from("cargo check found no problems"),
+                    (errors, warnings) => {
+                        format!("cargo check: {} errors, {} warnings", errors, warnings)
+                    }
+                };
+                each_buffer(s, |s| {
+                    s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                        if !code.filename().ends_with(".rs") {
+                            return;
+                        }
+                        let path = canonicalize(&*code.filename()).ok();
+                        let found = diagnostics
+                            .iter()
+                            .filter(|(file, _)| Some(file) == path.as_ref())
+                            .map(|(_, diagnostic)| diagnostic.clone())
+                            .collect();
+                        code.set_diagnostics(found);
+                    })
+                });
+                s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                    code.bell(summary)
+                });
+            }
+            Err(e) => s.add_layer(Dialog::info(format!("Could not check the project: {}", e))),
+        }));
+    });
+}
+
+// Start the language server for the open file if it isn't running, and tell
+// it what's in the file. Returns the server's key and the file's full path.
+#[cfg(feature = "lsp")]
+fn sync_language_server(s: &mut Cursive) -> Option<((String, PathBuf), PathBuf)> {
+    let (file, text) = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        (code.filename().to_string(), code.text())
+    })?;
+    let (command, language_id) = server_for(&file)?;
+    let path = canonicalize(&file).ok()?;
+    let key = (command.to_string(), project_root(&path));
+
+    let sink = s.cb_sink().clone();
+    let session = s.user_data::<Session>()?;
+    let server = session
+        .language_servers
+        .entry(key.clone())
+        .or_insert_with(|| {
+            let server_key = key.clone();
+            let on_message = move |message: Json| {
+                let key = server_key.clone();
+                let _ = sink.send(Box::new(move |s: &mut Cursive| {
+                    receive_from_server(s, &key, message)
+                }));
+            };
+            LanguageServer::start(command, &key.1, on_message).ok()
+        });
+    // A server that stopped isn't started again
+    if server.as_mut()?.sync(&path, language_id, &text).is_err() {
+        *server = None;
+        return None;
+    }
+    Some((key, path))
+}
+
+// Ask the language server something about the place the cursor is at
+#[cfg(feature = "lsp")]
+fn ask_language_server(
+    s: &mut Cursive,
+    ask: impl FnOnce(&mut LanguageServer, &Path, usize, usize) -> io::Result<()>,
+) {
+    let (key, path) = match sync_language_server(s) {
+        Some(synced) => synced,
+        None => {
+            s.add_layer(Dialog::info(
+                "There's no language server running for this file",
+            ));
+            return;
+        }
+    };
+    let position = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.cursor_position()
+    });
+    let (line, column) = match position {
+        Some(position) => position,
+        None => return,
+    };
+    let asked = s.user_data::<Session>().and_then(|session| {
+        let server = session.language_servers.get_mut(&key)?.as_mut()?;
+        Some(ask(server, &path, line, column))
+    });
+    if let Some(Err(e)) = asked {
+        s.add_layer(Dialog::info(format!("The language server stopped: {}", e)));
+    }
+}
+
+// Have the language server format the selection, or the whole file if
+// nothing is selected, for file types with no formatter of their own
+#[cfg(feature = "lsp")]
+fn format_with_language_server(s: &mut Cursive) {
+    let (key, path) = match sync_language_server(s) {
+        Some(synced) => synced,
+        None => {
+            s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                code.bell("There's no formatter for this file type")
+            });
+            return;
+        }
+    };
+    let options = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        let point = |(row, col): (i32, i32)| (row as usize, col as usize);
+        let range = code
+            .selection_bounds()
+            .map(|(start, end)| (point(start), point(end)));
+        (range, code.tab_width(), code.hard_tabs())
+    });
+    let (range, tab_width, hard_tabs) = match options {
+        Some(options) => options,
+        None => return,
+    };
+    let asked = s.user_data::<Session>().and_then(|session| {
+        let server = session.language_servers.get_mut(&key)?.as_mut()?;
+        Some(server.format(&path, range, tab_width, hard_tabs))
+    });
+    if let Some(Err(e)) = asked {
+        s.add_layer(Dialog::info(format!("The language server stopped: {}", e)));
+    }
+}
+
+// Ask what to rename the symbol at the cursor to, and have the language server rename it
+#[cfg(feature = "lsp")]
+fn ask_rename(s: &mut Cursive) {
+    if s.find_name::<CodeArea<DefaultHighlighter>>("code")
+        .is_none()
+    {
+        return;
+    }
+
+    let input = EditView::new().on_submit(|s, name| {
+        s.pop_layer();
+        let name = name.trim().to_string();
+        if !name.is_empty() {
+            ask_language_server(s, move |server, path, line, column| {
+                server.rename(path, line, column, &name)
+            });
+        }
+    });
+    s.add_layer(
+        Dialog::new()
+            .title("Rename to")
+            .content(input.fixed_width(30))
+            .dismiss_button("Cancel"),
+    );
+}
+
+// Show the changes the edits a language server sent would make to each file,
+// and make them if they're accepted
+#[cfg(feature = "lsp")]
+fn preview_workspace_edits(s: &mut Cursive, title: &str, files: Vec<(PathBuf, Vec<TextEdit>)>) {
+    let dir = current_dir().unwrap_or_default();
+    let mut preview = String::new();
+    for (file, edits) in &files {
+        let name = file
+            .strip_prefix(&dir)
+            .unwrap_or(file)
+            .display()
+            .to_string();
+        match preview_edits(s, file, edits) {
+            Ok(hunks) if hunks.is_empty() => continue,
+            Ok(hunks) => {
+                preview += &format!("{}\n", name);
+                for hunk in hunks {
+                    preview += &hunk.to_string();
+                }
+            }
+            Err(e) => preview += &format!("{}\nCould not preview the edits: {}\n", name, e),
+        }
+        preview.push('\n');
+    }
+    if preview.is_empty() {
+        s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            code.bell("The language server's edits don't change anything")
+        });
+        return;
+    }
+    s.add_layer(
+        Dialog::around(TextView::new(preview.trim_end()).scrollable())
+            .title(title)
+            .button("Apply", move |s| {
+                s.pop_layer();
+                apply_workspace_edits(s, files.clone());
+            })
+            .dismiss_button("Cancel"),
+    );
+}
+
+// The changes some edits would make to a file, as it is in its buffer if it's open
+#[cfg(feature = "lsp")]
+fn preview_edits(s: &mut Cursive, file: &Path, edits: &[TextEdit]) -> io::Result<Vec<Hunk>> {
+    let open = each_buffer(s, |s| {
+        s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            let path = canonicalize(&*code.filename()).ok();
+            (path.as_deref() == Some(file)).then(|| code.preview_edits(edits))
+        })
+        .flatten()
+    });
+    match open.into_iter().next() {
+        Some(preview) => preview,
+        None => CodeArea::<DefaultHighlighter>::new()
+            .open_file(file.display())?
+            .preview_edits(edits),
+    }
+}
+
+// Make the edits a language server sent for each file in the file's buffer,
+// opening the files that aren't open in buffers of their own. They're left
+// unsaved, so they can be looked over first.
+#[cfg(feature = "lsp")]
+fn apply_workspace_edits(s: &mut Cursive, mut files: Vec<(PathBuf, Vec<TextEdit>)>) {
+    let mut errors = Vec::new();
+    each_buffer(s, |s| {
+        let applied = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            let path = canonicalize(&*code.filename()).ok();
+            let i = files
+                .iter()
+                .position(|(file, _)| Some(file) == path.as_ref())?;
+            let (file, edits) = files.remove(i);
+            Some(
+                code.apply_edits(&edits)
+                    .map_err(|e| format!("{}: {}", file.display(), e)),
+            )
+        })??;
+        match applied {
+            // The title is updated while its buffer is the one shown
+            Ok(Some(callback)) => callback(s),
+            Ok(None) => {}
+            Err(error) => errors.push(error),
+        }
+        Some(())
+    });
+    let active = s.active_screen();
+    for (file, edits) in files {
+        show_buffer(s, &file);
+        let applied = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            let path = canonicalize(&*code.filename()).ok();
+            (path.as_ref() == Some(&file)).then(|| code.apply_edits(&edits))
+        });
+        match applied.flatten() {
+            Some(Ok(Some(callback))) => callback(s),
+            Some(Ok(None)) => {}
+            Some(Err(e)) => errors.push(format!("{}: {}", file.display(), e)),
+            None => errors.push(format!("{}: the file could not be opened", file.display())),
+        }
+    }
+    s.set_screen(active);
+    if !errors.is_empty() {
+        s.add_layer(Dialog::info(format!(
+            "Could not edit every file:\n{}",
+            errors.join("\n")
+        )));
+    }
+}
+
+// Handle a message from a language server
+#[cfg(feature = "lsp")]
+fn receive_from_server(s: &mut Cursive, key: &(String, PathBuf), message: Json) {
+    let received = s.user_data::<Session>().and_then(|session| {
+        let server = session.language_servers.get_mut(key)?.as_mut()?;
+        Some(server.receive(message))
+    });
+    let reply = match received {
+        Some(Ok(Some(reply))) => reply,
+        Some(Err(e)) => {
+            s.add_layer(Dialog::info(format!("The language server stopped: {}", e)));
+            return;
+        }
+        _ => return,
+    };
+
+    match reply {
+        Reply::Diagnostics(path, diagnostics) => {
+            each_buffer(s, |s| {
+                s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                    if canonicalize(&*code.filename()).ok().as_ref() == Some(&path) {
+                        code.set_diagnostics(diagnostics.clone());
+                    }
+                })
+            });
+        }
+        Reply::Hover(Some(text)) => s.add_layer(Dialog::info(text)),
+        Reply::Hover(None) => {
+            s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                code.bell("The language server has nothing to say about this")
+            });
+        }
+        Reply::Definition(Some(location)) => goto_location(s, &location),
+        Reply::Definition(None) => {
+            s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                code.bell("Could not find where this is defined")
+            });
+        }
+        Reply::Rename(Some(files)) => preview_workspace_edits(s, "Rename", files),
+        Reply::Rename(None) => {
+            s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                code.bell("The language server could not rename this")
+            });
+        }
+        Reply::Format(path, Some(edits)) => {
+            let applied = each_buffer(s, |s| {
+                s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                    let matches = canonicalize(&*code.filename()).ok().as_ref() == Some(&path);
+                    matches.then(|| code.apply_edits(&edits))
+                })
+                .flatten()
+            });
+            for result in applied {
+                match result {
+                    Ok(Some(callback)) => callback(s),
+                    Ok(None) => {}
+                    Err(e) => s.add_layer(Dialog::info(format!("Could not format: {}", e))),
+                }
+            }
+        }
+        Reply::Format(_, None) => {
+            s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                code.bell("The language server could not format this")
+            });
+        }
+        Reply::CodeActions(actions) if actions.is_empty() => {
+            s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                code.bell("There are no code actions here")
+            });
+        }
+        Reply::CodeActions(actions) => show_code_actions(s, actions),
+    }
+}
+
+// Show the code actions a language server offers, and make the one that's picked
+#[cfg(feature = "lsp")]
+fn show_code_actions(s: &mut Cursive, actions: Vec<CodeAction>) {
+    let mut picker = SelectView::new().on_submit(|s, action: &CodeAction| {
+        s.pop_layer();
+        apply_workspace_edits(s, action.edits.clone());
+    });
+    for action in actions {
+        picker.add_item(action.title.clone(), action);
+    }
+    s.add_layer(
+        Dialog::new()
+            .title("Code actions")
+            .content(picker.scrollable())
+            .dismiss_button("Cancel"),
+    );
+}
+
+// Move the cursor to a place in a file, opening the file if it isn't the open one
+#[cfg(feature = "lsp")]
+fn goto_location(s: &mut Cursive, location: &Location) {
+    let open_file = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        (code.filename().to_string(), code.is_modified())
+    });
+    let is_open = |file: &str| canonicalize(file).ok().as_ref() == Some(&location.path);
+    match open_file {
+        Some((file, _)) if is_open(&file) => {}
+        Some((_, true)) => {
+            s.add_layer(Dialog::info(
+                "Save your changes before opening another file",
+            ));
+            return;
+        }
+        _ => {
+            let dir = current_dir().unwrap_or_default();
+            let file = location.path.strip_prefix(&dir).unwrap_or(&location.path);
+            let file = file.to_string_lossy();
+            s.pop_layer();
+            open_path(s, &file, None);
+        }
+    }
+    s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.goto_position(location.line, location.column)
+    });
+}
+
+// Show the next or previous file from the command line
+fn switch_buffer(s: &mut Cursive, forward: bool) {
+    let buffers = s
+        .user_data::<Session>()
+        .map_or(1, |session| session.buffers.max(1));
+    let active = s.active_screen();
+    let next = if forward {
+        (active + 1) % buffers
+    } else {
+        (active + buffers - 1) % buffers
+    };
+    s.set_screen(next);
+}
+
+// Run something on every buffer's screen, and collect what it returns
+fn each_buffer<T>(s: &mut Cursive, mut f: impl FnMut(&mut Cursive) -> Option<T>) -> Vec<T> {
+    let buffers = s
+        .user_data::<Session>()
+        .map_or(1, |session| session.buffers.max(1));
+    let active = s.active_screen();
+    let mut results = Vec::new();
+    for screen in 0..buffers {
+        s.set_screen(screen);
+        results.extend(f(s));
+    }
+    s.set_screen(active);
+    results
+}
+
+// The name a buffer is shown with
+fn buffer_name(code: &CodeArea<DefaultHighlighter>) -> String {
+    if code.is_scratch() {
+        SCRATCH_TITLE.to_string()
+    } else {
+        code.filename().to_string()
+    }
+}
+
+// Save every buffer, or only the ones with unsaved changes, and report the
+// ones that couldn't be saved. Returns whether they were all saved.
+fn save_buffers(s: &mut Cursive, only_modified: bool) -> bool {
+    let failures = each_buffer(s, |s| {
+        let failure = s
+            .call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                // Saving over another editor's changes is what the lock prevents
+                if only_modified && !code.is_modified() || code.is_locked_elsewhere() {
+                    return None;
+                }
+                let name = buffer_name(code);
+                code.save_content()
+                    .err()
+                    .map(|e| format!("{}: {}", name, e))
+            })
+            .flatten();
+        update_title(s);
+        failure
+    });
+    if failures.is_empty() {
+        return true;
+    }
+    s.add_layer(Dialog::info(format!("Could not save:\n{}", failures.join("\n"))).title("Save"));
+    false
+}
+
+// Quit, but first ask whether to save any buffers with unsaved changes
+fn ask_quit(s: &mut Cursive) {
+    let modified = each_buffer(s, |s| {
+        s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            Some(buffer_name(code)).filter(|_| code.is_modified())
+        })
+        .flatten()
+    });
+    if modified.is_empty() {
+        s.quit();
+        return;
+    }
+
+    let save_and_quit = |only_modified| {
+        move |s: &mut Cursive| {
+            s.pop_layer();
+            if save_buffers(s, only_modified) {
+                s.quit();
+            }
+        }
+    };
+    s.add_layer(
+        Dialog::text(format!(
+            "There are unsaved changes in:\n{}\nSave them before quitting?",
+            modified.join("\n")
+        ))
+        .title("Quit")
+        .button("Save modified", save_and_quit(true))
+        .button("Save all", save_and_quit(false))
+        .button("Discard", |s| s.quit())
+        .dismiss_button("Cancel"),
+    );
+}
+
+// Reload a file that changed on disk, or if it has unsaved changes,
+// ask whether to merge the changes on disk into them
+fn ask_file_changed(s: &mut Cursive) {
+    let changed = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        (buffer_name(code), code.is_modified())
+    });
+    let (name, modified) = match changed {
+        Some(changed) => changed,
+        None => return,
+    };
+    if !modified {
+        reload(s, false);
+        return;
+    }
+
+    s.add_layer(
+        Dialog::text(format!(
+            "{} changed on disk, and it has unsaved changes here.\n\
+             Merge the changes on disk into them, or reload and lose them?",
+            name
+        ))
+        .title("File changed")
+        .button("Merge", |s| {
+            s.pop_layer();
+            reload(s, true);
+        })
+        .button("Reload", |s| {
+            s.pop_layer();
+            reload(s, false);
+        })
+        .dismiss_button("Keep mine"),
+    );
+}
+
+// Reload the buffer from disk, or merge the file on disk into it
+fn reload(s: &mut Cursive, merge: bool) {
+    let result = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        if merge {
+            code.merge_from_disk().map(|conflicts| (conflicts, None))
+        } else {
+            code.reload_from_disk().map(|callback| (0, callback))
+        }
+    });
+    match result {
+        None => {}
+        Some(Ok((conflicts, callback))) => {
+            if let Some(callback) = callback {
+                callback(s);
+            }
+            if conflicts > 0 {
+                s.add_layer(Dialog::info(format!(
+                    "{} conflicting changes are marked with <<<<<<< and >>>>>>>",
+                    conflicts
+                )));
+            }
+        }
+        Some(Err(e)) => s.add_layer(Dialog::info(format!("Could not read the file: {}", e))),
+    }
+    update_title(s);
+}
+
+// The command line arguments
+#[derive(Default)]
+struct Args {
+    // Each file, and the line and column to open it at
+    files: Vec<(String, Option<(usize, usize)>)>,
+    read_only: bool,
+    help: bool,
+}
+
+// Parse arguments like `--readonly +42 notes.txt src/main.rs:120:15`
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    // The line from a `+LINE` argument, for the file after it
+    let mut line = None;
+    let mut options = true;
+    for arg in args {
+        if options && arg == "--" {
+            options = false;
+        } else if options && arg == "--readonly" {
+            parsed.read_only = true;
+        } else if options && (arg == "-h" || arg == "--help") {
+            parsed.help = true;
+        } else if options && arg.starts_with('+') {
+            let number = arg[1..]
+                .parse()
+                .map_err(|_| format!("bad line `{}`", arg))?;
+            line = Some(number);
+        } else if options && arg.starts_with('-') && arg != "-" {
+            return Err(format!("unknown option `{}`", arg));
+        } else {
+            let (file, position) = split_position(&arg);
+            let position = position.or_else(|| line.take().map(|line| (line, 1)));
+            parsed.files.push((file.to_string(), position));
+        }
+    }
+    Ok(parsed)
+}
+
+// Split a file like `src/main.rs:120:15` into its path and position,
+// unless a file with the whole name exists
+fn split_position(arg: &str) -> (&str, Option<(usize, usize)>) {
+    if !Path::new(arg).exists() {
+        for (i, _) in arg.match_indices(':').filter(|&(i, _)| i > 0) {
+            if let Some(position) = parse_position(&arg[i + 1..]) {
+                return (&arg[..i], Some(position));
+            }
+        }
+    }
+    (arg, None)
+}
+
+// Parse a position like `120` or `120:15` into a line and column
+fn parse_position(text: &str) -> Option<(usize, usize)> {
+    let mut parts = text.trim().splitn(2, ':');
+    let line = parts.next()?.trim().parse().ok()?;
+    let col = match parts.next() {
+        Some(col) => col.trim().parse().ok()?,
+        None => 1,
+    };
+    Some((line, col))
+}
+
+// Pick one of the project's tasks to run
+fn ask_task(s: &mut Cursive) {
+    let dir = current_dir().unwrap_or_default();
+    let tasks = match project_tasks(&dir) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            s.add_layer(Dialog::info(format!("Could not read the tasks: {}", e)));
+            return;
+        }
+    };
+    if tasks.is_empty() {
+        s.add_layer(Dialog::info(
+            "No tasks found, add a [tasks] table to .editor.toml",
+        ));
+        return;
+    }
+
+    let mut picker = SelectView::new().on_submit(|s, task: &Task| {
+        s.pop_layer();
+        run_task(s, task.clone());
+    });
+    for task in tasks {
+        let label = if task.command.ends_with(&task.name) {
+            task.command.clone()
+        } else {
+            format!("{}  {}", task.name, task.command)
+        };
+        picker.add_item(label, task);
+    }
+    s.add_layer(
+        Dialog::new()
+            .title("Run task")
+            .content(picker.scrollable())
+            .dismiss_button("Cancel"),
+    );
+}
+
+// Run the task that was run last, or pick one if there isn't one yet
+fn run_last_task(s: &mut Cursive) {
+    let last_task = s
+        .user_data::<Session>()
+        .and_then(|session| session.last_task.clone());
+    match last_task {
+        Some(task) => run_task(s, task),
+        None => ask_task(s),
+    }
+}
+
+// Start running a task, and return its output, which grows as it's printed,
+// and the task while it runs
+fn start_task(s: &mut Cursive, task: &Task) -> (TextContent, Option<Running>) {
+    let content = TextContent::new(format!("$ {}\n", task.command));
+    let output = content.clone();
+    let sink = s.cb_sink().clone();
+    let started = task.run(move |event| {
+        match event {
+            Output::Line(line) => output.append(format!("{}\n", line)),
+            Output::Done(Ok(status)) => output.append(format!("\n[{}]\n", status)),
+            Output::Done(Err(e)) => output.append(format!("\n[could not wait for it: {}]\n", e)),
+        }
+        // Redraw the screen with the new output
+        let _ = sink.send(Box::new(Cursive::noop));
+    });
+    match started {
+        Ok(running) => (content, Some(running)),
+        Err(e) => {
+            content.append(format!("could not run it: {}\n", e));
+            (content, None)
+        }
+    }
+}
+
+// Save the open file and run it with the run command for its language,
+// showing the output in a panel below the code as it's printed
+fn run_file(s: &mut Cursive) {
+    let saved = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        let saved = if code.is_modified() || code.is_scratch() {
+            code.save_content()
+        } else {
+            Ok(())
+        };
+        saved.map(|()| code.filename().to_string())
+    });
+    let file = match saved {
+        Some(Ok(file)) => file,
+        Some(Err(e)) => {
+            let message = format!("Could not save the file to run it: {}", e);
+            s.add_layer(Dialog::info(message));
+            return;
+        }
+        None => return,
+    };
+    update_title(s);
+    let command = match config(s).run_command(&file) {
+        Some(command) => command,
+        None => {
+            let message = match extension(&file) {
+                Some(extension) => format!(
+                    "There's no command to run {}, it can be set as `run` under [languages.{}] in the config",
+                    file, extension
+                ),
+                None => format!("There's no command to run {}", file),
+            };
+            s.add_layer(Dialog::info(message));
+            return;
+        }
+    };
+
+    let task = Task::for_file(&command, Path::new(&file));
+    close_output_panel(s);
+    let (content, running) = start_task(s, &task);
+    if let Some(session) = s.user_data::<Session>() {
+        session.run = running;
+    }
+    let output = TextView::new_with_content(content)
+        .with_name("run output text")
+        .scrollable()
+        .scroll_strategy(ScrollStrategy::StickToBottom);
+    let panel = Panel::new(output)
+        .title(format!("{} (Shift+F5 to close)", task.command))
+        .fixed_height(12)
+        .with_name("run output");
+    s.call_on_name("panes", |panes: &mut LinearLayout| {
+        panes.add_child(panel);
+        // Typing still goes to the code
+        let _ = panes.set_focus_index(0);
+    });
+}
+
+fn close_output_panel(s: &mut Cursive) {
+    if let Some(session) = s.user_data::<Session>() {
+        session.run = None;
+    }
+    s.call_on_name("panes", |panes: &mut LinearLayout| {
+        if let Some(i) = panes.find_child_from_name("run output") {
+            panes.remove_child(i);
+        }
+    });
+}
+
+// The marks of the project the editor was started in
+fn project_marks(s: &mut Cursive) -> Option<Marks> {
+    let dir = current_dir().unwrap_or_default();
+    match Marks::load(&dir) {
+        Ok(marks) => Some(marks),
+        Err(e) => {
+            s.add_layer(Dialog::info(format!("Could not read the marks: {}", e)));
+            None
+        }
+    }
+}
+
+// Show the file pinned to a slot
+fn jump_to_mark(s: &mut Cursive, slot: usize) {
+    let marks = match project_marks(s) {
+        Some(marks) => marks,
+        None => return,
+    };
+    match marks.get(slot) {
+        Some(file) => show_buffer(s, &file),
+        None => {
+            s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+                code.bell(format!("There's no file pinned to mark {}", slot))
+            });
+        }
+    }
+}
+
+// List the pinned files. Pressing a slot's number pins the open file to it,
+// Delete clears the selected slot, and Enter shows its file.
+fn ask_marks(s: &mut Cursive) {
+    let marks = match project_marks(s) {
+        Some(marks) => marks,
+        None => return,
+    };
+    let file = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.filename().to_string()
+    });
+
+    let mut picker = SelectView::new().on_submit(|s, &slot: &usize| {
+        s.pop_layer();
+        jump_to_mark(s, slot);
+    });
+    for slot in 1..=SLOTS {
+        let label = match marks.get(slot) {
+            Some(file) => format!("{}  {}", slot, file.display()),
+            None => format!("{}", slot),
+        };
+        picker.add_item(label, slot);
+    }
+    let change = |slot: usize, file: Option<String>| {
+        move |s: &mut Cursive| {
+            s.pop_layer();
+            let mut marks = match project_marks(s) {
+                Some(marks) => marks,
+                None => return,
+            };
+            marks.set(slot, file.as_deref().map(Path::new));
+            match marks.save() {
+                Ok(()) => ask_marks(s),
+                Err(e) => s.add_layer(Dialog::info(format!("Could not save the marks: {}", e))),
+            }
+        }
+    };
+
+    let title = format!("Marks (1-{}: pin this file, Del: clear)", SLOTS);
+    let mut keys = OnEventView::new(
+        Dialog::around(picker.with_name("marks").scrollable())
+            .title(title)
+            .dismiss_button("Close"),
+    );
+    for slot in 1..=SLOTS {
+        let ch = std::char::from_digit(slot as u32, 10).unwrap_or_default();
+        if let Some(file) = &file {
+            keys.set_on_pre_event(Event::Char(ch), change(slot, Some(file.clone())));
+        }
+    }
+    keys.set_on_pre_event(Event::Key(Key::Del), move |s| {
+        let selected = s.call_on_name("marks", |picker: &mut SelectView<usize>| picker.selection());
+        if let Some(Some(slot)) = selected {
+            change(*slot, None)(s);
+        }
+    });
+    s.add_layer(keys);
+}
+
+// Open a shell in a panel below the code, or close it and end the shell
+fn toggle_terminal(s: &mut Cursive) {
+    let closed = s.call_on_name("panes", |panes: &mut LinearLayout| {
+        let i = panes.find_child_from_name("terminal panel")?;
+        panes.remove_child(i)
+    });
+    match closed {
+        Some(Some(_)) | None => return,
+        Some(None) => {}
+    }
+
+    let dir = current_dir().unwrap_or_default();
+    // The panel's border takes up two rows and columns
+    let size = (s.screen_size().x.saturating_sub(2), 10);
+    let terminal = TerminalView::new(&dir, size, s.cb_sink().clone())
+        .on_location(goto_output_location)
+        .with_name("terminal");
+    let panel = Panel::new(terminal)
+        .title("Terminal (Alt+` to close)")
+        .fixed_height(12)
+        .with_name("terminal panel");
+    s.call_on_name("panes", |panes: &mut LinearLayout| {
+        panes.add_child(panel);
+        let _ = panes.set_focus_index(panes.len() - 1);
+    });
+}
+
+// The places in files printed in the output of tasks and in the terminal,
+// which are lines like `src/main.rs:3:5` in compiler errors
+fn output_locations(s: &mut Cursive) -> Vec<FileLocation> {
+    let mut lines = Vec::new();
+    for name in &["run output text", "task output"] {
+        s.call_on_name(name, |output: &mut TextView| {
+            let content = output.get_content();
+            lines.extend(content.source().lines().map(String::from));
+        });
+    }
+    if let Some(terminal) = s.find_name::<TerminalView>("terminal") {
+        lines.extend(terminal.lines());
+    }
+    let finder = LocationFinder::default();
+    lines
+        .iter()
+        .flat_map(|line| finder.find(line))
+        .filter(|location| find_output_file(s, &location.file).is_some())
+        .collect()
+}
+
+// Find a file named in some output. Tasks run in their own directories,
+// and the files they print may be relative to those or to the open file.
+fn find_output_file(s: &mut Cursive, file: &str) -> Option<PathBuf> {
+    let mut dirs = vec![current_dir().unwrap_or_default()];
+    let task = s
+        .user_data::<Session>()
+        .and_then(|session| session.last_task.clone());
+    if let Some(task) = task {
+        dirs.push(task.dir);
+    }
+    let open = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.filename().to_string()
+    });
+    if let Some(dir) = open.as_deref().and_then(|open| Path::new(open).parent()) {
+        dirs.push(dir.to_path_buf());
+    }
+    dirs.into_iter()
+        .map(|dir| dir.join(file))
+        .find(|path| path.is_file())
+}
+
+// Go to the next place in a file printed in the output, or the previous one
+fn goto_next_location(s: &mut Cursive, forward: bool) {
+    let locations = output_locations(s);
+    if locations.is_empty() {
+        s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            code.bell("There are no places in files in the output")
+        });
+        return;
+    }
+    // The output may have changed since the last place was gone to,
+    // so it's looked for again rather than remembered by its index
+    let last = s
+        .user_data::<Session>()
+        .and_then(|session| session.location.clone())
+        .and_then(|last| locations.iter().position(|location| *location == last));
+    let count = locations.len();
+    let i = match (last, forward) {
+        (Some(last), true) => (last + 1) % count,
+        (Some(last), false) => (last + count - 1) % count,
+        (None, true) => 0,
+        (None, false) => count - 1,
+    };
+    let location = locations[i].clone();
+    s.with_user_data(|session: &mut Session| session.location = Some(location.clone()));
+    goto_output_location(s, &location);
+}
+
+// Show a file printed in the output at the place it was printed with.
+// Going back with Alt+, returns to where the cursor was.
+fn goto_output_location(s: &mut Cursive, location: &FileLocation) {
+    let path = match find_output_file(s, &location.file) {
+        Some(path) => path,
+        None => {
+            s.add_layer(Dialog::info(format!("Could not find {}", location.file)));
+            return;
+        }
+    };
+    // The output of a task covers the code
+    if s.find_name::<Dialog>("output").is_some() {
+        s.pop_layer();
+    }
+    let position = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.cursor_position()
+    });
+    let from = s.active_screen();
+    show_buffer(s, &path);
+    if let Some(position) = position {
+        s.with_user_data(|session: &mut Session| session.jumps.push((from, position)));
+    }
+    goto(s, Some((location.line, location.col)));
+    let _ = s.focus_name("code");
+}
+
+// Show the open file in a second view below the first, or close the views
+// split off from it. The views show the same text, with a cursor and scroll
+// position each.
+fn toggle_second_view(s: &mut Cursive) {
+    if split_names(s).is_empty() {
+        split(s, Split::Below);
+    } else {
+        while close_split(s) {}
+    }
+}
+
+// Where another view of the code goes
+#[derive(Clone, Copy)]
+enum Split {
+    Below,
+    Beside,
+}
+
+// Show the open file in another view
+fn split(s: &mut Cursive, direction: Split) {
+    let area = code_area(s).on_modified(|s, _| update_title(s));
+    let second = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.second_view(area)
+    });
+    add_split(s, second, direction);
+}
+
+// Choose another open file to show in a view beside this one.
+// Edits in either view are made to that file's buffer.
+fn ask_split_buffer(s: &mut Cursive) {
+    let active = s.active_screen();
+    let mut screen = 0;
+    let names = each_buffer(s, |s| {
+        screen += 1;
+        s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            (screen - 1, buffer_name(code))
+        })
+    });
+
+    let mut picker = SelectView::new().on_submit(move |s, &screen: &usize| {
+        s.pop_layer();
+        // The title that says whether the file is modified is on its own screen
+        let area = code_area(s).on_modified(move |s, _| {
+            let active = s.active_screen();
+            s.set_screen(screen);
+            update_title(s);
+            s.set_screen(active);
+        });
+        s.set_screen(screen);
+        let second = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            code.second_view(area)
+        });
+        s.set_screen(active);
+        add_split(s, second, Split::Beside);
+    });
+    for (screen, name) in names {
+        if screen != active {
+            picker.add_item(name, screen);
+        }
+    }
+    if picker.is_empty() {
+        s.add_layer(Dialog::info("There are no other files open"));
+        return;
+    }
+    s.add_layer(
+        Dialog::new()
+            .title("Show beside this file")
+            .content(picker.scrollable())
+            .dismiss_button("Cancel"),
+    );
+}
+
+// Put another view of a buffer below the code or beside it, and focus it
+fn add_split(
+    s: &mut Cursive,
+    second: Option<Option<CodeArea<DefaultHighlighter>>>,
+    direction: Split,
+) {
+    let second = match second {
+        Some(Some(second)) => second,
+        Some(None) => {
+            s.add_layer(Dialog::info(
+                "Widen the buffer before showing it in a second view",
+            ));
+            return;
+        }
+        None => return,
+    };
+    let name = match s.user_data::<Session>() {
+        Some(session) => {
+            session.splits += 1;
+            format!("split {}", session.splits)
+        }
+        None => return,
+    };
+    let second = second.with_name(name);
+    match direction {
+        // Right below the focused pane
+        Split::Below => {
+            s.call_on_name("panes", |panes: &mut LinearLayout| {
+                let i = panes.get_focus_index() + 1;
+                panes.insert_child(i, second);
+                let _ = panes.set_focus_index(i);
+            });
+        }
+        // The outline stays at the right
+        Split::Beside => {
+            s.call_on_name("columns", |columns: &mut LinearLayout| {
+                let outline = columns.find_child_from_name("outline panel");
+                let i = (columns.get_focus_index() + 1).min(outline.unwrap_or(columns.len()));
+                columns.insert_child(i, second.full_width());
+                let _ = columns.set_focus_index(i);
+            });
+        }
+    }
+}
+
+// The names of the views split off from the code, oldest first
+fn split_names(s: &mut Cursive) -> Vec<String> {
+    let splits = s.user_data::<Session>().map_or(0, |session| session.splits);
+    (1..=splits)
+        .map(|n| format!("split {}", n))
+        .filter(|name| s.find_name::<CodeArea<DefaultHighlighter>>(name).is_some())
+        .collect()
+}
+
+// Whether a view below the code or beside it has the focus
+fn is_focused(s: &mut Cursive, name: &str) -> bool {
+    let column = s.call_on_name("columns", |columns: &mut LinearLayout| {
+        columns.find_child_from_name(name) == Some(columns.get_focus_index())
+    });
+    let pane = s.call_on_name("panes", |panes: &mut LinearLayout| {
+        let i = panes.find_child_from_name(name)?;
+        Some(i == panes.get_focus_index())
+    });
+    column == Some(true) && pane.flatten() != Some(false)
+}
+
+// Close the view split off from the code that has the focus, or the last
+// one that was split off. Returns whether there was one.
+fn close_split(s: &mut Cursive) -> bool {
+    let names = split_names(s);
+    let name = match names.iter().find(|name| is_focused(s, name)) {
+        Some(name) => name,
+        None => match names.last() {
+            Some(name) => name,
+            None => return false,
+        },
+    };
+    s.call_on_name("panes", |panes: &mut LinearLayout| {
+        if let Some(i) = panes.find_child_from_name(name) {
+            panes.remove_child(i);
+        }
+    });
+    s.call_on_name("columns", |columns: &mut LinearLayout| {
+        if let Some(i) = columns.find_child_from_name(name) {
+            columns.remove_child(i);
+        }
+    });
+    true
+}
+
+// What a key pressed after Ctrl+W does to the panes of the open buffer
+#[derive(Clone, Copy)]
+enum PaneMove {
+    Up,
+    Down,
+    Left,
+    Right,
+    Next,
+    Rotate,
+    Swap,
+    Split(Split),
+    SplitBuffer,
+    Close,
+}
+
+// Wait for a key saying which pane to focus, or how to rearrange them.
+// The panes are stacked, with other views or the outline beside them.
+fn ask_pane_move(s: &mut Cursive) {
+    if s.find_name::<LinearLayout>("panes").is_none() {
+        return;
+    }
+
+    let mut keys = OnEventView::new(
+        Dialog::text(
+            "k or Up: focus the pane above\n\
+             j or Down: focus the pane below\n\
+             h or Left, l or Right: focus the pane to the side\n\
+             w: focus the next pane\n\
+             r: rotate the panes\n\
+             x: swap with the next pane\n\
+             s: show the file again below\n\
+             v: show the file again beside it\n\
+             b: show another open file beside it\n\
+             c: close this view, or the last one split off",
+        )
+        .title("Panes")
+        .dismiss_button("Cancel"),
+    );
+    for (event, pane_move) in &[
+        (Event::Char('k'), PaneMove::Up),
+        (Event::Key(Key::Up), PaneMove::Up),
+        (Event::Char('j'), PaneMove::Down),
+        (Event::Key(Key::Down), PaneMove::Down),
+        (Event::Char('h'), PaneMove::Left),
+        (Event::Key(Key::Left), PaneMove::Left),
+        (Event::Char('l'), PaneMove::Right),
+        (Event::Key(Key::Right), PaneMove::Right),
+        (Event::Char('w'), PaneMove::Next),
+        (Event::CtrlChar('w'), PaneMove::Next),
+        (Event::Char('r'), PaneMove::Rotate),
+        (Event::Char('x'), PaneMove::Swap),
+        (Event::Char('s'), PaneMove::Split(Split::Below)),
+        (Event::Char('v'), PaneMove::Split(Split::Beside)),
+        (Event::Char('b'), PaneMove::SplitBuffer),
+        (Event::Char('c'), PaneMove::Close),
+    ] {
+        let pane_move = *pane_move;
+        keys.set_on_pre_event(event.clone(), move |s| {
+            s.pop_layer();
+            move_pane(s, pane_move);
+        });
+    }
+    s.add_layer(keys);
+}
+
+fn move_pane(s: &mut Cursive, pane_move: PaneMove) {
+    match pane_move {
+        PaneMove::Split(direction) => return split(s, direction),
+        PaneMove::SplitBuffer => return ask_split_buffer(s),
+        PaneMove::Close => {
+            close_split(s);
+            return;
+        }
+        _ => {}
+    }
+    if let PaneMove::Left | PaneMove::Right = pane_move {
+        s.call_on_name("columns", |columns: &mut LinearLayout| {
+            let focus = columns.get_focus_index();
+            let column = match pane_move {
+                PaneMove::Left => focus.checked_sub(1),
+                _ => Some(focus + 1),
+            };
+            if let Some(column) = column {
+                let _ = columns.set_focus_index(column);
+            }
+        });
+        return;
+    }
+    s.call_on_name("panes", |panes: &mut LinearLayout| {
+        let count = panes.len();
+        if count < 2 {
+            return;
+        }
+        let focus = panes.get_focus_index();
+        match pane_move {
+            // Panes that can't take focus, like an empty output panel, are skipped
+            PaneMove::Up => {
+                for i in (0..focus).rev() {
+                    if panes.set_focus_index(i).is_ok() {
+                        break;
+                    }
+                }
+            }
+            PaneMove::Down => {
+                for i in focus + 1..count {
+                    if panes.set_focus_index(i).is_ok() {
+                        break;
+                    }
+                }
+            }
+            PaneMove::Next => {
+                for step in 1..count {
+                    if panes.set_focus_index((focus + step) % count).is_ok() {
+                        break;
+                    }
+                }
+            }
+            // The last pane moves to the top, and the focused pane keeps focus
+            PaneMove::Rotate => {
+                if let Some(last) = panes.remove_child(count - 1) {
+                    panes.insert_child(0, last);
+                }
+                let _ = panes.set_focus_index((focus + 1) % count);
+            }
+            PaneMove::Swap => {
+                let next = (focus + 1) % count;
+                panes.swap_children(focus, next);
+                let _ = panes.set_focus_index(next);
+            }
+            _ => {}
+        }
+    });
+}
+
+// Hide everything but the text of the open file, and center it for writing,
+// or show the rest again
+fn toggle_zen(s: &mut Cursive) {
+    let zen = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.toggle_zen();
+        code.is_zen()
+    });
+    if zen == Some(true) {
+        s.call_on_name("columns", |columns: &mut LinearLayout| {
+            if let Some(i) = columns.find_child_from_name("outline panel") {
+                columns.remove_child(i);
+            }
+        });
+    }
+    update_title(s);
+}
+
+// Show the functions, types or headings of the open file beside it,
+// or close the outline if it's open
+fn toggle_outline(s: &mut Cursive) {
+    let closed = s.call_on_name("columns", |columns: &mut LinearLayout| {
+        let i = columns.find_child_from_name("outline panel")?;
+        columns.remove_child(i)
+    });
+    match closed {
+        Some(Some(_)) | None => return,
+        Some(None) => {}
+    }
+
+    let file = s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+        code.filename().to_string()
+    });
+    if file.as_deref().and_then(Outliner::from_path).is_none() {
+        s.add_layer(Dialog::info("There's no outline for this kind of file"));
+        return;
+    }
+
+    let outline = SelectView::<usize>::new().on_submit(|s, &line| {
+        s.call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            code.goto_line(line)
+        });
+        let _ = s.focus_name("code");
+    });
+    let panel = Panel::new(outline.with_name("outline").scrollable())
+        .title("Outline")
+        .fixed_width(30)
+        .with_name("outline panel");
+    s.call_on_name("columns", |columns: &mut LinearLayout| {
+        columns.add_child(panel);
+    });
+    refresh_outline(s);
+}
+
+// List the symbols of the open file in its outline, if it's shown,
+// keeping the selected one selected
+fn refresh_outline(s: &mut Cursive) {
+    if s.find_name::<SelectView<usize>>("outline").is_none() {
+        return;
+    }
+    let entries = s
+        .call_on_name("code", |code: &mut CodeArea<DefaultHighlighter>| {
+            code.outline()
+        })
+        .unwrap_or_default();
+    s.call_on_name("outline", |outline: &mut SelectView<usize>| {
+        show_outline(outline, &entries)
+    });
+}
+
+fn show_outline(outline: &mut SelectView<usize>, entries: &[Entry]) {
+    let items: Vec<(String, usize)> = entries
+        .iter()
+        .map(|entry| {
+            let indent = "  ".repeat(entry.depth);
+            (
+                format!("{}{} {}", indent, entry.kind, entry.name),
+                entry.line,
+            )
+        })
+        .collect();
+    let unchanged = outline.len() == items.len()
+        && outline
+            .iter()
+            .zip(&items)
+            .all(|((label, &line), (new_label, new_line))| label == new_label && line == *new_line);
+    if unchanged {
+        return;
+    }
+
+    let selected = outline.selected_id();
+    outline.clear();
+    for (label, line) in items {
+        outline.add_item(label, line);
+    }
+    if let Some(selected) = selected {
+        let _ = outline.set_selection(selected.min(outline.len().saturating_sub(1)));
+    }
+}
+
+// Run a task, and show its output as it's printed
+fn run_task(s: &mut Cursive, task: Task) {
+    // Replace the output of the last task
+    if s.find_name::<Dialog>("output").is_some() {
+        close_task_output(s);
+    }
+
+    let (content, running) = start_task(s, &task);
+    if let Some(session) = s.user_data::<Session>() {
+        session.last_task = Some(task.clone());
+        session.task = running;
+    }
+    s.add_layer(
+        Dialog::new()
+            .title(format!("Output of {}", task.name))
+            .content(
+                TextView::new_with_content(content)
+                    .with_name("task output")
+                    .scrollable()
+                    .scroll_strategy(ScrollStrategy::StickToBottom)
+                    .fixed_height(15),
+            )
+            .button("Run again", run_last_task)
+            .button("Close", close_task_output)
+            .with_name("output"),
+    );
+}
+
+// Close the output of a task, and stop the task if it's still running
+fn close_task_output(s: &mut Cursive) {
+    s.pop_layer();
+    if let Some(session) = s.user_data::<Session>() {
+        session.task = None;
+    }
 }
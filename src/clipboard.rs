@@ -0,0 +1,187 @@
+use log::debug;
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::rc::Rc;
+
+/// How many earlier cuts and copies are kept to paste again
+const HISTORY: usize = 16;
+
+/// How cut or copied text is pasted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipboardKind {
+    /// Text pasted at the cursor
+    Chars,
+    /// Whole lines, pasted above the cursor's line
+    Lines,
+    /// The rows of a block, pasted onto the rows from the cursor down
+    Block,
+}
+
+/// Which code areas share a clipboard
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sharing {
+    /// Each code area has its own clipboard
+    Local,
+    /// All the code areas share one clipboard
+    Shared,
+    /// All the code areas share one clipboard, which is also
+    /// the clipboard of the desktop
+    System,
+}
+
+impl Sharing {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "local" => Some(Self::Local),
+            "shared" => Some(Self::Shared),
+            "system" => Some(Self::System),
+            _ => None,
+        }
+    }
+}
+
+/// Cut and copied text, with the recent cuts and copies before it
+#[derive(Clone, Debug)]
+pub struct Clipboard {
+    text: String,
+    kind: ClipboardKind,
+    /// Recent cuts and copies, newest first, and how each is pasted
+    history: Vec<(String, ClipboardKind)>,
+    /// Whether the text is also put on the desktop's clipboard
+    system: bool,
+}
+
+impl Default for Clipboard {
+    fn default() -> Self {
+        Self {
+            text: String::new(),
+            kind: ClipboardKind::Chars,
+            history: Vec::new(),
+            system: false,
+        }
+    }
+}
+
+impl Clipboard {
+    /// A clipboard that code areas can share
+    pub fn shared(sharing: Sharing) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self {
+            system: sharing == Sharing::System,
+            ..Self::default()
+        }))
+    }
+
+    /// Put text on the clipboard, keeping what was there before in the history
+    pub fn set(&mut self, text: String, kind: ClipboardKind) {
+        if self.system && text != self.text {
+            if let Err(e) = copy_to_system(&text) {
+                debug!("Could not copy to the system clipboard: {}", e);
+            }
+        }
+        self.remember(text, kind);
+    }
+
+    fn remember(&mut self, text: String, kind: ClipboardKind) {
+        self.history.retain(|(old, _)| *old != text);
+        self.history.insert(0, (text.clone(), kind));
+        self.history.truncate(HISTORY);
+        self.text = text;
+        self.kind = kind;
+    }
+
+    /// Take text copied in another program since the last cut or copy here,
+    /// to be pasted at the cursor. This runs a program to read the desktop's
+    /// clipboard, so it should be done once for each paste.
+    pub fn read_system(&mut self) {
+        if !self.system {
+            return;
+        }
+        match paste_from_system() {
+            Ok(text) if !text.is_empty() && text != self.text => {
+                self.remember(text, ClipboardKind::Chars)
+            }
+            Ok(_) => {}
+            Err(e) => debug!("Could not paste from the system clipboard: {}", e),
+        }
+    }
+
+    /// The text to paste and how to paste it
+    pub fn contents(&self) -> (String, ClipboardKind) {
+        (self.text.clone(), self.kind)
+    }
+
+    /// Recent cuts and copies, newest first
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(|(text, _)| text.as_str())
+    }
+
+    /// Put an entry from the history back on the clipboard
+    pub fn restore(&mut self, index: usize) -> bool {
+        match self.history.get(index).cloned() {
+            Some((text, kind)) => {
+                self.set(text, kind);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// The command that copies its input to the desktop's clipboard,
+/// and the one that prints what's on it
+fn system_commands() -> Option<(&'static [&'static str], &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        Some((&["pbcopy"], &["pbpaste"]))
+    } else if env::var_os("WAYLAND_DISPLAY").is_some() {
+        Some((&["wl-copy"], &["wl-paste", "-n"]))
+    } else if env::var_os("DISPLAY").is_some() {
+        Some((
+            &["xclip", "-selection", "clipboard"],
+            &["xclip", "-selection", "clipboard", "-o"],
+        ))
+    } else {
+        None
+    }
+}
+
+fn copy_to_system(text: &str) -> io::Result<()> {
+    let (copy, _) = system_commands().ok_or_else(no_clipboard)?;
+    // The copying programs fork to keep serving the text, so waiting
+    // only waits until they've read it. Their output is ignored.
+    let mut child = command(copy)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(text.as_bytes())?;
+    child.wait()?;
+    Ok(())
+}
+
+fn paste_from_system() -> io::Result<String> {
+    let (_, paste) = system_commands().ok_or_else(no_clipboard)?;
+    let output = command(paste).stdin(Stdio::null()).output()?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+fn command(words: &[&str]) -> Command {
+    let mut command = Command::new(words[0]);
+    command.args(&words[1..]);
+    command
+}
+
+fn no_clipboard() -> io::Error {
+    io::Error::other("there is no desktop clipboard")
+}
@@ -0,0 +1,124 @@
+use crate::languages::Language;
+
+/// How a language writes comments
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CommentStyle {
+    /// What starts a comment that runs to the end of the line, like `// `
+    pub line: Option<&'static str>,
+    /// What starts and ends a comment that can go anywhere, like `/* ` and ` */`
+    pub block: Option<(&'static str, &'static str)>,
+}
+
+const C: CommentStyle = CommentStyle {
+    line: Some("// "),
+    block: Some(("/* ", " */")),
+};
+
+const HASH: CommentStyle = CommentStyle {
+    line: Some("# "),
+    block: None,
+};
+
+const HTML: CommentStyle = CommentStyle {
+    line: None,
+    block: Some(("<!-- ", " -->")),
+};
+
+impl CommentStyle {
+    /// Guess how a file writes comments from its name
+    pub fn from_path(path: &str) -> Option<Self> {
+        let style = match Language::from_path(path)? {
+            Language::Rust
+            | Language::C
+            | Language::Cpp
+            | Language::CSharp
+            | Language::Java
+            | Language::Kotlin
+            | Language::Scala
+            | Language::Go
+            | Language::Swift
+            | Language::Dart
+            | Language::JavaScript
+            | Language::TypeScript
+            | Language::Php
+            | Language::Scss
+            | Language::Less
+            | Language::Zig => C,
+            Language::Python
+            | Language::Sh
+            | Language::Bash
+            | Language::Zsh
+            | Language::Fish
+            | Language::Ruby
+            | Language::Perl
+            | Language::R
+            | Language::Yaml
+            | Language::Toml
+            | Language::Conf
+            | Language::CMake
+            | Language::Nix
+            | Language::Elixir
+            | Language::Julia
+            | Language::Make
+            | Language::Docker => HASH,
+            Language::Html
+            | Language::Xhtml
+            | Language::Xml
+            | Language::Markdown
+            | Language::Vue => HTML,
+            Language::Css => Self {
+                line: None,
+                block: Some(("/* ", " */")),
+            },
+            Language::Sql => Self {
+                line: Some("-- "),
+                block: Some(("/* ", " */")),
+            },
+            Language::Lua => Self {
+                line: Some("-- "),
+                block: Some(("--[[ ", " ]]")),
+            },
+            Language::Haskell | Language::Elm => Self {
+                line: Some("-- "),
+                block: Some(("{- ", " -}")),
+            },
+            Language::OCaml => Self {
+                line: None,
+                block: Some(("(* ", " *)")),
+            },
+            Language::Lisp | Language::Ini | Language::Assembly => Self {
+                line: Some("; "),
+                block: None,
+            },
+            Language::TeX | Language::Erlang => Self {
+                line: Some("% "),
+                block: None,
+            },
+            Language::Vim => Self {
+                line: Some("\" "),
+                block: None,
+            },
+        };
+        Some(style)
+    }
+}
+
+/// If `text` is wrapped in a block comment, how many bytes its start and end
+/// markers take up. The spaces inside the markers can be left out.
+pub fn block_comment_markers(text: &str, (open, close): (&str, &str)) -> Option<(usize, usize)> {
+    let open_len = if text.starts_with(open) {
+        open.len()
+    } else if text.starts_with(open.trim_end()) {
+        open.trim_end().len()
+    } else {
+        return None;
+    };
+    let close_len = if text.ends_with(close) {
+        close.len()
+    } else if text.ends_with(close.trim_start()) {
+        close.trim_start().len()
+    } else {
+        return None;
+    };
+    Some((open_len, close_len)).filter(|(open_len, close_len)| open_len + close_len <= text.len())
+}
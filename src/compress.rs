@@ -0,0 +1,51 @@
+use crate::pipe::pipe_through;
+use std::io;
+use std::process::Command;
+
+/// A single-file compression format
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// Guess the compression format of a file from its extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        [Self::Gzip, Self::Zstd, Self::Xz]
+            .iter()
+            .copied()
+            .find(|compression| path.ends_with(compression.extension()))
+    }
+
+    /// The extension of files compressed in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Gzip => ".gz",
+            Self::Zstd => ".zst",
+            Self::Xz => ".xz",
+        }
+    }
+
+    /// The program used to compress and decompress this format.
+    /// All of them take the same flags for the things we need.
+    fn program(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Xz => "xz",
+        }
+    }
+
+    pub fn decompress(&self, compressed: &[u8]) -> io::Result<Vec<u8>> {
+        pipe_through(
+            Command::new(self.program()).args(["-q", "-d", "-c"]),
+            compressed,
+        )
+    }
+
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        pipe_through(Command::new(self.program()).args(["-q", "-c"]), data)
+    }
+}
@@ -0,0 +1,376 @@
+use crate::backup::{Autosave, BackupStyle};
+use crate::clipboard::Sharing;
+use crate::formatter::Formatter;
+use crate::highlight_rules::{parse_effect, HighlightRule};
+use crate::keymap::{parse_key, Command, Keymap};
+use crate::languages::extension;
+use crate::settings::LanguageSettings;
+use crate::tasks::default_run_command;
+use crate::views::{CodeArea, Highlighter};
+use cursive::theme::Color;
+use regex::Regex;
+use std::collections::HashMap;
+use std::env;
+use std::fs::read_to_string;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use toml::Value;
+
+/// Settings loaded from `~/.config/editor/config.toml`, like:
+///
+/// ```toml
+/// theme = "~/.config/editor/theme.toml"
+/// tab_width = 2
+/// hard_tabs = false
+/// auto_pairs = true
+/// comment = "# "
+/// page_size = 20
+/// zen_width = 72        # how wide the text is in distraction-free mode
+/// typewriter = true     # keep the cursor's row in the middle of the view
+/// soft_wrap = true      # wrap long lines instead of scrolling sideways
+/// bell = false          # don't say when a command can't do anything
+/// backup = "tilde"      # or "hidden"
+/// format_on_save = true
+/// clipboard = "system"  # or "shared" between files, or "local" to each
+///
+/// [[highlight]]         # highlighted on top of the language's highlighting
+/// pattern = "\\b(TODO|FIXME)\\b"
+/// color = "light red"
+/// effects = ["bold", "underline"]
+///
+/// [autosave]
+/// mode = "snapshot"     # or "save"
+/// delay = 30            # seconds without edits
+///
+/// [keys]
+/// ctrl-w = "quit"
+/// alt-backspace = "delete-word-left"
+/// ctrl-k = "none"       # unbind a key
+///
+/// [languages.md]        # settings for files with an extension
+/// tab_width = 2
+/// spell_check = false   # prose is checked with aspell or hunspell
+///
+/// [languages.py]
+/// formatter = "ruff format -"
+/// run = "python3 -u {file}"  # what F5 runs
+///
+/// [languages.go]
+/// formatter = "none"    # don't format Go files
+///
+/// [languages.rs.snippets]  # typed, then expanded with Tab, which jumps to $1, $2, ..., $0
+/// fn = "fn $1($2) {\n\t$0\n}"
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    /// The path of the cursive theme file
+    pub theme: PathBuf,
+    pub tab_width: usize,
+    pub hard_tabs: bool,
+    pub auto_pairs: bool,
+    /// The comment prefix, instead of the one for each file type
+    pub comment: Option<String>,
+    pub page_size: usize,
+    /// How many columns of text are shown in distraction-free mode
+    pub zen_width: usize,
+    /// Whether to keep the cursor's row in the middle of the view
+    pub typewriter: bool,
+    /// Whether long lines wrap onto the rows below them
+    pub soft_wrap: bool,
+    /// Whether to show a message when a command can't do anything
+    pub bell: bool,
+    pub backup: Option<BackupStyle>,
+    pub autosave: Option<(Autosave, Duration)>,
+    /// Whether to format files when they're saved
+    pub format_on_save: bool,
+    /// Which code areas share a clipboard
+    pub clipboard: Sharing,
+    pub highlight_rules: Vec<HighlightRule>,
+    /// Settings that replace the ones above for files with an extension,
+    /// like their formatter and snippets
+    pub languages: HashMap<String, LanguageSettings>,
+    pub keymap: Keymap,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            theme: PathBuf::from("assets/style.toml"),
+            tab_width: 4,
+            hard_tabs: false,
+            auto_pairs: true,
+            comment: None,
+            page_size: 8,
+            zen_width: 80,
+            typewriter: false,
+            soft_wrap: false,
+            bell: true,
+            backup: None,
+            autosave: None,
+            format_on_save: false,
+            clipboard: Sharing::Shared,
+            highlight_rules: Vec::new(),
+            languages: ["md", "markdown", "txt"]
+                .iter()
+                .map(|extension| (extension.to_string(), LanguageSettings::prose()))
+                .collect(),
+            keymap: Keymap::default(),
+        }
+    }
+}
+
+impl Config {
+    /// The path of the config file, in `$XDG_CONFIG_HOME` or `~/.config`
+    pub fn path() -> Option<PathBuf> {
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("editor").join("config.toml"))
+    }
+
+    /// Load the config file, or use the defaults if there isn't one
+    pub fn load() -> io::Result<Self> {
+        match Self::path() {
+            Some(path) if path.exists() => Self::from_file(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Load a config file. Relative paths in it are relative to the file.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::from_toml(&read_to_string(path)?, dir)
+    }
+
+    /// Parse a config, where relative paths are relative to `dir`
+    pub fn from_toml(text: &str, dir: &Path) -> io::Result<Self> {
+        let value: Value = text.parse().map_err(invalid)?;
+        let mut config = Self::default();
+
+        if let Some(theme) = get(&value, "theme", Value::as_str)? {
+            config.theme = expand_path(theme, dir);
+        }
+        if let Some(width) = get(&value, "tab_width", Value::as_integer)? {
+            config.tab_width = positive(width, "tab_width")?;
+        }
+        if let Some(hard_tabs) = get(&value, "hard_tabs", Value::as_bool)? {
+            config.hard_tabs = hard_tabs;
+        }
+        if let Some(auto_pairs) = get(&value, "auto_pairs", Value::as_bool)? {
+            config.auto_pairs = auto_pairs;
+        }
+        if let Some(comment) = get(&value, "comment", Value::as_str)? {
+            config.comment = Some(comment.to_string());
+        }
+        if let Some(page_size) = get(&value, "page_size", Value::as_integer)? {
+            config.page_size = positive(page_size, "page_size")?;
+        }
+        if let Some(zen_width) = get(&value, "zen_width", Value::as_integer)? {
+            config.zen_width = positive(zen_width, "zen_width")?;
+        }
+        if let Some(typewriter) = get(&value, "typewriter", Value::as_bool)? {
+            config.typewriter = typewriter;
+        }
+        if let Some(soft_wrap) = get(&value, "soft_wrap", Value::as_bool)? {
+            config.soft_wrap = soft_wrap;
+        }
+        if let Some(bell) = get(&value, "bell", Value::as_bool)? {
+            config.bell = bell;
+        }
+        config.backup = match get(&value, "backup", Value::as_str)? {
+            None | Some("none") => None,
+            Some("tilde") => Some(BackupStyle::Tilde),
+            Some("hidden") => Some(BackupStyle::Hidden),
+            Some(other) => return Err(invalid(format!("unknown backup style `{}`", other))),
+        };
+        if let Some(format_on_save) = get(&value, "format_on_save", Value::as_bool)? {
+            config.format_on_save = format_on_save;
+        }
+        if let Some(clipboard) = get(&value, "clipboard", Value::as_str)? {
+            config.clipboard = Sharing::from_name(clipboard)
+                .ok_or_else(|| invalid(format!("unknown clipboard `{}`", clipboard)))?;
+        }
+
+        if let Some(autosave) = value.get("autosave") {
+            let mode = match get(autosave, "mode", Value::as_str)? {
+                None | Some("save") => Autosave::Save,
+                Some("snapshot") => Autosave::Snapshot,
+                Some(other) => return Err(invalid(format!("unknown autosave mode `{}`", other))),
+            };
+            let delay = get(autosave, "delay", Value::as_integer)?.unwrap_or(30);
+            let delay = positive(delay, "autosave.delay")?;
+            config.autosave = Some((mode, Duration::from_secs(delay as u64)));
+        }
+
+        if let Some(languages) = get(&value, "languages", Value::as_table)? {
+            for (extension, table) in languages {
+                let settings = config.languages.entry(extension.clone()).or_default();
+                language_settings(settings, table)
+                    .map_err(|e| invalid(format!("in `languages.{}`: {}", extension, e)))?;
+            }
+        }
+
+        for rule in get(&value, "highlight", Value::as_array)?.unwrap_or(&Vec::new()) {
+            config.highlight_rules.push(highlight_rule(rule)?);
+        }
+
+        if let Some(keys) = get(&value, "keys", Value::as_table)? {
+            for (key, command) in keys {
+                let event =
+                    parse_key(key).ok_or_else(|| invalid(format!("unknown key `{}`", key)))?;
+                match command.as_str() {
+                    Some("none") => config.keymap.unbind(&event),
+                    Some(name) => match Command::from_name(name) {
+                        Some(command) => config.keymap.bind(event, command),
+                        None => return Err(invalid(format!("unknown command `{}`", name))),
+                    },
+                    None => return Err(invalid(format!("`{}` should be a command", key))),
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// The command that runs a file, where `{file}` stands for its name
+    pub fn run_command(&self, file: &str) -> Option<String> {
+        let settings = extension(file).and_then(|extension| self.languages.get(extension));
+        match settings.and_then(|settings| settings.run.as_ref()) {
+            Some(command) => Some(command.clone()),
+            None => default_run_command(file).map(String::from),
+        }
+    }
+
+    /// Apply the settings to a code area
+    pub fn apply<H: Highlighter>(&self, area: CodeArea<H>) -> CodeArea<H> {
+        let mut area = area
+            .with_tab_width(self.tab_width)
+            .with_hard_tabs(self.hard_tabs)
+            .with_auto_pairs(self.auto_pairs)
+            .with_page_size(self.page_size)
+            .with_zen_width(self.zen_width)
+            .with_typewriter(self.typewriter)
+            .with_soft_wrap(self.soft_wrap)
+            .with_bell(self.bell)
+            .with_format_on_save(self.format_on_save)
+            .with_highlight_rules(self.highlight_rules.clone())
+            .with_keymap(self.keymap.clone());
+        if let Some(comment) = &self.comment {
+            area = area.with_comment(comment);
+        }
+        if let Some(backup) = self.backup {
+            area = area.with_backup(backup);
+        }
+        if let Some((autosave, delay)) = self.autosave {
+            area = area.with_autosave(autosave, delay);
+        }
+        for (extension, settings) in &self.languages {
+            area = area.with_language_settings(extension, settings.clone());
+        }
+        area
+    }
+}
+
+/// Change the settings for an extension to the ones in its `[languages.*]` table
+fn language_settings(settings: &mut LanguageSettings, table: &Value) -> io::Result<()> {
+    match get(table, "prose", Value::as_bool)? {
+        Some(true) => *settings = LanguageSettings::prose(),
+        Some(false) => *settings = LanguageSettings::default(),
+        None => {}
+    }
+    if let Some(width) = get(table, "tab_width", Value::as_integer)? {
+        settings.tab_width = Some(positive(width, "tab_width")?);
+    }
+    if let Some(hard_tabs) = get(table, "hard_tabs", Value::as_bool)? {
+        settings.hard_tabs = Some(hard_tabs);
+    }
+    if let Some(auto_pairs) = get(table, "auto_pairs", Value::as_bool)? {
+        settings.auto_pairs = Some(auto_pairs);
+    }
+    if let Some(typewriter) = get(table, "typewriter", Value::as_bool)? {
+        settings.typewriter = Some(typewriter);
+    }
+    if let Some(soft_wrap) = get(table, "soft_wrap", Value::as_bool)? {
+        settings.soft_wrap = Some(soft_wrap);
+    }
+    if let Some(spell_check) = get(table, "spell_check", Value::as_bool)? {
+        settings.spell_check = Some(spell_check);
+    }
+    if let Some(formatter) = get(table, "formatter", Value::as_str)? {
+        settings.formatter = match formatter {
+            "none" => Some(None),
+            command => Some(Some(Formatter::new(command))),
+        };
+    }
+    if let Some(run) = get(table, "run", Value::as_str)? {
+        settings.run = Some(run.to_string());
+    }
+    if let Some(snippets) = get(table, "snippets", Value::as_table)? {
+        for (trigger, body) in snippets {
+            let body = body
+                .as_str()
+                .ok_or_else(|| invalid(format!("snippet `{}` should be text", trigger)))?;
+            settings.snippets.insert(trigger.clone(), body.to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Parse a `[[highlight]]` rule
+fn highlight_rule(rule: &Value) -> io::Result<HighlightRule> {
+    let pattern = get(rule, "pattern", Value::as_str)?
+        .ok_or_else(|| invalid("a highlight rule needs a `pattern`"))?;
+    let pattern = Regex::new(pattern).map_err(invalid)?;
+    let color = match get(rule, "color", Value::as_str)? {
+        Some(name) => {
+            Some(Color::parse(name).ok_or_else(|| invalid(format!("unknown color `{}`", name)))?)
+        }
+        None => None,
+    };
+    let mut effects = Vec::new();
+    for effect in get(rule, "effects", Value::as_array)?.unwrap_or(&Vec::new()) {
+        let name = effect
+            .as_str()
+            .ok_or_else(|| invalid("`effects` should be a list of names"))?;
+        let effect =
+            parse_effect(name).ok_or_else(|| invalid(format!("unknown effect `{}`", name)))?;
+        effects.push(effect);
+    }
+    Ok(HighlightRule::new(pattern, color, &effects))
+}
+
+fn invalid(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+/// Get a setting from a table, if it's there and has the right type
+fn get<'a, T>(
+    table: &'a Value,
+    key: &str,
+    convert: impl Fn(&'a Value) -> Option<T>,
+) -> io::Result<Option<T>> {
+    match table.get(key) {
+        Some(value) => convert(value)
+            .map(Some)
+            .ok_or_else(|| invalid(format!("`{}` can't be a {}", key, value.type_str()))),
+        None => Ok(None),
+    }
+}
+
+fn positive(number: i64, key: &str) -> io::Result<usize> {
+    if number > 0 {
+        Ok(number as usize)
+    } else {
+        Err(invalid(format!("`{}` should be more than 0", key)))
+    }
+}
+
+/// Expand a leading `~` to the home directory, and make relative paths relative to `dir`
+fn expand_path(path: &str, dir: &Path) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => dir.join(path),
+    }
+}
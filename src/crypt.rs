@@ -0,0 +1,132 @@
+use crate::pipe::pipe_through;
+use std::io;
+use std::process::Command;
+
+/// A program used to encrypt files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cipher {
+    /// Symmetric `gpg` encryption, unlocked with a passphrase
+    Gpg,
+    /// `age` encryption, unlocked with an identity (private key) file
+    Age,
+}
+
+impl Cipher {
+    /// Guess the cipher used for a file from its extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        [Self::Gpg, Self::Age]
+            .iter()
+            .copied()
+            .find(|cipher| path.ends_with(cipher.extension()))
+    }
+
+    /// The extension of files encrypted with this cipher
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Gpg => ".gpg",
+            Self::Age => ".age",
+        }
+    }
+
+    /// What the user has to enter to unlock a file
+    pub fn secret_name(&self) -> &'static str {
+        match self {
+            Self::Gpg => "Passphrase",
+            Self::Age => "Identity file",
+        }
+    }
+
+    /// Whether the secret should be hidden while it is typed
+    pub fn secret_is_hidden(&self) -> bool {
+        match self {
+            Self::Gpg => true,
+            Self::Age => false,
+        }
+    }
+}
+
+/// A cipher along with the secret needed to decrypt and re-encrypt a file.
+///
+/// Plaintext is only ever passed to `gpg` or `age` through a pipe,
+/// so it never touches the disk.
+#[derive(Clone)]
+pub struct Encryption {
+    cipher: Cipher,
+    secret: String,
+}
+
+impl Encryption {
+    pub fn new(cipher: Cipher, secret: impl ToString) -> Self {
+        Self {
+            cipher,
+            secret: secret.to_string(),
+        }
+    }
+
+    pub fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+
+    /// Decrypt the contents of an encrypted file
+    pub fn decrypt(&self, ciphertext: &[u8]) -> io::Result<Vec<u8>> {
+        match self.cipher {
+            Cipher::Gpg => pipe_through(
+                Command::new("gpg").args([
+                    "--batch",
+                    "--quiet",
+                    "--pinentry-mode",
+                    "loopback",
+                    "--passphrase-fd",
+                    "0",
+                    "--decrypt",
+                ]),
+                &self.with_passphrase(ciphertext),
+            ),
+            Cipher::Age => pipe_through(
+                Command::new("age")
+                    .arg("--decrypt")
+                    .arg("--identity")
+                    .arg(&self.secret),
+                ciphertext,
+            ),
+        }
+    }
+
+    /// Encrypt plaintext with the same secret it was decrypted with
+    pub fn encrypt(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        match self.cipher {
+            Cipher::Gpg => pipe_through(
+                Command::new("gpg").args([
+                    "--batch",
+                    "--quiet",
+                    "--yes",
+                    "--pinentry-mode",
+                    "loopback",
+                    "--passphrase-fd",
+                    "0",
+                    "--symmetric",
+                    "--output",
+                    "-",
+                ]),
+                &self.with_passphrase(plaintext),
+            ),
+            // `age` encrypts to the recipients of an identity file
+            // when it's given one with `--encrypt`
+            Cipher::Age => pipe_through(
+                Command::new("age")
+                    .arg("--encrypt")
+                    .arg("--identity")
+                    .arg(&self.secret),
+                plaintext,
+            ),
+        }
+    }
+
+    /// `gpg` reads the passphrase from the first line of its
+    /// standard input, and the data to process from the rest
+    fn with_passphrase(&self, data: &[u8]) -> Vec<u8> {
+        let mut input = format!("{}\n", self.secret).into_bytes();
+        input.extend_from_slice(data);
+        input
+    }
+}
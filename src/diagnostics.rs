@@ -0,0 +1,126 @@
+use crate::json::Json;
+use cursive::theme::{BaseColor, Color};
+use std::fs::canonicalize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// How serious a diagnostic is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    /// The color diagnostics of this severity are shown in
+    pub fn color(&self) -> Color {
+        match self {
+            Self::Error => Color::Dark(BaseColor::Red),
+            Self::Warning => Color::Dark(BaseColor::Yellow),
+            Self::Info => Color::Dark(BaseColor::Blue),
+            Self::Hint => Color::Dark(BaseColor::Cyan),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Info => "info",
+            Self::Hint => "hint",
+        }
+    }
+}
+
+/// A message about a place in a file, like a compiler error
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// The line, counting from 1
+    pub line: usize,
+    /// The column, counting from 1
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(line: usize, column: usize, severity: Severity, message: impl ToString) -> Self {
+        Self {
+            line,
+            column,
+            severity,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Run `cargo check` on the package a directory is in, and return its
+/// diagnostics with the full paths of the files they're in
+pub fn cargo_check(dir: &Path) -> io::Result<Vec<(PathBuf, Diagnostic)>> {
+    let dir = canonicalize(dir)?;
+    let package = dir
+        .ancestors()
+        .find(|dir| dir.join("Cargo.toml").is_file())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "there's no Cargo.toml"))?;
+    let output = Command::new("cargo")
+        .args(["check", "--message-format=json"])
+        .current_dir(package)
+        .output()?;
+    let diagnostics = parse_cargo_messages(&String::from_utf8_lossy(&output.stdout));
+    // Cargo couldn't build anything, like when Cargo.toml is broken
+    if diagnostics.is_empty() && !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let error = stderr.lines().rev().find(|line| !line.trim().is_empty());
+        return Err(io::Error::other(error.unwrap_or("cargo check failed")));
+    }
+
+    // Files are relative to the workspace, which can be above the package
+    Ok(diagnostics
+        .into_iter()
+        .map(|(file, diagnostic)| {
+            let path = package
+                .ancestors()
+                .map(|dir| dir.join(&file))
+                .find(|path| path.is_file())
+                .unwrap_or_else(|| package.join(&file));
+            (path, diagnostic)
+        })
+        .collect())
+}
+
+/// Read the diagnostics from what `cargo check --message-format=json` prints,
+/// with the files they're in. The file names are relative to the workspace.
+pub fn parse_cargo_messages(output: &str) -> Vec<(PathBuf, Diagnostic)> {
+    output
+        .lines()
+        .filter_map(|line| Json::parse(line).ok())
+        .filter(|message| message.get("reason").and_then(Json::as_str) == Some("compiler-message"))
+        .filter_map(|message| cargo_diagnostic(message.get("message")?))
+        .collect()
+}
+
+fn cargo_diagnostic(message: &Json) -> Option<(PathBuf, Diagnostic)> {
+    let severity = match message.get("level")?.as_str()? {
+        "error" | "error: internal compiler error" => Severity::Error,
+        "warning" => Severity::Warning,
+        "note" => Severity::Info,
+        "help" => Severity::Hint,
+        _ => return None,
+    };
+    // Summaries like "aborting due to 2 previous errors" aren't about any place
+    let span = message
+        .get("spans")?
+        .as_array()?
+        .iter()
+        .find(|span| span.get("is_primary").and_then(Json::as_bool) == Some(true))?;
+    let diagnostic = Diagnostic::new(
+        span.get("line_start")?.as_usize()?,
+        span.get("column_start")?.as_usize()?,
+        severity,
+        message.get("message")?.as_str()?,
+    );
+    Some((PathBuf::from(span.get("file_name")?.as_str()?), diagnostic))
+}
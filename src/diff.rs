@@ -0,0 +1,415 @@
+use std::fmt;
+
+/// A line of a diff between two versions of some text
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiffLine {
+    /// A line that's in both versions
+    Same(String),
+    /// A line that's only in the old version
+    Removed(String),
+    /// A line that's only in the new version
+    Added(String),
+}
+
+impl DiffLine {
+    /// The text of the line
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Same(text) | Self::Removed(text) | Self::Added(text) => text,
+        }
+    }
+
+    /// The character a unified diff marks the line with
+    pub fn marker(&self) -> char {
+        match self {
+            Self::Same(_) => ' ',
+            Self::Removed(_) => '-',
+            Self::Added(_) => '+',
+        }
+    }
+}
+
+/// Find the fewest lines to remove and add to turn `old` into `new`, with
+/// Myers' algorithm. The lines are in the order they appear in both versions.
+pub fn diff(old: &[impl AsRef<str>], new: &[impl AsRef<str>]) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.iter().map(AsRef::as_ref).collect();
+    let new: Vec<&str> = new.iter().map(AsRef::as_ref).collect();
+
+    // Only the lines between the common start and end need to be searched
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old_middle, new_middle) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    let mut lines: Vec<DiffLine> = old[..prefix]
+        .iter()
+        .map(|line| DiffLine::Same(line.to_string()))
+        .collect();
+    lines.extend(diff_middle(old_middle, new_middle));
+    lines.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|line| DiffLine::Same(line.to_string())),
+    );
+    lines
+}
+
+/// The most lines a diff searches for ways to remove and add before giving
+/// up and replacing everything that changed, which keeps the search's memory small
+const MAX_EDITS: usize = 2000;
+
+fn diff_middle(old: &[&str], new: &[&str]) -> Vec<DiffLine> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    // `furthest[k]` is the furthest row of `old` reached on diagonal `k`. The
+    // diagonals each round reads are kept, to walk the path back afterwards.
+    let mut furthest = vec![0isize; 2 * max + 3];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max as isize {
+        if d as usize > MAX_EDITS {
+            return replace_all(old, new);
+        }
+        let window = (offset - d - 1) as usize..=(offset + d + 1) as usize;
+        trace.push(furthest[window].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && furthest[index - 1] < furthest[index + 1]) {
+                furthest[index + 1]
+            } else {
+                furthest[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            furthest[index] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, furthest) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| furthest[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            lines.push(DiffLine::Same(old[x as usize].to_string()));
+        }
+        if d > 0 {
+            if x == prev_x {
+                lines.push(DiffLine::Added(new[prev_y as usize].to_string()));
+            } else {
+                lines.push(DiffLine::Removed(old[prev_x as usize].to_string()));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    lines.reverse();
+    lines
+}
+
+fn replace_all(old: &[&str], new: &[&str]) -> Vec<DiffLine> {
+    old.iter()
+        .map(|line| DiffLine::Removed(line.to_string()))
+        .chain(new.iter().map(|line| DiffLine::Added(line.to_string())))
+        .collect()
+}
+
+/// A run of changed lines, with some unchanged lines around them
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hunk {
+    /// The line the hunk starts on in the old version, counting from 1
+    pub old_start: usize,
+    /// The line the hunk starts on in the new version, counting from 1
+    pub new_start: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+impl Hunk {
+    /// The number of lines the hunk covers in the old version
+    pub fn old_len(&self) -> usize {
+        self.lines
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Added(_)))
+            .count()
+    }
+
+    /// The number of lines the hunk covers in the new version
+    pub fn new_len(&self) -> usize {
+        self.lines
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Removed(_)))
+            .count()
+    }
+
+    /// The `@@ -1,3 +1,4 @@` line that starts the hunk in a unified diff
+    pub fn header(&self) -> String {
+        format!(
+            "@@ -{},{} +{},{} @@",
+            self.old_start,
+            self.old_len(),
+            self.new_start,
+            self.new_len()
+        )
+    }
+}
+
+impl fmt::Display for Hunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.header())?;
+        for line in &self.lines {
+            writeln!(f, "{}{}", line.marker(), line.text())?;
+        }
+        Ok(())
+    }
+}
+
+/// Group the changes in a diff into hunks, each with up to `context`
+/// unchanged lines before and after. Hunks that would overlap are joined.
+pub fn hunks(lines: &[DiffLine], context: usize) -> Vec<Hunk> {
+    let changed: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, DiffLine::Same(_)))
+        .map(|(i, _)| i)
+        .collect();
+
+    // The ranges of `lines` that each hunk covers
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(lines.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    ranges
+        .into_iter()
+        .map(|(start, end)| {
+            let before = &lines[..start];
+            let count =
+                |skip: fn(&DiffLine) -> bool| before.iter().filter(|line| !skip(line)).count() + 1;
+            Hunk {
+                old_start: count(|line| matches!(line, DiffLine::Added(_))),
+                new_start: count(|line| matches!(line, DiffLine::Removed(_))),
+                lines: lines[start..end].to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// How a line of the new version of some text differs from the old version
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineChange {
+    /// The line was added between lines that are in both versions
+    Added,
+    /// The line replaced lines of the old version
+    Modified,
+    /// Lines of the old version were removed just above the line, or
+    /// below it if it's the last line
+    Removed,
+}
+
+/// How each line of the new version in a diff differs from the old version,
+/// or None for the lines that are the same in both
+pub fn line_changes(lines: &[DiffLine]) -> Vec<Option<LineChange>> {
+    let mut changes = Vec::new();
+    // What changed since the last line that's in both versions
+    let (mut removed, mut added) = (false, false);
+    for line in lines {
+        match line {
+            DiffLine::Removed(_) => removed = true,
+            DiffLine::Added(_) if removed => {
+                added = true;
+                changes.push(Some(LineChange::Modified));
+            }
+            DiffLine::Added(_) => {
+                added = true;
+                changes.push(Some(LineChange::Added));
+            }
+            DiffLine::Same(_) => {
+                changes.push(Some(LineChange::Removed).filter(|_| removed && !added));
+                removed = false;
+                added = false;
+            }
+        }
+    }
+    if removed && !added {
+        if let Some(last) = changes.last_mut() {
+            last.get_or_insert(LineChange::Removed);
+        }
+    }
+    changes
+}
+
+/// The result of merging two versions of some text that were both changed
+/// from the same original
+#[derive(Clone, Debug, PartialEq)]
+pub struct Merge {
+    pub lines: Vec<String>,
+    /// How many places both versions changed differently. Each is marked
+    /// in `lines` with both versions between `<<<<<<<`, `=======` and `>>>>>>>`.
+    pub conflicts: usize,
+}
+
+/// A run of lines of the original, from `start` up to `end`, that a version
+/// replaced with `lines`. Lines added without removing any have `start == end`.
+#[derive(Clone, Debug, PartialEq)]
+struct Edit {
+    start: usize,
+    end: usize,
+    lines: Vec<String>,
+}
+
+impl Edit {
+    /// An edit that hasn't changed anything yet, before line `line`
+    fn at(line: usize) -> Self {
+        Self {
+            start: line,
+            end: line,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Whether the edit touches the lines from `start` up to `end`. Edits
+    /// that add lines at the same place touch too, since either could go first.
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.start == start || (self.start < end && start < self.end)
+    }
+}
+
+/// The edits that turn `base` into `new`
+fn edits(base: &[&str], new: &[&str]) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    let mut editing: Option<Edit> = None;
+    let mut line = 0;
+    for diff_line in diff(base, new) {
+        match diff_line {
+            DiffLine::Same(_) => {
+                edits.extend(editing.take());
+                line += 1;
+            }
+            DiffLine::Removed(_) => {
+                editing.get_or_insert_with(|| Edit::at(line)).end += 1;
+                line += 1;
+            }
+            DiffLine::Added(text) => {
+                editing
+                    .get_or_insert_with(|| Edit::at(line))
+                    .lines
+                    .push(text);
+            }
+        }
+    }
+    edits.extend(editing);
+    edits
+}
+
+/// The lines of `base` from `start` up to `end`, with `edits` made to them
+fn apply(base: &[&str], start: usize, end: usize, edits: &[Edit]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = start;
+    for edit in edits {
+        lines.extend(base[line..edit.start].iter().map(|line| line.to_string()));
+        lines.extend(edit.lines.iter().cloned());
+        line = edit.end;
+    }
+    lines.extend(base[line..end].iter().map(|line| line.to_string()));
+    lines
+}
+
+/// Merge two versions of `base` that were changed separately, keeping the
+/// changes from both. Where they changed the same lines differently,
+/// both versions are kept, with conflict markers naming them.
+pub fn merge(
+    base: &[impl AsRef<str>],
+    (ours_name, ours): (&str, &[impl AsRef<str>]),
+    (theirs_name, theirs): (&str, &[impl AsRef<str>]),
+) -> Merge {
+    let base: Vec<&str> = base.iter().map(AsRef::as_ref).collect();
+    let ours: Vec<&str> = ours.iter().map(AsRef::as_ref).collect();
+    let theirs: Vec<&str> = theirs.iter().map(AsRef::as_ref).collect();
+    let (ours_edits, theirs_edits) = (edits(&base, &ours), edits(&base, &theirs));
+
+    let mut merge = Merge {
+        lines: Vec::new(),
+        conflicts: 0,
+    };
+    let (mut i, mut j, mut line) = (0, 0, 0);
+    while i < ours_edits.len() || j < theirs_edits.len() {
+        // Start with whichever edit comes first, and take in every edit
+        // from either version that overlaps the lines taken in so far
+        let first = match (ours_edits.get(i), theirs_edits.get(j)) {
+            (Some(a), Some(b)) if b.start < a.start => b,
+            (Some(a), _) => a,
+            (None, Some(b)) => b,
+            (None, None) => break,
+        };
+        let (start, mut end) = (first.start, first.end);
+        let (ours_from, theirs_from) = (i, j);
+        loop {
+            if let Some(edit) = ours_edits.get(i).filter(|edit| edit.overlaps(start, end)) {
+                end = end.max(edit.end);
+                i += 1;
+            } else if let Some(edit) = theirs_edits.get(j).filter(|edit| edit.overlaps(start, end))
+            {
+                end = end.max(edit.end);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        merge
+            .lines
+            .extend(base[line..start].iter().map(|line| line.to_string()));
+        let ours_lines = apply(&base, start, end, &ours_edits[ours_from..i]);
+        let theirs_lines = apply(&base, start, end, &theirs_edits[theirs_from..j]);
+        if ours_from == i || theirs_from == j || ours_lines == theirs_lines {
+            // Only one version changed these lines, or both made the same change
+            let changed = if ours_from == i {
+                theirs_lines
+            } else {
+                ours_lines
+            };
+            merge.lines.extend(changed);
+        } else {
+            merge.conflicts += 1;
+            merge.lines.push(format!("<<<<<<< {}", ours_name));
+            merge.lines.extend(ours_lines);
+            merge.lines.push(String::from("======="));
+            merge.lines.extend(theirs_lines);
+            merge.lines.push(format!(">>>>>>> {}", theirs_name));
+        }
+        line = end;
+    }
+    merge
+        .lines
+        .extend(base[line..].iter().map(|line| line.to_string()));
+    merge
+}
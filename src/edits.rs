@@ -0,0 +1,27 @@
+/// A change to some text: replacing the text between two positions, which
+/// are rows and columns counting from 0. Columns count graphemes, like the
+/// cursor's column does.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextEdit {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub text: String,
+}
+
+impl TextEdit {
+    pub fn replace(start: (usize, usize), end: (usize, usize), text: impl ToString) -> Self {
+        Self {
+            start,
+            end,
+            text: text.to_string(),
+        }
+    }
+
+    pub fn insert(at: (usize, usize), text: impl ToString) -> Self {
+        Self::replace(at, at, text)
+    }
+
+    pub fn delete(start: (usize, usize), end: (usize, usize)) -> Self {
+        Self::replace(start, end, "")
+    }
+}
@@ -0,0 +1,507 @@
+use crate::languages::Language;
+
+/// The syntaxes that Emmet abbreviations can be expanded into
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    Html,
+    Css,
+}
+
+impl Syntax {
+    /// Guess which abbreviations a file uses from its extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        match Language::from_path(path)? {
+            Language::Html | Language::Xhtml => Some(Self::Html),
+            Language::Css | Language::Scss | Language::Less => Some(Self::Css),
+            _ => None,
+        }
+    }
+
+    /// Expand an abbreviation, starting every line after the first with
+    /// `indent`, and indenting nested elements by another `tab` each
+    pub fn expand(&self, abbreviation: &str, indent: &str, tab: &str) -> Option<Expansion> {
+        match self {
+            Self::Html => expand_html(abbreviation, indent, tab),
+            Self::Css => expand_css(abbreviation),
+        }
+    }
+}
+
+/// The text an abbreviation expands to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expansion {
+    pub text: String,
+    /// Byte offset in `text` where the cursor should be placed,
+    /// which is the first place that still needs to be filled in
+    pub cursor: usize,
+}
+
+/// Find the abbreviation that ends at the end of some text. Abbreviations
+/// end at whitespace, unless the whitespace is inside `{text}` or `[attributes]`.
+pub fn abbreviation_before(text: &str) -> &str {
+    let mut depth = 0;
+    for (i, ch) in text.char_indices().rev() {
+        match ch {
+            '}' | ']' => depth += 1,
+            '{' | '[' if depth > 0 => depth -= 1,
+            ch if ch.is_whitespace() && depth == 0 => return &text[i + ch.len_utf8()..],
+            _ => {}
+        }
+    }
+    text
+}
+
+/// HTML elements that never have a closing tag
+const VOID_ELEMENTS: [&str; 9] = [
+    "area", "br", "col", "embed", "hr", "img", "input", "link", "meta",
+];
+
+/// Attributes that are added to elements when they aren't given any
+const DEFAULT_ATTRIBUTES: [(&str, &[(&str, &str)]); 6] = [
+    ("a", &[("href", "")]),
+    ("img", &[("src", ""), ("alt", "")]),
+    ("input", &[("type", "text")]),
+    ("link", &[("rel", "stylesheet"), ("href", "")]),
+    ("form", &[("action", "")]),
+    ("label", &[("for", "")]),
+];
+
+/// The most elements an abbreviation can expand to, so that
+/// something like `div*1000>p*1000` doesn't hang the editor
+const MAX_ELEMENTS: usize = 1000;
+
+#[derive(Clone, Debug, Default)]
+struct Node {
+    /// The tag name, which is empty for groups and implicit tags
+    name: String,
+    is_group: bool,
+    id: Option<String>,
+    classes: Vec<String>,
+    attributes: Vec<(String, String)>,
+    text: String,
+    count: usize,
+    children: Vec<Node>,
+}
+
+/// A recursive descent parser for HTML abbreviations
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    /// Parse siblings until the end of the abbreviation or group.
+    /// Also returns whether parsing stopped because of a climb up with `^`.
+    fn parse_level(&mut self) -> Option<(Vec<Node>, bool)> {
+        let mut nodes = vec![];
+        loop {
+            nodes.push(self.parse_item()?);
+            match self.chars.peek() {
+                Some('>') => {
+                    self.chars.next();
+                    let (children, climbed) = self.parse_level()?;
+                    nodes.last_mut()?.children.extend(children);
+                    if !climbed {
+                        return Some((nodes, false));
+                    }
+                    // Each `^` climbs up one level, so `^^` climbs out of this one too
+                    if self.chars.peek() == Some(&'^') {
+                        self.chars.next();
+                        return Some((nodes, true));
+                    }
+                }
+                Some('+') => {
+                    self.chars.next();
+                }
+                Some('^') => {
+                    self.chars.next();
+                    return Some((nodes, true));
+                }
+                _ => return Some((nodes, false)),
+            }
+        }
+    }
+
+    /// Parse an element or a group, along with its multiplier
+    fn parse_item(&mut self) -> Option<Node> {
+        let mut node = Node::default();
+        if self.chars.peek() == Some(&'(') {
+            self.chars.next();
+            node.is_group = true;
+            node.children = self.parse_level()?.0;
+            if self.chars.next() != Some(')') {
+                return None;
+            }
+        } else {
+            node.name = self.take_name();
+            loop {
+                match self.chars.peek() {
+                    Some('#') => {
+                        self.chars.next();
+                        node.id = Some(self.take_name());
+                    }
+                    Some('.') => {
+                        self.chars.next();
+                        node.classes.push(self.take_name());
+                    }
+                    Some('[') => {
+                        self.chars.next();
+                        node.attributes.extend(self.parse_attributes()?);
+                    }
+                    Some('{') => {
+                        self.chars.next();
+                        node.text = self.take_until('}')?;
+                    }
+                    _ => break,
+                }
+            }
+            if node.name.is_empty()
+                && node.id.is_none()
+                && node.classes.is_empty()
+                && node.attributes.is_empty()
+                && node.text.is_empty()
+            {
+                return None;
+            }
+        }
+
+        node.count = 1;
+        if self.chars.peek() == Some(&'*') {
+            self.chars.next();
+            node.count = self.take_name().parse().ok()?;
+            if node.count == 0 || node.count > MAX_ELEMENTS {
+                return None;
+            }
+        }
+        Some(node)
+    }
+
+    /// Parse attributes like `[href=# title="A title" disabled]`
+    fn parse_attributes(&mut self) -> Option<Vec<(String, String)>> {
+        let mut attributes = vec![];
+        loop {
+            while self.chars.peek() == Some(&' ') {
+                self.chars.next();
+            }
+            if self.chars.peek() == Some(&']') {
+                self.chars.next();
+                return Some(attributes);
+            }
+
+            let name = self.take_name();
+            if name.is_empty() {
+                return None;
+            }
+            let mut value = String::new();
+            if self.chars.peek() == Some(&'=') {
+                self.chars.next();
+                value = match self.chars.peek() {
+                    Some(&quote) if quote == '"' || quote == '\'' => {
+                        self.chars.next();
+                        self.take_until(quote)?
+                    }
+                    _ => {
+                        let mut value = String::new();
+                        while let Some(&ch) = self.chars.peek() {
+                            if ch == ' ' || ch == ']' {
+                                break;
+                            }
+                            value.push(ch);
+                            self.chars.next();
+                        }
+                        value
+                    }
+                };
+            }
+            attributes.push((name, value));
+        }
+    }
+
+    /// Take a tag name, class, id, attribute name, or number
+    fn take_name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(&ch) = self.chars.peek() {
+            if ch.is_alphanumeric() || "-_:$@!".contains(ch) {
+                name.push(ch);
+                self.chars.next();
+            } else {
+                break;
+            }
+        }
+        name
+    }
+
+    /// Take everything up to a closing character, and skip over it
+    fn take_until(&mut self, end: char) -> Option<String> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next()? {
+                ch if ch == end => return Some(text),
+                ch => text.push(ch),
+            }
+        }
+    }
+}
+
+/// How many elements some nodes expand to, counting their children
+fn elements(nodes: &[Node]) -> usize {
+    nodes
+        .iter()
+        .map(|node| {
+            let own = if node.is_group { 0 } else { 1 };
+            node.count.saturating_mul(own + elements(&node.children))
+        })
+        .fold(0, usize::saturating_add)
+}
+
+/// Replace `$` with the index of a repeated element, padding
+/// it with zeros when there are several `$` in a row
+fn number(text: &str, index: usize) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch == '$' {
+            let mut width = 1;
+            while chars.peek() == Some(&'$') {
+                chars.next();
+                width += 1;
+            }
+            result.push_str(&format!("{:0width$}", index, width = width));
+        } else {
+            result.push(ch);
+        }
+    }
+    result
+}
+
+/// Write out HTML for some nodes, remembering the first place that needs filling in
+struct Renderer<'a> {
+    indent: &'a str,
+    tab: &'a str,
+    text: String,
+    cursor: Option<usize>,
+}
+
+impl<'a> Renderer<'a> {
+    fn mark_cursor(&mut self) {
+        if self.cursor.is_none() {
+            self.cursor = Some(self.text.len());
+        }
+    }
+
+    fn newline(&mut self, depth: usize) {
+        self.text.push('\n');
+        self.text.push_str(self.indent);
+        self.text.push_str(&self.tab.repeat(depth));
+    }
+
+    fn render_all(&mut self, nodes: &[Node], parent: &str, depth: usize, first: &mut bool) {
+        for node in nodes {
+            for index in 1..=node.count {
+                if node.is_group {
+                    self.render_all(&node.children, parent, depth, first);
+                } else {
+                    if !*first {
+                        self.newline(depth);
+                    }
+                    *first = false;
+                    self.render(node, parent, depth, index);
+                }
+            }
+        }
+    }
+
+    fn render(&mut self, node: &Node, parent: &str, depth: usize, index: usize) {
+        let name = if node.name.is_empty() {
+            // Implicit tag names depend on what they're inside of
+            match parent {
+                "ul" | "ol" => "li",
+                "table" | "tbody" | "thead" => "tr",
+                "tr" => "td",
+                "select" => "option",
+                _ => "div",
+            }
+            .to_string()
+        } else {
+            number(&node.name, index)
+        };
+
+        self.text.push('<');
+        self.text.push_str(&name);
+        if let Some(id) = &node.id {
+            self.text
+                .push_str(&format!(" id=\"{}\"", number(id, index)));
+        }
+        if !node.classes.is_empty() {
+            let classes: Vec<String> = node.classes.iter().map(|c| number(c, index)).collect();
+            self.text
+                .push_str(&format!(" class=\"{}\"", classes.join(" ")));
+        }
+
+        let mut attributes = node.attributes.clone();
+        if attributes.is_empty() {
+            if let Some((_, defaults)) = DEFAULT_ATTRIBUTES.iter().find(|(tag, _)| *tag == name) {
+                for (attribute, value) in defaults.iter() {
+                    attributes.push((attribute.to_string(), value.to_string()));
+                }
+            }
+        }
+        for (attribute, value) in attributes {
+            self.text.push_str(&format!(" {}=\"", attribute));
+            if value.is_empty() {
+                self.mark_cursor();
+            }
+            self.text.push_str(&number(&value, index));
+            self.text.push('"');
+        }
+        self.text.push('>');
+
+        if VOID_ELEMENTS.contains(&name.as_str()) {
+            return;
+        }
+
+        self.text.push_str(&number(&node.text, index));
+        if node.children.is_empty() {
+            if node.text.is_empty() {
+                self.mark_cursor();
+            }
+        } else {
+            self.newline(depth + 1);
+            let mut first = true;
+            self.render_all(&node.children, &name, depth + 1, &mut first);
+            self.newline(depth);
+        }
+        self.text.push_str(&format!("</{}>", name));
+    }
+}
+
+fn expand_html(abbreviation: &str, indent: &str, tab: &str) -> Option<Expansion> {
+    let mut parser = Parser {
+        chars: abbreviation.chars().peekable(),
+    };
+    let (nodes, _) = parser.parse_level()?;
+    if parser.chars.next().is_some() || elements(&nodes) > MAX_ELEMENTS {
+        return None;
+    }
+
+    let mut renderer = Renderer {
+        indent,
+        tab,
+        text: String::new(),
+        cursor: None,
+    };
+    renderer.render_all(&nodes, "", 0, &mut true);
+    let cursor = renderer.cursor.unwrap_or(renderer.text.len());
+    Some(Expansion {
+        text: renderer.text,
+        cursor,
+    })
+}
+
+/// Abbreviations for CSS properties
+const CSS_PROPERTIES: [(&str, &str); 34] = [
+    ("m", "margin"),
+    ("mt", "margin-top"),
+    ("mr", "margin-right"),
+    ("mb", "margin-bottom"),
+    ("ml", "margin-left"),
+    ("p", "padding"),
+    ("pt", "padding-top"),
+    ("pr", "padding-right"),
+    ("pb", "padding-bottom"),
+    ("pl", "padding-left"),
+    ("w", "width"),
+    ("h", "height"),
+    ("maw", "max-width"),
+    ("mah", "max-height"),
+    ("miw", "min-width"),
+    ("mih", "min-height"),
+    ("t", "top"),
+    ("r", "right"),
+    ("b", "bottom"),
+    ("l", "left"),
+    ("d", "display"),
+    ("pos", "position"),
+    ("fl", "float"),
+    ("c", "color"),
+    ("bg", "background"),
+    ("bgc", "background-color"),
+    ("bd", "border"),
+    ("bdrs", "border-radius"),
+    ("fz", "font-size"),
+    ("fw", "font-weight"),
+    ("lh", "line-height"),
+    ("ta", "text-align"),
+    ("op", "opacity"),
+    ("z", "z-index"),
+];
+
+/// Abbreviations for keyword values, like `d:n` for `display: none`
+const CSS_KEYWORDS: [(&str, &str); 17] = [
+    ("n", "none"),
+    ("b", "block"),
+    ("i", "inline"),
+    ("ib", "inline-block"),
+    ("f", "flex"),
+    ("g", "grid"),
+    ("a", "absolute"),
+    ("r", "relative"),
+    ("s", "static"),
+    ("fx", "fixed"),
+    ("st", "sticky"),
+    ("l", "left"),
+    ("c", "center"),
+    ("j", "justify"),
+    ("bd", "bold"),
+    ("nm", "normal"),
+    ("au", "auto"),
+];
+
+/// Properties whose numbers don't have a unit
+const UNITLESS_PROPERTIES: [&str; 4] = ["opacity", "z-index", "line-height", "font-weight"];
+
+fn expand_css(abbreviation: &str) -> Option<Expansion> {
+    let split = abbreviation
+        .find(|ch: char| !ch.is_ascii_alphabetic())
+        .unwrap_or(abbreviation.len());
+    let (name, value) = abbreviation.split_at(split);
+    let (_, property) = CSS_PROPERTIES.iter().find(|(short, _)| *short == name)?;
+
+    let value = if let Some(keyword) = value.strip_prefix(':') {
+        CSS_KEYWORDS
+            .iter()
+            .find(|(short, _)| *short == keyword)
+            .map(|(_, long)| long.to_string())
+            .unwrap_or_else(|| keyword.to_string())
+    } else if value.starts_with('#') {
+        value.to_string()
+    } else {
+        // Numbers are separated by `-`, and can end with a unit
+        let mut numbers = vec![];
+        for number in value.split('-').filter(|number| !number.is_empty()) {
+            let digits = number
+                .find(|ch: char| !(ch.is_ascii_digit() || ch == '.'))
+                .unwrap_or(number.len());
+            let (digits, unit) = number.split_at(digits);
+            let unit = match unit {
+                "" if digits == "0" || UNITLESS_PROPERTIES.contains(property) => "",
+                "" => "px",
+                "p" => "%",
+                "e" => "em",
+                "r" => "rem",
+                "x" => "ex",
+                other => other,
+            };
+            numbers.push(format!("{}{}", digits, unit));
+        }
+        numbers.join(" ")
+    };
+
+    let text = format!("{}: {};", property, value);
+    // Leave the cursor where the value goes if there isn't one
+    let cursor = if value.is_empty() {
+        text.len() - 1
+    } else {
+        text.len()
+    };
+    Some(Expansion { text, cursor })
+}
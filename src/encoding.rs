@@ -0,0 +1,121 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use std::io;
+use std::str;
+
+/// How the text of a file is stored on disk
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextEncoding {
+    encoding: &'static Encoding,
+    /// Whether the file starts with a byte order mark
+    bom: bool,
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        Self {
+            encoding: UTF_8,
+            bom: false,
+        }
+    }
+}
+
+impl TextEncoding {
+    /// Guess how some bytes are encoded: by their byte order mark if they have one,
+    /// as UTF-16 if most of their high bytes are zero, as UTF-8 if they're valid
+    /// UTF-8, and as Windows-1252 (a superset of Latin-1) otherwise
+    pub fn detect(bytes: &[u8]) -> Self {
+        if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+            return Self {
+                encoding,
+                bom: true,
+            };
+        }
+        let encoding = match utf16_without_bom(bytes) {
+            Some(encoding) => encoding,
+            None if str::from_utf8(bytes).is_ok() => UTF_8,
+            None => WINDOWS_1252,
+        };
+        Self {
+            encoding,
+            bom: false,
+        }
+    }
+
+    /// The name of the encoding, like `UTF-16LE`
+    pub fn name(&self) -> String {
+        if self.bom && self.encoding == UTF_8 {
+            String::from("UTF-8 with BOM")
+        } else {
+            self.encoding.name().to_string()
+        }
+    }
+
+    /// The byte order mark files in this encoding start with
+    fn bom(&self) -> &'static [u8] {
+        if !self.bom {
+            &[]
+        } else if self.encoding == UTF_16LE {
+            &[0xFF, 0xFE]
+        } else if self.encoding == UTF_16BE {
+            &[0xFE, 0xFF]
+        } else {
+            &[0xEF, 0xBB, 0xBF]
+        }
+    }
+
+    /// Decode the contents of a file. Bytes that aren't valid in the
+    /// encoding become replacement characters.
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        let bytes = bytes.strip_prefix(self.bom()).unwrap_or(bytes);
+        self.encoding
+            .decode_without_bom_handling(bytes)
+            .0
+            .into_owned()
+    }
+
+    /// Encode text to save it, or fail if it has characters the encoding doesn't have
+    pub fn encode(&self, text: &str) -> io::Result<Vec<u8>> {
+        let mut bytes = self.bom().to_vec();
+        // encoding_rs only decodes UTF-16, so it's encoded by hand
+        if self.encoding == UTF_16LE {
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        } else if self.encoding == UTF_16BE {
+            bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+        } else {
+            let (encoded, _, unmappable) = self.encoding.encode(text);
+            if unmappable {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("some characters can't be saved as {}", self.name()),
+                ));
+            }
+            bytes.extend_from_slice(&encoded);
+        }
+        Ok(bytes)
+    }
+}
+
+/// Guess whether text without a byte order mark is UTF-16, from how many of
+/// the bytes where ASCII characters would have their zero high byte are zero
+fn utf16_without_bom(bytes: &[u8]) -> Option<&'static Encoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = bytes.len() / 2;
+    let zeros = |parity| {
+        bytes
+            .iter()
+            .skip(parity)
+            .step_by(2)
+            .filter(|&&byte| byte == 0)
+            .count()
+    };
+    let (even, odd) = (zeros(0), zeros(1));
+    if odd * 2 >= units && even * 10 < units {
+        Some(UTF_16LE)
+    } else if even * 2 >= units && odd * 10 < units {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
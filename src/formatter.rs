@@ -0,0 +1,42 @@
+use crate::languages::Language;
+use crate::pipe::pipe_through;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// An external program that formats code, like `rustfmt`. It's run as a
+/// shell command that reads the code on standard input and prints it formatted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Formatter {
+    pub command: String,
+}
+
+impl Formatter {
+    pub fn new(command: impl ToString) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+
+    /// The usual formatter for a file, chosen by its language
+    pub fn from_path(path: &str) -> Option<Self> {
+        let command = match Language::from_path(path)? {
+            Language::Rust => "rustfmt --edition 2021",
+            Language::Python => "black --quiet -",
+            Language::C | Language::Cpp => "clang-format",
+            Language::Go => "gofmt",
+            Language::Zig => "zig fmt --stdin",
+            Language::Sh | Language::Bash => "shfmt",
+            _ => return None,
+        };
+        Some(Self::new(command))
+    }
+
+    /// Format code, running the formatter in `dir` so it finds the project's settings
+    pub fn format(&self, code: &str, dir: &Path) -> io::Result<String> {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&self.command).current_dir(dir);
+        let formatted = pipe_through(&mut command, code.as_bytes())?;
+        String::from_utf8(formatted).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
@@ -0,0 +1,43 @@
+/// Score how well a query fuzzily matches some text, or `None` if it doesn't match.
+/// Every character of the query has to appear in the text in order, ignoring case.
+/// Higher scores are better matches: runs of consecutive characters and
+/// characters at the start of words score more, and shorter texts win ties.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut query = query.chars().flat_map(char::to_lowercase).peekable();
+    let mut previous: Option<char> = None;
+    let mut consecutive = false;
+
+    for ch in text.chars() {
+        let wanted = match query.peek() {
+            Some(&wanted) => wanted,
+            None => break,
+        };
+        if ch.to_lowercase().eq(wanted.to_lowercase()) {
+            query.next();
+            score += 1;
+            if consecutive {
+                score += 5;
+            }
+            // The start of a word, like the `B` in `fooBar` or `foo_bar`
+            let at_word_start = match previous {
+                None => true,
+                Some(previous) => {
+                    !previous.is_alphanumeric() || (previous.is_lowercase() && ch.is_uppercase())
+                }
+            };
+            if at_word_start {
+                score += 10;
+            }
+            consecutive = true;
+        } else {
+            consecutive = false;
+        }
+        previous = Some(ch);
+    }
+
+    if query.peek().is_some() {
+        return None;
+    }
+    Some(score * 100 - text.chars().count() as i64)
+}
@@ -0,0 +1,141 @@
+use crate::languages::file_dir;
+use crate::pipe::pipe_through;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// The text of a file as of the last commit of the git repository it's in.
+/// It's an error if the file isn't in a repository, or hasn't been committed.
+pub fn head_version(path: &Path) -> io::Result<String> {
+    let (dir, name) = split_path(path)?;
+    // `./` makes the path relative to `dir`, instead of the repository's root
+    let mut object = OsString::from("HEAD:./");
+    object.push(name);
+    let text = pipe_through(
+        Command::new("git").arg("show").arg(object).current_dir(dir),
+        &[],
+    )?;
+    Ok(String::from_utf8_lossy(&text).replace("\r\n", "\n"))
+}
+
+/// What lines that have changed since the last commit are annotated with
+pub const NOT_COMMITTED: &str = "Not committed yet";
+
+/// Who last changed a line of a file, and when
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlameLine {
+    /// The hash of the commit that last changed the line,
+    /// which is all zeros if the change isn't committed yet
+    pub commit: String,
+    pub author: String,
+    /// When the change was made, in seconds since the Unix epoch
+    /// in the author's time zone
+    pub time: i64,
+}
+
+impl BlameLine {
+    /// A line to show beside the line it's about, like `1a2b3c4d Adam McDanie 2020-05-17`
+    pub fn annotation(&self) -> String {
+        if self.commit.chars().all(|ch| ch == '0') {
+            return NOT_COMMITTED.to_string();
+        }
+        let commit: String = self.commit.chars().take(8).collect();
+        let author: String = self.author.chars().take(12).collect();
+        format!("{:8} {:12} {}", commit, author, date(self.time))
+    }
+}
+
+/// Find who last changed each line of a file, where `text` is what's in the
+/// file now. Lines changed since the last commit are blamed on no one yet.
+pub fn blame(path: &Path, text: &str) -> io::Result<Vec<BlameLine>> {
+    let (dir, name) = split_path(path)?;
+    let output = pipe_through(
+        Command::new("git")
+            .args(["blame", "--porcelain", "--contents", "-", "--"])
+            .arg(name)
+            .current_dir(dir),
+        text.as_bytes(),
+    )?;
+    Ok(parse_blame(&String::from_utf8_lossy(&output)))
+}
+
+/// Parse the output of `git blame --porcelain`. Each line of the file has a
+/// header with its commit, and the first line of each commit is followed by
+/// what's known about the commit, like `author Adam McDaniel`.
+fn parse_blame(output: &str) -> Vec<BlameLine> {
+    let mut commits: HashMap<&str, BlameLine> = HashMap::new();
+    let mut lines = Vec::new();
+    let mut current = "";
+    for line in output.lines() {
+        if line.starts_with('\t') {
+            // The line of the file itself ends the line's header
+            lines.extend(commits.get(current).cloned());
+            continue;
+        }
+        let hash = line.split(' ').next().unwrap_or_default();
+        if hash.len() == 40 && hash.chars().all(|ch| ch.is_ascii_hexdigit()) {
+            current = hash;
+            commits.entry(hash).or_insert_with(|| BlameLine {
+                commit: hash.to_string(),
+                author: String::new(),
+                time: 0,
+            });
+            continue;
+        }
+        let commit = match commits.get_mut(current) {
+            Some(commit) => commit,
+            None => continue,
+        };
+        if let Some(author) = line.strip_prefix("author ") {
+            commit.author = author.to_string();
+        } else if let Some(time) = line.strip_prefix("author-time ") {
+            commit.time += time.parse::<i64>().unwrap_or_default();
+        } else if let Some(zone) = line.strip_prefix("author-tz ") {
+            commit.time += zone_offset(zone);
+        }
+    }
+    lines
+}
+
+/// The seconds a time zone like `-0500` is ahead of UTC
+fn zone_offset(zone: &str) -> i64 {
+    let (sign, digits) = match zone.strip_prefix('-') {
+        Some(digits) => (-1, digits),
+        None => (1, zone.trim_start_matches('+')),
+    };
+    let hours: i64 = digits.get(..2).and_then(|h| h.parse().ok()).unwrap_or(0);
+    let minutes: i64 = digits.get(2..4).and_then(|m| m.parse().ok()).unwrap_or(0);
+    sign * (hours * 3600 + minutes * 60)
+}
+
+/// The date of a time in seconds since the Unix epoch, like `2020-05-17`
+fn date(time: i64) -> String {
+    // Howard Hinnant's `civil_from_days`, with years starting in March
+    // so leap days come at the end
+    let days = time.div_euclid(86400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// The directory a file is in, and its name in that directory
+fn split_path(path: &Path) -> io::Result<(&Path, &OsStr)> {
+    let dir = file_dir(path);
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::other("not a file"))?;
+    Ok((dir, name))
+}
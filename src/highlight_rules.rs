@@ -0,0 +1,94 @@
+use cursive::theme::{Color, ColorStyle, ColorType, Effect, Style};
+use cursive::utils::markup::StyledString;
+use regex::Regex;
+use std::iter::once;
+
+/// Text to highlight on top of how the language highlighter highlights it,
+/// like `TODO` markers, log levels or IP addresses. Rules are set in the config:
+///
+/// ```toml
+/// [[highlight]]
+/// pattern = "\\b(TODO|FIXME)\\b"
+/// color = "light red"
+/// effects = ["bold", "underline"]
+/// ```
+#[derive(Clone, Debug)]
+pub struct HighlightRule {
+    pub pattern: Regex,
+    pub style: Style,
+}
+
+impl PartialEq for HighlightRule {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern.as_str() == other.pattern.as_str() && self.style == other.style
+    }
+}
+
+impl HighlightRule {
+    /// A rule that gives matches of `pattern` a color and effects
+    pub fn new(pattern: Regex, color: Option<Color>, effects: &[Effect]) -> Self {
+        let mut style = Style::none();
+        if let Some(color) = color {
+            // Code is drawn reversed, so the back color is the color of the text
+            let mut color_style = ColorStyle::secondary();
+            color_style.back = ColorType::Color(color);
+            style = style.combine(color_style);
+        }
+        for &effect in effects {
+            style = style.combine(effect);
+        }
+        Self { pattern, style }
+    }
+}
+
+/// An effect by its name in the config, like `bold`
+pub fn parse_effect(name: &str) -> Option<Effect> {
+    let effect = match name {
+        "bold" => Effect::Bold,
+        "italic" => Effect::Italic,
+        "underline" => Effect::Underline,
+        "strikethrough" => Effect::Strikethrough,
+        "reverse" => Effect::Reverse,
+        "blink" => Effect::Blink,
+        _ => return None,
+    };
+    Some(effect)
+}
+
+/// Restyle the parts of a highlighted line that rules match, on top of how
+/// they were highlighted. Later rules go on top of earlier ones.
+pub fn apply_rules(rules: &[HighlightRule], line: &str, highlighted: StyledString) -> StyledString {
+    // The style each byte of the line gets from the rules
+    let mut styles: Vec<Option<Style>> = vec![None; line.len()];
+    for rule in rules {
+        for found in rule.pattern.find_iter(line) {
+            for style in &mut styles[found.range()] {
+                *style = Some(style.map_or(rule.style, |style| style.combine(rule.style)));
+            }
+        }
+    }
+    if styles.iter().all(Option::is_none) {
+        return highlighted;
+    }
+
+    let mut result = StyledString::new();
+    let mut offset = 0;
+    for span in highlighted.spans() {
+        let content = span.content;
+        let style_at = |i: usize| styles.get(offset + i).copied().flatten();
+        // The span is split where the style from the rules changes
+        let mut start = 0;
+        for (i, _) in content.char_indices().chain(once((content.len(), ' '))) {
+            if i > start && (i == content.len() || style_at(i) != style_at(start)) {
+                let attr = match style_at(start) {
+                    Some(style) => span.attr.combine(style),
+                    None => *span.attr,
+                };
+                result.append_styled(&content[start..i], attr);
+                start = i;
+            }
+        }
+        offset += content.len();
+    }
+    result
+}
@@ -0,0 +1,244 @@
+use crate::languages::Language;
+use std::ops::Range;
+
+/// A language whose imports can be grouped and sorted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportStyle {
+    Rust,
+    Python,
+}
+
+/// Modules in the Python standard library, which are grouped
+/// before third party modules
+const PYTHON_STDLIB: [&str; 72] = [
+    "abc",
+    "argparse",
+    "array",
+    "ast",
+    "asyncio",
+    "base64",
+    "bisect",
+    "builtins",
+    "calendar",
+    "cmath",
+    "collections",
+    "concurrent",
+    "configparser",
+    "contextlib",
+    "copy",
+    "csv",
+    "ctypes",
+    "dataclasses",
+    "datetime",
+    "decimal",
+    "difflib",
+    "email",
+    "enum",
+    "errno",
+    "fnmatch",
+    "fractions",
+    "functools",
+    "gc",
+    "getpass",
+    "glob",
+    "gzip",
+    "hashlib",
+    "heapq",
+    "hmac",
+    "html",
+    "http",
+    "importlib",
+    "inspect",
+    "io",
+    "itertools",
+    "json",
+    "logging",
+    "math",
+    "multiprocessing",
+    "operator",
+    "os",
+    "pathlib",
+    "pickle",
+    "platform",
+    "pprint",
+    "queue",
+    "random",
+    "re",
+    "shlex",
+    "shutil",
+    "signal",
+    "socket",
+    "sqlite3",
+    "statistics",
+    "string",
+    "struct",
+    "subprocess",
+    "sys",
+    "tempfile",
+    "textwrap",
+    "threading",
+    "time",
+    "traceback",
+    "typing",
+    "unittest",
+    "urllib",
+    "uuid",
+];
+
+impl ImportStyle {
+    /// Guess how a file imports things from its extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        match Language::from_path(path)? {
+            Language::Rust => Some(Self::Rust),
+            Language::Python => Some(Self::Python),
+            _ => None,
+        }
+    }
+
+    /// Find the block of imports near the top of a file, and return the
+    /// rows it covers along with the sorted and grouped imports to replace it
+    pub fn organize(&self, lines: &[String]) -> Option<(Range<usize>, Vec<String>)> {
+        let mut statements: Vec<String> = vec![];
+        let mut start = None;
+        let mut end = 0;
+        let mut in_docstring = false;
+
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i].trim();
+            if in_docstring {
+                in_docstring = !(line.contains("\"\"\"") || line.contains("'''"));
+                i += 1;
+                continue;
+            } else if start.is_none() && self.is_preamble(line) {
+                // Python docstrings can span several lines
+                if *self == Self::Python && (line.starts_with("\"\"\"") || line.starts_with("'''"))
+                {
+                    in_docstring = !line[3..].contains(&line[..3]);
+                }
+                i += 1;
+                continue;
+            } else if line.is_empty() {
+                i += 1;
+                continue;
+            } else if !self.is_import(line) {
+                break;
+            }
+
+            start.get_or_insert(i);
+            let first = i;
+            while !self.is_complete(&lines[first..=i].join("\n")) && i + 1 < lines.len() {
+                i += 1;
+            }
+            let statement = lines[first..=i].join("\n");
+            if !statements.contains(&statement) {
+                statements.push(statement);
+            }
+            i += 1;
+            end = i;
+        }
+
+        statements.sort_by_key(|statement| self.sort_key(statement));
+        let mut organized = vec![];
+        let mut last_group = None;
+        for statement in statements {
+            let group = self.sort_key(&statement).0;
+            if last_group.is_some() && last_group != Some(group) {
+                organized.push(String::new());
+            }
+            last_group = Some(group);
+            organized.extend(statement.lines().map(String::from));
+        }
+        Some((start?..end, organized))
+    }
+
+    /// Whether a line can come before the imports, like a comment
+    fn is_preamble(&self, line: &str) -> bool {
+        match self {
+            Self::Rust => line.starts_with("//") || line.starts_with("#!"),
+            Self::Python => {
+                line.starts_with('#') || line.starts_with("\"\"\"") || line.starts_with("'''")
+            }
+        }
+    }
+
+    /// Whether a line starts an import
+    fn is_import(&self, line: &str) -> bool {
+        match self {
+            Self::Rust => {
+                let line = strip_visibility(line);
+                line.starts_with("use ") || line.starts_with("extern crate ")
+            }
+            Self::Python => line.starts_with("import ") || line.starts_with("from "),
+        }
+    }
+
+    /// Whether an import statement has ended, or continues onto the next line
+    fn is_complete(&self, statement: &str) -> bool {
+        match self {
+            Self::Rust => statement.contains(';'),
+            Self::Python => {
+                statement.matches('(').count() == statement.matches(')').count()
+                    && !statement.ends_with('\\')
+            }
+        }
+    }
+
+    /// The group an import belongs in, and how it's sorted within that group
+    fn sort_key(&self, statement: &str) -> (u8, u8, String) {
+        match self {
+            Self::Rust => {
+                let statement = strip_visibility(statement);
+                if statement.starts_with("extern crate ") {
+                    return (0, 0, statement.to_string());
+                }
+                let path = statement
+                    .trim_start_matches("use ")
+                    .trim_start_matches("::");
+                let root = path.split("::").next().unwrap_or("");
+                let group = match root {
+                    "std" | "core" | "alloc" => 1,
+                    "crate" | "self" | "super" => 3,
+                    _ => 2,
+                };
+                (group, 0, path.to_string())
+            }
+            Self::Python => {
+                let (kind, rest) = if let Some(rest) = statement.strip_prefix("import ") {
+                    (0, rest)
+                } else {
+                    (1, statement.trim_start_matches("from "))
+                };
+                let module = rest.split_whitespace().next().unwrap_or("");
+                let root = module.split('.').next().unwrap_or("");
+                let group = if module == "__future__" {
+                    0
+                } else if module.starts_with('.') {
+                    3
+                } else if PYTHON_STDLIB.contains(&root) {
+                    1
+                } else {
+                    2
+                };
+                (group, kind, rest.to_lowercase())
+            }
+        }
+    }
+}
+
+/// Remove the visibility from the start of a Rust item, like `pub(crate)`
+fn strip_visibility(line: &str) -> &str {
+    if !line.starts_with("pub") {
+        return line;
+    }
+    let rest = &line[3..];
+    let rest = if rest.starts_with('(') {
+        match rest.find(')') {
+            Some(close) => &rest[close + 1..],
+            None => return line,
+        }
+    } else {
+        rest
+    };
+    rest.trim_start()
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+use std::io;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// A JSON value, for reading the messages that tools like `cargo` print
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    /// The members of an object, in the order they were written
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// Parse a JSON value, which must be all of `text` apart from whitespace
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut parser = Parser {
+            chars: text.chars().peekable(),
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        match parser.chars.next() {
+            Some(ch) => Err(invalid(format!("unexpected `{}` after the value", ch))),
+            None => Ok(value),
+        }
+    }
+
+    /// An object with members in the order they're given
+    pub fn object(members: Vec<(&str, Json)>) -> Self {
+        Self::Object(
+            members
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// A member of an object
+    pub fn get(&self, key: &str) -> Option<&Self> {
+        match self {
+            Self::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    /// The value as a whole number that isn't negative
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_f64()
+            .filter(|number| *number >= 0.0 && number.fract() == 0.0)
+            .map(|number| number as usize)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Self]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(text: &str) -> Self {
+        Self::String(text.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(text: String) -> Self {
+        Self::String(text)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(number: usize) -> Self {
+        Self::Number(number as f64)
+    }
+}
+
+impl From<bool> for Json {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<Vec<Json>> for Json {
+    fn from(items: Vec<Json>) -> Self {
+        Self::Array(items)
+    }
+}
+
+/// Writes the value as compact JSON
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Number(number) if number.is_finite() => write!(f, "{}", number),
+            Self::Number(_) => write!(f, "null"),
+            Self::String(text) => write_string(f, text),
+            Self::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Self::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, text: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in text.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => write!(f, "{}", ch)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+fn invalid(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|ch| ch.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> io::Result<()> {
+        match self.chars.next() {
+            Some(ch) if ch == expected => Ok(()),
+            Some(ch) => Err(invalid(format!("expected `{}`, found `{}`", expected, ch))),
+            None => Err(invalid(format!("expected `{}`, found the end", expected))),
+        }
+    }
+
+    /// Read a word like `true`, which starts with a character that was already read
+    fn word(&mut self, word: &str, value: Json) -> io::Result<Json> {
+        for expected in word.chars().skip(1) {
+            self.expect(expected)?;
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self) -> io::Result<Json> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some('n') => self.word("null", Json::Null),
+            Some('t') => self.word("true", Json::Bool(true)),
+            Some('f') => self.word("false", Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => self.array(),
+            Some('{') => self.object(),
+            Some(ch) if ch == '-' || ch.is_ascii_digit() => self.number(ch),
+            Some(ch) => Err(invalid(format!("unexpected `{}`", ch))),
+            None => Err(invalid("expected a value, found the end")),
+        }
+    }
+
+    fn number(&mut self, first: char) -> io::Result<Json> {
+        let mut text = first.to_string();
+        while let Some(&ch) = self.chars.peek() {
+            if !(ch.is_ascii_digit() || matches!(ch, '.' | 'e' | 'E' | '+' | '-')) {
+                break;
+            }
+            text.push(ch);
+            self.chars.next();
+        }
+        text.parse()
+            .map(Json::Number)
+            .map_err(|_| invalid(format!("`{}` is not a number", text)))
+    }
+
+    /// Read a string, after its opening quote
+    fn string(&mut self) -> io::Result<String> {
+        let mut text = String::new();
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(text),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some('r') => text.push('\r'),
+                    Some('b') => text.push('\u{8}'),
+                    Some('f') => text.push('\u{c}'),
+                    Some('u') => text.push(self.escaped_char()?),
+                    Some(ch) => text.push(ch),
+                    None => return Err(invalid("unterminated string")),
+                },
+                Some(ch) => text.push(ch),
+                None => return Err(invalid("unterminated string")),
+            }
+        }
+    }
+
+    /// Read the character of a `\u` escape, which can be a pair of them
+    fn escaped_char(&mut self) -> io::Result<char> {
+        let first = self.hex()?;
+        let code = if (0xd800..0xdc00).contains(&first) {
+            self.expect('\\')?;
+            self.expect('u')?;
+            let second = self.hex()?;
+            0x10000 + ((first - 0xd800) << 10) + (second.wrapping_sub(0xdc00) & 0x3ff)
+        } else {
+            first
+        };
+        Ok(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn hex(&mut self) -> io::Result<u32> {
+        let digits: String = (0..4).filter_map(|_| self.chars.next()).collect();
+        u32::from_str_radix(&digits, 16)
+            .map_err(|_| invalid(format!("`{}` is not a hex escape", digits)))
+    }
+
+    /// Read an array, after its opening bracket
+    fn array(&mut self) -> io::Result<Json> {
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&']') {
+            self.chars.next();
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Json::Array(items)),
+                _ => return Err(invalid("expected `,` or `]` in an array")),
+            }
+        }
+    }
+
+    /// Read an object, after its opening brace
+    fn object(&mut self) -> io::Result<Json> {
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            self.expect('"')?;
+            let name = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((name, self.value()?));
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Json::Object(members)),
+                _ => return Err(invalid("expected `,` or `}` in an object")),
+            }
+        }
+    }
+}
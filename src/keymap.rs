@@ -0,0 +1,617 @@
+use cursive::event::{Event, Key};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Something the code area can do, which keys can be bound to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Command {
+    Quit,
+    Save,
+    Paste,
+    Copy,
+    Cut,
+    ToggleComment,
+    CopyLineDown,
+    Undo,
+    Redo,
+    /// Put the buffer back how it looked some time ago
+    Earlier(Duration),
+    /// Redo the changes made in some time after the buffer was put back
+    Later(Duration),
+    FileInfo,
+    /// Show what saving would change in the file
+    ShowChanges,
+    /// Show what has changed in the buffer since the file's last git commit
+    ShowHeadChanges,
+    /// Switch between LF and CRLF line endings
+    ToggleLineEnding,
+    CloseTag,
+    JumpToMatchingBracket,
+    /// Move the cursor to the next or previous rows that differ from the last git commit
+    NextChange,
+    PreviousChange,
+    /// Move the cursor to the next or previous diagnostic, like a compiler error
+    NextDiagnostic,
+    PreviousDiagnostic,
+    OrganizeImports,
+    /// Run the buffer through the formatter for its file type
+    Format,
+    /// Change the case of the selection, or the word under the cursor
+    Uppercase,
+    Lowercase,
+    ToggleCase,
+    /// Sort the selected lines, or the whole buffer
+    SortLines,
+    SortLinesDescending,
+    /// Remove repeats of earlier lines from the selected lines, or the whole buffer
+    DeduplicateLines,
+    ExpandAbbreviation,
+    /// Offer the words in the buffer that start with the word before the cursor
+    Complete,
+    NewLine,
+    /// Open a new line below the cursor's line, without splitting it
+    InsertLineBelow,
+    /// Open a new line above the cursor's line
+    InsertLineAbove,
+    Delete,
+    Backspace,
+    DeleteWordLeft,
+    DeleteWordRight,
+    DeleteToEndOfLine,
+    /// Delete the selected lines, or the cursor's line
+    DeleteLine,
+    /// Join the next line, or the selected lines, onto the cursor's line
+    JoinLines,
+    /// Cut from the cursor to the end of the line
+    KillToEndOfLine,
+    /// Cut from the start of the line to the cursor
+    KillToStartOfLine,
+    /// Indent the selected lines, or insert a tab
+    Indent,
+    Dedent,
+    Home,
+    End,
+    SelectHome,
+    SelectEnd,
+    /// Select from the cursor to the start of the buffer
+    SelectToStart,
+    /// Select from the cursor to the end of the buffer
+    SelectToEnd,
+    PageUp,
+    PageDown,
+    SelectPageUp,
+    SelectPageDown,
+    MoveLineUp,
+    MoveLineDown,
+    /// Swap the argument or list element at the cursor with the one before
+    /// or after it, and keep the cursor on it
+    MoveArgumentLeft,
+    MoveArgumentRight,
+    Up,
+    Down,
+    Left,
+    Right,
+    SelectUp,
+    SelectDown,
+    SelectLeft,
+    SelectRight,
+    WordLeft,
+    WordRight,
+    SelectWordLeft,
+    SelectWordRight,
+    /// Grow a rectangular selection of the same columns on each row
+    SelectBlockUp,
+    SelectBlockDown,
+    SelectBlockLeft,
+    SelectBlockRight,
+    SelectAll,
+    /// Select the word under the cursor
+    SelectWord,
+    /// Select the lines the cursor is on, or one more line each time
+    SelectLine,
+    /// Add a cursor where the word under the cursor next appears
+    AddCursorAtNextMatch,
+    /// Remove every cursor but the main one
+    ClearCursors,
+    /// Start recording the commands that run, or stop if they're being recorded
+    RecordMacro,
+    /// Run the recorded commands again
+    PlayMacro,
+    /// Hide every line but the selected ones, or show them all again
+    Narrow,
+    Widen,
+    /// Move the cursor to the start of the next or previous sentence
+    NextSentence,
+    PreviousSentence,
+    /// Move the cursor to the start of the next or previous paragraph
+    NextParagraph,
+    PreviousParagraph,
+    /// Scroll long lines sideways, without moving the cursor
+    ScrollLeft,
+    ScrollRight,
+    /// Keep the cursor's row in the middle of the view, scrolling the text instead
+    ToggleTypewriter,
+    /// Show who last changed each line in git, and when
+    ToggleBlame,
+    /// Stop or allow changes to the buffer, which can still be searched and copied from
+    ToggleReadOnly,
+    /// Type a character
+    Type(char),
+}
+
+/// The names of the commands, for config files
+const NAMES: [(&str, Command); 93] = [
+    ("quit", Command::Quit),
+    ("save", Command::Save),
+    ("paste", Command::Paste),
+    ("copy", Command::Copy),
+    ("cut", Command::Cut),
+    ("toggle-comment", Command::ToggleComment),
+    ("copy-line-down", Command::CopyLineDown),
+    ("undo", Command::Undo),
+    ("redo", Command::Redo),
+    ("earlier", Command::Earlier(Duration::from_secs(60))),
+    ("later", Command::Later(Duration::from_secs(60))),
+    ("file-info", Command::FileInfo),
+    ("show-changes", Command::ShowChanges),
+    ("show-head-changes", Command::ShowHeadChanges),
+    ("toggle-line-ending", Command::ToggleLineEnding),
+    ("close-tag", Command::CloseTag),
+    ("jump-to-matching-bracket", Command::JumpToMatchingBracket),
+    ("next-change", Command::NextChange),
+    ("previous-change", Command::PreviousChange),
+    ("next-diagnostic", Command::NextDiagnostic),
+    ("previous-diagnostic", Command::PreviousDiagnostic),
+    ("organize-imports", Command::OrganizeImports),
+    ("format", Command::Format),
+    ("uppercase", Command::Uppercase),
+    ("lowercase", Command::Lowercase),
+    ("toggle-case", Command::ToggleCase),
+    ("sort-lines", Command::SortLines),
+    ("sort-lines-descending", Command::SortLinesDescending),
+    ("deduplicate-lines", Command::DeduplicateLines),
+    ("expand-abbreviation", Command::ExpandAbbreviation),
+    ("complete", Command::Complete),
+    ("new-line", Command::NewLine),
+    ("insert-line-below", Command::InsertLineBelow),
+    ("insert-line-above", Command::InsertLineAbove),
+    ("delete", Command::Delete),
+    ("backspace", Command::Backspace),
+    ("delete-word-left", Command::DeleteWordLeft),
+    ("delete-word-right", Command::DeleteWordRight),
+    ("delete-to-end-of-line", Command::DeleteToEndOfLine),
+    ("delete-line", Command::DeleteLine),
+    ("join-lines", Command::JoinLines),
+    ("kill-to-end-of-line", Command::KillToEndOfLine),
+    ("kill-to-start-of-line", Command::KillToStartOfLine),
+    ("indent", Command::Indent),
+    ("dedent", Command::Dedent),
+    ("home", Command::Home),
+    ("end", Command::End),
+    ("select-home", Command::SelectHome),
+    ("select-end", Command::SelectEnd),
+    ("select-to-start", Command::SelectToStart),
+    ("select-to-end", Command::SelectToEnd),
+    ("page-up", Command::PageUp),
+    ("page-down", Command::PageDown),
+    ("select-page-up", Command::SelectPageUp),
+    ("select-page-down", Command::SelectPageDown),
+    ("move-line-up", Command::MoveLineUp),
+    ("move-line-down", Command::MoveLineDown),
+    ("move-argument-left", Command::MoveArgumentLeft),
+    ("move-argument-right", Command::MoveArgumentRight),
+    ("up", Command::Up),
+    ("down", Command::Down),
+    ("left", Command::Left),
+    ("right", Command::Right),
+    ("select-up", Command::SelectUp),
+    ("select-down", Command::SelectDown),
+    ("select-left", Command::SelectLeft),
+    ("select-right", Command::SelectRight),
+    ("word-left", Command::WordLeft),
+    ("word-right", Command::WordRight),
+    ("select-word-left", Command::SelectWordLeft),
+    ("select-word-right", Command::SelectWordRight),
+    ("select-block-up", Command::SelectBlockUp),
+    ("select-block-down", Command::SelectBlockDown),
+    ("select-block-left", Command::SelectBlockLeft),
+    ("select-block-right", Command::SelectBlockRight),
+    ("select-all", Command::SelectAll),
+    ("select-word", Command::SelectWord),
+    ("select-line", Command::SelectLine),
+    ("add-cursor-at-next-match", Command::AddCursorAtNextMatch),
+    ("clear-cursors", Command::ClearCursors),
+    ("record-macro", Command::RecordMacro),
+    ("play-macro", Command::PlayMacro),
+    ("next-sentence", Command::NextSentence),
+    ("previous-sentence", Command::PreviousSentence),
+    ("next-paragraph", Command::NextParagraph),
+    ("previous-paragraph", Command::PreviousParagraph),
+    ("scroll-left", Command::ScrollLeft),
+    ("scroll-right", Command::ScrollRight),
+    ("toggle-typewriter", Command::ToggleTypewriter),
+    ("toggle-blame", Command::ToggleBlame),
+    ("toggle-read-only", Command::ToggleReadOnly),
+    ("narrow", Command::Narrow),
+    ("widen", Command::Widen),
+];
+
+/// The names of the keys that aren't characters, which are matched
+/// in any case when they're parsed
+const KEYS: [(&str, Key); 26] = [
+    ("Enter", Key::Enter),
+    ("Tab", Key::Tab),
+    ("Backspace", Key::Backspace),
+    ("Esc", Key::Esc),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Ins", Key::Ins),
+    ("Del", Key::Del),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+    ("F1", Key::F1),
+    ("F2", Key::F2),
+    ("F3", Key::F3),
+    ("F4", Key::F4),
+    ("F5", Key::F5),
+    ("F6", Key::F6),
+    ("F7", Key::F7),
+    ("F8", Key::F8),
+    ("F9", Key::F9),
+    ("F10", Key::F10),
+    ("F11", Key::F11),
+    ("F12", Key::F12),
+];
+
+impl Command {
+    /// Find a command by its name, like `delete-word-left`
+    pub fn from_name(name: &str) -> Option<Self> {
+        NAMES
+            .iter()
+            .find(|(other, _)| *other == name)
+            .map(|&(_, command)| command)
+    }
+
+    /// The name of the command, if it has one
+    pub fn name(&self) -> Option<&'static str> {
+        NAMES
+            .iter()
+            .find(|(_, other)| other == self)
+            .map(|&(name, _)| name)
+    }
+
+    /// Whether the command changes the buffer
+    pub fn is_edit(&self) -> bool {
+        matches!(
+            self,
+            Self::Paste
+                | Self::Cut
+                | Self::ToggleComment
+                | Self::CopyLineDown
+                | Self::Undo
+                | Self::Redo
+                | Self::Earlier(_)
+                | Self::Later(_)
+                | Self::ToggleLineEnding
+                | Self::CloseTag
+                | Self::OrganizeImports
+                | Self::Format
+                | Self::Uppercase
+                | Self::Lowercase
+                | Self::ToggleCase
+                | Self::SortLines
+                | Self::SortLinesDescending
+                | Self::DeduplicateLines
+                | Self::ExpandAbbreviation
+                | Self::Complete
+                | Self::NewLine
+                | Self::InsertLineBelow
+                | Self::InsertLineAbove
+                | Self::Delete
+                | Self::Backspace
+                | Self::DeleteWordLeft
+                | Self::DeleteWordRight
+                | Self::DeleteToEndOfLine
+                | Self::DeleteLine
+                | Self::JoinLines
+                | Self::KillToEndOfLine
+                | Self::KillToStartOfLine
+                | Self::Indent
+                | Self::Dedent
+                | Self::MoveLineUp
+                | Self::MoveLineDown
+                | Self::MoveArgumentLeft
+                | Self::MoveArgumentRight
+                | Self::Type(_)
+        )
+    }
+
+    /// Whether the command runs at every cursor when there are several
+    pub fn applies_to_each_cursor(&self) -> bool {
+        matches!(
+            self,
+            Self::Paste
+                | Self::NewLine
+                | Self::InsertLineBelow
+                | Self::InsertLineAbove
+                | Self::Delete
+                | Self::Backspace
+                | Self::DeleteWordLeft
+                | Self::DeleteWordRight
+                | Self::DeleteToEndOfLine
+                | Self::Home
+                | Self::End
+                | Self::Up
+                | Self::Down
+                | Self::Left
+                | Self::Right
+                | Self::WordLeft
+                | Self::WordRight
+                | Self::Type(_)
+        )
+    }
+
+    /// Whether the selection is still there after the command runs
+    pub fn keeps_selection(&self) -> bool {
+        matches!(
+            self,
+            Self::ToggleComment
+                | Self::Indent
+                | Self::Dedent
+                | Self::MoveLineUp
+                | Self::MoveLineDown
+                | Self::SelectPageUp
+                | Self::SelectPageDown
+                | Self::SelectUp
+                | Self::SelectDown
+                | Self::SelectLeft
+                | Self::SelectRight
+                | Self::SelectWordLeft
+                | Self::SelectWordRight
+                | Self::SelectHome
+                | Self::SelectEnd
+                | Self::SelectToStart
+                | Self::SelectToEnd
+                | Self::SelectBlockUp
+                | Self::SelectBlockDown
+                | Self::SelectBlockLeft
+                | Self::SelectBlockRight
+                | Self::SelectAll
+                | Self::SelectWord
+                | Self::SelectLine
+                | Self::Uppercase
+                | Self::Lowercase
+                | Self::ToggleCase
+        )
+    }
+}
+
+/// Which command each key runs
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keymap {
+    bindings: HashMap<Event, Command>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Self::empty();
+        for &(ch, command) in &[
+            ('q', Command::Quit),
+            ('s', Command::Save),
+            ('v', Command::Paste),
+            ('f', Command::Copy),
+            ('x', Command::Cut),
+            ('k', Command::ToggleComment),
+            ('d', Command::AddCursorAtNextMatch),
+            ('a', Command::SelectAll),
+            ('l', Command::SelectLine),
+            ('u', Command::KillToStartOfLine),
+            ('z', Command::Undo),
+            ('y', Command::Redo),
+            (']', Command::JumpToMatchingBracket),
+            // Terminals send Ctrl+] as the same byte as Ctrl+5
+            ('5', Command::JumpToMatchingBracket),
+            (' ', Command::Complete),
+        ] {
+            keymap.bind(Event::CtrlChar(ch), command);
+        }
+        for &(ch, command) in &[
+            ('i', Command::FileInfo),
+            ('d', Command::ShowChanges),
+            ('D', Command::ShowHeadChanges),
+            ('l', Command::ToggleLineEnding),
+            ('/', Command::CloseTag),
+            ('o', Command::OrganizeImports),
+            ('f', Command::Format),
+            ('e', Command::ExpandAbbreviation),
+            ('k', Command::KillToEndOfLine),
+            ('w', Command::SelectWord),
+            // Terminals send Alt+Shift+K as Alt and a capital K
+            ('K', Command::DeleteLine),
+            ('U', Command::Uppercase),
+            ('L', Command::Lowercase),
+            ('C', Command::ToggleCase),
+            ('j', Command::JoinLines),
+            ('N', Command::Narrow),
+            ('W', Command::Widen),
+            ('T', Command::ToggleTypewriter),
+            ('B', Command::ToggleBlame),
+            ('R', Command::ToggleReadOnly),
+            // Like the sentence and paragraph motions of vi
+            (')', Command::NextSentence),
+            ('(', Command::PreviousSentence),
+            ('}', Command::NextParagraph),
+            ('{', Command::PreviousParagraph),
+            ('<', Command::MoveArgumentLeft),
+            ('>', Command::MoveArgumentRight),
+        ] {
+            keymap.bind(Event::AltChar(ch), command);
+        }
+        for (event, command) in &[
+            (Event::Key(Key::Enter), Command::NewLine),
+            (Event::Ctrl(Key::Enter), Command::InsertLineBelow),
+            (Event::CtrlShift(Key::Enter), Command::InsertLineAbove),
+            (Event::Key(Key::Del), Command::Delete),
+            (Event::Key(Key::Backspace), Command::Backspace),
+            (Event::Ctrl(Key::Backspace), Command::DeleteWordLeft),
+            (Event::Ctrl(Key::Del), Command::DeleteWordRight),
+            (Event::Key(Key::Tab), Command::Indent),
+            (Event::Shift(Key::Tab), Command::Dedent),
+            (Event::Key(Key::Home), Command::Home),
+            (Event::Key(Key::End), Command::End),
+            (Event::Shift(Key::Home), Command::SelectHome),
+            (Event::Shift(Key::End), Command::SelectEnd),
+            (Event::CtrlShift(Key::Home), Command::SelectToStart),
+            (Event::CtrlShift(Key::End), Command::SelectToEnd),
+            (Event::Key(Key::PageUp), Command::PageUp),
+            (Event::Shift(Key::PageUp), Command::SelectPageUp),
+            (Event::Key(Key::PageDown), Command::PageDown),
+            (Event::Shift(Key::PageDown), Command::SelectPageDown),
+            (Event::Ctrl(Key::Up), Command::MoveLineUp),
+            (Event::Key(Key::Up), Command::Up),
+            (Event::Shift(Key::Up), Command::SelectUp),
+            (Event::Ctrl(Key::Down), Command::MoveLineDown),
+            (Event::Key(Key::Down), Command::Down),
+            (Event::Shift(Key::Down), Command::SelectDown),
+            (Event::Key(Key::Left), Command::Left),
+            (Event::Shift(Key::Left), Command::SelectLeft),
+            (Event::Ctrl(Key::Left), Command::WordLeft),
+            (Event::CtrlShift(Key::Left), Command::SelectWordLeft),
+            (Event::Key(Key::Right), Command::Right),
+            (Event::Shift(Key::Right), Command::SelectRight),
+            (Event::Ctrl(Key::Right), Command::WordRight),
+            (Event::CtrlShift(Key::Right), Command::SelectWordRight),
+            (Event::AltShift(Key::Up), Command::SelectBlockUp),
+            (Event::AltShift(Key::Down), Command::SelectBlockDown),
+            (Event::AltShift(Key::Left), Command::SelectBlockLeft),
+            (Event::AltShift(Key::Right), Command::SelectBlockRight),
+            (Event::Alt(Key::Down), Command::CopyLineDown),
+            (Event::Alt(Key::Left), Command::ScrollLeft),
+            (Event::Alt(Key::Right), Command::ScrollRight),
+            (Event::Key(Key::Esc), Command::ClearCursors),
+            (Event::Key(Key::F3), Command::RecordMacro),
+            (Event::Key(Key::F4), Command::PlayMacro),
+            (Event::Key(Key::F6), Command::NextChange),
+            (Event::Shift(Key::F6), Command::PreviousChange),
+            (Event::Key(Key::F8), Command::NextDiagnostic),
+            (Event::Shift(Key::F8), Command::PreviousDiagnostic),
+        ] {
+            keymap.bind(event.clone(), *command);
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    /// A keymap without any bindings
+    pub fn empty() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
+
+    /// Make a key run a command, instead of what it ran before
+    pub fn bind(&mut self, event: Event, command: Command) {
+        self.bindings.insert(event, command);
+    }
+
+    /// Make a key do nothing
+    pub fn unbind(&mut self, event: &Event) {
+        self.bindings.remove(event);
+    }
+
+    /// The command a key runs
+    pub fn get(&self, event: &Event) -> Option<Command> {
+        self.bindings.get(event).copied()
+    }
+
+    /// The keys that run a command
+    pub fn keys_for(&self, command: Command) -> Vec<&Event> {
+        self.bindings
+            .iter()
+            .filter(|(_, other)| **other == command)
+            .map(|(event, _)| event)
+            .collect()
+    }
+}
+
+/// Parse a key like `ctrl-s`, `alt-shift-left`, `f9`, `ctrl-space` or `x`
+pub fn parse_key(text: &str) -> Option<Event> {
+    let (mut ctrl, mut alt, mut shift) = (false, false, false);
+    let mut key = text;
+    // The key itself can be `-`, as in `ctrl--`
+    while let Some(dash) = key[..last_char(key)].find('-') {
+        match key[..dash].to_lowercase().as_str() {
+            "ctrl" => ctrl = true,
+            "alt" => alt = true,
+            "shift" => shift = true,
+            _ => return None,
+        }
+        key = &key[dash + 1..];
+    }
+
+    if key.eq_ignore_ascii_case("space") {
+        key = " ";
+    }
+    let mut chars = key.chars();
+    if let (Some(ch), None) = (chars.next(), chars.next()) {
+        return match (ctrl, alt, shift) {
+            (false, false, false) => Some(Event::Char(ch)),
+            (true, false, false) => Some(Event::CtrlChar(ch)),
+            (false, true, false) => Some(Event::AltChar(ch)),
+            _ => None,
+        };
+    }
+
+    let key = KEYS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+        .map(|&(_, key)| key)?;
+    match (ctrl, alt, shift) {
+        (false, false, false) => Some(Event::Key(key)),
+        (false, false, true) => Some(Event::Shift(key)),
+        (true, false, false) => Some(Event::Ctrl(key)),
+        (false, true, false) => Some(Event::Alt(key)),
+        (true, false, true) => Some(Event::CtrlShift(key)),
+        (false, true, true) => Some(Event::AltShift(key)),
+        (true, true, false) => Some(Event::CtrlAlt(key)),
+        (true, true, true) => None,
+    }
+}
+
+/// How a key is written in messages, like `Ctrl+S` or `Alt+Shift+R`
+pub fn key_name(event: &Event) -> Option<String> {
+    // Terminals send Alt+Shift+R as Alt and a capital R
+    let char_name = |ch: char| match ch {
+        ' ' => String::from("Space"),
+        ch if ch.is_uppercase() => format!("Shift+{}", ch),
+        ch => ch.to_uppercase().to_string(),
+    };
+    let key_name = |key: Key| {
+        KEYS.iter()
+            .find(|&&(_, other)| other == key)
+            .map(|&(name, _)| name)
+    };
+    Some(match *event {
+        Event::Char(ch) => ch.to_string(),
+        Event::CtrlChar(ch) => format!("Ctrl+{}", char_name(ch)),
+        Event::AltChar(ch) => format!("Alt+{}", char_name(ch)),
+        Event::Key(key) => key_name(key)?.to_string(),
+        Event::Shift(key) => format!("Shift+{}", key_name(key)?),
+        Event::Ctrl(key) => format!("Ctrl+{}", key_name(key)?),
+        Event::Alt(key) => format!("Alt+{}", key_name(key)?),
+        Event::CtrlShift(key) => format!("Ctrl+Shift+{}", key_name(key)?),
+        Event::AltShift(key) => format!("Alt+Shift+{}", key_name(key)?),
+        Event::CtrlAlt(key) => format!("Ctrl+Alt+{}", key_name(key)?),
+        _ => return None,
+    })
+}
+
+/// The byte offset of the last character of some text
+fn last_char(text: &str) -> usize {
+    text.char_indices().last().map_or(0, |(i, _)| i)
+}
@@ -0,0 +1,177 @@
+use std::path::Path;
+
+/// The languages the editor knows about. Everything that depends on the
+/// language of a file, like how it's commented or formatted, finds it with
+/// `Language::from_path` rather than looking at the file's extension itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Language {
+    Assembly,
+    Bash,
+    C,
+    CMake,
+    CSharp,
+    Conf,
+    Cpp,
+    Css,
+    Dart,
+    Docker,
+    Elixir,
+    Elm,
+    Erlang,
+    Fish,
+    Go,
+    Haskell,
+    Html,
+    Ini,
+    Java,
+    JavaScript,
+    Julia,
+    Kotlin,
+    Less,
+    Lisp,
+    Lua,
+    Make,
+    Markdown,
+    Nix,
+    OCaml,
+    Perl,
+    Php,
+    Python,
+    R,
+    Ruby,
+    Rust,
+    Scala,
+    Scss,
+    Sh,
+    Sql,
+    Swift,
+    TeX,
+    Toml,
+    TypeScript,
+    Vim,
+    Vue,
+    Xhtml,
+    Xml,
+    Yaml,
+    Zig,
+    Zsh,
+}
+
+/// The extensions of each language's files, in lowercase
+const EXTENSIONS: &[(Language, &[&str])] = &[
+    (Language::Assembly, &["asm", "s"]),
+    (Language::Bash, &["bash"]),
+    (Language::C, &["c", "h"]),
+    (Language::CMake, &["cmake"]),
+    (Language::CSharp, &["cs"]),
+    (Language::Conf, &["conf"]),
+    (Language::Cpp, &["cc", "cpp", "cxx", "hpp", "hh"]),
+    (Language::Css, &["css"]),
+    (Language::Dart, &["dart"]),
+    (Language::Elixir, &["ex", "exs"]),
+    (Language::Elm, &["elm"]),
+    (Language::Erlang, &["erl"]),
+    (Language::Fish, &["fish"]),
+    (Language::Go, &["go"]),
+    (Language::Haskell, &["hs"]),
+    (Language::Html, &["html", "htm"]),
+    (Language::Ini, &["ini"]),
+    (Language::Java, &["java"]),
+    (Language::JavaScript, &["js", "jsx", "mjs", "cjs"]),
+    (Language::Julia, &["jl"]),
+    (Language::Kotlin, &["kt", "kts"]),
+    (Language::Less, &["less"]),
+    (Language::Lisp, &["lisp", "el", "clj", "scm", "rkt"]),
+    (Language::Lua, &["lua"]),
+    (Language::Markdown, &["md", "markdown"]),
+    (Language::Nix, &["nix"]),
+    (Language::OCaml, &["ml", "mli"]),
+    (Language::Perl, &["pl"]),
+    (Language::Php, &["php"]),
+    (Language::Python, &["py", "pyi"]),
+    (Language::R, &["r"]),
+    (Language::Ruby, &["rb"]),
+    (Language::Rust, &["rs"]),
+    (Language::Scala, &["scala"]),
+    (Language::Scss, &["scss"]),
+    (Language::Sh, &["sh"]),
+    (Language::Sql, &["sql"]),
+    (Language::Swift, &["swift"]),
+    (Language::TeX, &["tex"]),
+    (Language::Toml, &["toml"]),
+    (Language::TypeScript, &["ts", "tsx"]),
+    (Language::Vim, &["vim"]),
+    (Language::Vue, &["vue"]),
+    (Language::Xhtml, &["xhtml"]),
+    (
+        Language::Xml,
+        &["xml", "svg", "xsl", "xslt", "xsd", "plist", "csproj"],
+    ),
+    (Language::Yaml, &["yaml", "yml"]),
+    (Language::Zig, &["zig"]),
+    (Language::Zsh, &["zsh"]),
+];
+
+/// Files whose language is known from their whole name
+const NAMES: [(&str, Language); 4] = [
+    ("Makefile", Language::Make),
+    ("makefile", Language::Make),
+    ("Dockerfile", Language::Docker),
+    ("CMakeLists.txt", Language::CMake),
+];
+
+impl Language {
+    /// The characters that start and end strings, and character literals
+    /// where they're written the same way
+    pub fn quotes(self) -> &'static [char] {
+        match self {
+            // `'` isn't a quote here, like in Rust's `'a` or Haskell's `x'`
+            Language::Rust
+            | Language::Haskell
+            | Language::Elm
+            | Language::OCaml
+            | Language::Lisp
+            | Language::Nix
+            | Language::CMake
+            | Language::Swift => &['"'],
+            // `"` starts comments
+            Language::Vim => &['\''],
+            Language::JavaScript
+            | Language::TypeScript
+            | Language::Go
+            | Language::Sh
+            | Language::Bash
+            | Language::Zsh
+            | Language::Fish => &['"', '\'', '`'],
+            Language::Markdown | Language::TeX => &[],
+            _ => &['"', '\''],
+        }
+    }
+
+    /// The language of a file, from its name or extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        let name = path.rsplit('/').next()?;
+        if let Some(&(_, language)) = NAMES.iter().find(|&&(known, _)| known == name) {
+            return Some(language);
+        }
+        let extension = extension(name)?.to_lowercase();
+        EXTENSIONS
+            .iter()
+            .find(|(_, extensions)| extensions.contains(&extension.as_str()))
+            .map(|&(language, _)| language)
+    }
+}
+
+/// The extension of a file name, like `rs` for `main.rs`
+pub fn extension(path: &str) -> Option<&str> {
+    let name = path.rsplit('/').next()?;
+    name.rsplit_once('.').map(|(_, extension)| extension)
+}
+
+/// The directory a file is in, which is `.` for a bare file name
+pub fn file_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
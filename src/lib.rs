@@ -1 +1,41 @@
+pub mod backup;
+pub mod checksum;
+pub mod clipboard;
+pub mod comments;
+pub mod compress;
+pub mod config;
+pub mod crypt;
+pub mod diagnostics;
+pub mod diff;
+pub mod edits;
+pub mod emmet;
+pub mod encoding;
+pub mod formatter;
+pub mod fuzzy;
+pub mod git;
+pub mod highlight_rules;
+pub mod imports;
+pub mod json;
+pub mod keymap;
+pub mod languages;
+pub mod line_ending;
+pub mod locations;
+pub mod lock;
+pub mod logs;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+pub mod marks;
+pub mod markup;
+pub mod notebook;
+pub mod outline;
+mod pipe;
+pub mod position;
+pub mod prose;
+pub mod settings;
+pub mod snippets;
+pub mod spelling;
+pub mod tags;
+pub mod tasks;
+pub mod terminal;
+pub mod undo;
 pub mod views;
@@ -0,0 +1,46 @@
+/// How the lines of a file are separated
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LineEnding {
+    /// `\n`, used on Unix
+    #[default]
+    Lf,
+    /// `\r\n`, used on Windows
+    CrLf,
+}
+
+impl LineEnding {
+    /// Find the line ending that most of the lines in some text use
+    pub fn detect(text: &str) -> Self {
+        let lines = text.matches('\n').count();
+        let crlf = text.matches("\r\n").count();
+        if crlf > lines - crlf {
+            Self::CrLf
+        } else {
+            Self::Lf
+        }
+    }
+
+    /// The characters that end a line
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+        }
+    }
+
+    /// The usual name of the line ending, like `CRLF`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::CrLf => "CRLF",
+        }
+    }
+
+    /// The other line ending
+    pub fn toggled(&self) -> Self {
+        match self {
+            Self::Lf => Self::CrLf,
+            Self::CrLf => Self::Lf,
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use regex::Regex;
+
+/// A place in a file mentioned in some output, like `src/main.rs:3:5`
+/// in a compiler error
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileLocation {
+    pub file: String,
+    /// The line and column, counting from 1. The column is 1 if it's not given.
+    pub line: usize,
+    pub col: usize,
+    /// Where it was found in the line of output, as byte offsets
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Finds the places in files mentioned in output, like `src/main.rs:3:5`
+/// or `app.py:12`. The files may not exist.
+pub struct LocationFinder {
+    pattern: Regex,
+}
+
+impl Default for LocationFinder {
+    fn default() -> Self {
+        Self {
+            pattern: Regex::new(
+                r"(?P<file>[\w.~/\\+-]*\w\.[A-Za-z0-9]+):(?P<line>\d+)(?::(?P<col>\d+))?",
+            )
+            .expect("the location pattern is valid"),
+        }
+    }
+}
+
+impl LocationFinder {
+    /// The places mentioned in a line of output, in order
+    pub fn find(&self, line: &str) -> Vec<FileLocation> {
+        self.pattern
+            .captures_iter(line)
+            .filter_map(|captures| {
+                let whole = captures.get(0)?;
+                let line = captures["line"].parse().ok().filter(|&line| line > 0)?;
+                let col = match captures.name("col") {
+                    Some(col) => col.as_str().parse().ok()?,
+                    None => 1,
+                };
+                Some(FileLocation {
+                    file: captures["file"].to_string(),
+                    line,
+                    col,
+                    start: whole.start(),
+                    end: whole.end(),
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,67 @@
+use std::fs::{read_to_string, remove_file, write, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A lock file that tells other editors that a file is being edited.
+/// The lock file is removed when the lock is dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    path: PathBuf,
+}
+
+impl FileLock {
+    /// Lock a file, or return `None` if another editor that's still running
+    /// has it locked. Locks left behind by editors that exited are taken over.
+    pub fn acquire(file: &Path) -> io::Result<Option<Self>> {
+        let path = lock_path(file);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut lock) => {
+                write!(lock, "{}", process::id())?;
+                Ok(Some(Self { path }))
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => match lock_holder(file) {
+                Some(_) => Ok(None),
+                None => Self::steal(file).map(Some),
+            },
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Lock a file, even if another editor has it locked
+    pub fn steal(file: &Path) -> io::Result<Self> {
+        let path = lock_path(file);
+        write(&path, process::id().to_string())?;
+        Ok(Self { path })
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        // If another editor stole the lock, the lock file is theirs now
+        let ours = read_to_string(&self.path)
+            .is_ok_and(|holder| holder.trim() == process::id().to_string());
+        if ours {
+            let _ = remove_file(&self.path);
+        }
+    }
+}
+
+/// The lock file for a file, like `.notes.txt.editor-lock` next to `notes.txt`
+pub fn lock_path(file: &Path) -> PathBuf {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+    file.with_file_name(format!(".{}.editor-lock", name))
+}
+
+/// The process ID of another running editor that has a file locked
+pub fn lock_holder(file: &Path) -> Option<u32> {
+    let pid = read_to_string(lock_path(file)).ok()?.trim().parse().ok()?;
+    Some(pid).filter(|&pid| pid != process::id() && is_running(pid))
+}
+
+/// Whether a process is running. Without `/proc` to look in,
+/// every process is assumed to be.
+fn is_running(pid: u32) -> bool {
+    let proc = Path::new("/proc");
+    !proc.join("self").exists() || proc.join(pid.to_string()).exists()
+}
@@ -0,0 +1,159 @@
+use crate::highlight_rules::{apply_rules, HighlightRule};
+use crate::views::Highlighter;
+use cursive::theme::{BaseColor, Color, Effect};
+use cursive::utils::markup::StyledString;
+use regex::Regex;
+
+/// How important a log line is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// The names each level goes by in logs
+const LEVELS: [(&str, Level); 11] = [
+    ("TRACE", Level::Trace),
+    ("DEBUG", Level::Debug),
+    ("INFO", Level::Info),
+    ("NOTICE", Level::Info),
+    ("WARN", Level::Warn),
+    ("WARNING", Level::Warn),
+    ("ERROR", Level::Error),
+    ("ERR", Level::Error),
+    ("FATAL", Level::Error),
+    ("CRITICAL", Level::Error),
+    ("PANIC", Level::Error),
+];
+
+impl Level {
+    /// A level by one of its names, in any case
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_uppercase();
+        LEVELS
+            .iter()
+            .find(|(level_name, _)| *level_name == name)
+            .map(|&(_, level)| level)
+    }
+
+    /// The level of a log line, from the first level name in it
+    pub fn of_line(line: &str) -> Option<Self> {
+        line.split(|ch: char| !ch.is_ascii_alphabetic())
+            .filter(|word| word.len() >= 3 && word.chars().all(|ch| ch.is_ascii_uppercase()))
+            .find_map(Self::from_name)
+    }
+}
+
+/// Whether a file is a log, like `server.log` or a rotated `server.log.1`
+pub fn is_log_file(path: &str) -> bool {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.ends_with(".log") || name.contains(".log.") || name == "syslog"
+}
+
+/// Whether a line continues the log entry above it, like a line of a stack trace
+pub fn is_continuation(line: &str) -> bool {
+    line.starts_with(char::is_whitespace)
+        || line.starts_with("Traceback ")
+        || line.starts_with("Caused by:")
+        || line.starts_with("stack backtrace:")
+}
+
+/// Which lines of a log to show
+#[derive(Clone, Debug)]
+pub enum LogFilter {
+    /// Entries of a level or a more important one
+    Level(Level),
+    /// Entries with a match of a pattern
+    Pattern(Regex),
+}
+
+impl LogFilter {
+    /// A filter for a level name like `warn`, or else a regex
+    pub fn parse(filter: &str) -> Result<Self, regex::Error> {
+        match Level::from_name(filter) {
+            Some(level) => Ok(Self::Level(level)),
+            None => Regex::new(filter).map(Self::Pattern),
+        }
+    }
+
+    fn matches(&self, line: &str) -> bool {
+        match self {
+            Self::Level(level) => Level::of_line(line).is_some_and(|of_line| of_line >= *level),
+            Self::Pattern(pattern) => pattern.is_match(line),
+        }
+    }
+
+    /// The indices of the lines that pass the filter. The stack traces and
+    /// other lines that continue an entry that passes are kept with it.
+    pub fn filter(&self, lines: &[String]) -> Vec<usize> {
+        let mut kept = Vec::new();
+        let mut keeping = false;
+        for (i, line) in lines.iter().enumerate() {
+            let continues = keeping && is_continuation(line) && !line.trim().is_empty();
+            keeping = continues || self.matches(line);
+            if keeping {
+                kept.push(i);
+            }
+        }
+        kept
+    }
+}
+
+/// A highlighter for log files, that colors timestamps, log levels and stack traces
+pub struct LogHighlighter {
+    rules: Vec<HighlightRule>,
+}
+
+impl Default for LogHighlighter {
+    fn default() -> Self {
+        let rule = |pattern: &str, color, effects: &[Effect]| {
+            let pattern = Regex::new(pattern).expect("log patterns are valid");
+            HighlightRule::new(pattern, Some(color), effects)
+        };
+        Self {
+            rules: vec![
+                // Timestamps like `2024-05-01T12:30:00.123Z`, `May  1 12:30:00` or `12:30:00`
+                rule(
+                    concat!(
+                        r"^\[?(\d{4}-\d\d-\d\d[T ]\d\d:\d\d:\d\d([.,]\d+)?(Z|[+-]\d\d:?\d\d)?",
+                        r"|[A-Z][a-z]{2} [ \d]\d \d\d:\d\d:\d\d",
+                        r"|\d\d:\d\d:\d\d([.,]\d+)?)\]?",
+                    ),
+                    Color::Dark(BaseColor::Cyan),
+                    &[],
+                ),
+                // Stack traces from Java, JavaScript, Python and Rust
+                rule(
+                    concat!(
+                        r"^(\s+at .*|\s+File .*, line \d+.*|\s+\d+: .*",
+                        r"|Traceback .*|Caused by:.*|stack backtrace:)$",
+                    ),
+                    Color::Light(BaseColor::Black),
+                    &[Effect::Italic],
+                ),
+                rule(r"\b(TRACE|DEBUG)\b", Color::Light(BaseColor::Blue), &[]),
+                rule(r"\b(INFO|NOTICE)\b", Color::Light(BaseColor::Green), &[]),
+                rule(
+                    r"\b(WARN|WARNING)\b",
+                    Color::Light(BaseColor::Yellow),
+                    &[Effect::Bold],
+                ),
+                rule(
+                    r"\b(ERROR|ERR|FATAL|CRITICAL|PANIC)\b",
+                    Color::Light(BaseColor::Red),
+                    &[Effect::Bold],
+                ),
+            ],
+        }
+    }
+}
+
+impl Highlighter for LogHighlighter {
+    fn highlight(&self, code: impl ToString) -> StyledString {
+        let line = code.to_string();
+        apply_rules(&self.rules, &line, StyledString::plain(line.clone()))
+    }
+}
@@ -0,0 +1,697 @@
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::edits::TextEdit;
+use crate::json::Json;
+use crate::languages::{file_dir, Language};
+use crate::position::PositionMapper;
+use std::collections::HashMap;
+use std::fs::{canonicalize, read_to_string};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, ChildStdin, Command, Stdio};
+use std::thread;
+
+/// The language server for a file, by its language, as the command that
+/// starts it and the id of the file's language
+pub fn server_for(path: &str) -> Option<(&'static str, &'static str)> {
+    let server = match Language::from_path(path)? {
+        Language::Rust => ("rust-analyzer", "rust"),
+        Language::Python => ("pylsp", "python"),
+        Language::C => ("clangd", "c"),
+        Language::Cpp => ("clangd", "cpp"),
+        Language::Go => ("gopls", "go"),
+        Language::JavaScript => ("typescript-language-server --stdio", "javascript"),
+        Language::TypeScript => ("typescript-language-server --stdio", "typescript"),
+        Language::Zig => ("zls", "zig"),
+        _ => return None,
+    };
+    Some(server)
+}
+
+/// The root of the project a file is in, which its language server is started in
+pub fn project_root(file: &Path) -> PathBuf {
+    const MARKERS: [&str; 6] = [
+        "Cargo.toml",
+        "go.mod",
+        "package.json",
+        "pyproject.toml",
+        "compile_commands.json",
+        ".git",
+    ];
+    let dir = file_dir(file);
+    dir.ancestors()
+        .find(|dir| MARKERS.iter().any(|marker| dir.join(marker).exists()))
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+/// A place in a file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub path: PathBuf,
+    /// The line, counting from 1
+    pub line: usize,
+    /// The column, counting from 1
+    pub column: usize,
+}
+
+/// A change a language server offers to make, like a quick fix or a refactoring
+#[derive(Clone, Debug, PartialEq)]
+pub struct CodeAction {
+    pub title: String,
+    /// The edits to each file that make the change
+    pub edits: Vec<(PathBuf, Vec<TextEdit>)>,
+}
+
+/// Something a language server told the editor
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reply {
+    /// The problems in a file, in place of the ones it had before
+    Diagnostics(PathBuf, Vec<Diagnostic>),
+    /// What the symbol at a place is, if the server knows
+    Hover(Option<String>),
+    /// Where the symbol at a place is defined, if the server knows
+    Definition(Option<Location>),
+    /// The edits to each file that rename a symbol, if the server could rename it
+    Rename(Option<Vec<(PathBuf, Vec<TextEdit>)>>),
+    /// The edits that format a file, if the server could format it
+    Format(PathBuf, Option<Vec<TextEdit>>),
+    /// The code actions there are at a place
+    CodeActions(Vec<CodeAction>),
+}
+
+/// What a request that hasn't been answered asked for
+#[derive(Clone, Debug, PartialEq)]
+enum Request {
+    Initialize,
+    Hover,
+    Definition,
+    Rename,
+    /// Formatting a file, as the version of it the server had when it was asked
+    Format(PathBuf, usize),
+    CodeActions,
+}
+
+/// A file the server has open, as the server last saw it
+struct Document {
+    version: usize,
+    text: String,
+}
+
+/// A running language server, like `rust-analyzer`, which the editor
+/// talks to with the Language Server Protocol. Files are sent to it whole
+/// each time they change.
+pub struct LanguageServer {
+    child: Child,
+    stdin: ChildStdin,
+    next_id: usize,
+    pending: HashMap<usize, Request>,
+    /// Messages to send once the server has started up
+    queued: Option<Vec<Json>>,
+    documents: HashMap<PathBuf, Document>,
+    /// The problems the server last found in each file, as it sent them,
+    /// to send back when asking for the code actions that fix them
+    diagnostics: HashMap<PathBuf, Vec<Json>>,
+}
+
+impl LanguageServer {
+    /// Start a language server for the project in `root`. Each message the
+    /// server sends is given to `on_message` from another thread, and should
+    /// be passed back to `receive`.
+    pub fn start(
+        command: &str,
+        root: &Path,
+        on_message: impl Fn(Json) + Send + 'static,
+    ) -> io::Result<Self> {
+        let mut words = command.split_whitespace();
+        let program = words.next().unwrap_or_default();
+        let mut child = Command::new(program)
+            .args(words)
+            .current_dir(root)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        thread::spawn(move || loop {
+            match read_message(&mut stdout) {
+                Ok(message) => on_message(message),
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => continue,
+                Err(_) => break,
+            }
+        });
+
+        let mut server = Self {
+            child,
+            stdin,
+            next_id: 0,
+            pending: HashMap::new(),
+            queued: Some(Vec::new()),
+            documents: HashMap::new(),
+            diagnostics: HashMap::new(),
+        };
+        let root = canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let kinds: Vec<Json> = ["", "quickfix", "refactor", "source"]
+            .iter()
+            .map(|&kind| kind.into())
+            .collect();
+        let capabilities = Json::object(vec![(
+            "textDocument",
+            Json::object(vec![
+                (
+                    "hover",
+                    Json::object(vec![("contentFormat", vec!["plaintext".into()].into())]),
+                ),
+                ("definition", Json::object(vec![])),
+                ("rename", Json::object(vec![])),
+                ("formatting", Json::object(vec![])),
+                ("rangeFormatting", Json::object(vec![])),
+                (
+                    "codeAction",
+                    Json::object(vec![(
+                        "codeActionLiteralSupport",
+                        Json::object(vec![(
+                            "codeActionKind",
+                            Json::object(vec![("valueSet", kinds.into())]),
+                        )]),
+                    )]),
+                ),
+                ("publishDiagnostics", Json::object(vec![])),
+            ]),
+        )]);
+        let params = Json::object(vec![
+            ("processId", (process::id() as usize).into()),
+            ("rootUri", path_to_uri(&root).into()),
+            ("capabilities", capabilities),
+        ]);
+        server.request(Request::Initialize, "initialize", params)?;
+        Ok(server)
+    }
+
+    /// Tell the server what's in a file now, if it changed since it was last told
+    pub fn sync(&mut self, path: &Path, language_id: &str, text: &str) -> io::Result<()> {
+        let uri = path_to_uri(path);
+        match self.documents.get_mut(path) {
+            Some(document) if document.text == text => Ok(()),
+            Some(document) => {
+                document.version += 1;
+                document.text = text.to_string();
+                let document = Json::object(vec![
+                    ("uri", uri.into()),
+                    ("version", document.version.into()),
+                ]);
+                let changes = vec![Json::object(vec![("text", text.into())])];
+                self.notify(
+                    "textDocument/didChange",
+                    Json::object(vec![
+                        ("textDocument", document),
+                        ("contentChanges", changes.into()),
+                    ]),
+                )
+            }
+            None => {
+                let document = Document {
+                    version: 0,
+                    text: text.to_string(),
+                };
+                self.documents.insert(path.to_path_buf(), document);
+                let document = Json::object(vec![
+                    ("uri", uri.into()),
+                    ("languageId", language_id.into()),
+                    ("version", 0.into()),
+                    ("text", text.into()),
+                ]);
+                self.notify(
+                    "textDocument/didOpen",
+                    Json::object(vec![("textDocument", document)]),
+                )
+            }
+        }
+    }
+
+    /// Ask what the symbol at a line and column of a file is.
+    /// The answer comes back later as a `Reply::Hover`.
+    pub fn hover(&mut self, path: &Path, line: usize, column: usize) -> io::Result<()> {
+        let params = self.position(path, line, column);
+        self.request(Request::Hover, "textDocument/hover", params)
+    }
+
+    /// Ask where the symbol at a line and column of a file is defined.
+    /// The answer comes back later as a `Reply::Definition`.
+    pub fn definition(&mut self, path: &Path, line: usize, column: usize) -> io::Result<()> {
+        let params = self.position(path, line, column);
+        self.request(Request::Definition, "textDocument/definition", params)
+    }
+
+    /// Ask how to rename the symbol at a line and column of a file to `name`.
+    /// The answer comes back later as a `Reply::Rename`.
+    pub fn rename(
+        &mut self,
+        path: &Path,
+        line: usize,
+        column: usize,
+        name: &str,
+    ) -> io::Result<()> {
+        let mut params = self.position(path, line, column);
+        if let Json::Object(members) = &mut params {
+            members.push(("newName".to_string(), name.into()));
+        }
+        self.request(Request::Rename, "textDocument/rename", params)
+    }
+
+    /// Ask what code actions there are at a line and column of a file, for the
+    /// problems the server found on that line. The answer comes back later as
+    /// a `Reply::CodeActions`.
+    pub fn code_actions(&mut self, path: &Path, line: usize, column: usize) -> io::Result<()> {
+        let row = line.saturating_sub(1);
+        let point = self.point(path, (row, column.saturating_sub(1)));
+        let on_row = |diagnostic: &&Json| {
+            let range = diagnostic.get("range");
+            let line = |end| range?.get(end)?.get("line")?.as_usize();
+            match (line("start"), line("end")) {
+                (Some(start), Some(end)) => start <= row && row <= end,
+                _ => false,
+            }
+        };
+        let diagnostics: Vec<Json> = self
+            .diagnostics
+            .get(path)
+            .map(|diagnostics| diagnostics.iter().filter(on_row).cloned().collect())
+            .unwrap_or_default();
+        let params = Json::object(vec![
+            (
+                "textDocument",
+                Json::object(vec![("uri", path_to_uri(path).into())]),
+            ),
+            (
+                "range",
+                Json::object(vec![("start", point.clone()), ("end", point)]),
+            ),
+            (
+                "context",
+                Json::object(vec![("diagnostics", diagnostics.into())]),
+            ),
+        ]);
+        self.request(Request::CodeActions, "textDocument/codeAction", params)
+    }
+
+    /// Ask how to format a file, or only the lines from `start` to `end` of it
+    /// if a range is given, with rows and columns counting from 0. The answer
+    /// comes back later as a `Reply::Format`.
+    pub fn format(
+        &mut self,
+        path: &Path,
+        range: Option<((usize, usize), (usize, usize))>,
+        tab_width: usize,
+        hard_tabs: bool,
+    ) -> io::Result<()> {
+        let version = self
+            .documents
+            .get(path)
+            .map_or(0, |document| document.version);
+        let options = Json::object(vec![
+            ("tabSize", tab_width.into()),
+            ("insertSpaces", (!hard_tabs).into()),
+        ]);
+        let mut params = vec![
+            (
+                "textDocument",
+                Json::object(vec![("uri", path_to_uri(path).into())]),
+            ),
+            ("options", options),
+        ];
+        let method = match range {
+            Some((start, end)) => {
+                let range = Json::object(vec![
+                    ("start", self.point(path, start)),
+                    ("end", self.point(path, end)),
+                ]);
+                params.push(("range", range));
+                "textDocument/rangeFormatting"
+            }
+            None => "textDocument/formatting",
+        };
+        let request = Request::Format(path.to_path_buf(), version);
+        self.request(request, method, Json::object(params))
+    }
+
+    /// Handle a message from the server, and return what it told the editor, if anything
+    pub fn receive(&mut self, message: Json) -> io::Result<Option<Reply>> {
+        let method = message.get("method").and_then(Json::as_str);
+        let id = message.get("id");
+        match (method, id) {
+            // The server asks things like what the settings are, which are left as they are
+            (Some(method), Some(id)) => {
+                let result = match message.get("params").and_then(|p| p.get("items")) {
+                    Some(Json::Array(items)) if method == "workspace/configuration" => {
+                        vec![Json::Null; items.len()].into()
+                    }
+                    _ => Json::Null,
+                };
+                let response = Json::object(vec![
+                    ("jsonrpc", "2.0".into()),
+                    ("id", id.clone()),
+                    ("result", result),
+                ]);
+                self.send(response)?;
+                Ok(None)
+            }
+            (Some("textDocument/publishDiagnostics"), None) => {
+                let params = message.get("params");
+                Ok(params.and_then(|params| self.diagnostics(params)))
+            }
+            (Some(_), None) => Ok(None),
+            (None, Some(id)) => {
+                let request = match id.as_usize().and_then(|id| self.pending.remove(&id)) {
+                    Some(request) => request,
+                    None => return Ok(None),
+                };
+                // Errors are answered like there's nothing to tell
+                let result = message.get("result").unwrap_or(&Json::Null);
+                match request {
+                    Request::Initialize => {
+                        self.write(&notification("initialized", Json::object(vec![])))?;
+                        for message in self.queued.take().unwrap_or_default() {
+                            self.write(&message)?;
+                        }
+                        Ok(None)
+                    }
+                    Request::Hover => Ok(Some(Reply::Hover(hover_text(result)))),
+                    Request::Definition => Ok(Some(Reply::Definition(self.location(result)))),
+                    Request::Rename => Ok(Some(Reply::Rename(self.workspace_edit(result)))),
+                    // The edits are for the file as it was, so they're dropped if it changed since
+                    Request::Format(path, version) => {
+                        match self.documents.get(&path) {
+                            Some(document) if document.version != version => return Ok(None),
+                            _ => {}
+                        }
+                        let edits = match result {
+                            Json::Null => Some(Vec::new()),
+                            edits => edits.as_array().and_then(|edits| {
+                                edits
+                                    .iter()
+                                    .map(|edit| self.text_edit(&path, edit))
+                                    .collect()
+                            }),
+                        };
+                        Ok(Some(Reply::Format(path, edits)))
+                    }
+                    Request::CodeActions => Ok(Some(Reply::CodeActions(self.actions(result)))),
+                }
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    fn request(&mut self, request: Request, method: &str, params: Json) -> io::Result<()> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let initialize = request == Request::Initialize;
+        self.pending.insert(id, request);
+        let message = Json::object(vec![
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ]);
+        // Nothing else can be sent until the server has started up
+        if initialize {
+            self.write(&message)
+        } else {
+            self.send(message)
+        }
+    }
+
+    fn notify(&mut self, method: &str, params: Json) -> io::Result<()> {
+        self.send(notification(method, params))
+    }
+
+    /// Send a message, or keep it for later if the server is still starting up
+    fn send(&mut self, message: Json) -> io::Result<()> {
+        match &mut self.queued {
+            Some(queued) => {
+                queued.push(message);
+                Ok(())
+            }
+            None => self.write(&message),
+        }
+    }
+
+    fn write(&mut self, message: &Json) -> io::Result<()> {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.stdin.flush()
+    }
+
+    /// The parameters that point at a place in a file, which count
+    /// from 0 and count columns in UTF-16 code units
+    fn position(&self, path: &Path, line: usize, column: usize) -> Json {
+        let point = (line.saturating_sub(1), column.saturating_sub(1));
+        Json::object(vec![
+            (
+                "textDocument",
+                Json::object(vec![("uri", path_to_uri(path).into())]),
+            ),
+            ("position", self.point(path, point)),
+        ])
+    }
+
+    /// A row and column of a file, counting from 0, as the server counts them
+    fn point(&self, path: &Path, (line, column): (usize, usize)) -> Json {
+        let character = match self.line_text(path, line) {
+            Some(text) => PositionMapper::new(&[text], 1).utf16_col(0, column as i32),
+            None => column,
+        };
+        Json::object(vec![("line", line.into()), ("character", character.into())])
+    }
+
+    /// A line of a file, counting from 0, as the server has it
+    fn line_text(&self, path: &Path, line: usize) -> Option<String> {
+        match self.documents.get(path) {
+            Some(document) => document.text.lines().nth(line).map(String::from),
+            None => read_to_string(path)
+                .ok()?
+                .lines()
+                .nth(line)
+                .map(String::from),
+        }
+    }
+
+    /// The column, counting from 1, of a place the server sent
+    fn column(&self, path: &Path, line: usize, character: usize) -> usize {
+        let text = self.line_text(path, line).unwrap_or_default();
+        PositionMapper::new(&[text], 1).col_at_utf16(0, character) as usize + 1
+    }
+
+    fn diagnostics(&mut self, params: &Json) -> Option<Reply> {
+        let path = uri_to_path(params.get("uri")?.as_str()?)?;
+        let sent = params.get("diagnostics")?.as_array()?;
+        self.diagnostics.insert(path.clone(), sent.to_vec());
+        let diagnostics = sent
+            .iter()
+            .filter_map(|diagnostic| {
+                let start = diagnostic.get("range")?.get("start")?;
+                let line = start.get("line")?.as_usize()?;
+                let column = self.column(&path, line, start.get("character")?.as_usize()?);
+                let severity = match diagnostic.get("severity").and_then(Json::as_usize) {
+                    Some(2) => Severity::Warning,
+                    Some(3) => Severity::Info,
+                    Some(4) => Severity::Hint,
+                    _ => Severity::Error,
+                };
+                let message = diagnostic.get("message")?.as_str()?;
+                Some(Diagnostic::new(line + 1, column, severity, message))
+            })
+            .collect();
+        Some(Reply::Diagnostics(path, diagnostics))
+    }
+
+    /// The code actions in a code action result that make their changes with
+    /// edits. Ones that run a command on the server instead are left out.
+    fn actions(&self, result: &Json) -> Vec<CodeAction> {
+        result
+            .as_array()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|action| {
+                Some(CodeAction {
+                    title: action.get("title")?.as_str()?.to_string(),
+                    edits: self.workspace_edit(action.get("edit")?)?,
+                })
+            })
+            .collect()
+    }
+
+    /// The first place in a definition result, which can be one
+    /// location or a list of locations or location links
+    fn location(&self, result: &Json) -> Option<Location> {
+        let location = match result {
+            Json::Array(locations) => locations.first()?,
+            location => location,
+        };
+        let uri = location.get("uri").or_else(|| location.get("targetUri"))?;
+        let range = location
+            .get("range")
+            .or_else(|| location.get("targetSelectionRange"))?;
+        let start = range.get("start")?;
+        let path = uri_to_path(uri.as_str()?)?;
+        let line = start.get("line")?.as_usize()?;
+        let column = self.column(&path, line, start.get("character")?.as_usize()?);
+        Some(Location {
+            path,
+            line: line + 1,
+            column,
+        })
+    }
+
+    /// The edits to each file in a workspace edit, which lists them either
+    /// by file or as a list of changes to documents
+    fn workspace_edit(&self, result: &Json) -> Option<Vec<(PathBuf, Vec<TextEdit>)>> {
+        let mut files = Vec::new();
+        if let Some(changes) = result.get("documentChanges") {
+            // Creating, renaming, and deleting files aren't supported,
+            // so a rename that needs them can't be made
+            for change in changes.as_array()? {
+                let uri = change.get("textDocument")?.get("uri")?.as_str()?;
+                files.push((uri, change.get("edits")?));
+            }
+        } else if let Some(Json::Object(changes)) = result.get("changes") {
+            for (uri, edits) in changes {
+                files.push((uri.as_str(), edits));
+            }
+        } else {
+            return None;
+        }
+        files
+            .into_iter()
+            .map(|(uri, edits)| {
+                let path = uri_to_path(uri)?;
+                let edits = edits
+                    .as_array()?
+                    .iter()
+                    .map(|edit| self.text_edit(&path, edit))
+                    .collect::<Option<_>>()?;
+                Some((path, edits))
+            })
+            .collect()
+    }
+
+    /// An edit to a file, with its range in rows and columns counting from 0
+    fn text_edit(&self, path: &Path, edit: &Json) -> Option<TextEdit> {
+        let range = edit.get("range")?;
+        let point = |position: &Json| {
+            let line = position.get("line")?.as_usize()?;
+            let character = position.get("character")?.as_usize()?;
+            Some((line, self.column(path, line, character) - 1))
+        };
+        let start = point(range.get("start")?)?;
+        let end = point(range.get("end")?)?;
+        Some(TextEdit::replace(
+            start,
+            end,
+            edit.get("newText")?.as_str()?,
+        ))
+    }
+}
+
+impl Drop for LanguageServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    Json::object(vec![
+        ("jsonrpc", "2.0".into()),
+        ("method", method.into()),
+        ("params", params),
+    ])
+}
+
+/// Read a message, which is a header with its length and then the JSON
+fn read_message(reader: &mut impl BufRead) -> io::Result<Json> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let length = length
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "a message has no length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Json::parse(&String::from_utf8_lossy(&body))
+}
+
+/// What a hover result says, without the fences around code in Markdown
+fn hover_text(result: &Json) -> Option<String> {
+    let text = marked_text(result.get("contents")?);
+    let lines: Vec<&str> = text
+        .lines()
+        .filter(|line| !line.starts_with("```"))
+        .collect();
+    Some(lines.join("\n").trim().to_string()).filter(|text| !text.is_empty())
+}
+
+/// The text of hover contents, which can be a string, an object
+/// with a `value`, or a list of those
+fn marked_text(contents: &Json) -> String {
+    match contents {
+        Json::String(text) => text.clone(),
+        Json::Array(items) => items
+            .iter()
+            .map(marked_text)
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        _ => contents
+            .get("value")
+            .and_then(Json::as_str)
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+/// A `file://` URI for a path, with the characters URIs can't have escaped
+fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.to_string_lossy().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::new();
+    let mut rest = uri.strip_prefix("file://")?.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(escaped) if byte == b'%' => {
+                bytes.push(escaped);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
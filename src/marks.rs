@@ -0,0 +1,135 @@
+use crate::tasks::{find_project_config, PROJECT_CONFIG};
+use std::fs::{read_to_string, write};
+use std::io;
+use std::path::{Path, PathBuf};
+use toml::Value;
+
+/// How many files can be pinned in a project
+pub const SLOTS: usize = 4;
+
+/// The files pinned to numbered slots in a project, to jump to quickly.
+/// They're kept in the project config, relative to its directory:
+///
+/// ```toml
+/// [marks]
+/// 1 = "src/main.rs"
+/// 2 = "README.md"
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marks {
+    /// The project config the marks are kept in, which may not exist yet
+    config: PathBuf,
+    files: [Option<PathBuf>; SLOTS],
+}
+
+impl Marks {
+    /// Read the marks of the project a directory is in. Without a project
+    /// config, the marks are saved to a new one in the directory.
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let config = match find_project_config(dir) {
+            Some(config) => config,
+            None => {
+                return Ok(Self {
+                    config: dir.join(PROJECT_CONFIG),
+                    files: Default::default(),
+                })
+            }
+        };
+        let value: Value = read_to_string(&config)?.parse().map_err(invalid)?;
+        let mut files: [Option<PathBuf>; SLOTS] = Default::default();
+        match value.get("marks") {
+            Some(Value::Table(marks)) => {
+                for (slot, file) in marks {
+                    let i = match slot.parse::<usize>() {
+                        Ok(slot) if (1..=SLOTS).contains(&slot) => slot - 1,
+                        _ => return Err(invalid(format!("there's no mark {}", slot))),
+                    };
+                    match file.as_str() {
+                        Some(file) => files[i] = Some(PathBuf::from(file)),
+                        None => return Err(invalid(format!("mark {} should be a file", slot))),
+                    }
+                }
+            }
+            Some(_) => return Err(invalid("`marks` should be a table")),
+            None => {}
+        }
+        Ok(Self { config, files })
+    }
+
+    /// The file pinned to a slot, counting from 1
+    pub fn get(&self, slot: usize) -> Option<PathBuf> {
+        let file = self.files.get(slot.checked_sub(1)?)?.as_ref()?;
+        Some(self.dir().join(file))
+    }
+
+    /// Pin a file to a slot, counting from 1, or clear the slot
+    pub fn set(&mut self, slot: usize, file: Option<&Path>) {
+        let dir = self.dir().canonicalize().unwrap_or_else(|_| self.dir());
+        let file = file.map(|file| {
+            let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+            file.strip_prefix(&dir)
+                .map_or(file.clone(), Path::to_path_buf)
+        });
+        if let Some(pinned) = slot.checked_sub(1).and_then(|i| self.files.get_mut(i)) {
+            *pinned = file;
+        }
+    }
+
+    /// Write the marks to the project config, in place of the ones there
+    pub fn save(&self) -> io::Result<()> {
+        let text = match read_to_string(&self.config) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let mut section = String::new();
+        for (i, file) in self.files.iter().enumerate() {
+            if let Some(file) = file {
+                let file = Value::String(file.to_string_lossy().into_owned());
+                section += &format!("{} = {}\n", i + 1, file);
+            }
+        }
+        write(&self.config, replace_section(&text, "marks", &section))
+    }
+
+    /// The directory the pinned files are relative to
+    fn dir(&self) -> PathBuf {
+        self.config
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .to_path_buf()
+    }
+}
+
+/// Replace the lines of a table in a TOML file, or add the table at the end.
+/// The rest of the file is kept as it is, with its comments.
+fn replace_section(text: &str, name: &str, lines: &str) -> String {
+    let header = format!("[{}]", name);
+    let mut kept = Vec::new();
+    let mut in_section = false;
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_section = trimmed == header;
+        }
+        if !in_section {
+            kept.push(line);
+        }
+    }
+    while kept.last().is_some_and(|line| line.trim().is_empty()) {
+        kept.pop();
+    }
+
+    let mut text = kept.join("\n");
+    if lines.is_empty() {
+        return if text.is_empty() { text } else { text + "\n" };
+    }
+    if !text.is_empty() {
+        text += "\n\n";
+    }
+    text + &header + "\n" + lines
+}
+
+fn invalid(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
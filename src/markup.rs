@@ -0,0 +1,212 @@
+use crate::languages::Language;
+
+/// A markup language whose tags can be matched up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Markup {
+    Html,
+    Xml,
+}
+
+/// HTML elements that never have a closing tag
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// HTML elements whose contents are raw text, and can contain a `<`
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+impl Markup {
+    /// Guess the markup language of a file from its extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        match Language::from_path(path)? {
+            Language::Html => Some(Self::Html),
+            Language::Xml | Language::Xhtml => Some(Self::Xml),
+            _ => None,
+        }
+    }
+
+    /// Whether two tag names refer to the same element.
+    /// HTML tag names are case insensitive, but XML tag names aren't.
+    pub fn same_name(&self, a: &str, b: &str) -> bool {
+        match self {
+            Self::Html => a.eq_ignore_ascii_case(b),
+            Self::Xml => a == b,
+        }
+    }
+
+    fn is_void(&self, name: &str) -> bool {
+        *self == Self::Html && VOID_ELEMENTS.iter().any(|void| self.same_name(void, name))
+    }
+
+    fn is_raw_text(&self, name: &str) -> bool {
+        *self == Self::Html
+            && RAW_TEXT_ELEMENTS
+                .iter()
+                .any(|raw| self.same_name(raw, name))
+    }
+}
+
+/// Whether a character can be part of a tag name
+pub fn is_name_char(ch: char) -> bool {
+    ch.is_alphanumeric() || "-_:.".contains(ch)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TagKind {
+    Open,
+    Close,
+    SelfClosing,
+}
+
+/// A tag found in a markup document
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    pub kind: TagKind,
+    /// Byte offset of the `<` that starts the tag
+    pub start: usize,
+    /// Byte offset just past the end of the tag
+    pub end: usize,
+    /// Byte offset of the first character of the tag's name
+    pub name_start: usize,
+}
+
+/// Find the tags in a document, skipping over comments, doctypes,
+/// processing instructions, CDATA sections, and script contents.
+pub fn scan_tags(text: &str, markup: Markup) -> Vec<Tag> {
+    let mut tags = vec![];
+    let mut i = 0;
+
+    // Skip past the first `terminator` after `from`, or to the end of the text
+    let skip_past = |from: usize, terminator: &str| {
+        text[from..]
+            .find(terminator)
+            .map(|found| from + found + terminator.len())
+            .unwrap_or_else(|| text.len())
+    };
+
+    while let Some(found) = text[i..].find('<') {
+        let start = i + found;
+        let rest = &text[start..];
+        if rest.starts_with("<!--") {
+            i = skip_past(start, "-->");
+            continue;
+        } else if rest.starts_with("<![CDATA[") {
+            i = skip_past(start, "]]>");
+            continue;
+        } else if rest.starts_with("<!") || rest.starts_with("<?") {
+            i = skip_past(start, ">");
+            continue;
+        }
+
+        let (mut kind, name_start) = if rest.starts_with("</") {
+            (TagKind::Close, start + 2)
+        } else {
+            (TagKind::Open, start + 1)
+        };
+        let name_len = text[name_start..]
+            .find(|ch: char| !is_name_char(ch))
+            .unwrap_or(text.len() - name_start);
+        if name_len == 0 {
+            // Just a `<` in the text, like `a < b`
+            i = start + 1;
+            continue;
+        }
+        let name = &text[name_start..name_start + name_len];
+
+        // Find the `>` that ends the tag, ignoring any in quoted attributes.
+        // A tag that's still being typed ends wherever the next one starts.
+        let mut end = text.len();
+        let mut quote = None;
+        for (offset, ch) in text[name_start + name_len..].char_indices() {
+            let at = name_start + name_len + offset;
+            match (quote, ch) {
+                (None, '"') | (None, '\'') => quote = Some(ch),
+                (Some(q), ch) if q == ch => quote = None,
+                (None, '>') => {
+                    end = at + 1;
+                    break;
+                }
+                (None, '<') => {
+                    end = at;
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        if kind == TagKind::Open
+            && (text[..end].trim_end_matches('>').ends_with('/') || markup.is_void(name))
+        {
+            kind = TagKind::SelfClosing;
+        }
+
+        tags.push(Tag {
+            name: name.to_string(),
+            kind,
+            start,
+            end,
+            name_start,
+        });
+        i = end;
+
+        // Scripts and styles can contain `<`, so jump straight to their closing tag
+        if kind == TagKind::Open && markup.is_raw_text(name) {
+            let closing = format!("</{}", name.to_ascii_lowercase());
+            if let Some(found) = text[i..].to_ascii_lowercase().find(&closing) {
+                i += found;
+            }
+        }
+    }
+
+    tags
+}
+
+/// Find the innermost tag that is still open at a byte offset
+pub fn unclosed_tag(tags: &[Tag], offset: usize, markup: Markup) -> Option<&Tag> {
+    let mut open: Vec<&Tag> = vec![];
+    for tag in tags.iter().take_while(|tag| tag.end <= offset) {
+        match tag.kind {
+            TagKind::Open => open.push(tag),
+            TagKind::Close => {
+                // Closing a tag also closes any unclosed tags inside of it
+                if let Some(i) = open
+                    .iter()
+                    .rposition(|open| markup.same_name(&open.name, &tag.name))
+                {
+                    open.truncate(i);
+                }
+            }
+            TagKind::SelfClosing => {}
+        }
+    }
+    open.pop()
+}
+
+/// Find the tag that closes an opening tag, or that opens a closing tag
+pub fn matching_tag(tags: &[Tag], index: usize, markup: Markup) -> Option<&Tag> {
+    let tag = tags.get(index)?;
+    let others: Vec<&Tag> = match tag.kind {
+        TagKind::Open => tags[index..].iter().collect(),
+        TagKind::Close => tags[..=index].iter().rev().collect(),
+        TagKind::SelfClosing => return None,
+    };
+
+    // Count tags with the same name, so that nested ones are skipped
+    let mut depth = 0;
+    for other in others {
+        if !markup.same_name(&other.name, &tag.name) {
+            continue;
+        }
+        if other.kind == tag.kind {
+            depth += 1;
+        } else if other.kind != TagKind::SelfClosing {
+            depth -= 1;
+            if depth == 0 {
+                return Some(other);
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,207 @@
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::languages::Language;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{create_dir, remove_dir_all};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Wraps `sys.stdout` so that everything printed is tagged with the line of
+/// the buffer that printed it. The code to run is read from standard input.
+const PYTHON_PRELUDE: &str = r#"
+import sys, traceback
+source = sys.stdin.read()
+out = sys.stdout
+def emit(kind, line, text):
+    text = text.replace('\\', '\\\\').replace('\n', '\\n')
+    out.write('%s\t%d\t%s\n' % (kind, line, text))
+class Lens:
+    def write(self, text):
+        frame = sys._getframe(1)
+        while frame and frame.f_code.co_filename != '<buffer>':
+            frame = frame.f_back
+        emit('out', frame.f_lineno if frame else 0, text)
+        return len(text)
+    def flush(self):
+        pass
+sys.stdout = sys.stderr = Lens()
+try:
+    exec(compile(source, '<buffer>', 'exec'), {'__name__': '__main__'})
+except SyntaxError as e:
+    emit('err', e.lineno or 0, '%s: %s' % (type(e).__name__, e.msg))
+except BaseException as e:
+    lines = [f.lineno for f in traceback.extract_tb(e.__traceback__) if f.filename == '<buffer>']
+    emit('err', lines[-1] if lines else 0, '%s: %s' % (type(e).__name__, e))
+"#;
+
+/// Replaces `console.log` and friends, and tags what they print with the line
+/// of the buffer that called them, from the stack trace
+const NODE_PRELUDE: &str = r#"
+const util = require('util');
+const source = require('fs').readFileSync(0, 'utf8');
+const lineOf = stack => { const m = /buffer\.js:(\d+)/.exec(stack || ''); return m ? m[1] : 0; };
+const emit = (kind, line, text) =>
+    process.stdout.write(kind + '\t' + line + '\t' + text.replace(/\\/g, '\\\\').replace(/\n/g, '\\n') + '\n');
+const show = (...args) => emit('out', lineOf(new Error().stack), util.format(...args) + '\n');
+console.log = console.info = console.warn = console.error = console.debug = show;
+process.on('uncaughtException', e => emit('err', lineOf(e && e.stack), String(e)));
+try {
+    require('vm').runInThisContext(source, { filename: 'buffer.js' });
+} catch (e) {
+    emit('err', lineOf(e && e.stack), String(e));
+}
+"#;
+
+/// A language that a buffer can be run in, with its printed output shown
+/// next to the lines that printed it, like a notebook
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpreter {
+    Python,
+    JavaScript,
+}
+
+impl Interpreter {
+    /// The interpreter for a file, from its extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        match Language::from_path(path)? {
+            Language::Python => Some(Self::Python),
+            Language::JavaScript => Some(Self::JavaScript),
+            _ => None,
+        }
+    }
+
+    fn command(&self) -> Command {
+        match self {
+            Self::Python => {
+                // Isolated mode ignores the environment and the user's site packages
+                let mut command = Command::new("python3");
+                command.args(["-I", "-c", PYTHON_PRELUDE]);
+                command
+            }
+            Self::JavaScript => {
+                let mut command = Command::new("node");
+                command.args(["-e", NODE_PRELUDE]);
+                command
+            }
+        }
+    }
+
+    /// Run some code, where `first_line` is the line it starts on in the file,
+    /// and return what each line printed. Errors become error diagnostics.
+    ///
+    /// The interpreter runs in a new empty temporary directory with an empty
+    /// environment and no input, and is killed if it runs for too long. That
+    /// keeps it from depending on where the editor was started, but it isn't
+    /// a sandbox: the code can still read and write anything the user can.
+    pub fn run(
+        &self,
+        code: &str,
+        first_line: usize,
+        timeout: Duration,
+    ) -> io::Result<Vec<Diagnostic>> {
+        let dir = new_temp_dir()?;
+        let result = self.run_in(&dir, code, timeout);
+        let _ = remove_dir_all(&dir);
+        Ok(parse_output(&result?, first_line))
+    }
+
+    /// Run some code in a directory, and return its tagged output
+    fn run_in(&self, dir: &Path, code: &str, timeout: Duration) -> io::Result<String> {
+        let mut child = self
+            .command()
+            .current_dir(dir)
+            .env_clear()
+            .env("PATH", env::var_os("PATH").unwrap_or_default())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let code = code.to_string();
+        let writer = thread::spawn(move || stdin.write_all(code.as_bytes()));
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let reader = thread::spawn(move || {
+            let mut output = String::new();
+            stdout.read_to_string(&mut output).map(|_| output)
+        });
+
+        let started = Instant::now();
+        while child.try_wait()?.is_none() {
+            if started.elapsed() > timeout {
+                child.kill()?;
+                child.wait()?;
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("took longer than {:?}", timeout),
+                ));
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        let _ = writer.join();
+        reader.join().expect("reader doesn't panic")
+    }
+}
+
+/// Make an empty directory that no other run uses, even one running at the same time
+fn new_temp_dir() -> io::Result<PathBuf> {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let run = RUNS.fetch_add(1, Ordering::Relaxed);
+        let name = format!("editor-notebook-{}-{}", process::id(), run);
+        let dir = env::temp_dir().join(name);
+        match create_dir(&dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => return result.map(|()| dir),
+        }
+    }
+}
+
+/// Turn the tagged output of a prelude into diagnostics, one for each line
+fn parse_output(output: &str, first_line: usize) -> Vec<Diagnostic> {
+    let mut printed: BTreeMap<usize, String> = BTreeMap::new();
+    let mut errors = Vec::new();
+    for record in output.lines() {
+        let mut fields = record.splitn(3, '\t');
+        let (kind, line, text) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(kind), Some(line), Some(text)) => (kind, line, unescape(text)),
+            _ => continue,
+        };
+        // Line 0 is output from outside the buffer, which goes on its first line
+        let line = line.parse::<usize>().unwrap_or(0).max(1) + first_line - 1;
+        match kind {
+            "out" => printed.entry(line).or_default().push_str(&text),
+            "err" => errors.push(Diagnostic::new(line, 1, Severity::Error, text)),
+            _ => {}
+        }
+    }
+
+    let mut diagnostics = errors;
+    for (line, text) in printed {
+        // Lines that print several times, like in a loop, show everything they printed
+        let text = text.trim_end().replace('\n', " | ");
+        diagnostics.push(Diagnostic::new(line, 1, Severity::Hint, text));
+    }
+    diagnostics
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            result.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some(escaped) => result.push(escaped),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
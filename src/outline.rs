@@ -0,0 +1,222 @@
+use crate::languages::Language;
+use regex::Regex;
+
+/// A function, type or heading in a file, as listed in its outline
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    /// What kind of symbol this is, like `fn` or `class`
+    pub kind: &'static str,
+    /// The line it's on, counting from 1
+    pub line: usize,
+    /// How far it's nested, like a method in a class or a subheading
+    pub depth: usize,
+}
+
+/// Patterns that find the symbols of a language, each with the kind of symbol
+/// it finds. The name of the symbol is the `name` group of the pattern.
+pub struct Outliner {
+    patterns: Vec<(Regex, &'static str)>,
+}
+
+const RUST: &[(&str, &str)] = &[
+    (
+        r#"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:(?:const|async|unsafe|extern(?:\s+"[^"]*")?)\s+)*fn\s+(?P<name>\w+)"#,
+        "fn",
+    ),
+    (
+        r"^\s*(?:pub(?:\([^)]*\))?\s+)?struct\s+(?P<name>\w+)",
+        "struct",
+    ),
+    (r"^\s*(?:pub(?:\([^)]*\))?\s+)?enum\s+(?P<name>\w+)", "enum"),
+    (
+        r"^\s*(?:pub(?:\([^)]*\))?\s+)?union\s+(?P<name>\w+)",
+        "union",
+    ),
+    (
+        r"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:unsafe\s+)?trait\s+(?P<name>\w+)",
+        "trait",
+    ),
+    (r"^\s*(?:pub(?:\([^)]*\))?\s+)?type\s+(?P<name>\w+)", "type"),
+    (
+        r"^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+(?P<name>\w+)\s*\{",
+        "mod",
+    ),
+    (
+        r"^\s*(?:unsafe\s+)?impl(?:<[^{]*?>)?\s+(?P<name>[^{]+?)\s*(?:where\b.*)?\{?\s*$",
+        "impl",
+    ),
+    (r"^\s*macro_rules!\s*(?P<name>\w+)", "macro"),
+];
+
+const PYTHON: &[(&str, &str)] = &[
+    (r"^\s*(?:async\s+)?def\s+(?P<name>\w+)", "def"),
+    (r"^\s*class\s+(?P<name>\w+)", "class"),
+];
+
+const JAVASCRIPT: &[(&str, &str)] = &[
+    (
+        r"^\s*(?:export\s+)?(?:default\s+)?(?:async\s+)?function\s*\*?\s*(?P<name>[\w$]+)",
+        "function",
+    ),
+    (
+        r"^\s*(?:export\s+)?(?:default\s+)?(?:abstract\s+)?class\s+(?P<name>[\w$]+)",
+        "class",
+    ),
+    (
+        r"^\s*(?:export\s+)?(?:const|let|var)\s+(?P<name>[\w$]+)\s*=\s*(?:async\s+)?(?:function\b|(?:\([^)]*\)|[\w$]+)\s*=>)",
+        "function",
+    ),
+    (
+        r"^\s*(?:export\s+)?interface\s+(?P<name>[\w$]+)",
+        "interface",
+    ),
+    (
+        r"^\s*(?:export\s+)?type\s+(?P<name>[\w$]+)\s*(?:<[^=]*>)?\s*=",
+        "type",
+    ),
+    (
+        r"^\s*(?:export\s+)?(?:const\s+)?enum\s+(?P<name>[\w$]+)",
+        "enum",
+    ),
+];
+
+const GO: &[(&str, &str)] = &[
+    (r"^func\s+(?:\([^)]*\)\s*)?(?P<name>\w+)", "func"),
+    (r"^type\s+(?P<name>\w+)", "type"),
+];
+
+const C: &[(&str, &str)] = &[
+    (
+        r"^\s*(?:typedef\s+)?struct\s+(?P<name>\w+)\s*\{?\s*$",
+        "struct",
+    ),
+    (
+        r"^\s*(?:typedef\s+)?enum\s+(?:class\s+)?(?P<name>\w+)\s*(?::[^{]*)?\{?\s*$",
+        "enum",
+    ),
+    (
+        r"^\s*(?:template\s*<.*>\s*)?class\s+(?P<name>\w+)\s*(?::[^;]*)?\{?\s*$",
+        "class",
+    ),
+    (r"^\s*namespace\s+(?P<name>\w+)", "namespace"),
+    // A function definition starts at the start of a line, and doesn't end with `;`
+    (
+        r"^[A-Za-z_][\w\s\*&:<>,]*?[\s\*&](?P<name>[A-Za-z_~][\w:~]*)\s*\([^;]*$",
+        "function",
+    ),
+];
+
+const JAVA: &[(&str, &str)] = &[
+    (
+        r"^\s*(?:(?:public|protected|private|static|final|abstract|sealed|data|open|internal)\s+)*(?:class|interface|enum|record|object)\s+(?P<name>\w+)",
+        "class",
+    ),
+    (
+        r"^\s+(?:(?:public|protected|private|static|final|abstract|synchronized|native|override)\s+)+[\w<>\[\],\s]*?\b(?P<name>\w+)\s*\([^;]*$",
+        "method",
+    ),
+    (
+        r"^\s*(?:(?:public|private|protected|internal|override|suspend)\s+)*fun\s+(?:<[^>]*>\s*)?(?:\w+\.)?(?P<name>\w+)",
+        "fun",
+    ),
+];
+
+const RUBY: &[(&str, &str)] = &[
+    (r"^\s*def\s+(?P<name>(?:self\.)?[\w?!=]+)", "def"),
+    (r"^\s*class\s+(?P<name>[\w:]+)", "class"),
+    (r"^\s*module\s+(?P<name>[\w:]+)", "module"),
+];
+
+const SHELL: &[(&str, &str)] = &[
+    (r"^\s*(?:function\s+)?(?P<name>[\w-]+)\s*\(\)", "function"),
+    (r"^\s*function\s+(?P<name>[\w-]+)", "function"),
+];
+
+const LUA: &[(&str, &str)] = &[(r"^\s*(?:local\s+)?function\s+(?P<name>[\w.:]+)", "function")];
+
+impl Outliner {
+    /// Find the patterns for a file's language from its name
+    pub fn from_path(path: &str) -> Option<Self> {
+        let patterns = match Language::from_path(path)? {
+            Language::Markdown => return Some(Self::headings()),
+            Language::Rust => RUST,
+            Language::Python => PYTHON,
+            Language::JavaScript | Language::TypeScript => JAVASCRIPT,
+            Language::Go => GO,
+            Language::C | Language::Cpp => C,
+            Language::Java | Language::Kotlin | Language::Scala | Language::CSharp => JAVA,
+            Language::Ruby => RUBY,
+            Language::Sh | Language::Bash | Language::Zsh => SHELL,
+            Language::Lua => LUA,
+            _ => return None,
+        };
+        Some(Self {
+            patterns: patterns
+                .iter()
+                .map(|&(pattern, kind)| (Regex::new(pattern).unwrap(), kind))
+                .collect(),
+        })
+    }
+
+    /// The headings of a Markdown file, nested by their level
+    fn headings() -> Self {
+        Self {
+            patterns: vec![(
+                Regex::new(r"^(?P<level>#{1,6})\s+(?P<name>.*?)[\s#]*$").unwrap(),
+                "#",
+            )],
+        }
+    }
+
+    /// The symbols in a file's lines, in the order they're defined.
+    /// Symbols are nested by how far their lines are indented.
+    pub fn outline(&self, lines: &[String]) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        // The indentation of the symbols that are still open, innermost last
+        let mut open: Vec<usize> = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            let found = self.patterns.iter().find_map(|(pattern, kind)| {
+                let captures = pattern.captures(line)?;
+                let name = captures.name("name")?.as_str().trim();
+                let indent = match captures.name("level") {
+                    Some(level) => level.as_str().len(),
+                    None => indentation(line),
+                };
+                Some((name, *kind, indent))
+            });
+            let (name, kind, indent) = match found {
+                Some(found) if !name_is_keyword(found.0) => found,
+                _ => continue,
+            };
+            while open.last().is_some_and(|&outer| outer >= indent) {
+                open.pop();
+            }
+            entries.push(Entry {
+                name: name.to_string(),
+                kind,
+                line: i + 1,
+                depth: open.len(),
+            });
+            open.push(indent);
+        }
+        entries
+    }
+}
+
+/// How far a line is indented, with tabs counting as 4 spaces
+fn indentation(line: &str) -> usize {
+    line.chars()
+        .take_while(|ch| ch.is_whitespace())
+        .map(|ch| if ch == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+/// Whether a name a loose pattern found is really a statement, like the
+/// `if` in `if (x) {`, which looks like a C function
+fn name_is_keyword(name: &str) -> bool {
+    matches!(
+        name,
+        "if" | "else" | "for" | "while" | "switch" | "return" | "catch" | "sizeof" | "do"
+    )
+}
@@ -0,0 +1,75 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long a command gets to finish before it's killed
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often a running command is checked on
+const POLL: Duration = Duration::from_millis(10);
+
+/// Runs `command` with `input` written to its standard input, and returns
+/// everything it printed to standard output.
+///
+/// The input is written from a separate thread so that a command that
+/// produces a lot of output before it finishes reading can't deadlock us.
+/// If the command fails, its standard error becomes the error message.
+/// A command that hasn't finished after `TIMEOUT` is killed, so one that
+/// hangs, like a formatter waiting for a prompt to be answered, can't
+/// freeze the editor.
+pub fn pipe_through(command: &mut Command, input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+    let stdout = read_all(child.stdout.take().expect("stdout is piped"));
+    let stderr = read_all(child.stderr.take().expect("stderr is piped"));
+
+    let deadline = Instant::now() + TIMEOUT;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("command took longer than {} seconds", TIMEOUT.as_secs()),
+            ));
+        }
+        thread::sleep(POLL);
+    };
+    // A command is allowed to stop reading its input early,
+    // so a broken pipe here is not an error by itself.
+    let _ = writer.join();
+    let stdout = stdout.join().expect("the reader doesn't panic")?;
+    let stderr = stderr.join().expect("the reader doesn't panic")?;
+
+    if status.success() {
+        Ok(stdout)
+    } else {
+        let stderr = String::from_utf8_lossy(&stderr).trim().to_string();
+        let message = if stderr.is_empty() {
+            format!("command exited with {}", status)
+        } else {
+            stderr
+        };
+        Err(io::Error::other(message))
+    }
+}
+
+/// Read everything from a pipe on another thread
+fn read_all<R: Read + Send + 'static>(mut pipe: R) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut bytes = Vec::new();
+        pipe.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    })
+}
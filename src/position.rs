@@ -0,0 +1,187 @@
+use std::cmp::{max, min};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Converts between the ways a place in some lines can be given: byte
+/// offsets, rows and columns of graphemes, and cells on screen. Rows and
+/// columns count from 0, and rows past the first or last one are clamped
+/// to it. Rows aren't wrapped, so each row takes up one row of cells.
+#[derive(Clone, Copy, Debug)]
+pub struct PositionMapper<'a> {
+    lines: &'a [String],
+    tab_width: usize,
+    /// The cells left of the text, like the line numbers
+    gutter_width: usize,
+    /// The first row shown, and how many cells long rows are scrolled sideways
+    scroll: (usize, usize),
+}
+
+impl<'a> PositionMapper<'a> {
+    /// Map places in some lines, which can't be empty, with tabs stretching
+    /// to a multiple of `tab_width`
+    pub fn new(lines: &'a [String], tab_width: usize) -> Self {
+        Self {
+            lines,
+            tab_width: max(tab_width, 1),
+            gutter_width: 0,
+            scroll: (0, 0),
+        }
+    }
+
+    /// Place the lines on screen after a gutter, scrolled down to a row
+    /// and sideways by some cells
+    pub fn with_view(mut self, gutter_width: usize, scroll: (usize, usize)) -> Self {
+        self.gutter_width = gutter_width;
+        self.scroll = scroll;
+        self
+    }
+
+    /// A row, clamped to the first or last one
+    pub fn line(&self, row: i32) -> &'a str {
+        &self.lines[min(max(row, 0), self.lines.len() as i32 - 1) as usize]
+    }
+
+    /// The number of graphemes in a row
+    pub fn row_len(&self, row: i32) -> i32 {
+        self.line(row).graphemes(true).count() as i32
+    }
+
+    /// The byte offset of the grapheme at column `col` of a row.
+    /// Columns past the end of the row map to the end of the row.
+    pub fn byte_offset(&self, row: i32, col: i32) -> usize {
+        let line = self.line(row);
+        line.grapheme_indices(true)
+            .nth(max(col, 0) as usize)
+            .map(|(i, _)| i)
+            .unwrap_or_else(|| line.len())
+    }
+
+    /// The column of the grapheme that starts at a byte offset of a row
+    pub fn col_at_byte(&self, row: i32, byte: usize) -> i32 {
+        self.line(row)[..byte].graphemes(true).count() as i32
+    }
+
+    /// The byte offset of a position in the whole text, where rows are joined by newlines
+    pub fn text_offset(&self, row: i32, col: i32) -> usize {
+        let row = min(max(row, 0), self.lines.len() as i32 - 1);
+        self.lines[..row as usize]
+            .iter()
+            .map(|line| line.len() + 1)
+            .sum::<usize>()
+            + self.byte_offset(row, col)
+    }
+
+    /// The row and byte offset in that row of a byte offset in the whole text
+    pub fn text_position(&self, offset: usize) -> (i32, usize) {
+        let mut start = 0;
+        for (row, line) in self.lines.iter().enumerate() {
+            if offset <= start + line.len() {
+                return (row as i32, offset - start);
+            }
+            start += line.len() + 1;
+        }
+        let last = self.lines.len() - 1;
+        (last as i32, self.lines[last].len())
+    }
+
+    /// The number of cells a grapheme takes up when it's drawn at cell `x` of a row.
+    /// Tabs stretch to the next tab stop, and wide characters take up two cells.
+    pub fn grapheme_width(&self, g: &str, x: usize) -> usize {
+        if g == "\t" {
+            self.tab_width - x % self.tab_width
+        } else {
+            g.width()
+        }
+    }
+
+    /// The number of cells taken up by the first `col` graphemes of a row
+    pub fn display_col(&self, row: i32, col: i32) -> usize {
+        let mut x = 0;
+        for g in self.line(row).graphemes(true).take(max(col, 0) as usize) {
+            x += self.grapheme_width(g, x);
+        }
+        x
+    }
+
+    /// The column of the grapheme drawn at cell `x` of a row
+    pub fn col_at_display(&self, row: i32, x: usize) -> i32 {
+        let mut width = 0;
+        for (col, g) in self.line(row).graphemes(true).enumerate() {
+            width += self.grapheme_width(g, width);
+            if width > x {
+                return col as i32;
+            }
+        }
+        self.row_len(row)
+    }
+
+    /// The columns a row is broken at to fit in rows of `width` cells, like
+    /// soft wrap does, starting with 0. Rows break after the last space that
+    /// fits, or in the middle of a word that's too long to fit on its own.
+    pub fn wrap_points(&self, row: i32, width: usize) -> Vec<i32> {
+        let mut points = vec![0];
+        if width == 0 {
+            return points;
+        }
+        // Where the current piece starts on screen, and where it could
+        // break after its last space
+        let (mut start_x, mut x) = (0, 0);
+        let mut after_space = None;
+        for (col, g) in self.line(row).graphemes(true).enumerate() {
+            let col = col as i32;
+            let g_width = self.grapheme_width(g, x);
+            while x + g_width - start_x > width && col > points[points.len() - 1] {
+                let (point, point_x) = after_space.take().unwrap_or((col, x));
+                points.push(point);
+                start_x = point_x;
+            }
+            x += g_width;
+            if g.chars().all(char::is_whitespace) {
+                after_space = Some((col + 1, x));
+            }
+        }
+        points
+    }
+
+    /// The cell on screen a position is drawn at, counting from the corner
+    /// of the view, or nothing if it's scrolled out of view above or left
+    pub fn cell(&self, row: i32, col: i32) -> Option<(usize, usize)> {
+        let (first_row, scroll_x) = self.scroll;
+        let y = (max(row, 0) as usize).checked_sub(first_row)?;
+        let x = self.display_col(row, col).checked_sub(scroll_x)?;
+        Some((self.gutter_width + x, y))
+    }
+
+    /// The position drawn at a cell on screen, counting from the corner of
+    /// the view, or nothing if the cell is in the gutter or below the last row
+    pub fn position_at_cell(&self, x: usize, y: usize) -> Option<(i32, i32)> {
+        let (first_row, scroll_x) = self.scroll;
+        let x = x.checked_sub(self.gutter_width)? + scroll_x;
+        let row = first_row + y;
+        if row >= self.lines.len() {
+            return None;
+        }
+        let row = row as i32;
+        Some((row, self.col_at_display(row, x)))
+    }
+
+    /// The number of UTF-16 code units before a column of a row,
+    /// which is how language servers count columns
+    pub fn utf16_col(&self, row: i32, col: i32) -> usize {
+        let byte = self.byte_offset(row, col);
+        self.line(row)[..byte].encode_utf16().count()
+    }
+
+    /// The column of a row after a number of UTF-16 code units. Units in
+    /// the middle of a grapheme map to the grapheme they're in.
+    pub fn col_at_utf16(&self, row: i32, units: usize) -> i32 {
+        let mut counted = 0;
+        for (col, g) in self.line(row).graphemes(true).enumerate() {
+            counted += g.encode_utf16().count();
+            if counted > units {
+                return col as i32;
+            }
+        }
+        self.row_len(row)
+    }
+}
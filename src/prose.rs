@@ -0,0 +1,51 @@
+/// Where each sentence in some lines starts, as rows and byte offsets.
+/// A sentence ends with `.`, `!` or `?`, and any closing quotes or brackets,
+/// followed by a space or the end of the line. Paragraphs, which are
+/// separated by blank lines, always start a new sentence.
+pub fn sentence_starts(lines: &[impl AsRef<str>]) -> Vec<(usize, usize)> {
+    let mut starts = Vec::new();
+    let mut at_start = true;
+    for (row, line) in lines.iter().enumerate() {
+        let line = line.as_ref();
+        if line.trim().is_empty() {
+            at_start = true;
+            continue;
+        }
+        // Whether the text so far ends a sentence, if a space comes next
+        let mut ending = false;
+        for (i, ch) in line.char_indices() {
+            if ch.is_whitespace() {
+                at_start |= ending;
+                ending = false;
+            } else if at_start {
+                starts.push((row, i));
+                at_start = false;
+                ending = is_sentence_end(ch);
+            } else if is_sentence_end(ch) {
+                ending = true;
+            } else if !(ending && is_closing(ch)) {
+                ending = false;
+            }
+        }
+        at_start |= ending;
+    }
+    starts
+}
+
+/// The rows each paragraph starts on, which are the lines with text
+/// after a blank line or at the start
+pub fn paragraph_starts(lines: &[impl AsRef<str>]) -> Vec<usize> {
+    let blank = |row: usize| lines[row].as_ref().trim().is_empty();
+    (0..lines.len())
+        .filter(|&row| !blank(row) && (row == 0 || blank(row - 1)))
+        .collect()
+}
+
+fn is_sentence_end(ch: char) -> bool {
+    matches!(ch, '.' | '!' | '?')
+}
+
+/// Whether a character can come after the end of a sentence, like a closing quote
+fn is_closing(ch: char) -> bool {
+    matches!(ch, '"' | '\'' | ')' | ']' | '”' | '’' | '»')
+}
@@ -0,0 +1,39 @@
+use crate::formatter::Formatter;
+use std::collections::HashMap;
+
+/// Settings for the files with an extension, from its `[languages.*]` table
+/// in the config, which take the place of the settings for every file.
+/// `prose = true` starts from the settings for writing prose, which Markdown
+/// and text files use unless `prose = false`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LanguageSettings {
+    pub tab_width: Option<usize>,
+    pub hard_tabs: Option<bool>,
+    pub auto_pairs: Option<bool>,
+    pub typewriter: Option<bool>,
+    pub soft_wrap: Option<bool>,
+    /// Whether to underline the words the spell checker doesn't know
+    pub spell_check: Option<bool>,
+    /// The formatter to use instead of the usual one.
+    /// `Some(None)` means the files aren't formatted.
+    pub formatter: Option<Option<Formatter>>,
+    /// The command that runs the files, where `{file}` stands for the name
+    pub run: Option<String>,
+    /// Snippets by the word that expands them
+    pub snippets: HashMap<String, String>,
+}
+
+impl LanguageSettings {
+    /// The settings for writing prose. Quotes aren't paired, since
+    /// apostrophes are typed on their own, and the spelling is checked.
+    /// Paragraphs are soft wrapped rather than broken into lines as they're
+    /// typed, so they stay one line each in the file.
+    pub fn prose() -> Self {
+        Self {
+            auto_pairs: Some(false),
+            soft_wrap: Some(true),
+            spell_check: Some(true),
+            ..Self::default()
+        }
+    }
+}
@@ -0,0 +1,49 @@
+/// The text a snippet expands to
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expansion {
+    pub text: String,
+    /// Byte offsets in `text` of the places the cursor jumps to, in order:
+    /// `$1`, `$2` and so on, and then `$0`, which is the end if there isn't one
+    pub stops: Vec<usize>,
+}
+
+/// Expand a snippet's body, like `fn $1($2) {\n\t$0\n}`. Every line after the
+/// first starts with `indent`, tabs in the body become `tab`, and `$$` is a `$`.
+pub fn expand_snippet(body: &str, indent: &str, tab: &str) -> Expansion {
+    let mut text = String::new();
+    let mut numbered: Vec<(usize, usize)> = Vec::new();
+    let mut chars = body.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '$' if chars.peek() == Some(&'$') => {
+                chars.next();
+                text.push('$');
+            }
+            '$' if chars.peek().is_some_and(|ch| ch.is_ascii_digit()) => {
+                let mut number = 0;
+                while let Some(digit) = chars.peek().and_then(|ch| ch.to_digit(10)) {
+                    number = number * 10 + digit as usize;
+                    chars.next();
+                }
+                // A stop that's used twice is only jumped to the first time
+                if numbered.iter().all(|&(n, _)| n != number) {
+                    numbered.push((number, text.len()));
+                }
+            }
+            '\n' => {
+                text.push('\n');
+                text.push_str(indent);
+            }
+            '\t' => text.push_str(tab),
+            ch => text.push(ch),
+        }
+    }
+
+    // `$0` comes last
+    numbered.sort_by_key(|&(number, _)| if number == 0 { usize::MAX } else { number });
+    let mut stops: Vec<usize> = numbered.iter().map(|&(_, offset)| offset).collect();
+    if numbered.iter().all(|&(number, _)| number != 0) {
+        stops.push(text.len());
+    }
+    Expansion { text, stops }
+}
@@ -0,0 +1,29 @@
+use crate::pipe::pipe_through;
+use std::collections::HashSet;
+use std::io;
+use std::process::Command;
+
+/// The spell checkers that can be used, with the arguments that make them
+/// list the words they don't know, in the order they're tried
+const CHECKERS: [(&str, &[&str]); 2] = [("aspell", &["list"]), ("hunspell", &["-l"])];
+
+/// The words in some text that the spell checker doesn't know, using
+/// `aspell` or `hunspell`, whichever is installed
+pub fn misspelled_words(text: &str) -> io::Result<HashSet<String>> {
+    for (program, args) in &CHECKERS {
+        let mut command = Command::new(program);
+        command.args(*args);
+        match pipe_through(&mut command, text.as_bytes()) {
+            Ok(output) => {
+                let words = String::from_utf8_lossy(&output);
+                return Ok(words.lines().map(String::from).collect());
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "install aspell or hunspell to check spelling",
+    ))
+}
@@ -0,0 +1,90 @@
+use std::fs::read_to_string;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where a symbol is defined in its file
+#[derive(Clone, Debug, PartialEq)]
+pub enum Location {
+    /// A line number, counting from 1
+    Line(usize),
+    /// The text of the line the symbol is defined on
+    Pattern(String),
+}
+
+/// A symbol from a tags file, like the ones generated by `ctags`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    /// The path of the file that defines the symbol
+    pub file: PathBuf,
+    pub location: Location,
+    /// What kind of symbol this is, like `f` for a function, if the tags file says
+    pub kind: Option<String>,
+}
+
+impl Symbol {
+    /// Find the line the symbol is defined on, counting from 1
+    pub fn line(&self) -> io::Result<usize> {
+        match &self.location {
+            Location::Line(line) => Ok(*line),
+            Location::Pattern(pattern) => read_to_string(&self.file)?
+                .lines()
+                .position(|line| line == pattern)
+                .map(|i| i + 1)
+                .ok_or_else(|| io::Error::other(format!("{} is no longer defined", self.name))),
+        }
+    }
+}
+
+/// Find the `tags` file for a directory, in the directory or one of its parents
+pub fn find_tags_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join("tags"))
+        .find(|path| path.is_file())
+}
+
+/// Read the symbols from a tags file. Files are relative to the tags file.
+pub fn read_tags(path: &Path) -> io::Result<Vec<Symbol>> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    Ok(read_to_string(path)?
+        .lines()
+        .filter(|line| !line.starts_with("!_TAG_"))
+        .filter_map(|line| parse_tag(line, dir))
+        .collect())
+}
+
+/// Parse a line like `main\tsrc/main.rs\t/^fn main() {$/;"\tf`
+fn parse_tag(line: &str, dir: &Path) -> Option<Symbol> {
+    let mut fields = line.splitn(3, '\t');
+    let name = fields.next()?;
+    let file = fields.next()?;
+    let rest = fields.next()?;
+
+    // The address can contain tabs, so it ends at the `;"` before the extra fields
+    let (address, extra) = match rest.find(";\"") {
+        Some(end) => (&rest[..end], &rest[end + 2..]),
+        None => (rest, ""),
+    };
+    let location = if let Ok(line) = address.parse() {
+        Location::Line(line)
+    } else {
+        let pattern = address
+            .trim_start_matches(&['/', '?'][..])
+            .trim_end_matches(&['/', '?'][..]);
+        let pattern = pattern.strip_prefix('^').unwrap_or(pattern);
+        let pattern = pattern.strip_suffix('$').unwrap_or(pattern);
+        Location::Pattern(pattern.replace("\\/", "/").replace("\\\\", "\\"))
+    };
+    let kind = extra
+        .split('\t')
+        .map(|field| field.trim_start_matches("kind:"))
+        .find(|field| !field.is_empty() && !field.contains(':'))
+        .map(String::from);
+
+    Some(Symbol {
+        name: name.to_string(),
+        file: dir.join(file),
+        location,
+        kind,
+    })
+}
@@ -0,0 +1,294 @@
+use crate::json::Json;
+use crate::languages::{file_dir, Language};
+use std::fs::read_to_string;
+use std::io::{self, BufRead, BufReader, Read};
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+use toml::Value;
+
+/// The name of the project config file, in the root of a project
+pub const PROJECT_CONFIG: &str = ".editor.toml";
+
+/// How often a running task is checked on
+const POLL: Duration = Duration::from_millis(50);
+
+/// A shell command that can be run in a project, like `cargo build`.
+/// Tasks are defined in the project config:
+///
+/// ```toml
+/// [tasks]
+/// build = "cargo build"
+/// test = "cargo test"
+/// lint = "cargo clippy -- -D warnings"
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Task {
+    pub name: String,
+    pub command: String,
+    /// The directory the command runs in
+    pub dir: PathBuf,
+}
+
+/// A task that was started. It's killed if it's still running when this is dropped.
+pub struct Running {
+    child: Arc<Mutex<Child>>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap_or_else(PoisonError::into_inner);
+        #[cfg(unix)]
+        let _ = Command::new("kill")
+            .args(["-KILL", "--", &format!("-{}", child.id())])
+            .stderr(Stdio::null())
+            .status();
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Something a running task did
+#[derive(Debug)]
+pub enum Output {
+    /// A line the task printed, to standard output or standard error
+    Line(String),
+    /// The task finished
+    Done(io::Result<ExitStatus>),
+}
+
+impl Task {
+    pub fn new(name: impl ToString, command: impl ToString, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.to_string(),
+            command: command.to_string(),
+            dir: dir.into(),
+        }
+    }
+
+    /// A task that runs a file with a command like `python3 {file}`, in the
+    /// file's directory. `{file}` stands for the name of the file.
+    pub fn for_file(command: &str, file: &Path) -> Self {
+        let dir = file_dir(file);
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let command = command.replace("{file}", &shell_quote(&name));
+        Self::new("run", command, dir)
+    }
+
+    /// Start running the task, and report its output as it's printed.
+    /// `on_output` is called from other threads, and gets `Output::Done` last.
+    pub fn run(&self, on_output: impl Fn(Output) + Send + Sync + 'static) -> io::Result<Running> {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(&self.command)
+            .current_dir(&self.dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        // The task gets a process group of its own,
+        // so the commands it starts can be killed with it
+        #[cfg(unix)]
+        command.process_group(0);
+        let mut child = command.spawn()?;
+
+        let on_output = Arc::new(on_output);
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+        let readers = vec![
+            forward_lines(stdout, on_output.clone()),
+            forward_lines(stderr, on_output.clone()),
+        ];
+        let child = Arc::new(Mutex::new(child));
+        let waited = child.clone();
+        thread::spawn(move || {
+            for reader in readers {
+                let _ = reader.join();
+            }
+            on_output(Output::Done(wait(&waited)));
+        });
+        Ok(Running { child })
+    }
+}
+
+/// Wait for a task to exit, without holding on to it,
+/// so that it can still be killed in the meantime
+fn wait(child: &Mutex<Child>) -> io::Result<ExitStatus> {
+    loop {
+        let mut child = child.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        drop(child);
+        thread::sleep(POLL);
+    }
+}
+
+/// Report each line read from a pipe until it closes
+fn forward_lines<F>(pipe: impl Read + Send + 'static, on_output: Arc<F>) -> thread::JoinHandle<()>
+where
+    F: Fn(Output) + Send + Sync + 'static,
+{
+    thread::spawn(move || {
+        for line in BufReader::new(pipe).split(b'\n') {
+            match line {
+                Ok(line) => on_output(Output::Line(
+                    String::from_utf8_lossy(&line)
+                        .trim_end_matches('\r')
+                        .to_string(),
+                )),
+                Err(_) => break,
+            }
+        }
+    })
+}
+
+/// The usual command that runs a file in its language, like `python3 {file}`
+/// for Python. `{file}` stands for the name of the file.
+pub fn default_run_command(file: &str) -> Option<&'static str> {
+    let command = match Language::from_path(file)? {
+        Language::Rust => "cargo run",
+        Language::Go => "go run {file}",
+        Language::Python => "python3 {file}",
+        Language::JavaScript => "node {file}",
+        Language::TypeScript => "npx tsx {file}",
+        Language::Ruby => "ruby {file}",
+        Language::Perl => "perl {file}",
+        Language::Php => "php {file}",
+        Language::Lua => "lua {file}",
+        Language::Julia => "julia {file}",
+        Language::Sh => "sh {file}",
+        Language::Bash => "bash {file}",
+        _ => return None,
+    };
+    Some(command)
+}
+
+/// Quote text so that the shell takes it as it is
+fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Find the project config for a directory, in the directory or one of its parents
+pub fn find_project_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG))
+        .find(|path| path.is_file())
+}
+
+/// Read the tasks from a project config. They run in the config's directory.
+pub fn read_tasks(path: &Path) -> io::Result<Vec<Task>> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let value: Value = read_to_string(path)?.parse().map_err(invalid)?;
+
+    let tasks = match value.get("tasks") {
+        Some(Value::Table(tasks)) => tasks,
+        Some(_) => return Err(invalid("`tasks` should be a table")),
+        None => return Ok(Vec::new()),
+    };
+    tasks
+        .iter()
+        .map(|(name, command)| match command.as_str() {
+            Some(command) => Ok(Task::new(name, command, dir)),
+            None => Err(invalid(format!("the `{}` task should be a command", name))),
+        })
+        .collect()
+}
+
+/// The tasks for the project a directory is in: the ones in its project config,
+/// followed by the standard targets of the build tools it uses
+pub fn project_tasks(dir: &Path) -> io::Result<Vec<Task>> {
+    let mut tasks = match find_project_config(dir) {
+        Some(path) => read_tasks(&path)?,
+        None => Vec::new(),
+    };
+    for task in detect_tasks(dir) {
+        if !tasks.iter().any(|other| other.command == task.command) {
+            tasks.push(task);
+        }
+    }
+    Ok(tasks)
+}
+
+/// Find the standard targets of the Cargo, Make, and npm projects a directory is in,
+/// in the nearest directory that has any of them
+pub fn detect_tasks(dir: &Path) -> Vec<Task> {
+    for dir in dir.ancestors() {
+        let mut tasks = Vec::new();
+        if dir.join("Cargo.toml").is_file() {
+            for target in &["build", "check", "test", "run", "clippy"] {
+                tasks.push(Task::new(target, format!("cargo {}", target), dir));
+            }
+        }
+        for makefile in &["GNUmakefile", "makefile", "Makefile"] {
+            if let Ok(text) = read_to_string(dir.join(makefile)) {
+                for target in make_targets(&text) {
+                    tasks.push(Task::new(target, format!("make {}", target), dir));
+                }
+                break;
+            }
+        }
+        if let Ok(text) = read_to_string(dir.join("package.json")) {
+            for script in npm_scripts(&text) {
+                tasks.push(Task::new(&script, format!("npm run {}", script), dir));
+            }
+        }
+        if !tasks.is_empty() {
+            return tasks;
+        }
+    }
+    Vec::new()
+}
+
+/// The explicit targets of a makefile, like `all` in `all: editor docs`
+fn make_targets(text: &str) -> Vec<&str> {
+    let mut targets = Vec::new();
+    for line in text.lines() {
+        // Recipes start with a tab, and `:=` and `::=` are assignments
+        if line.starts_with('\t') {
+            continue;
+        }
+        let colon = match line.find(':') {
+            Some(colon)
+                if !line[colon..].starts_with(":=") && !line[colon..].starts_with("::=") =>
+            {
+                colon
+            }
+            _ => continue,
+        };
+        if line[..colon].contains(&['=', '$', '#'][..]) {
+            continue;
+        }
+        for target in line[..colon].split_whitespace() {
+            // Skip special targets like `.PHONY` and pattern rules like `%.o`
+            if !target.starts_with('.') && !target.contains('%') && !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    targets
+}
+
+/// The names of the scripts in a `package.json`, which is the `scripts` object
+/// of the file's top-level object
+fn npm_scripts(text: &str) -> Vec<String> {
+    match Json::parse(text) {
+        Ok(package) => match package.get("scripts") {
+            Some(Json::Object(scripts)) => scripts
+                .iter()
+                .filter(|(_, command)| command.as_str().is_some())
+                .map(|(name, _)| name.clone())
+                .collect(),
+            _ => Vec::new(),
+        },
+        Err(_) => Vec::new(),
+    }
+}
+
+fn invalid(error: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
@@ -0,0 +1,521 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command};
+use std::thread;
+
+/// How many lines a terminal keeps after they scroll off the screen
+const SCROLLBACK: usize = 5000;
+
+/// The terminal programs are told they're running in. `Screen` follows
+/// the escape sequences a VT100 uses to move the cursor and clear the screen.
+const TERM: &str = "vt100";
+
+/// The user's shell, running in a pseudo-terminal of its own so it prompts,
+/// echoes and handles Ctrl+C like it does in a terminal.
+pub struct Shell {
+    child: Child,
+    /// The editor's end of the pseudo-terminal. What's written to it is
+    /// typed into the shell, and what the shell prints is read from it.
+    master: File,
+}
+
+impl Shell {
+    /// Start the shell in a directory, in a terminal with a number of
+    /// columns and rows. `on_output` is called from another thread with
+    /// what the shell prints, and with nothing when the shell exits.
+    pub fn spawn(
+        dir: &Path,
+        size: (usize, usize),
+        on_output: impl Fn(&[u8]) + Send + 'static,
+    ) -> io::Result<Self> {
+        let (master, slave) = pty::open(size)?;
+        let shell = std::env::var("SHELL").unwrap_or_else(|_| String::from("sh"));
+        let mut command = Command::new(shell);
+        command
+            .current_dir(dir)
+            .env("TERM", TERM)
+            .stdin(slave.try_clone()?)
+            .stdout(slave.try_clone()?)
+            .stderr(slave);
+        pty::make_controlling(&mut command);
+        let child = command.spawn()?;
+        // Only the shell keeps its end open, so reading stops when it exits
+        drop(command);
+
+        let mut output = master.try_clone()?;
+        thread::spawn(move || {
+            let mut buffer = [0; 4096];
+            loop {
+                match output.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => on_output(&buffer[..n]),
+                }
+            }
+            on_output(&[]);
+        });
+        Ok(Self { child, master })
+    }
+
+    /// Send keys to the shell, like they were typed
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.master.write_all(bytes)?;
+        self.master.flush()
+    }
+
+    /// Tell the shell, and the program running in it, that the terminal
+    /// has a new number of columns and rows
+    pub fn resize(&self, size: (usize, usize)) -> io::Result<()> {
+        pty::resize(&self.master, size)
+    }
+}
+
+impl Drop for Shell {
+    fn drop(&mut self) {
+        pty::hang_up(&self.master, &self.child);
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(unix)]
+mod pty {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::os::unix::process::CommandExt;
+    use std::process::{Child, Command};
+    use std::ptr;
+
+    fn window_size((cols, rows): (usize, usize)) -> libc::winsize {
+        libc::winsize {
+            ws_row: rows.clamp(1, u16::MAX as usize) as u16,
+            ws_col: cols.clamp(1, u16::MAX as usize) as u16,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
+    }
+
+    /// Open a pseudo-terminal of a size, and return its two ends: the one
+    /// the editor reads and writes, and the one the shell runs in
+    pub fn open(size: (usize, usize)) -> io::Result<(File, File)> {
+        let (mut master, mut slave) = (0, 0);
+        let mut size = window_size(size);
+        let opened = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                &mut size as *mut libc::winsize,
+            )
+        };
+        if opened < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe { Ok((File::from_raw_fd(master), File::from_raw_fd(slave))) }
+    }
+
+    /// Start the command in a session of its own, with its standard input
+    /// as its controlling terminal, so the terminal sends it signals like
+    /// the one for Ctrl+C
+    pub fn make_controlling(command: &mut Command) {
+        unsafe {
+            command.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    pub fn resize(master: &File, size: (usize, usize)) -> io::Result<()> {
+        let size = window_size(size);
+        if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ as _, &size) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Send a hang up to the shell and to the program running in the
+    /// terminal, like closing a terminal window does
+    pub fn hang_up(master: &File, shell: &Child) {
+        unsafe {
+            let foreground = libc::tcgetpgrp(master.as_raw_fd());
+            if foreground > 0 {
+                libc::kill(-foreground, libc::SIGHUP);
+            }
+            libc::kill(-(shell.id() as libc::pid_t), libc::SIGHUP);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod pty {
+    use std::fs::File;
+    use std::io;
+    use std::process::{Child, Command};
+
+    fn unsupported() -> io::Error {
+        io::Error::other("terminals are only supported on Unix")
+    }
+
+    pub fn open(_: (usize, usize)) -> io::Result<(File, File)> {
+        Err(unsupported())
+    }
+
+    pub fn make_controlling(_: &mut Command) {}
+
+    pub fn resize(_: &File, _: (usize, usize)) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn hang_up(_: &File, _: &Child) {}
+}
+
+/// What a terminal shows: the rows of the screen, below the lines that
+/// scrolled off it. The cursor moves around the screen with the escape
+/// sequences of a VT100. Colors and other escape sequences are left out.
+#[derive(Clone, Debug)]
+pub struct Screen {
+    /// The lines that scrolled off the screen, oldest first, and then the
+    /// rows of the screen. Rows below the last one written to aren't here yet.
+    lines: VecDeque<Vec<char>>,
+    cols: usize,
+    rows: usize,
+    /// The line in `lines` the cursor is on, and its column
+    cursor: (usize, usize),
+    /// The cursor saved with `ESC 7`, as a row of the screen and a column
+    saved: (usize, usize),
+    /// The first and last rows of the screen that scroll, if it's not all of them
+    region: Option<(usize, usize)>,
+    escape: Escape,
+    /// The start of a character that was cut off at the end of some output
+    partial: Vec<u8>,
+}
+
+/// How far into an escape sequence the output is
+#[derive(Clone, Debug, Default, PartialEq)]
+enum Escape {
+    #[default]
+    None,
+    /// After `ESC`
+    Started,
+    /// After `ESC (` or `ESC )`, which pick a character set
+    Charset,
+    /// After `ESC [`, with the parameters so far
+    Csi(String),
+    /// After `ESC ]`, until `BEL` or `ESC \`
+    Osc,
+}
+
+impl Screen {
+    /// An empty screen with a number of columns and rows
+    pub fn new((cols, rows): (usize, usize)) -> Self {
+        Self {
+            lines: VecDeque::from(vec![Vec::new()]),
+            cols: cols.max(1),
+            rows: rows.max(1),
+            cursor: (0, 0),
+            saved: (0, 0),
+            region: None,
+            escape: Escape::None,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Every line on the screen and in the scrollback, oldest first
+    pub fn lines(&self) -> Vec<String> {
+        self.last_lines(self.lines.len())
+    }
+
+    /// The last lines, which are the ones on the screen when it's `count` rows tall
+    pub fn last_lines(&self, count: usize) -> Vec<String> {
+        let first = self.lines.len().saturating_sub(count);
+        self.lines
+            .range(first..)
+            .map(|line| line.iter().collect())
+            .collect()
+    }
+
+    /// How many lines there are, on the screen and in the scrollback
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// The line the cursor is on, counting from the oldest one, and its column
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    /// Change the number of columns and rows, after the terminal was resized.
+    /// Empty rows below the cursor are dropped, so it stays on the screen.
+    pub fn resize(&mut self, (cols, rows): (usize, usize)) {
+        self.cols = cols.max(1);
+        self.rows = rows.max(1);
+        self.region = None;
+        while self.lines.len() > self.cursor.0 + 1
+            && self.lines.back().is_some_and(|line| line.is_empty())
+        {
+            self.lines.pop_back();
+        }
+        self.cursor.1 = self.cursor.1.min(self.cols - 1);
+    }
+
+    /// Show some output of a program
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.partial.extend_from_slice(bytes);
+        let partial = std::mem::take(&mut self.partial);
+        let valid = match std::str::from_utf8(&partial) {
+            Ok(text) => text.len(),
+            // An invalid byte is shown as �, but a cut off character waits for the rest
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => partial.len(),
+        };
+        for ch in String::from_utf8_lossy(&partial[..valid]).chars() {
+            self.put(ch);
+        }
+        self.partial = partial[valid..].to_vec();
+    }
+
+    /// The line in `lines` of the top row of the screen
+    fn top(&self) -> usize {
+        self.lines.len().saturating_sub(self.rows)
+    }
+
+    /// The row of the screen the cursor is on
+    fn row(&self) -> usize {
+        self.cursor.0 - self.top()
+    }
+
+    /// The rows that scroll, which are all of them unless a region is set
+    fn scrolled_rows(&self) -> (usize, usize) {
+        self.region.unwrap_or((0, self.rows - 1))
+    }
+
+    /// Move the cursor to a row of the screen, adding the lines down to it
+    fn go_to_row(&mut self, row: usize) {
+        let line = self.top() + row.min(self.rows - 1);
+        while self.lines.len() <= line {
+            self.lines.push_back(Vec::new());
+        }
+        self.cursor.0 = line;
+    }
+
+    fn put(&mut self, ch: char) {
+        match std::mem::take(&mut self.escape) {
+            Escape::Started => {
+                self.escape(ch);
+                return;
+            }
+            Escape::Charset => return,
+            Escape::Csi(mut parameters) => {
+                if ('@'..='~').contains(&ch) {
+                    self.csi(&parameters, ch);
+                } else {
+                    parameters.push(ch);
+                    self.escape = Escape::Csi(parameters);
+                }
+                return;
+            }
+            Escape::Osc => {
+                if ch != '\x07' && ch != '\x1b' {
+                    self.escape = Escape::Osc;
+                }
+                return;
+            }
+            Escape::None => {}
+        }
+        match ch {
+            '\x1b' => self.escape = Escape::Started,
+            '\n' | '\x0b' | '\x0c' => self.line_feed(),
+            '\r' => self.cursor.1 = 0,
+            '\x08' => self.cursor.1 = self.cursor.1.saturating_sub(1),
+            '\t' => self.cursor.1 = ((self.cursor.1 / 8 + 1) * 8).min(self.cols - 1),
+            ch if ch.is_control() => {}
+            ch => {
+                // A character past the last column goes at the start of the next row
+                if self.cursor.1 >= self.cols {
+                    self.cursor.1 = 0;
+                    self.line_feed();
+                }
+                let (row, col) = self.cursor;
+                let line = &mut self.lines[row];
+                if col < line.len() {
+                    line[col] = ch;
+                } else {
+                    line.resize(col, ' ');
+                    line.push(ch);
+                }
+                self.cursor.1 += 1;
+            }
+        }
+    }
+
+    /// Move the cursor down a row, scrolling if it's on the last row that scrolls
+    fn line_feed(&mut self) {
+        let (top, bottom) = self.scrolled_rows();
+        let row = self.row();
+        if row != bottom {
+            if row < self.rows - 1 {
+                self.go_to_row(row + 1);
+            }
+        } else if top == 0 && bottom == self.rows - 1 {
+            // The top row scrolls off the screen into the scrollback
+            self.lines.push_back(Vec::new());
+            self.cursor.0 += 1;
+            if self.lines.len() > SCROLLBACK + self.rows {
+                self.lines.pop_front();
+                self.cursor.0 -= 1;
+            }
+        } else {
+            let first = self.top() + top;
+            self.go_to_row(bottom);
+            self.lines.remove(first);
+            self.lines.insert(self.cursor.0, Vec::new());
+        }
+    }
+
+    /// Move the cursor up a row, scrolling down if it's on the first row that scrolls
+    fn reverse_line_feed(&mut self) {
+        let (top, bottom) = self.scrolled_rows();
+        let row = self.row();
+        if row != top {
+            self.go_to_row(row.saturating_sub(1));
+        } else {
+            let last = self.top() + bottom;
+            if last < self.lines.len() {
+                self.lines.remove(last);
+            }
+            self.lines.insert(self.cursor.0, Vec::new());
+        }
+    }
+
+    /// Follow an `ESC` sequence that isn't `ESC [` or `ESC ]`
+    fn escape(&mut self, ch: char) {
+        match ch {
+            '[' => self.escape = Escape::Csi(String::new()),
+            ']' => self.escape = Escape::Osc,
+            '(' | ')' => self.escape = Escape::Charset,
+            '7' => self.saved = (self.row(), self.cursor.1),
+            '8' => {
+                let (row, col) = self.saved;
+                self.go_to_row(row);
+                self.cursor.1 = col.min(self.cols - 1);
+            }
+            'D' => self.line_feed(),
+            'E' => {
+                self.cursor.1 = 0;
+                self.line_feed();
+            }
+            'M' => self.reverse_line_feed(),
+            'c' => *self = Self::new((self.cols, self.rows)),
+            _ => {}
+        }
+    }
+
+    /// Follow an `ESC [` sequence, which can move the cursor
+    /// or clear some of the screen
+    fn csi(&mut self, parameters: &str, command: char) {
+        // Private sequences, like the ones that switch modes, are left out
+        if parameters.starts_with('?') || parameters.starts_with('>') {
+            return;
+        }
+        let numbers: Vec<usize> = parameters
+            .split(';')
+            .map(|number| number.parse().unwrap_or(0))
+            .collect();
+        let number = |i: usize| numbers.get(i).copied().unwrap_or(0);
+        let count = number(0).max(1);
+        let row = self.row();
+        let (line, col) = self.cursor;
+        match command {
+            'A' => self.go_to_row(row.saturating_sub(count)),
+            'B' | 'e' => self.go_to_row(row + count),
+            'C' | 'a' => self.cursor.1 = (col + count).min(self.cols - 1),
+            'D' => self.cursor.1 = col.saturating_sub(count),
+            'G' | '`' => self.cursor.1 = (count - 1).min(self.cols - 1),
+            'd' => self.go_to_row(count - 1),
+            'H' | 'f' => {
+                self.go_to_row(number(0).max(1) - 1);
+                self.cursor.1 = (number(1).max(1) - 1).min(self.cols - 1);
+            }
+            // Clear from the cursor to the end of the line, from the start
+            // of the line to the cursor, or the whole line
+            'K' => {
+                let text = &mut self.lines[line];
+                match number(0) {
+                    0 => text.truncate(col),
+                    1 => {
+                        for ch in text.iter_mut().take(col + 1) {
+                            *ch = ' ';
+                        }
+                    }
+                    _ => text.clear(),
+                }
+            }
+            // Clear from the cursor to the end of the screen, from the top
+            // of the screen to the cursor, the whole screen, or the scrollback
+            'J' => {
+                let top = self.top();
+                match number(0) {
+                    0 => {
+                        self.lines[line].truncate(col);
+                        self.lines.range_mut(line + 1..).for_each(Vec::clear);
+                    }
+                    1 => {
+                        self.lines.range_mut(top..line).for_each(Vec::clear);
+                        let text = &mut self.lines[line];
+                        for ch in text.iter_mut().take(col + 1) {
+                            *ch = ' ';
+                        }
+                    }
+                    2 => self.lines.range_mut(top..).for_each(Vec::clear),
+                    _ => {
+                        self.lines.drain(..top);
+                        self.cursor.0 -= top;
+                    }
+                }
+            }
+            // Insert blanks at the cursor, delete characters there, or blank them out
+            '@' => {
+                let text = &mut self.lines[line];
+                if col < text.len() {
+                    text.splice(col..col, std::iter::repeat_n(' ', count));
+                    text.truncate(self.cols);
+                }
+            }
+            'P' => {
+                let text = &mut self.lines[line];
+                let end = (col + count).min(text.len());
+                if col < end {
+                    text.drain(col..end);
+                }
+            }
+            'X' => {
+                let text = &mut self.lines[line];
+                for ch in text.iter_mut().skip(col).take(count) {
+                    *ch = ' ';
+                }
+            }
+            // Set the rows that scroll, and put the cursor at the top left
+            'r' => {
+                let top = number(0).max(1) - 1;
+                let bottom = match number(1) {
+                    0 => self.rows - 1,
+                    bottom => (bottom - 1).min(self.rows - 1),
+                };
+                self.region = if top < bottom && (top, bottom) != (0, self.rows - 1) {
+                    Some((top, bottom))
+                } else {
+                    None
+                };
+                self.go_to_row(0);
+                self.cursor.1 = 0;
+            }
+            _ => {}
+        }
+    }
+}
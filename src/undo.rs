@@ -0,0 +1,225 @@
+use std::cmp::min;
+use std::time::{Duration, Instant};
+
+/// A change to a run of rows in a buffer, which can be undone and redone
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The first row that changed
+    start: usize,
+    /// The rows that were replaced
+    old: Vec<String>,
+    /// The rows that replaced them
+    new: Vec<String>,
+    cursor_before: (i32, i32),
+    cursor_after: (i32, i32),
+    /// When the change was made
+    time: Instant,
+}
+
+impl Change {
+    /// Find the rows that differ between two versions of a buffer,
+    /// or `None` if they're the same
+    pub fn between(
+        before: &[String],
+        after: &[String],
+        cursor_before: (i32, i32),
+        cursor_after: (i32, i32),
+    ) -> Option<Self> {
+        let prefix = before
+            .iter()
+            .zip(after)
+            .take_while(|(old, new)| old == new)
+            .count();
+        if prefix == before.len() && prefix == after.len() {
+            return None;
+        }
+        let suffix = before
+            .iter()
+            .rev()
+            .zip(after.iter().rev())
+            .take(min(before.len(), after.len()) - prefix)
+            .take_while(|(old, new)| old == new)
+            .count();
+
+        Some(Self {
+            start: prefix,
+            old: before[prefix..before.len() - suffix].to_vec(),
+            new: after[prefix..after.len() - suffix].to_vec(),
+            cursor_before,
+            cursor_after,
+            time: Instant::now(),
+        })
+    }
+
+    /// Fold a change made right after this one into it, if the rows it changed
+    /// are all rows that this one made. Returns whether it could.
+    fn absorb(&mut self, next: &Change) -> bool {
+        let end = self.start + self.new.len();
+        if next.start < self.start || next.start + next.old.len() > end {
+            return false;
+        }
+        let offset = next.start - self.start;
+        self.new
+            .splice(offset..offset + next.old.len(), next.new.iter().cloned());
+        self.cursor_after = next.cursor_after;
+        self.time = next.time;
+        true
+    }
+
+    /// Put back the old rows, and return where the cursor was before the change
+    fn undo(&self, contents: &mut Vec<String>) -> (i32, i32) {
+        let end = self.start + self.new.len();
+        contents.splice(self.start..end, self.old.iter().cloned());
+        self.cursor_before
+    }
+
+    /// Put back the new rows, and return where the cursor was after the change
+    fn redo(&self, contents: &mut Vec<String>) -> (i32, i32) {
+        let end = self.start + self.old.len();
+        contents.splice(self.start..end, self.new.iter().cloned());
+        self.cursor_after
+    }
+}
+
+/// A copy of a buffer that's kept up to date by copying only the rows that
+/// change, so that each edit can be found without copying the whole buffer
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    rows: Vec<String>,
+}
+
+impl Snapshot {
+    /// Find what changed in a buffer since the last update, and update the copy to match
+    pub fn update(
+        &mut self,
+        contents: &[String],
+        cursor_before: (i32, i32),
+        cursor_after: (i32, i32),
+    ) -> Option<Change> {
+        let change = Change::between(&self.rows, contents, cursor_before, cursor_after)?;
+        change.redo(&mut self.rows);
+        Some(change)
+    }
+}
+
+/// The changes made to a buffer, so that they can be undone and redone
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    done: Vec<Change>,
+    undone: Vec<Change>,
+    /// The time the buffer was put back to, if it was rewound with `undo_for`
+    rewound_to: Option<Instant>,
+    /// How many changes were done when the buffer was last saved, or `None`
+    /// if undoing and redoing can't get back to how it was saved
+    saved: Option<usize>,
+}
+
+impl History {
+    /// Remember a change. Anything that was undone can't be redone after this.
+    pub fn record(&mut self, change: Change) {
+        if self.saved.is_some_and(|saved| saved > self.done.len()) {
+            self.saved = None;
+        }
+        self.done.push(change);
+        self.undone.clear();
+        self.rewound_to = None;
+    }
+
+    /// Remember a change as part of the last change, so they're undone together
+    pub fn record_grouped(&mut self, change: Change) {
+        let absorbed = self.undone.is_empty()
+            && self
+                .done
+                .last_mut()
+                .is_some_and(|last| last.absorb(&change));
+        if !absorbed {
+            self.record(change);
+        } else if self.saved == Some(self.done.len()) {
+            self.saved = None;
+        }
+    }
+
+    /// Remember whether the buffer is saved as it is now
+    pub fn set_saved(&mut self, saved: bool) {
+        self.saved = if saved { Some(self.done.len()) } else { None };
+    }
+
+    /// Whether undoing or redoing put the buffer back how it was last saved
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.done.len())
+    }
+
+    /// Undo the last change, and return where the cursor should go
+    pub fn undo(&mut self, contents: &mut Vec<String>) -> Option<(i32, i32)> {
+        self.rewound_to = None;
+        self.step_back(contents)
+    }
+
+    /// Redo the last change that was undone, and return where the cursor should go
+    pub fn redo(&mut self, contents: &mut Vec<String>) -> Option<(i32, i32)> {
+        self.rewound_to = None;
+        self.step_forward(contents)
+    }
+
+    /// Undo every change made in the last `duration`, to put the buffer back
+    /// how it was then, and return where the cursor should go
+    pub fn undo_for(
+        &mut self,
+        duration: Duration,
+        contents: &mut Vec<String>,
+    ) -> Option<(i32, i32)> {
+        let now = Instant::now();
+        let mut cursor = None;
+        while self
+            .done
+            .last()
+            .is_some_and(|change| now.duration_since(change.time) < duration)
+        {
+            cursor = self.step_back(contents);
+        }
+        self.rewound_to = now.checked_sub(duration);
+        cursor
+    }
+
+    /// Redo the changes made in the `duration` after the time the buffer was
+    /// put back to, and return where the cursor should go
+    pub fn redo_for(
+        &mut self,
+        duration: Duration,
+        contents: &mut Vec<String>,
+    ) -> Option<(i32, i32)> {
+        let base = self
+            .rewound_to
+            .or_else(|| self.done.last().map(|change| change.time))
+            .or_else(|| self.undone.last().map(|change| change.time))?;
+        let target = base + duration;
+        let mut cursor = None;
+        while self
+            .undone
+            .last()
+            .is_some_and(|change| change.time <= target)
+        {
+            cursor = self.step_forward(contents);
+        }
+        self.rewound_to = if self.undone.is_empty() {
+            None
+        } else {
+            Some(target)
+        };
+        cursor
+    }
+
+    fn step_back(&mut self, contents: &mut Vec<String>) -> Option<(i32, i32)> {
+        let change = self.done.pop()?;
+        let cursor = change.undo(contents);
+        self.undone.push(change);
+        Some(cursor)
+    }
+
+    fn step_forward(&mut self, contents: &mut Vec<String>) -> Option<(i32, i32)> {
+        let change = self.undone.pop()?;
+        let cursor = change.redo(contents);
+        self.done.push(change);
+        Some(cursor)
+    }
+}
@@ -1,37 +1,258 @@
+use crate::backup::{snapshot_path, Autosave, BackupStyle};
+use crate::checksum::sha256_hex;
+use crate::clipboard::{Clipboard, ClipboardKind};
+use crate::comments::{block_comment_markers, CommentStyle};
+use crate::compress::Compression;
+use crate::crypt::{Cipher, Encryption};
+use crate::diagnostics::Diagnostic;
+use crate::diff::{diff, hunks, line_changes, merge, DiffLine, Hunk, LineChange};
+use crate::edits::TextEdit;
+use crate::emmet::{abbreviation_before, Syntax};
+use crate::encoding::TextEncoding;
+use crate::formatter::Formatter;
+use crate::git::{blame, head_version, NOT_COMMITTED};
+use crate::highlight_rules::{apply_rules, HighlightRule};
+use crate::imports::ImportStyle;
+use crate::keymap::{key_name, Command, Keymap};
+use crate::languages::{extension, file_dir, Language};
+use crate::line_ending::LineEnding;
+use crate::lock::FileLock;
+use crate::logs::{is_log_file, LogHighlighter};
+use crate::markup::{is_name_char, matching_tag, scan_tags, unclosed_tag, Markup, TagKind};
+use crate::outline::{Entry, Outliner};
+use crate::pipe::pipe_through;
+use crate::position::PositionMapper;
+use crate::prose::{paragraph_starts, sentence_starts};
+use crate::settings::LanguageSettings;
+use crate::snippets::expand_snippet;
+use crate::spelling::misspelled_words;
+use crate::undo::{Change, History, Snapshot};
 use cursive::direction::Direction;
 use cursive::event::{Callback, Event, EventResult, Key, MouseButton, MouseEvent};
 use cursive::theme::{BaseColor, Color, ColorStyle, ColorType, Effect, Style};
+use cursive::traits::{Nameable, Scrollable};
 use cursive::utils::lines::simple::{prefix, simple_prefix, LinesIterator, Row};
 use cursive::utils::markup::StyledString;
 use cursive::view::{ScrollBase, SizeCache, View};
+use cursive::views::{Dialog, TextView};
+use cursive::Cursive;
 use cursive::Rect;
 use cursive::Vec2;
 use cursive::{Printer, With, XY};
 use log::debug;
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::cmp::{max, min};
-use std::fs::{read_to_string, write};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::{copy, metadata, read, remove_file, write};
+use std::io;
+use std::mem::{replace, take};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime};
 use unicode_segmentation::UnicodeSegmentation;
 use unicode_width::UnicodeWidthStr;
 
+/// The space the commit, author and date of each line take up in blame mode
+const BLAME_SPACE: usize = 33;
+
 /// Maximum space that the line number prefix will consume
-/// This includes the space and `|` character after the number
-pub const LN_SPACE: i32 = 6;
+/// This includes the column for the git change marker,
+/// and the `|` character and space after the number
+pub const LN_SPACE: i32 = 7;
+
+/// Brackets and quotes that can be closed automatically
+const PAIRS: [(char, char); 5] = [('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
+
+/// How long typing can pause and still be undone in one step
+const TYPING_PAUSE: Duration = Duration::from_secs(1);
+
+/// Brackets that can be matched up with each other
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// The widest the old side of a side-by-side diff can be
+const SIDE_BY_SIDE_WIDTH: usize = 60;
+
+/// How many lines a paste has to have to be pasted a chunk at a time
+const LARGE_PASTE: usize = 50_000;
+
+/// How many lines of a large paste are pasted at a time
+const PASTE_CHUNK: usize = 10_000;
+
+/// How many words the completion popup shows at once
+const COMPLETION_ROWS: usize = 8;
+
+/// The most times a macro can be played at once
+const MAX_MACRO_PLAYS: usize = 10_000;
+
+/// Whether a segment of text from word segmentation is a word,
+/// rather than whitespace or punctuation
+fn is_word(segment: &str) -> bool {
+    segment.chars().any(|ch| ch.is_alphanumeric() || ch == '_')
+}
+
+/// What a line leaves open at its end, which the next line starts inside of
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum LineState {
+    /// Nothing is left open
+    #[default]
+    Code,
+    /// A string that ends with this delimiter, like `"` or `"""`
+    String(String),
+    /// A heredoc that ends with a line of just this word, like `EOF`
+    Heredoc(String),
+}
 
 /// An object used to highlight displayed text
 pub trait Highlighter: Default + 'static {
     fn highlight(&self, code: impl ToString) -> StyledString;
+
+    /// Highlight a line that starts in the state the line before it ended in,
+    /// and return the state it ends in, so that strings and other things that
+    /// go on for more than one line are highlighted on all of them.
+    /// Highlighters that only look at one line at a time don't need this.
+    fn highlight_line(&self, line: &str, _state: &LineState) -> (StyledString, LineState) {
+        (self.highlight(line), LineState::Code)
+    }
+
+    /// Adapt to the file being highlighted, from its name. This is called
+    /// when a file is opened, before any of it is highlighted.
+    fn set_file_name(&mut self, _name: &str) {}
+}
+
+/// Whether each byte of a line is code, rather than part of a string or
+/// comment, in a language that writes comments with `comments` and starts
+/// and ends strings with any of `quotes`. Comments that go on past the end
+/// of the line are only recognized on the line they start on.
+fn code_mask(line: &str, comments: Option<CommentStyle>, quotes: &[char]) -> Vec<bool> {
+    let line_comment = comments.and_then(|style| style.line).map(str::trim_end);
+    let block_comment = comments
+        .and_then(|style| style.block)
+        .map(|(open, close)| (open.trim_end(), close.trim_start()));
+    let mut mask = vec![true; line.len()];
+    let mut quote = None;
+    let mut i = 0;
+    while let Some(ch) = line[i..].chars().next() {
+        let rest = &line[i..];
+        let mut len = ch.len_utf8();
+        match quote {
+            Some(open) => {
+                if ch == open && !is_escaped(line, i) {
+                    quote = None;
+                }
+            }
+            None if line_comment.is_some_and(|start| rest.starts_with(start)) => len = rest.len(),
+            None => match block_comment.filter(|(open, _)| rest.starts_with(open)) {
+                Some((open, close)) => {
+                    len = rest[open.len()..]
+                        .find(close)
+                        .map_or(rest.len(), |end| open.len() + end + close.len());
+                }
+                None if quotes.contains(&ch) => quote = Some(ch),
+                None => {
+                    i += len;
+                    continue;
+                }
+            },
+        }
+        for is_code in &mut mask[i..i + len] {
+            *is_code = false;
+        }
+        i += len;
+    }
+    mask
+}
+
+/// Whether the character at a byte offset is escaped, which it is
+/// after an odd number of backslashes, so `"\\"` ends where it should
+fn is_escaped(text: &str, i: usize) -> bool {
+    let backslashes = text[..i].bytes().rev().take_while(|&b| b == b'\\').count();
+    backslashes % 2 == 1
+}
+
+/// What strings start and end with, longest first
+const STRING_DELIMITERS: [&str; 3] = ["\"\"\"", "'''", "\""];
+
+/// The word of `words` that `code` has at byte `i`, if it isn't part of a longer word
+fn word_at<'a>(code: &str, i: usize, words: &[&'a str]) -> Option<&'a str> {
+    let is_alphabetic = |ch: Option<char>| ch.is_some_and(char::is_alphabetic);
+    if is_alphabetic(code[..i].chars().next_back()) {
+        return None;
+    }
+    words.iter().copied().find(|word| {
+        code[i..].starts_with(word) && !is_alphabetic(code[i + word.len()..].chars().next())
+    })
+}
+
+/// How long the character literal at the start of `code` is, like `'"'` or `'\''`
+fn char_literal_len(code: &str) -> Option<usize> {
+    let mut chars = code.char_indices().skip(1);
+    let (_, ch) = chars.next()?;
+    if ch == '\'' {
+        return None;
+    }
+    if ch == '\\' {
+        chars.next()?;
+    }
+    match chars.next()? {
+        (end, '\'') => Some(end + 1),
+        _ => None,
+    }
+}
+
+/// The word that ends a heredoc that `code` starts with, like `EOF` for
+/// `<<EOF`, `<<-'EOF'` or `<<~"EOF"`, and how long the start of it is
+fn heredoc_start(code: &str) -> Option<(&str, usize)> {
+    let rest = code.strip_prefix("<<")?;
+    let rest = rest.strip_prefix(['-', '~']).unwrap_or(rest);
+    let quote = rest.chars().next().filter(|&ch| ch == '\'' || ch == '"');
+    let rest = &rest[quote.map_or(0, char::len_utf8)..];
+    let len = rest
+        .find(|ch: char| !ch.is_ascii_alphanumeric() && ch != '_')
+        .unwrap_or(rest.len());
+    let word = &rest[..len];
+    // Words are capitalized, so shifts like `1 << n` aren't taken for heredocs
+    if !word.starts_with(|ch: char| ch.is_ascii_uppercase()) {
+        return None;
+    }
+    let quote_len = match quote {
+        Some(quote) if rest[len..].starts_with(quote) => quote.len_utf8(),
+        Some(_) => return None,
+        None => 0,
+    };
+    Some((word, code.len() - rest.len() + len + quote_len))
 }
 
 #[derive(Default)]
-pub struct DefaultHighlighter;
+pub struct DefaultHighlighter {
+    /// Log files are highlighted as logs instead of code
+    log: Option<LogHighlighter>,
+}
+
 impl Highlighter for DefaultHighlighter {
     fn highlight(&self, code: impl ToString) -> StyledString {
-        let code = code.to_string() + " ";
+        self.highlight_line(&code.to_string(), &LineState::Code).0
+    }
+
+    fn set_file_name(&mut self, name: &str) {
+        self.log = if is_log_file(name) {
+            Some(LogHighlighter::default())
+        } else {
+            None
+        };
+    }
+
+    fn highlight_line(&self, line: &str, state: &LineState) -> (StyledString, LineState) {
+        if let Some(log) = &self.log {
+            return log.highlight_line(line, state);
+        }
+        let code = line.to_string() + " ";
         let mut result = StyledString::plain("");
-        let mut in_string = false;
 
         let mut string_color = ColorStyle::secondary();
         string_color.back = ColorType::Color(Color::Light(BaseColor::Green));
+        let string_style = Style::from(string_color);
 
         let mut number_color = ColorStyle::secondary();
         number_color.back = ColorType::Color(Color::Light(BaseColor::Yellow));
@@ -45,6 +266,16 @@ impl Highlighter for DefaultHighlighter {
         let mut type_color = ColorStyle::secondary();
         type_color.back = ColorType::Color(Color::Dark(BaseColor::Blue));
 
+        // The body of a heredoc is all string, up to the line that ends it
+        if let LineState::Heredoc(word) = state {
+            let state = if line.trim() == word {
+                LineState::Code
+            } else {
+                state.clone()
+            };
+            return (StyledString::styled(code, string_style), state);
+        }
+
         let types = vec![
             "Self", "Vec", "i32", "i64", "f32", "f64", "int", "double", "float", "char", "bool",
             "self", "String", "str", "true", "false", "True", "False",
@@ -60,396 +291,3759 @@ impl Highlighter for DefaultHighlighter {
 
         let symbols = [';', ',', ':', '?', '{', '}', '(', ')', '!'];
 
+        let mut in_string = match state {
+            LineState::String(delimiter) => Some(delimiter.as_str()),
+            _ => None,
+        };
+        let mut heredoc = None;
         let mut skip = 0;
 
-        for (i, ch) in code.chars().enumerate() {
-            for key in &keywords {
-                if code.len() < i + key.len() + 1 {
-                    continue;
-                }
-
-                if i > 0
-                    && code
-                        .chars()
-                        .nth(i - 1)
-                        .and_then(|ch| Some(ch.is_alphabetic()))
-                        .unwrap_or(false)
-                {
-                    continue;
-                }
-
-                if &code[i..i + key.len()] == *key
-                    && code
-                        .chars()
-                        .nth(i + key.len())
-                        .and_then(|ch| Some(!ch.is_alphabetic()))
-                        .unwrap_or(false)
-                {
-                    result.append_styled(*key, Style::from(keyword_color.clone()));
-                    skip = key.len();
-                    break;
-                }
+        // `i` is a byte offset, so that multi-byte characters can't break slicing
+        for (i, ch) in code.char_indices() {
+            if skip > 0 {
+                skip -= 1;
+                continue;
             }
+            let rest = &code[i..];
+            let escaped = is_escaped(&code, i);
 
-            for t in &types {
-                if code.len() < i + t.len() + 1 {
-                    continue;
-                }
-
-                if i > 0
-                    && code
-                        .chars()
-                        .nth(i - 1)
-                        .and_then(|ch| Some(ch.is_alphabetic()))
-                        .unwrap_or(false)
-                {
-                    continue;
+            if let Some(delimiter) = in_string {
+                if rest.starts_with(delimiter) && !escaped {
+                    result.append_styled(delimiter, string_style);
+                    skip = delimiter.len() - 1;
+                    in_string = None;
+                } else {
+                    result.append_styled(ch.to_string(), string_style);
                 }
+                continue;
+            }
 
-                if &code[i..i + t.len()] == *t
-                    && code
-                        .chars()
-                        .nth(i + t.len())
-                        .and_then(|ch| Some(!ch.is_alphabetic()))
-                        .unwrap_or(false)
-                {
-                    result.append_styled(*t, Style::from(type_color.clone()));
-                    skip = t.len();
-                    break;
-                }
+            if let Some(key) = word_at(&code, i, &keywords) {
+                result.append_styled(key, Style::from(keyword_color));
+                skip = key.len() - 1;
+                continue;
+            }
+            if let Some(t) = word_at(&code, i, &types) {
+                result.append_styled(t, Style::from(type_color));
+                skip = t.len() - 1;
+                continue;
             }
 
-            if skip > 0 {
-                skip -= 1;
+            let delimiter = STRING_DELIMITERS
+                .iter()
+                .find(|delimiter| rest.starts_with(*delimiter));
+            if let Some(&delimiter) = delimiter.filter(|_| !escaped) {
+                result.append_styled(delimiter, string_style);
+                skip = delimiter.len() - 1;
+                in_string = Some(delimiter);
+                continue;
+            }
+            // A quote in a character literal doesn't start a string
+            if let Some(len) = char_literal_len(rest).filter(|_| ch == '\'') {
+                result.append_styled(&rest[..len], string_style);
+                skip = rest[..len].chars().count() - 1;
+                continue;
+            }
+            // `<<<` is a here-string, which is all on one line
+            if let Some((word, len)) = heredoc_start(rest).filter(|_| !code[..i].ends_with('<')) {
+                result.append_styled(&rest[..len], string_style);
+                skip = len - 1;
+                heredoc = Some(word.to_string());
                 continue;
             }
 
             match ch {
-                '\"' if i > 1 && code.chars().nth(max(i - 1, 0) as usize) == Some('\\') => {
-                    result.append_styled("\"", Style::from(string_color.clone()));
-                }
-                '\"' => {
-                    result.append_styled("\"", Style::from(string_color.clone()));
-                    in_string = !in_string;
-                }
                 ch if ch.is_digit(10) => {
-                    result.append_styled(&ch.to_string(), Style::from(number_color.clone()))
-                }
-                ch if in_string => {
-                    result.append_styled(&ch.to_string(), Style::from(string_color.clone()))
+                    result.append_styled(ch.to_string(), Style::from(number_color))
                 }
                 ch if symbols.contains(&ch) => {
-                    result.append_styled(&ch.to_string(), Style::from(symbol_color.clone()))
+                    result.append_styled(ch.to_string(), Style::from(symbol_color))
                 }
-                ch => result.append_plain(&ch.to_string()),
+                ch => result.append_plain(ch.to_string()),
             }
         }
-        result
+
+        let state = match (in_string, heredoc) {
+            (Some(delimiter), _) => LineState::String(delimiter.to_string()),
+            (None, Some(word)) => LineState::Heredoc(word),
+            (None, None) => LineState::Code,
+        };
+        (result, state)
     }
 }
 
-/// Multi-lines text editor.
-///
-/// A `TextArea` will attempt to grow vertically and horizontally
-/// dependent on the content.  Wrap it in a `ResizedView` to
-/// constrain its size.
-///
-/// # Examples
-///
-/// ```
-/// use cursive::traits::{Resizable, Identifiable};
-/// use cursive::views::TextArea;
-///
-/// let text_area = TextArea::new()
-///     .content("Write description here...")
-///     .with_name("text_area")
-///     .fixed_width(30)
-///     .min_height(5);
-/// ```
-pub struct CodeArea<H>
-where
-    H: Highlighter,
-{
-    /// Filename for saving
-    filename: String,
+/// A callback run when a buffer's modified state changes
+type OnModified = Rc<dyn Fn(&mut Cursive, bool)>;
 
-    /// The highlighter for displaying code syntax
-    highlighter: H,
+/// Highlighted rows and the states they end in, by the state they start in and their text
+type HighlightCache = HashMap<LineState, HashMap<String, (StyledString, LineState)>>;
 
-    /// The marker used for selection
-    selection_marker: Option<(i32, i32)>,
+/// The state the highlighter is in at the start of each row from the top,
+/// kept between draws so that only the rows from the first one that was
+/// edited are gone through again
+#[derive(Default)]
+struct RowStates {
+    /// The version of the buffer the rows were last checked against
+    version: Option<usize>,
+    /// The text of each row that the state after it was found from
+    rows: Vec<String>,
+    /// The state at the start of each row, with one more than there are `rows`
+    starts: Vec<LineState>,
+}
 
-    /// The string to comment out code
-    comment_prefix: String,
+/// The name of the file an encrypted file holds, like `notes.txt.gz` for
+/// `notes.txt.gz.gpg`
+fn decrypted_name<'a>(file: &'a str, encryption: &Encryption) -> &'a str {
+    file.strip_suffix(encryption.cipher().extension())
+        .unwrap_or(file)
+}
 
-    /// Stores the content of the code area
-    contents: Vec<String>,
+/// The part of a file's name that says what kind of file it holds, without
+/// the extensions of its encryption and compression, like `main.rs` for
+/// `main.rs.gz.gpg`
+fn file_type_name(file: &str) -> &str {
+    let file = Cipher::from_path(file)
+        .and_then(|cipher| file.strip_suffix(cipher.extension()))
+        .unwrap_or(file);
+    Compression::from_path(file)
+        .and_then(|compression| file.strip_suffix(compression.extension()))
+        .unwrap_or(file)
+}
 
-    /// Stores cut and copied text
-    clipboard: String,
+/// Run one callback and then another, if there are both
+fn chain(first: Option<Callback>, second: Option<Callback>) -> Option<Callback> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(Callback::from_fn(move |s| {
+            first(s);
+            second(s);
+        })),
+        (first, second) => first.or(second),
+    }
+}
 
-    /// When `false`, we don't take any input.
-    enabled: bool,
+/// Why a command that moves or deletes towards the start or end of the
+/// buffer didn't do anything
+fn stuck_message(command: Command) -> Option<&'static str> {
+    match command {
+        Command::Up
+        | Command::PageUp
+        | Command::SelectUp
+        | Command::SelectPageUp
+        | Command::MoveLineUp => Some("Already at the top of the buffer"),
+        Command::Down
+        | Command::PageDown
+        | Command::SelectDown
+        | Command::SelectPageDown
+        | Command::MoveLineDown => Some("Already at the bottom of the buffer"),
+        Command::MoveArgumentLeft | Command::MoveArgumentRight => {
+            Some("There's no argument to swap with")
+        }
+        Command::Left
+        | Command::WordLeft
+        | Command::SelectLeft
+        | Command::SelectWordLeft
+        | Command::Backspace
+        | Command::DeleteWordLeft
+        | Command::KillToStartOfLine => Some("Already at the start of the buffer"),
+        Command::Right
+        | Command::WordRight
+        | Command::SelectRight
+        | Command::SelectWordRight
+        | Command::Delete
+        | Command::DeleteWordRight
+        | Command::KillToEndOfLine => Some("Already at the end of the buffer"),
+        Command::JumpToMatchingBracket => Some("No matching bracket"),
+        _ => None,
+    }
+}
 
-    /// Base for scrolling features
-    scrollbase: ScrollBase,
+/// A callback that has the focused view paste the next chunk of a large
+/// paste, after the keys pressed in the meantime, like Esc, are handled
+fn continue_paste() -> Callback {
+    Callback::from_fn(|s| {
+        let _ = s.cb_sink().send(Box::new(|s| s.on_event(Event::Refresh)));
+    })
+}
 
-    /// Byte offset of the currently selected grapheme.
-    cursor: (i32, i32),
+/// Swap the case of each letter
+fn toggle_case(text: &str) -> String {
+    text.chars()
+        .flat_map(|ch| {
+            if ch.is_uppercase() {
+                ch.to_lowercase().collect::<Vec<_>>()
+            } else {
+                ch.to_uppercase().collect()
+            }
+        })
+        .collect()
 }
 
-impl<H> Default for CodeArea<H>
-where
-    H: Highlighter,
-{
-    fn default() -> Self {
-        Self::new()
-    }
+/// The message for a formatter that failed, which is usually a syntax error
+fn format_error(error: &io::Error) -> String {
+    let error = error.to_string();
+    let first_line = error.lines().next().unwrap_or_default();
+    format!("Could not format the file: {}", first_line)
 }
 
-impl<H> CodeArea<H>
-where
-    H: Highlighter,
-{
-    pub fn new() -> Self {
-        Self {
-            highlighter: H::default(),
-            filename: String::new(),
-            selection_marker: None,
-            comment_prefix: String::from("// "),
-            clipboard: String::new(),
-            contents: vec![String::new(), String::new()],
-            enabled: true,
-            scrollbase: ScrollBase::new().right_padding(0),
-            cursor: (0, 0),
+/// Color a diff like `git diff` does
+fn styled_hunks(hunks: &[Hunk]) -> StyledString {
+    let mut styled = StyledString::new();
+    for hunk in hunks {
+        let header = format!("{}\n", hunk.header());
+        styled.append_styled(header, Style::from(Color::Dark(BaseColor::Cyan)));
+        for line in &hunk.lines {
+            let text = format!("{}{}\n", line.marker(), line.text());
+            match line {
+                DiffLine::Same(_) => styled.append_plain(text),
+                DiffLine::Removed(_) => {
+                    styled.append_styled(text, Style::from(Color::Dark(BaseColor::Red)))
+                }
+                DiffLine::Added(_) => {
+                    styled.append_styled(text, Style::from(Color::Dark(BaseColor::Green)))
+                }
+            }
         }
     }
+    styled
+}
 
-    pub fn open_file(mut self, file: impl ToString) -> Self {
-        let result = read_to_string(file.to_string());
-        self.filename = file.to_string();
-        if let Ok(contents) = result {
-            self.with_content(contents)
-        } else {
-            self
+/// The byte ranges of the arguments or list elements between the innermost
+/// brackets around an offset in some text, not counting the commas between
+/// them. `mask` says which bytes are code, like `code_mask`.
+fn arguments_around(text: &str, mask: &[bool], offset: usize) -> Option<Vec<(usize, usize)>> {
+    let bytes = text.as_bytes();
+    let is_open = |byte: u8| BRACKETS.iter().any(|&(open, _)| open as u8 == byte);
+    let is_close = |byte: u8| BRACKETS.iter().any(|&(_, close)| close as u8 == byte);
+
+    let mut depth = 0;
+    let mut open = None;
+    for i in (0..offset).rev().filter(|&i| mask[i]) {
+        if is_close(bytes[i]) {
+            depth += 1;
+        } else if is_open(bytes[i]) && depth == 0 {
+            open = Some(i);
+            break;
+        } else if is_open(bytes[i]) {
+            depth -= 1;
         }
     }
+    let open = open?;
 
-    pub fn with_content(mut self, content: impl ToString) -> Self {
-        self.insert_str(content);
-        self.cursor = (0, 0);
-        self
+    let mut arguments = Vec::new();
+    let mut start = open + 1;
+    for i in (open + 1..bytes.len()).filter(|&i| mask[i]) {
+        if is_open(bytes[i]) {
+            depth += 1;
+        } else if is_close(bytes[i]) && depth == 0 {
+            let (_, close) = BRACKETS.iter().find(|&&(o, _)| o as u8 == bytes[open])?;
+            if *close as u8 != bytes[i] {
+                return None;
+            }
+            arguments.push((start, i));
+            return Some(arguments);
+        } else if is_close(bytes[i]) {
+            depth -= 1;
+        } else if bytes[i] == b',' && depth == 0 {
+            arguments.push((start, i));
+            start = i + 1;
+        }
     }
+    None
+}
 
-    pub fn with_comment(mut self, comment: impl ToString) -> Self {
-        self.comment_prefix = comment.to_string();
-        self
+/// Color a diff with the old lines on the left and the new lines on the
+/// right, where removed lines are paired with the lines that replaced them
+fn styled_side_by_side(hunks: &[Hunk]) -> StyledString {
+    // The left column is as wide as its longest line, up to a point
+    let width = hunks
+        .iter()
+        .flat_map(|hunk| &hunk.lines)
+        .filter(|line| !matches!(line, DiffLine::Added(_)))
+        .map(|line| line.text().width())
+        .max()
+        .unwrap_or(0)
+        .min(SIDE_BY_SIDE_WIDTH);
+    let red = Style::from(Color::Dark(BaseColor::Red));
+    let green = Style::from(Color::Dark(BaseColor::Green));
+
+    let mut styled = StyledString::new();
+    for hunk in hunks {
+        let header = format!("{}\n", hunk.header());
+        styled.append_styled(header, Style::from(Color::Dark(BaseColor::Cyan)));
+        let mut lines = hunk.lines.iter().peekable();
+        while let Some(line) = lines.next() {
+            if let DiffLine::Same(text) = line {
+                styled.append_plain(format!("{} │ {}\n", fit(text, width), text));
+                continue;
+            }
+            // A run of removed lines, and then the added lines that replace them
+            let (mut removed, mut added) = (Vec::new(), Vec::new());
+            match line {
+                DiffLine::Removed(_) => removed.push(line.text()),
+                _ => added.push(line.text()),
+            }
+            while let Some(&line) = lines.peek() {
+                match line {
+                    DiffLine::Removed(text) if added.is_empty() => removed.push(text.as_str()),
+                    DiffLine::Added(text) => added.push(text.as_str()),
+                    _ => break,
+                }
+                lines.next();
+            }
+            for i in 0..max(removed.len(), added.len()) {
+                match removed.get(i) {
+                    Some(text) => styled.append_styled(fit(text, width), red),
+                    None => styled.append_plain(" ".repeat(width)),
+                }
+                styled.append_plain(" │ ");
+                if let Some(text) = added.get(i) {
+                    styled.append_styled(text.to_string(), green);
+                }
+                styled.append_plain("\n");
+            }
+        }
     }
+    styled
+}
 
-    pub fn save_content(&mut self) {
-        write(&self.filename, self.contents.join("\n"));
+/// Cut off or pad a line so it's exactly `width` columns wide
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted = String::new();
+    let mut fitted_width = 0;
+    for grapheme in text.graphemes(true) {
+        if fitted_width + grapheme.width() > width {
+            break;
+        }
+        fitted.push_str(grapheme);
+        fitted_width += grapheme.width();
     }
+    fitted + &" ".repeat(width - fitted_width)
+}
 
-    pub fn is_selecting(&self) -> bool {
-        self.selection_marker.is_some()
+/// Show a diff in a dialog, which can switch between
+/// a unified diff and a side-by-side one
+fn changes_callback(
+    title: String,
+    changes: Result<Vec<Hunk>, String>,
+    empty: &'static str,
+) -> Callback {
+    Callback::from_fn(move |s| {
+        let hunks = match &changes {
+            Ok(hunks) if hunks.is_empty() => return s.add_layer(Dialog::info(empty)),
+            Ok(hunks) => hunks.clone(),
+            Err(e) => return s.add_layer(Dialog::info(e.clone())),
+        };
+        let (unified, side_by_side) = (styled_hunks(&hunks), styled_side_by_side(&hunks));
+        let show = |styled: StyledString| {
+            move |s: &mut Cursive| {
+                s.call_on_name("changes", |view: &mut TextView| {
+                    view.set_content(styled.clone())
+                });
+            }
+        };
+        let text = TextView::new(unified.clone()).with_name("changes");
+        let dialog = Dialog::around(text.scrollable())
+            .title(title.clone())
+            .button("Unified", show(unified))
+            .button("Side by side", show(side_by_side))
+            .dismiss_button("Close");
+        s.add_layer(dialog)
+    })
+}
+
+/// Write a file, first copying what was there to its backup if there is one
+fn write_file(file: &str, bytes: &[u8], backup: Option<BackupStyle>) -> io::Result<()> {
+    if let Some(backup) = backup {
+        if Path::new(file).exists() {
+            copy(file, backup.path(file))?;
+        }
     }
+    write(file, bytes)
+}
 
-    pub fn continue_selection(&mut self) {
-        if !self.is_selecting() {
-            self.selection_marker = Some(self.cursor)
+/// A summary of a file and the buffer editing it,
+/// used to check what is about to be saved
+pub struct FileInfo {
+    pub filename: String,
+    pub lines: usize,
+    pub encoding: String,
+    pub line_ending: &'static str,
+    /// The size and SHA-256 of the file on disk, if it could be read
+    pub on_disk: Option<(usize, String)>,
+    /// The size and SHA-256 of what would be written if the buffer was saved now
+    pub in_buffer: Option<(usize, String)>,
+}
+
+impl fmt::Display for FileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let describe = |contents: &Option<(usize, String)>| match contents {
+            Some((size, hash)) => format!("{} bytes\n  sha256 {}", size, hash),
+            None => String::from("unavailable"),
+        };
+
+        writeln!(f, "File:        {}", self.filename)?;
+        writeln!(f, "Lines:       {}", self.lines)?;
+        writeln!(f, "Encoding:    {}", self.encoding)?;
+        writeln!(f, "Line ending: {}", self.line_ending)?;
+        writeln!(f, "On disk:     {}", describe(&self.on_disk))?;
+        write!(f, "In buffer:   {}", describe(&self.in_buffer))?;
+        if self.on_disk.is_some() && self.on_disk == self.in_buffer {
+            write!(f, "\n(the buffer matches the file on disk)")?;
         }
+        Ok(())
     }
+}
 
-    pub fn forget_selection(&mut self) {
-        self.selection_marker = None
+/// A rectangle of the same columns selected on several rows. The corners
+/// are rows and the cells they're drawn at, so a block can reach past the
+/// end of short rows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Block {
+    /// The corner where the selection started
+    anchor: (i32, usize),
+    /// The corner that moves, which the cursor is on
+    corner: (i32, usize),
+}
+
+impl Block {
+    /// The first and last rows of the block
+    fn rows(&self) -> (i32, i32) {
+        (
+            min(self.anchor.0, self.corner.0),
+            max(self.anchor.0, self.corner.0),
+        )
     }
 
-    pub fn row(&mut self, i: i32) -> &mut String {
-        let len = (self.contents.len() - 1) as i32;
-        &mut self.contents[min(max(i, 0), len) as usize]
+    /// The first cell of the block, and the cell after the last one
+    fn cells(&self) -> (usize, usize) {
+        (
+            min(self.anchor.1, self.corner.1),
+            max(self.anchor.1, self.corner.1),
+        )
     }
 
-    pub fn row_len(&self, i: i32) -> i32 {
-        self.contents[min(max(i, 0), (self.contents.len() - 1) as i32) as usize].len() as i32
+    /// Make the block an empty column at cell `x`, like a cursor on each row
+    fn collapse_to(&mut self, x: usize) {
+        self.anchor.1 = x;
+        self.corner.1 = x;
     }
+}
 
-    /// Cuts the current line of the cursor
-    pub fn cut(&mut self) {
-        self.fix();
+/// The stops left to jump to in a snippet that was expanded, with the stop
+/// the cursor jumped to last, and how long its row and the buffer were then
+struct SnippetStops {
+    stops: Vec<(i32, i32)>,
+    at: (i32, i32),
+    row_len: i32,
+    rows: usize,
+}
 
-        let (row, col) = self.cursor;
-        // Will be stored into clipboard
-        let mut result = String::new();
+/// A paste too big to do at once, which is done a chunk at a time so
+/// that it can show how far along it is and be cancelled
+struct PendingPaste {
+    text: String,
+    /// How many bytes of the text have been pasted
+    done: usize,
+    /// The contents and cursor from before the paste, to go back to if it's cancelled
+    before: Vec<String>,
+    cursor_before: (i32, i32),
+}
 
-        if let Some((mrow, mcol)) = self.selection_marker {
-            if (mrow, mcol) == (row, col) {
-                return;
-            }
+/// The text of a buffer and what's known about it, which every code area
+/// that shows the buffer shares. Each of them has its own cursor and scroll
+/// position.
+struct Buffer {
+    /// The rows of the text
+    contents: Vec<String>,
 
-            let top;
-            let bottom;
-            if mrow < row {
-                top = (mrow, mcol);
-                bottom = (row, col);
-            } else if row < mrow {
-                top = (row, col);
-                bottom = (mrow, mcol);
-            } else if row == mrow && mcol < col {
-                top = (mrow, mcol);
-                bottom = (row, col);
-            } else if row == mrow && col < mcol {
-                top = (row, col);
-                bottom = (mrow, mcol);
-            } else {
-                unreachable!()
-            }
+    /// Filename for saving
+    filename: String,
 
-            let mut chars_between = 0;
-            let (top_row, top_col) = top;
-            let (bottom_row, bottom_col) = bottom;
-            for ln in top_row..bottom_row + 1 {
-                if ln == top_row && ln == bottom_row {
-                    chars_between += bottom_col - top_col - 1;
-                } else if ln == top_row {
-                    chars_between += self.row_len(ln) - top_col;
-                } else if top_row < ln && ln < bottom_row {
-                    chars_between += self.row_len(ln) + 1;
-                } else if ln == bottom_row {
-                    chars_between += bottom_col;
-                }
-            }
+    /// How the file is encrypted on disk, if it is at all
+    encryption: Option<Encryption>,
 
-            self.cursor = top;
+    /// How the file is compressed on disk, if it is at all
+    compression: Option<Compression>,
 
-            for _ in 0..chars_between + 1 {
-                if let Some(ch) = self.row(top_row).chars().nth(top_col as usize) {
-                    result.push(ch);
-                } else {
-                    result.push('\n');
-                }
-                self.delete();
-            }
+    /// How lines are separated when the file is saved
+    line_ending: LineEnding,
 
-            self.clipboard = result;
-        } else {
-            if self.contents.len() > 1 {
-                let result = self.row(row).clone() + "\n";
-                self.contents.remove(row as usize);
-                self.clipboard = result;
-                self.move_cursor_home();
-            }
-        }
+    /// How the text is encoded on disk
+    text_encoding: TextEncoding,
 
-        self.fix();
-    }
+    /// The contents as they were last opened or saved, which changes on disk
+    /// and unsaved changes are merged from
+    saved: Vec<String>,
 
-    pub fn copy(&mut self) {
-        let save_pos = self.cursor;
-        if self.is_selecting() {
-            self.cut();
-            self.paste();
-            self.cursor = save_pos;
+    /// The changes that can be undone and redone
+    history: History,
+
+    /// The contents as of the last command, to find what each command changed
+    snapshot: Snapshot,
+
+    /// When `true`, there are changes that haven't been saved
+    modified: bool,
+
+    /// When the file on disk was last changed, as of when it was last opened,
+    /// saved or checked for changes
+    disk_modified: Option<SystemTime>,
+
+    /// Messages about the code, like compiler errors
+    diagnostics: Vec<Diagnostic>,
+
+    /// The file as of the last git commit, if it's in a repository
+    head: Option<HeadDiff>,
+
+    /// The symbols in the file, if its language has an outline
+    outline: Option<Outline>,
+
+    /// When `true`, another editor had the file locked when it was opened,
+    /// so it stays read-only until the lock can be taken
+    locked_elsewhere: bool,
+
+    /// The lock taken once the other editor let go of the file
+    lock: Option<FileLock>,
+
+    /// Goes up each time the contents are borrowed to be changed, so the
+    /// code areas can tell when another one changed them
+    version: usize,
+}
+
+impl Buffer {
+    /// Find what the contents changed by since the last time,
+    /// which is usually before the last command
+    fn take_change(
+        &mut self,
+        cursor_before: (i32, i32),
+        cursor_after: (i32, i32),
+    ) -> Option<Change> {
+        self.snapshot
+            .update(&self.contents, cursor_before, cursor_after)
+    }
+
+    /// Undo or redo changes to the contents, and return where the cursor should go
+    fn step(
+        &mut self,
+        step: impl FnOnce(&mut History, &mut Vec<String>) -> Option<(i32, i32)>,
+    ) -> Option<(i32, i32)> {
+        self.version += 1;
+        step(&mut self.history, &mut self.contents)
+    }
+}
+
+/// Words from the buffer that could finish the word before the cursor,
+/// shown in a popup below it
+struct Completion {
+    /// The part of the word that's already typed
+    prefix: String,
+    words: Vec<String>,
+    selected: usize,
+}
+
+/// The parts of the buffer hidden while it's narrowed to some of its lines
+struct Narrowing {
+    above: Vec<String>,
+    below: Vec<String>,
+    /// The lines that were narrowed to, and where the cursor was, before
+    /// they were edited, so widening can be undone as one change
+    region: Vec<String>,
+    cursor: (i32, i32),
+    /// The changes from before narrowing, which come back when it's widened
+    history: History,
+}
+
+/// The lines of a file as of the last git commit, and how each row of the
+/// buffer differed from them as of the buffer's `version`
+#[derive(Clone)]
+struct HeadDiff {
+    lines: Vec<String>,
+    version: Option<usize>,
+    changes: Vec<Option<LineChange>>,
+}
+
+/// Who last changed each line of the buffer and when, as of when it had the
+/// lines in `lines`, at its `version`. Lines that have changed since are
+/// blamed on no one yet.
+struct Blame {
+    lines: Vec<String>,
+    version: usize,
+    annotations: Vec<String>,
+}
+
+/// The symbols of a file, as of the buffer's `version`, found with the
+/// patterns for the language of the file at `path`
+struct Outline {
+    path: String,
+    outliner: Outliner,
+    version: Option<usize>,
+    entries: Vec<Entry>,
+}
+
+/// Multi-lines text editor.
+///
+/// A `TextArea` will attempt to grow vertically and horizontally
+/// dependent on the content.  Wrap it in a `ResizedView` to
+/// constrain its size.
+///
+/// # Examples
+///
+/// ```
+/// use cursive::traits::{Resizable, Identifiable};
+/// use cursive::views::TextArea;
+///
+/// let text_area = TextArea::new()
+///     .content("Write description here...")
+///     .with_name("text_area")
+///     .fixed_width(30)
+///     .min_height(5);
+/// ```
+pub struct CodeArea<H>
+where
+    H: Highlighter,
+{
+    /// The highlighter for displaying code syntax
+    highlighter: H,
+
+    /// Rows that have already been highlighted, by the state they start in and
+    /// their text, so that only rows that changed are highlighted again when the
+    /// view is redrawn. Each is kept with the state it ends in.
+    highlighted: RefCell<HighlightCache>,
+
+    /// The state each row from the top starts in, as of the last draw
+    row_states: RefCell<RowStates>,
+
+    /// Text that's highlighted on top of how the highlighter highlights it
+    highlight_rules: Vec<HighlightRule>,
+
+    /// The marker used for selection
+    selection_marker: Option<(i32, i32)>,
+
+    /// The rectangular selection, which is used instead of the selection marker
+    block: Option<Block>,
+
+    /// More cursors besides the main one, which edits and movement happen at too
+    cursors: Vec<(i32, i32)>,
+
+    /// The string to comment out code, instead of the one for the file type
+    comment_prefix: Option<String>,
+
+    /// How the file type writes comments
+    comments: Option<CommentStyle>,
+
+    /// The language of the file, if it's one the editor knows
+    language: Option<Language>,
+
+    /// The number of cells between tab stops
+    tab_width: usize,
+
+    /// When `true`, the tab key inserts a tab character instead of spaces
+    hard_tabs: bool,
+
+    /// The number of lines that page up and page down move
+    page_size: usize,
+
+    /// When `true`, the line numbers and status line are hidden,
+    /// and the text is drawn in a column in the middle of the view
+    zen: bool,
+
+    /// How wide the column of text is when `zen` is on
+    zen_width: usize,
+
+    /// The markup language of the file, used to complete closing tags
+    markup: Option<Markup>,
+
+    /// The syntax Emmet abbreviations expand into, for HTML and CSS files
+    emmet: Option<Syntax>,
+
+    /// How the file imports things, used to organize its imports
+    imports: Option<ImportStyle>,
+
+    /// The program that formats the file, picked by its extension
+    formatter: Option<Formatter>,
+
+    /// When `true`, the file is formatted before it's saved
+    format_on_save: bool,
+
+    /// The extension of the file, used to pick its snippets
+    extension: Option<String>,
+
+    /// Settings for files with an extension, in place of the usual ones
+    languages: HashMap<String, LanguageSettings>,
+
+    /// The usual settings that the file's language settings took the place
+    /// of, which are put back when the file type changes
+    replaced: LanguageSettings,
+
+    /// The places left to jump to in the snippet that was expanded last
+    snippet_stops: Option<SnippetStops>,
+
+    /// When `true`, typing a bracket or quote also inserts its closing pair
+    auto_pairs: bool,
+
+    /// Where the closing brackets and quotes that auto-pairing inserted are,
+    /// on the cursor's row. Only these are stepped over by typing them.
+    auto_closers: Vec<(i32, i32)>,
+
+    /// When `true`, a message is shown when a command can't do anything
+    bell: bool,
+
+    /// A message shown at the bottom of the view until the next command
+    message: Option<String>,
+
+    /// The commands run since recording a macro started, if one is being recorded
+    recording: Option<Vec<Command>>,
+
+    /// The commands of the last macro that was recorded
+    recorded_macro: Vec<Command>,
+
+    /// Whether a macro is being played, so its commands' changes
+    /// are recorded together rather than one at a time
+    playing_macro: bool,
+
+    /// A large paste that's being done a chunk at a time
+    pending_paste: Option<PendingPaste>,
+
+    /// The text, which other code areas that show the same buffer share
+    buffer: Rc<RefCell<Buffer>>,
+
+    /// The version of the buffer this code area last saw
+    seen_version: usize,
+
+    /// Stores cut and copied text, which can be shared with other code areas
+    clipboard: Rc<RefCell<Clipboard>>,
+
+    /// The rest of the buffer, when it's narrowed to some lines
+    narrowing: Option<Narrowing>,
+
+    /// The words offered to finish the word before the cursor
+    completion: Option<Completion>,
+
+    /// The last command that was run, to group typing into one undo step
+    last_command: Option<Command>,
+
+    /// How to back up the file before a save overwrites it
+    backup: Option<BackupStyle>,
+
+    /// What to do after a while without edits, and how long to wait
+    autosave: Option<(Autosave, Duration)>,
+
+    /// When the buffer was last changed
+    last_edit: Instant,
+
+    /// When `true`, the buffer has been autosaved since it was last changed
+    autosaved: bool,
+
+    /// Called when the buffer becomes modified, or is saved
+    on_modified: Option<OnModified>,
+
+    /// Called to ask where to save a scratch buffer
+    on_save_as: Option<Callback>,
+
+    /// Called instead of quitting, to check other buffers first
+    on_quit: Option<Callback>,
+
+    /// Called to format a file type that has no formatter, like with a language server
+    on_format: Option<Callback>,
+
+    /// Set by the Save button of the quit dialog, to save and quit once the
+    /// dialog is closed and the view is sent `Event::Refresh`
+    save_and_quit: Rc<Cell<bool>>,
+
+    /// Called when the file changes on disk, like when another program writes it
+    on_file_changed: Option<Callback>,
+
+    /// Which command each key runs
+    keymap: Keymap,
+
+    /// Who last changed each line, if it's shown beside the line numbers
+    blame: Option<Blame>,
+
+    /// When `false`, we don't take any input.
+    enabled: bool,
+
+    /// When `true`, commands that would change the buffer are ignored
+    read_only: bool,
+
+    /// When `true`, scroll to the cursor once the view knows its size
+    scroll_pending: bool,
+
+    /// Base for scrolling features
+    scrollbase: ScrollBase,
+
+    /// How many columns long lines are scrolled sideways
+    scroll_x: usize,
+
+    /// When `true`, rows too long to fit go on in the rows of cells below
+    /// them, instead of being cut off and scrolled sideways
+    soft_wrap: bool,
+
+    /// The row, and the column it starts at, shown on each row of cells
+    /// when `soft_wrap` is on, from when the view was last laid out
+    wrapped_rows: Vec<(i32, i32)>,
+
+    /// When `true`, the words the spell checker doesn't know are underlined
+    spell_check: bool,
+
+    /// The words the spell checker didn't know, and the version of the
+    /// buffer it checked
+    misspelled: Option<(usize, HashSet<String>)>,
+
+    /// When `true`, the view scrolls to keep the cursor's row in the middle
+    typewriter: bool,
+
+    /// The row the view was last scrolled to put in the middle, in typewriter mode
+    centered_row: Option<i32>,
+
+    /// How many columns of text fit beside the line numbers
+    text_width: usize,
+
+    /// How wide the view was when it was last laid out
+    width: usize,
+
+    /// Byte offset of the currently selected grapheme.
+    cursor: (i32, i32),
+}
+
+impl<H> Default for CodeArea<H>
+where
+    H: Highlighter,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H> CodeArea<H>
+where
+    H: Highlighter,
+{
+    pub fn new() -> Self {
+        Self {
+            highlighter: H::default(),
+            highlighted: RefCell::new(HashMap::new()),
+            row_states: RefCell::new(RowStates::default()),
+            highlight_rules: Vec::new(),
+            selection_marker: None,
+            block: None,
+            cursors: Vec::new(),
+            comment_prefix: None,
+            comments: None,
+            language: None,
+            tab_width: 4,
+            hard_tabs: false,
+            page_size: 8,
+            zen: false,
+            zen_width: 80,
+            markup: None,
+            emmet: None,
+            imports: None,
+            formatter: None,
+            format_on_save: false,
+            extension: None,
+            languages: HashMap::new(),
+            replaced: LanguageSettings::default(),
+            snippet_stops: None,
+            auto_pairs: false,
+            auto_closers: Vec::new(),
+            bell: true,
+            message: None,
+            recording: None,
+            recorded_macro: Vec::new(),
+            playing_macro: false,
+            pending_paste: None,
+            clipboard: Rc::default(),
+            narrowing: None,
+            completion: None,
+            last_command: None,
+            backup: None,
+            autosave: None,
+            last_edit: Instant::now(),
+            autosaved: false,
+            on_modified: None,
+            on_save_as: None,
+            on_quit: None,
+            on_format: None,
+            save_and_quit: Rc::new(Cell::new(false)),
+            on_file_changed: None,
+            keymap: Keymap::default(),
+            blame: None,
+            buffer: Rc::new(RefCell::new(Buffer {
+                contents: vec![String::new(), String::new()],
+                filename: String::new(),
+                encryption: None,
+                compression: None,
+                line_ending: LineEnding::Lf,
+                text_encoding: TextEncoding::default(),
+                saved: Vec::new(),
+                history: History::default(),
+                snapshot: Snapshot::default(),
+                modified: false,
+                disk_modified: None,
+                diagnostics: Vec::new(),
+                head: None,
+                outline: None,
+                locked_elsewhere: false,
+                lock: None,
+                version: 0,
+            })),
+            seen_version: 0,
+            enabled: true,
+            read_only: false,
+            scroll_pending: false,
+            scroll_x: 0,
+            soft_wrap: false,
+            wrapped_rows: Vec::new(),
+            spell_check: false,
+            misspelled: None,
+            typewriter: false,
+            centered_row: None,
+            text_width: 0,
+            width: 0,
+            scrollbase: ScrollBase::new().right_padding(0),
+            cursor: (0, 0),
+        }
+    }
+
+    /// Open a file, or start a new one if there's no file with its name.
+    /// A file that can't be read or decompressed isn't opened, so that it
+    /// can't be saved over with an empty buffer.
+    pub fn open_file(mut self, file: impl ToString) -> io::Result<Self> {
+        let file = file.to_string();
+        let contents = match read(&file) {
+            Ok(bytes) => Some(self.decode_file(&file, bytes)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        self.detect_file_type(&file);
+        self.buffer_mut().filename = file;
+        self.buffer_mut().disk_modified = self.disk_modified_time();
+        self.read_head();
+        Ok(match contents {
+            Some(contents) => self.with_content(contents),
+            None => self,
+        })
+    }
+
+    /// Read the file as of the last git commit, to mark the rows that differ
+    /// from it. Encrypted and compressed files aren't compared.
+    pub fn read_head(&mut self) {
+        self.buffer_mut().head = None;
+        if self.buffer().encryption.is_some() || self.buffer().compression.is_some() {
+            return;
+        }
+        let path = self.filename().to_string();
+        if let Ok(text) = head_version(Path::new(&path)) {
+            self.buffer_mut().head = Some(HeadDiff {
+                lines: text.split('\n').map(String::from).collect(),
+                version: None,
+                changes: Vec::new(),
+            });
+        }
+    }
+
+    /// Diff the buffer against the last git commit again, if it changed.
+    /// A narrowed buffer keeps the changes from before it was narrowed.
+    fn update_head_changes(&mut self) {
+        if self.narrowing.is_some() {
+            return;
+        }
+        let mut buffer = self.buffer_mut();
+        let Buffer {
+            head,
+            contents,
+            version,
+            ..
+        } = &mut *buffer;
+        let head = match head {
+            Some(head) if head.version != Some(*version) => head,
+            _ => return,
+        };
+        head.changes = line_changes(&diff(&head.lines, contents));
+        head.version = Some(*version);
+    }
+
+    /// How a row differs from the last git commit
+    fn head_change(&self, row: i32) -> Option<LineChange> {
+        if self.narrowing.is_some() {
+            return None;
+        }
+        let buffer = self.buffer();
+        let head = buffer.head.as_ref()?;
+        *head.changes.get(row as usize)?
+    }
+
+    /// The functions, types or headings in the buffer, in the order they're
+    /// defined. They're only looked for again after the buffer is edited.
+    pub fn outline(&mut self) -> Vec<Entry> {
+        let path = file_type_name(&self.filename()).to_string();
+        let mut buffer = self.buffer_mut();
+        let Buffer {
+            outline,
+            contents,
+            version,
+            ..
+        } = &mut *buffer;
+        if outline.as_ref().map(|outline| &outline.path) != Some(&path) {
+            *outline = Outliner::from_path(&path).map(|outliner| Outline {
+                path,
+                outliner,
+                version: None,
+                entries: Vec::new(),
+            });
+        }
+        let outline = match outline {
+            Some(outline) => outline,
+            None => return Vec::new(),
+        };
+        if outline.version != Some(*version) {
+            outline.entries = outline.outliner.outline(contents);
+            outline.version = Some(*version);
+        }
+        outline.entries.clone()
+    }
+
+    /// Show who last changed each line and when beside the line numbers,
+    /// or stop showing it
+    pub fn toggle_blame(&mut self) {
+        if self.blame.take().is_some() {
+            return;
+        }
+        if self.buffer().encryption.is_some() || self.buffer().compression.is_some() {
+            self.bell("Encrypted and compressed files can't be blamed");
+            return;
+        }
+        let lines = self.whole_contents();
+        let text = lines.join(self.line_ending().as_str());
+        let path = self.filename().to_string();
+        match blame(Path::new(&path), &text) {
+            Ok(blamed) => {
+                let annotations = blamed.iter().map(|line| line.annotation()).collect();
+                let version = self.buffer().version;
+                self.blame = Some(Blame {
+                    lines,
+                    version,
+                    annotations,
+                });
+            }
+            Err(e) => self.bell(format!("Could not blame {}: {}", path, e)),
+        }
+    }
+
+    /// Move the blame of each line along with it after edits, without
+    /// running git again. Changed lines aren't blamed on anyone yet.
+    fn update_blame(&mut self) {
+        let version = self.buffer().version;
+        let lines = match &self.blame {
+            Some(blame) if blame.version != version => self.whole_contents(),
+            _ => return,
+        };
+        let blame = match &mut self.blame {
+            Some(blame) => blame,
+            None => return,
+        };
+        blame.version = version;
+        if blame.lines == lines {
+            return;
+        }
+        let mut old = blame.annotations.iter();
+        let mut annotations = Vec::new();
+        for line in diff(&blame.lines, &lines) {
+            match line {
+                DiffLine::Same(_) => annotations.push(old.next().cloned().unwrap_or_default()),
+                DiffLine::Removed(_) => {
+                    old.next();
+                }
+                DiffLine::Added(_) => annotations.push(String::from(NOT_COMMITTED)),
+            }
+        }
+        blame.annotations = annotations;
+        blame.lines = lines;
+    }
+
+    /// Move the cursor to the start of the next block of rows that differ
+    /// from the last git commit, or the previous one, going around the end
+    pub fn jump_to_head_change(&mut self, forward: bool) {
+        self.update_head_changes();
+        if self.buffer().head.is_none() {
+            self.bell("The file isn't in a git repository");
+            return;
+        }
+        let starts: Vec<i32> = (0..self.contents().len() as i32)
+            .filter(|&row| {
+                self.head_change(row).is_some() && (row == 0 || self.head_change(row - 1).is_none())
+            })
+            .collect();
+        let row = self.cursor.0;
+        let found = if forward {
+            starts.iter().find(|&&start| start > row)
+        } else {
+            starts.iter().rev().find(|&&start| start < row)
+        };
+        let wrapped = if forward {
+            starts.first()
+        } else {
+            starts.last()
+        };
+        match found.or(wrapped) {
+            Some(&start) => {
+                self.cursor = (start, 0);
+                self.forget_selection();
+                self.fix();
+            }
+            None => self.bell("There are no changes since the last commit"),
+        }
+    }
+
+    /// Open a file encrypted with `gpg` or `age`. The decrypted contents
+    /// are only held in memory, and are encrypted again on save.
+    pub fn open_encrypted(
+        mut self,
+        file: impl ToString,
+        encryption: Encryption,
+    ) -> io::Result<Self> {
+        let file = file.to_string();
+        let plaintext = encryption.decrypt(&read(&file)?)?;
+        // Encrypted files can hold compressed files, like `notes.txt.gz.gpg`
+        let inner_name = decrypted_name(&file, &encryption);
+        let contents = self.decode_file(inner_name, plaintext)?;
+        self.detect_file_type(inner_name);
+
+        self.buffer_mut().filename = file;
+        self.buffer_mut().encryption = Some(encryption);
+        self.buffer_mut().disk_modified = self.disk_modified_time();
+        Ok(self.with_content(contents))
+    }
+
+    /// Replace the contents with the file as it is on disk now, like after
+    /// another program changed it. Unsaved changes are lost, but the reload
+    /// can be undone. Returns the `on_modified` callback if the buffer was
+    /// modified before.
+    pub fn reload_from_disk(&mut self) -> io::Result<Option<Callback>> {
+        let was_modified = self.buffer().modified;
+        self.widen();
+        let text = self.read_from_disk()?;
+        let before = replace(
+            &mut *self.contents_mut(),
+            vec![String::new(), String::new()],
+        );
+        let cursor = self.cursor;
+        self.cursor = (0, 0);
+        self.load_text(text);
+        self.cursor = cursor;
+        self.fix();
+        self.record_change(&before, cursor);
+        self.buffer_mut().history.set_saved(true);
+        self.forget_selection();
+        self.buffer_mut().modified = false;
+        self.buffer_mut().disk_modified = self.disk_modified_time();
+        // The file may have changed because it was committed, or checked out
+        self.read_head();
+        Ok(self.notify_modified(was_modified, None))
+    }
+
+    /// Merge the file as it is on disk now into the buffer, keeping the unsaved
+    /// changes that don't conflict with the changes on disk, and return how many
+    /// conflicts there were. Conflicts are marked in the buffer with both versions.
+    /// The merge can be undone, and the cursor stays on the same text.
+    pub fn merge_from_disk(&mut self) -> io::Result<usize> {
+        self.widen();
+        let text = self.read_from_disk()?.replace("\r\n", "\n");
+        let theirs: Vec<String> = text.split('\n').map(String::from).collect();
+        let merge = merge(
+            &self.buffer().saved,
+            ("buffer", &self.contents()),
+            ("disk", &theirs),
+        );
+
+        let before = replace(&mut *self.contents_mut(), merge.lines);
+        let cursor = self.cursor;
+        self.cursor.0 = self.follow_row(&before, cursor.0);
+        self.fix();
+        self.record_change(&before, cursor);
+        self.forget_selection();
+        self.cursors.clear();
+        let modified = *self.contents() != theirs;
+        self.buffer_mut().modified = modified;
+        self.buffer_mut().history.set_saved(!modified);
+        self.buffer_mut().saved = theirs;
+        self.buffer_mut().disk_modified = self.disk_modified_time();
+        Ok(merge.conflicts)
+    }
+
+    /// Where a row of `before` is in the contents now, which changed from it
+    fn follow_row(&self, before: &[String], row: i32) -> i32 {
+        let (mut old, mut new) = (0, 0);
+        for line in diff(before, &self.contents()) {
+            match line {
+                DiffLine::Added(_) => new += 1,
+                _ if old == row => break,
+                DiffLine::Same(_) => {
+                    old += 1;
+                    new += 1;
+                }
+                DiffLine::Removed(_) => old += 1,
+            }
+        }
+        new
+    }
+
+    /// Read the file as it is on disk now
+    fn read_from_disk(&mut self) -> io::Result<String> {
+        let mut bytes = read(&*self.filename())?;
+        let mut name = self.filename().to_string();
+        if let Some(encryption) = &self.buffer().encryption {
+            bytes = encryption.decrypt(&bytes)?;
+            name = decrypted_name(&name, encryption).to_string();
+        }
+        self.decode_file(&name, bytes)
+    }
+
+    /// When the file on disk was last changed
+    fn disk_modified_time(&self) -> Option<SystemTime> {
+        metadata(&*self.filename())
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Set a callback to run when the file changes on disk, which could call
+    /// `reload_from_disk`. Files are checked for changes when the view gets
+    /// a refresh event, so the `Cursive` it's in should have an FPS set.
+    pub fn on_file_changed<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&mut Cursive) + 'static,
+    {
+        self.on_file_changed = Some(Callback::from_fn(callback));
+        self
+    }
+
+    /// The `on_file_changed` callback, if the file changed on disk since it was last checked
+    fn file_changed_callback(&mut self) -> Option<Callback> {
+        let on_file_changed = self.on_file_changed.clone()?;
+        let modified = self.disk_modified_time();
+        if modified.is_none() || modified == self.buffer().disk_modified {
+            return None;
+        }
+        self.buffer_mut().disk_modified = modified;
+        Some(on_file_changed)
+    }
+
+    /// Decompress and decode the contents of a file, and remember
+    /// to encode and compress it the same way when saving
+    fn decode_file(&mut self, file: &str, bytes: Vec<u8>) -> io::Result<String> {
+        let compression = Compression::from_path(file);
+        let bytes = match compression {
+            Some(compression) => compression.decompress(&bytes)?,
+            None => bytes,
+        };
+        let text_encoding = TextEncoding::detect(&bytes);
+        let mut buffer = self.buffer_mut();
+        buffer.compression = compression;
+        buffer.text_encoding = text_encoding;
+        Ok(text_encoding.decode(&bytes))
+    }
+
+    /// Turn on the language features that a file's name calls for
+    fn detect_file_type(&mut self, file: &str) {
+        let file = file_type_name(file);
+        self.markup = Markup::from_path(file);
+        self.emmet = Syntax::from_path(file);
+        self.imports = ImportStyle::from_path(file);
+        self.comments = CommentStyle::from_path(file);
+        self.language = Language::from_path(file);
+        self.highlighter.set_file_name(file);
+        self.highlighted.get_mut().clear();
+        *self.row_states.get_mut() = RowStates::default();
+        self.extension = extension(file).map(String::from);
+        let settings = self
+            .extension
+            .as_ref()
+            .and_then(|ext| self.languages.get(ext))
+            .cloned()
+            .unwrap_or_default();
+        self.formatter = match settings.formatter {
+            Some(formatter) => formatter,
+            None => Formatter::from_path(file),
+        };
+
+        let replaced = take(&mut self.replaced);
+        self.tab_width = replaced.tab_width.unwrap_or(self.tab_width);
+        self.hard_tabs = replaced.hard_tabs.unwrap_or(self.hard_tabs);
+        self.auto_pairs = replaced.auto_pairs.unwrap_or(self.auto_pairs);
+        self.typewriter = replaced.typewriter.unwrap_or(self.typewriter);
+        self.soft_wrap = replaced.soft_wrap.unwrap_or(self.soft_wrap);
+        self.spell_check = replaced.spell_check.unwrap_or(self.spell_check);
+        if let Some(width) = settings.tab_width {
+            self.replaced.tab_width = Some(replace(&mut self.tab_width, max(width, 1)));
+        }
+        if let Some(hard_tabs) = settings.hard_tabs {
+            self.replaced.hard_tabs = Some(replace(&mut self.hard_tabs, hard_tabs));
+        }
+        if let Some(auto_pairs) = settings.auto_pairs {
+            self.replaced.auto_pairs = Some(replace(&mut self.auto_pairs, auto_pairs));
+        }
+        if let Some(typewriter) = settings.typewriter {
+            self.replaced.typewriter = Some(replace(&mut self.typewriter, typewriter));
+        }
+        if let Some(soft_wrap) = settings.soft_wrap {
+            self.replaced.soft_wrap = Some(replace(&mut self.soft_wrap, soft_wrap));
+        }
+        if let Some(spell_check) = settings.spell_check {
+            self.replaced.spell_check = Some(replace(&mut self.spell_check, spell_check));
+        }
+        if self.soft_wrap {
+            self.scroll_x = 0;
+        }
+        self.misspelled = None;
+    }
+
+    /// Set the content, which is saved with the line ending most of its lines use
+    pub fn with_content(mut self, content: impl ToString) -> Self {
+        self.load_text(content.to_string());
+        self
+    }
+
+    /// Insert the text of a file, and save it with the line ending most of its lines use
+    fn load_text(&mut self, content: String) {
+        let line_ending = LineEnding::detect(&content);
+        self.buffer_mut().line_ending = line_ending;
+        match line_ending {
+            LineEnding::CrLf => self.insert_str(content.replace("\r\n", "\n")),
+            LineEnding::Lf => self.insert_str(content),
+        }
+        self.cursor = (0, 0);
+        let saved = self.contents().clone();
+        self.buffer_mut().saved = saved;
+        self.buffer_mut().history.set_saved(true);
+    }
+
+    /// How the text is encoded on disk
+    pub fn text_encoding(&self) -> TextEncoding {
+        self.buffer().text_encoding
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.buffer().line_ending
+    }
+
+    /// Save the file with a different line ending
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if self.line_ending() != line_ending {
+            let mut buffer = self.buffer_mut();
+            buffer.line_ending = line_ending;
+            buffer.modified = true;
+            drop(buffer);
+            self.autosaved = false;
+        }
+    }
+
+    pub fn with_comment(mut self, comment: impl ToString) -> Self {
+        self.comment_prefix = Some(comment.to_string());
+        self
+    }
+
+    pub fn with_tab_width(mut self, width: usize) -> Self {
+        self.tab_width = max(width, 1);
+        self.highlighted.get_mut().clear();
+        self
+    }
+
+    /// Highlight the text that rules match, on top of how the highlighter
+    /// highlights it, in place of the old rules
+    pub fn with_highlight_rules(mut self, rules: Vec<HighlightRule>) -> Self {
+        self.highlight_rules = rules;
+        self.highlighted.get_mut().clear();
+        self
+    }
+
+    pub fn with_hard_tabs(mut self, hard_tabs: bool) -> Self {
+        self.hard_tabs = hard_tabs;
+        self
+    }
+
+    /// How many columns a tab takes up
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
+    /// Whether lines are indented with tabs instead of spaces
+    pub fn hard_tabs(&self) -> bool {
+        self.hard_tabs
+    }
+
+    pub fn with_page_size(mut self, lines: usize) -> Self {
+        self.page_size = lines;
+        self
+    }
+
+    pub fn with_typewriter(mut self, typewriter: bool) -> Self {
+        self.typewriter = typewriter;
+        self
+    }
+
+    /// Wrap rows too long to fit onto the rows of cells below them
+    pub fn with_soft_wrap(mut self, soft_wrap: bool) -> Self {
+        self.soft_wrap = soft_wrap;
+        self.scroll_x = 0;
+        self
+    }
+
+    /// Underline the words the spell checker doesn't know, which needs
+    /// `aspell` or `hunspell`
+    pub fn with_spell_check(mut self, spell_check: bool) -> Self {
+        self.spell_check = spell_check;
+        self.misspelled = None;
+        self
+    }
+
+    pub fn with_zen_width(mut self, columns: usize) -> Self {
+        self.zen_width = columns;
+        self
+    }
+
+    pub fn with_keymap(mut self, keymap: Keymap) -> Self {
+        self.keymap = keymap;
+        self
+    }
+
+    /// Show diagnostics at the ends of their lines, in place of the old ones
+    pub fn set_diagnostics(&mut self, diagnostics: Vec<Diagnostic>) {
+        self.buffer_mut().diagnostics = diagnostics;
+    }
+
+    pub fn diagnostics(&self) -> Ref<'_, [Diagnostic]> {
+        Ref::map(self.buffer(), |buffer| buffer.diagnostics.as_slice())
+    }
+
+    /// The most serious diagnostic on a row
+    fn worst_diagnostic(&self, row: i32) -> Option<Ref<'_, Diagnostic>> {
+        let line = row as usize + 1 + self.hidden_above();
+        Ref::filter_map(self.buffer(), |buffer| {
+            buffer
+                .diagnostics
+                .iter()
+                .filter(|d| d.line == line)
+                .min_by_key(|d| d.severity)
+        })
+        .ok()
+    }
+
+    /// Move the cursor to the next diagnostic after it, or the previous one
+    /// before it, going around the end of the buffer
+    pub fn jump_to_diagnostic(&mut self, forward: bool) {
+        let first = self.hidden_above() as i32;
+        let rows = self.contents().len() as i32;
+        let mut positions: Vec<(i32, i32)> = self
+            .diagnostics()
+            .iter()
+            .map(|d| (d.line as i32 - 1 - first, d.column as i32 - 1))
+            .filter(|&(row, _)| 0 <= row && row < rows)
+            .collect();
+        positions.sort_unstable();
+        let found = if forward {
+            positions.iter().find(|&&pos| pos > self.cursor)
+        } else {
+            positions.iter().rev().find(|&&pos| pos < self.cursor)
+        };
+        let wrapped = if forward {
+            positions.first()
+        } else {
+            positions.last()
+        };
+        match found.or(wrapped) {
+            Some(&(row, col)) => {
+                self.cursor = (row, col);
+                self.fix();
+            }
+            None => self.bell("There are no diagnostics"),
+        }
+    }
+
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Whether another editor had the file locked when it was opened,
+    /// and it hasn't been locked here since, so it can't be saved
+    pub fn is_locked_elsewhere(&self) -> bool {
+        self.buffer().locked_elsewhere
+    }
+
+    /// Mark the file as locked by another editor, so changes to it can't be
+    /// allowed until the lock is taken
+    pub fn with_locked_elsewhere(mut self, locked: bool) -> Self {
+        self.buffer_mut().locked_elsewhere = locked;
+        self
+    }
+
+    /// Stop changes to the buffer, or allow them again. A file that another
+    /// editor had locked is locked here first.
+    pub fn toggle_read_only(&mut self) {
+        if self.read_only && self.buffer().locked_elsewhere && !self.take_lock() {
+            return;
+        }
+        self.read_only = !self.read_only;
+        self.completion = None;
+    }
+
+    /// Lock the file for this editor if the one that had it locked let go of
+    /// it. Returns whether it could.
+    fn take_lock(&mut self) -> bool {
+        let path = self.filename().to_string();
+        match FileLock::acquire(Path::new(&path)) {
+            Ok(Some(lock)) => {
+                let mut buffer = self.buffer_mut();
+                buffer.lock = Some(lock);
+                buffer.locked_elsewhere = false;
+                true
+            }
+            Ok(None) => {
+                self.bell("Another editor still has the file locked");
+                false
+            }
+            Err(e) => {
+                self.bell(format!("Could not lock the file: {}", e));
+                false
+            }
+        }
+    }
+
+    /// The keymap, to rebind keys after the code area is made
+    pub fn keymap_mut(&mut self) -> &mut Keymap {
+        &mut self.keymap
+    }
+
+    pub fn with_auto_pairs(mut self, auto_pairs: bool) -> Self {
+        self.auto_pairs = auto_pairs;
+        self
+    }
+
+    /// Share a clipboard with other code areas
+    pub fn with_clipboard(mut self, clipboard: Rc<RefCell<Clipboard>>) -> Self {
+        self.clipboard = clipboard;
+        self
+    }
+
+    /// Whether to show a message when a command can't do anything
+    pub fn with_bell(mut self, bell: bool) -> Self {
+        self.bell = bell;
+        self
+    }
+
+    /// Show a message at the bottom of the view until the next command,
+    /// like when a command can't do anything. Does nothing if the bell is off.
+    pub fn bell(&mut self, message: impl ToString) {
+        if self.bell {
+            self.message = Some(message.to_string());
+        }
+    }
+
+    /// The message shown at the bottom of the view, if there is one
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+
+    /// Start recording the commands that run as a macro, or stop recording
+    pub fn toggle_macro_recording(&mut self) {
+        match self.recording.take() {
+            Some(commands) => self.recorded_macro = commands,
+            None => self.recording = Some(Vec::new()),
+        }
+    }
+
+    /// Whether a macro is being recorded
+    pub fn is_recording_macro(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Run the commands of the last recorded macro some number of times,
+    /// stopping the recording first if there is one. Everything the macro
+    /// changed is undone in one step.
+    pub fn play_macro(&mut self, times: usize) -> Option<Callback> {
+        if let Some(commands) = self.recording.take() {
+            self.recorded_macro = commands;
+        }
+        if self.recorded_macro.is_empty() {
+            self.bell("No macro has been recorded");
+            return None;
+        }
+        if times > MAX_MACRO_PLAYS {
+            self.bell(format!(
+                "A macro can be played at most {} times",
+                MAX_MACRO_PLAYS
+            ));
+            return None;
+        }
+        let commands = self.recorded_macro.clone();
+        let before = self.contents().clone();
+        let cursor_before = self.cursor;
+        let mut callback = None;
+        self.playing_macro = true;
+        for _ in 0..times {
+            for &command in &commands {
+                callback = chain(callback, self.run_command(command));
+            }
+        }
+        self.playing_macro = false;
+        self.record_change(&before, cursor_before);
+        callback
+    }
+
+    pub fn with_markup(mut self, markup: Markup) -> Self {
+        self.markup = Some(markup);
+        self
+    }
+
+    pub fn with_backup(mut self, backup: BackupStyle) -> Self {
+        self.backup = Some(backup);
+        self
+    }
+
+    /// Autosave after `delay` has passed without any edits. This needs
+    /// the `Cursive` root to refresh regularly, for example with `set_fps`.
+    pub fn with_autosave(mut self, autosave: Autosave, delay: Duration) -> Self {
+        self.autosave = Some((autosave, delay));
+        self
+    }
+
+    pub fn with_emmet(mut self, syntax: Syntax) -> Self {
+        self.emmet = Some(syntax);
+        self
+    }
+
+    pub fn with_import_style(mut self, style: ImportStyle) -> Self {
+        self.imports = Some(style);
+        self
+    }
+
+    /// Use different settings for files with an extension, like `md`,
+    /// including their formatter and snippets
+    pub fn with_language_settings(
+        mut self,
+        extension: impl ToString,
+        settings: LanguageSettings,
+    ) -> Self {
+        self.languages.insert(extension.to_string(), settings);
+        self
+    }
+
+    /// Format the file every time it's saved with the save command
+    pub fn with_format_on_save(mut self, format_on_save: bool) -> Self {
+        self.format_on_save = format_on_save;
+        self
+    }
+
+    pub fn save_content(&mut self) -> io::Result<()> {
+        if self.is_scratch() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the buffer has no file name",
+            ));
+        }
+        // Saving would overwrite the changes the other editor makes
+        if self.is_locked_elsewhere() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "another editor has the file locked",
+            ));
+        }
+        write_file(&self.filename(), &self.file_bytes()?, self.backup)?;
+        self.buffer_mut().modified = false;
+        self.buffer_mut().saved = self.whole_contents();
+        self.buffer_mut().history.set_saved(true);
+        self.buffer_mut().disk_modified = self.disk_modified_time();
+
+        // A snapshot is out of date once the file itself is saved
+        if let Some((Autosave::Snapshot, _)) = self.autosave {
+            let _ = remove_file(snapshot_path(&self.filename()));
+        }
+        Ok(())
+    }
+
+    /// Autosave if it's turned on, and the buffer hasn't been changed for long enough
+    /// Check the spelling again if spell checking is on and the buffer
+    /// changed since it was last checked, once typing pauses. If it can't
+    /// be checked, spell checking is turned off.
+    fn check_spelling_if_idle(&mut self) {
+        let version = self.buffer().version;
+        let checked = self.misspelled.as_ref().map(|(checked, _)| *checked);
+        if !self.spell_check || checked == Some(version) {
+            return;
+        }
+        if checked.is_some() && self.last_edit.elapsed() < TYPING_PAUSE {
+            return;
+        }
+        let text = self.contents().join("\n");
+        match misspelled_words(&text) {
+            Ok(words) => self.misspelled = Some((version, words)),
+            Err(e) => {
+                self.spell_check = false;
+                self.misspelled = None;
+                self.message = Some(format!("Could not check spelling: {}", e));
+            }
+        }
+    }
+
+    fn autosave_if_idle(&mut self) -> io::Result<()> {
+        // A scratch buffer has nowhere to be saved
+        let (autosave, delay) = match self.autosave {
+            Some(autosave) if !self.is_scratch() => autosave,
+            _ => return Ok(()),
+        };
+        if !self.buffer().modified || self.autosaved || self.last_edit.elapsed() < delay {
+            return Ok(());
+        }
+
+        // Don't try again until there's another edit, even if this fails
+        self.autosaved = true;
+        match autosave {
+            Autosave::Save => self.save_content(),
+            Autosave::Snapshot => write(snapshot_path(&self.filename()), self.file_bytes()?),
+        }
+    }
+
+    /// Save the file under a new name, and keep editing it under that name.
+    /// The new name decides how the file is compressed, but an encrypted
+    /// file has to keep its cipher's extension so it isn't saved as plaintext.
+    pub fn save_as(&mut self, file: impl ToString) -> io::Result<()> {
+        let file = file.to_string();
+        let inner_name = match &self.buffer().encryption {
+            Some(encryption) => {
+                let extension = encryption.cipher().extension();
+                match file.strip_suffix(extension) {
+                    Some(inner_name) => inner_name.to_string(),
+                    None => {
+                        return Err(io::Error::other(format!(
+                            "encrypted files must be saved with a {} extension",
+                            extension
+                        )))
+                    }
+                }
+            }
+            None => file.clone(),
+        };
+
+        // A copy under another name isn't the file another editor has locked
+        let locked_elsewhere = self.is_locked_elsewhere();
+        let mut buffer = self.buffer_mut();
+        buffer.locked_elsewhere &= file == buffer.filename;
+        let old_filename = replace(&mut buffer.filename, file);
+        let old_compression = replace(&mut buffer.compression, Compression::from_path(&inner_name));
+        drop(buffer);
+        if let Err(e) = self.save_content() {
+            let mut buffer = self.buffer_mut();
+            buffer.filename = old_filename;
+            buffer.compression = old_compression;
+            buffer.locked_elsewhere = locked_elsewhere;
+            return Err(e);
+        }
+        self.detect_file_type(&inner_name);
+        Ok(())
+    }
+
+    /// The name of the file being edited
+    pub fn filename(&self) -> Ref<'_, str> {
+        Ref::map(self.buffer(), |buffer| buffer.filename.as_str())
+    }
+
+    /// Whether the buffer has no file, like text read from standard input
+    pub fn is_scratch(&self) -> bool {
+        self.filename().is_empty()
+    }
+
+    /// Set a callback to ask where to save a scratch buffer, which is run
+    /// instead of saving it. It should call `save_as` on this code area.
+    pub fn on_save_as<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&mut Cursive) + 'static,
+    {
+        self.on_save_as = Some(Callback::from_fn(callback));
+        self
+    }
+
+    /// Set a callback to run instead of quitting, like to check whether
+    /// other buffers have unsaved changes. It should quit when it's done.
+    pub fn on_quit<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&mut Cursive) + 'static,
+    {
+        self.on_quit = Some(Callback::from_fn(callback));
+        self
+    }
+
+    /// Set a callback to run when the format command is used on a file type
+    /// with no formatter, like to ask a language server to format it. It
+    /// should call `apply_edits` with the edits that format the buffer.
+    pub fn on_format<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&mut Cursive) + 'static,
+    {
+        self.on_format = Some(Callback::from_fn(callback));
+        self
+    }
+
+    /// Whether there are changes that haven't been saved
+    pub fn is_modified(&self) -> bool {
+        self.buffer().modified
+    }
+
+    /// Forget that there are unsaved changes, like after saving the buffer somewhere else
+    pub fn set_clean(&mut self) {
+        self.buffer_mut().modified = false;
+        self.buffer_mut().history.set_saved(true);
+    }
+
+    /// Set a callback to run when the buffer becomes modified or is saved,
+    /// which is given whether the buffer is now modified
+    pub fn set_on_modified<F>(&mut self, callback: F)
+    where
+        F: Fn(&mut Cursive, bool) + 'static,
+    {
+        self.on_modified = Some(Rc::new(callback));
+    }
+
+    pub fn on_modified<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&mut Cursive, bool) + 'static,
+    {
+        self.set_on_modified(callback);
+        self
+    }
+
+    /// Add a call to the `on_modified` callback after another
+    /// callback, if the buffer's modified state has changed
+    fn notify_modified(&self, was_modified: bool, callback: Option<Callback>) -> Option<Callback> {
+        let on_modified = match &self.on_modified {
+            Some(on_modified) if was_modified != self.buffer().modified => on_modified.clone(),
+            _ => return callback,
+        };
+        let modified = self.buffer().modified;
+        Some(Callback::from_fn(move |s| {
+            if let Some(callback) = &callback {
+                callback(s);
+            }
+            on_modified(s, modified);
+        }))
+    }
+
+    /// A callback that quits, after asking whether to save
+    /// first if there are unsaved changes
+    fn quit_callback(&self) -> Callback {
+        if !self.buffer().modified {
+            return Callback::from_fn_mut(|s| s.quit());
+        }
+        if self.is_scratch() {
+            return Callback::from_fn(|s| {
+                s.add_layer(
+                    Dialog::text("This buffer has never been saved. Quit anyway?")
+                        .title("Quit")
+                        .button("Quit", |s| s.quit())
+                        .dismiss_button("Cancel"),
+                )
+            });
+        }
+
+        // The view saves itself once the dialog is closed, like it does for
+        // the save command
+        let save_and_quit = self.save_and_quit.clone();
+        Callback::from_fn(move |s| {
+            let save_and_quit = save_and_quit.clone();
+            let save = move |s: &mut Cursive| {
+                save_and_quit.set(true);
+                s.pop_layer();
+                s.on_event(Event::Refresh);
+            };
+            s.add_layer(
+                Dialog::text("There are unsaved changes. Save them before quitting?")
+                    .title("Quit")
+                    .button("Save", save)
+                    .button("Discard", |s| s.quit())
+                    .dismiss_button("Cancel"),
+            );
+        })
+    }
+
+    /// The bytes that will be written to disk when the file is saved
+    pub fn file_bytes(&self) -> io::Result<Vec<u8>> {
+        let text = self.whole_contents().join(self.line_ending().as_str());
+        let mut bytes = self.text_encoding().encode(&text)?;
+        if let Some(compression) = self.buffer().compression {
+            bytes = compression.compress(&bytes)?;
+        }
+        match &self.buffer().encryption {
+            Some(encryption) => encryption.encrypt(&bytes),
+            None => Ok(bytes),
+        }
+    }
+
+    /// The text of the file as it is on disk, or nothing if it hasn't been saved yet
+    pub fn saved_text(&self) -> io::Result<String> {
+        let mut bytes = match read(&*self.filename()) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(String::new()),
+            Err(e) => return Err(e),
+        };
+        if let Some(encryption) = &self.buffer().encryption {
+            bytes = encryption.decrypt(&bytes)?;
+        }
+        if let Some(compression) = self.buffer().compression {
+            bytes = compression.decompress(&bytes)?;
+        }
+        Ok(self.text_encoding().decode(&bytes).replace("\r\n", "\n"))
+    }
+
+    /// The changes that saving would make to the file on disk
+    pub fn unsaved_changes(&self) -> io::Result<Vec<Hunk>> {
+        let saved = self.saved_text()?;
+        let saved: Vec<&str> = saved.split('\n').collect();
+        Ok(hunks(&diff(&saved, &self.whole_contents()), 3))
+    }
+
+    /// The changes to the buffer since the file's last git commit,
+    /// including ones that haven't been saved yet
+    pub fn head_changes(&self) -> io::Result<Vec<Hunk>> {
+        if self.buffer().encryption.is_some() || self.buffer().compression.is_some() {
+            return Err(io::Error::other("the file isn't stored as text"));
+        }
+        let committed = head_version(Path::new(&*self.filename()))?;
+        let committed: Vec<&str> = committed.split('\n').collect();
+        Ok(hunks(&diff(&committed, &self.whole_contents()), 3))
+    }
+
+    /// Summarize the file on disk and the contents that would replace it
+    pub fn file_info(&self) -> FileInfo {
+        let summarize = |bytes: Vec<u8>| (bytes.len(), sha256_hex(&bytes));
+        FileInfo {
+            filename: self.filename().to_string(),
+            // The last line is always the empty line after the final newline
+            lines: self.whole_contents().len() - 1,
+            encoding: self.text_encoding().name(),
+            line_ending: self.line_ending().name(),
+            on_disk: read(&*self.filename()).ok().map(summarize),
+            in_buffer: self.file_bytes().ok().map(summarize),
+        }
+    }
+
+    pub fn is_selecting(&self) -> bool {
+        self.selection_marker.is_some()
+    }
+
+    pub fn continue_selection(&mut self) {
+        if !self.is_selecting() {
+            self.selection_marker = Some(self.cursor)
+        }
+    }
+
+    pub fn forget_selection(&mut self) {
+        self.selection_marker = None;
+        self.block = None;
+    }
+
+    /// Select the whole buffer
+    pub fn select_all(&mut self) {
+        let last = self.contents().len() as i32 - 1;
+        self.block = None;
+        self.selection_marker = Some((0, 0));
+        self.cursor = (last, self.row_len(last));
+    }
+
+    /// Select the word under the cursor
+    pub fn select_word(&mut self) {
+        let row = self.cursor.0;
+        if let Some((start, end)) = self.word_at(self.cursor) {
+            self.block = None;
+            self.selection_marker = Some((row, self.col_at_byte(row, start)));
+            self.cursor = (row, self.col_at_byte(row, end));
+        }
+    }
+
+    /// The word under the cursor, or just before it
+    pub fn word_under_cursor(&self) -> Option<String> {
+        let (start, end) = self.word_at(self.cursor)?;
+        Some(self.line(self.cursor.0)[start..end].to_string())
+    }
+
+    /// Select the lines the cursor or selection is on, with their line breaks.
+    /// If whole lines are already selected, the next line is added.
+    pub fn select_line(&mut self) {
+        let last = self.contents().len() as i32 - 1;
+        let (top, mut bottom) = self
+            .selected_rows()
+            .unwrap_or((self.cursor.0, self.cursor.0));
+        let line_end = |area: &Self, bottom: i32| {
+            if bottom < last {
+                (bottom + 1, 0)
+            } else {
+                (bottom, area.row_len(bottom))
+            }
+        };
+        if self.selection_bounds() == Some(((top, 0), line_end(self, bottom))) && bottom < last {
+            bottom += 1;
+        }
+        self.block = None;
+        self.selection_marker = Some((top, 0));
+        self.cursor = line_end(self, bottom);
+    }
+
+    /// Whether there's a rectangular selection
+    pub fn is_selecting_block(&self) -> bool {
+        self.block.is_some()
+    }
+
+    /// Start a rectangular selection at the cursor, if there isn't one already
+    fn continue_block_selection(&mut self) {
+        if self.block.is_none() {
+            let (row, col) = self.cursor;
+            let x = self.display_col(row, col);
+            self.selection_marker = None;
+            self.block = Some(Block {
+                anchor: (row, x),
+                corner: (row, x),
+            });
+        }
+    }
+
+    /// Move the moving corner of the block, and the cursor with it
+    fn move_block_corner(&mut self, rows: i32, cells: isize) {
+        let last_row = self.contents().len() as i32 - 1;
+        if let Some(block) = &mut self.block {
+            let (row, x) = block.corner;
+            block.corner = (
+                min(max(row + rows, 0), last_row),
+                max(x as isize + cells, 0) as usize,
+            );
+        }
+        self.sync_block_cursor();
+    }
+
+    /// Put the cursor on the moving corner of the block
+    fn sync_block_cursor(&mut self) {
+        if let Some(Block {
+            corner: (row, x), ..
+        }) = self.block
+        {
+            self.cursor = (row, self.col_at_display(row, x));
+        }
+    }
+
+    /// The bytes of a row that are in the cells from `left` up to `right`
+    fn row_cells(&self, row: i32, left: usize, right: usize) -> (usize, usize) {
+        (
+            self.byte_offset(row, self.col_at_display(row, left)),
+            self.byte_offset(row, self.col_at_display(row, right)),
+        )
+    }
+
+    /// Add spaces to the end of a row until it reaches cell `x`
+    fn pad_row(&mut self, row: i32, x: usize) {
+        let width = self.display_col(row, self.row_len(row));
+        if width < x {
+            self.row(row).push_str(&" ".repeat(x - width));
+        }
+    }
+
+    /// Copy the rows of the block, to be pasted as a block
+    pub fn copy_block(&mut self) {
+        let block = match self.block {
+            Some(block) => block,
+            None => return,
+        };
+        let ((top, bottom), (left, right)) = (block.rows(), block.cells());
+        let rows: Vec<String> = (top..=bottom)
+            .map(|row| {
+                let (start, end) = self.row_cells(row, left, right);
+                self.line(row)[start..end].to_string()
+            })
+            .collect();
+        self.set_clipboard(rows.join("\n"), ClipboardKind::Block);
+    }
+
+    /// Remove the text in the block, leaving it an empty column
+    fn delete_block_text(&mut self) {
+        let mut block = match self.block {
+            Some(block) => block,
+            None => return,
+        };
+        let ((top, bottom), (left, right)) = (block.rows(), block.cells());
+        for row in top..=bottom {
+            let (start, end) = self.row_cells(row, left, right);
+            self.row(row).replace_range(start..end, "");
+        }
+        block.collapse_to(left);
+        self.block = Some(block);
+        self.sync_block_cursor();
+    }
+
+    /// Cut the rows of the block, to be pasted as a block
+    pub fn cut_block(&mut self) {
+        self.copy_block();
+        self.delete_block_text();
+    }
+
+    /// Type a character on every row of the block, replacing what's in it
+    fn type_in_block(&mut self, ch: char) {
+        self.delete_block_text();
+        let mut block = match self.block {
+            Some(block) => block,
+            None => return,
+        };
+        let ((top, bottom), (x, _)) = (block.rows(), block.cells());
+        for row in top..=bottom {
+            self.pad_row(row, x);
+            let byte = self.byte_offset(row, self.col_at_display(row, x));
+            self.row(row).insert(byte, ch);
+        }
+        let (row, _) = block.corner;
+        let col = self.col_at_display(row, x) + 1;
+        block.collapse_to(self.display_col(row, col));
+        self.block = Some(block);
+        self.sync_block_cursor();
+    }
+
+    /// Delete the text in the block, or if it's empty, the character
+    /// before it (or after it, with `forward`) on every row
+    fn delete_in_block(&mut self, forward: bool) {
+        let mut block = match self.block {
+            Some(block) => block,
+            None => return,
+        };
+        let ((top, bottom), (x, right)) = (block.rows(), block.cells());
+        if x != right {
+            self.delete_block_text();
+            return;
+        }
+        if !forward && x == 0 {
+            return;
+        }
+
+        let (corner_row, _) = block.corner;
+        let new_x = if forward {
+            x
+        } else {
+            let col = self.col_at_display(corner_row, x - 1);
+            self.display_col(corner_row, col)
+        };
+        for row in top..=bottom {
+            let width = self.display_col(row, self.row_len(row));
+            let target = if forward { x } else { x - 1 };
+            if target >= width {
+                continue;
+            }
+            let col = self.col_at_display(row, target);
+            let (start, end) = (self.byte_offset(row, col), self.byte_offset(row, col + 1));
+            self.row(row).replace_range(start..end, "");
+        }
+        block.collapse_to(new_x);
+        self.block = Some(block);
+        self.sync_block_cursor();
+    }
+
+    /// Paste the rows of a copied block onto the rows from the cursor down,
+    /// at the cursor's column
+    pub fn paste_block(&mut self, rows: &str) {
+        let (row, col) = self.cursor;
+        let x = self.display_col(row, col);
+        for (i, text) in rows.split('\n').enumerate() {
+            let row = row + i as i32;
+            while row as usize >= self.contents().len() {
+                self.contents_mut().push(String::new());
+            }
+            self.pad_row(row, x);
+            let byte = self.byte_offset(row, self.col_at_display(row, x));
+            self.row(row).insert_str(byte, text);
+        }
+        self.fix();
+    }
+
+    /// The start and end of the selection, in the order they appear in the file.
+    /// The end of the selection is exclusive.
+    pub fn selection_bounds(&self) -> Option<((i32, i32), (i32, i32))> {
+        self.selection_marker
+            .map(|marker| (min(marker, self.cursor), max(marker, self.cursor)))
+    }
+
+    /// What the bottom of the view shows when there's no message, marked if the
+    /// buffer is read-only
+    fn status(&self) -> Option<String> {
+        let status = self.buffer_status();
+        if !self.read_only {
+            return status;
+        }
+        Some(match status {
+            Some(status) => format!("Read-only | {}", status),
+            None => format!(
+                "Read-only{}",
+                self.key_hint(Command::ToggleReadOnly, "allow editing")
+            ),
+        })
+    }
+
+    /// The message or status shown on the bottom row of the view, if any
+    fn bottom_line(&self) -> Option<String> {
+        match (&self.message, &self.recording) {
+            (Some(message), _) => Some(message.clone()),
+            (None, Some(_)) => Some(format!(
+                "Recording a macro{}",
+                self.key_hint(Command::RecordMacro, "stop")
+            )),
+            (None, None) if self.zen => None,
+            (None, None) => self.status(),
+        }
+    }
+
+    /// The size of the selection, the diagnostic on the cursor's line, or which
+    /// lines the buffer is narrowed to
+    fn buffer_status(&self) -> Option<String> {
+        if let Some(summary) = self.selection_summary() {
+            return Some(summary);
+        }
+        if let Some(diagnostic) = self.worst_diagnostic(self.cursor.0) {
+            let message = diagnostic.message.lines().next().unwrap_or_default();
+            return Some(format!("{}: {}", diagnostic.severity.name(), message));
+        }
+        self.narrowing.as_ref()?;
+        let first = self.hidden_above() + 1;
+        let last = first + self.contents().len() - 1;
+        Some(format!(
+            "Narrowed to lines {}-{}{}",
+            first,
+            last,
+            self.key_hint(Command::Widen, "widen")
+        ))
+    }
+
+    /// Which key to press to run a command, like ` (Alt+Shift+R to allow editing)`,
+    /// or nothing if no key runs it
+    fn key_hint(&self, command: Command, what: &str) -> String {
+        let key = self
+            .keymap
+            .keys_for(command)
+            .into_iter()
+            .filter_map(key_name)
+            .min();
+        match key {
+            Some(key) => format!(" ({} to {})", key, what),
+            None => String::new(),
+        }
+    }
+
+    /// How many lines and characters are selected, like `2 lines, 14 chars selected`.
+    /// Line breaks count as characters.
+    pub fn selection_summary(&self) -> Option<String> {
+        let ((top_row, top_col), (bottom_row, bottom_col)) = self.selection_bounds()?;
+        if (top_row, top_col) == (bottom_row, bottom_col) {
+            return None;
+        }
+        let chars = if top_row == bottom_row {
+            bottom_col - top_col
+        } else {
+            (top_row + 1..bottom_row)
+                .map(|row| self.row_len(row) + 1)
+                .sum::<i32>()
+                + self.row_len(top_row)
+                - top_col
+                + 1
+                + bottom_col
+        };
+        let lines = bottom_row - top_row + 1;
+        let plural = |n: i32| if n == 1 { "" } else { "s" };
+        Some(format!(
+            "{} line{}, {} char{} selected",
+            lines,
+            plural(lines),
+            chars,
+            plural(chars)
+        ))
+    }
+
+    pub fn row(&mut self, i: i32) -> RefMut<'_, String> {
+        RefMut::map(self.contents_mut(), |contents| {
+            let len = (contents.len() - 1) as i32;
+            &mut contents[min(max(i, 0), len) as usize]
+        })
+    }
+
+    /// The contents of a row, without allowing it to be changed
+    pub fn line(&self, i: i32) -> Ref<'_, str> {
+        Ref::map(self.contents(), |contents| {
+            let len = (contents.len() - 1) as i32;
+            contents[min(max(i, 0), len) as usize].as_str()
+        })
+    }
+
+    /// The lines of the buffer, or the lines it's narrowed to
+    pub fn lines(&self) -> Ref<'_, [String]> {
+        Ref::map(self.contents(), Vec::as_slice)
+    }
+
+    /// The buffer this code area shows
+    fn buffer(&self) -> Ref<'_, Buffer> {
+        self.buffer.borrow()
+    }
+
+    /// The buffer this code area shows, to change what's known about it.
+    /// The contents are changed with `contents_mut`.
+    fn buffer_mut(&mut self) -> RefMut<'_, Buffer> {
+        self.buffer.borrow_mut()
+    }
+
+    /// The rows of the buffer
+    fn contents(&self) -> Ref<'_, Vec<String>> {
+        Ref::map(self.buffer(), |buffer| &buffer.contents)
+    }
+
+    /// The rows of the buffer, to change them
+    fn contents_mut(&mut self) -> RefMut<'_, Vec<String>> {
+        let mut buffer = self.buffer_mut();
+        buffer.version += 1;
+        RefMut::map(buffer, |buffer| &mut buffer.contents)
+    }
+
+    /// Remember how the rows changed from `before`, so the change can be
+    /// undone, and return whether they did
+    fn record_change(&mut self, before: &[String], cursor_before: (i32, i32)) -> bool {
+        let change = Change::between(before, &self.contents(), cursor_before, self.cursor);
+        match change {
+            Some(change) => {
+                self.buffer_mut().history.record(change);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The whole text of the buffer, with the lines that narrowing hides
+    pub fn text(&self) -> String {
+        self.whole_contents().join("\n")
+    }
+
+    /// The number of graphemes in a row
+    pub fn row_len(&self, i: i32) -> i32 {
+        self.line(i).graphemes(true).count() as i32
+    }
+
+    /// Convert between byte offsets in the buffer, rows and columns,
+    /// and cells in the view, as the view was last drawn
+    pub fn map_positions<T>(&self, f: impl FnOnce(PositionMapper<'_>) -> T) -> T {
+        let scroll = (self.scrollbase.start_line, self.scroll_x);
+        let contents = self.contents();
+        f(PositionMapper::new(&contents, self.tab_width).with_view(self.gutter_width(), scroll))
+    }
+
+    /// The byte offset of the grapheme at column `col` of a row.
+    /// Columns past the end of the row map to the end of the row.
+    pub fn byte_offset(&self, row: i32, col: i32) -> usize {
+        self.map_positions(|mapper| mapper.byte_offset(row, col))
+    }
+
+    /// The column of the grapheme that starts at a byte offset of a row
+    pub fn col_at_byte(&self, row: i32, byte: usize) -> i32 {
+        self.map_positions(|mapper| mapper.col_at_byte(row, byte))
+    }
+
+    /// Make many edits at once, like the edits of a refactoring or the ones a
+    /// language server sends. The positions of every edit are in the buffer
+    /// from before any of them are made, and the edits can't overlap. The
+    /// rows are only split and the cursor fixed once, however many edits
+    /// there are, and they're undone in one step. Returns the `on_modified`
+    /// callback if the buffer was saved before.
+    pub fn apply_edits(&mut self, edits: &[TextEdit]) -> io::Result<Option<Callback>> {
+        if self.read_only {
+            let error = "the buffer is read-only";
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, error));
+        }
+        let was_modified = self.buffer().modified;
+        let before = self.contents().clone();
+        let cursor_before = self.cursor;
+        self.make_edits(edits)?;
+        self.cursors.clear();
+        self.snippet_stops = None;
+        if self.record_change(&before, cursor_before) {
+            self.buffer_mut().modified = true;
+            self.last_edit = Instant::now();
+            self.autosaved = false;
+        }
+        Ok(self.notify_modified(was_modified, None))
+    }
+
+    /// The changes that `apply_edits` would make, to show them before they're made
+    pub fn preview_edits(&self, edits: &[TextEdit]) -> io::Result<Vec<Hunk>> {
+        let mut copy = Self::new();
+        *copy.contents_mut() = self.contents().clone();
+        copy.make_edits(edits)?;
+        let changes = hunks(&diff(&self.contents(), &copy.contents()), 1);
+        Ok(changes)
+    }
+
+    /// Make edits like `apply_edits` does, for commands, which
+    /// `run_command` records the changes of
+    fn make_edits(&mut self, edits: &[TextEdit]) -> io::Result<()> {
+        let offset = |(row, col): (usize, usize)| self.text_offset(row as i32, col as i32);
+        let mut ranges: Vec<(usize, usize, &str)> = edits
+            .iter()
+            .map(|edit| {
+                let (start, end) = (offset(edit.start), offset(edit.end));
+                (min(start, end), max(start, end), edit.text.as_str())
+            })
+            .collect();
+        // Edits that insert at the same place are made in the order they're given
+        ranges.sort_by_key(|&(start, end, _)| (start, end));
+        if ranges.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+            let error = "the edits overlap";
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, error));
+        }
+
+        let text = self.contents().join("\n");
+        let cursor = self.text_offset(self.cursor.0, self.cursor.1);
+        let mut edited = String::with_capacity(text.len());
+        let mut copied = 0;
+        // The cursor stays on the same text, or goes after the text that replaced it
+        let mut cursor_after = cursor as isize;
+        let mut replaced_cursor = None;
+        for &(start, end, replacement) in &ranges {
+            edited.push_str(&text[copied..start]);
+            edited.push_str(replacement);
+            copied = end;
+            if end <= cursor {
+                cursor_after += replacement.len() as isize - (end - start) as isize;
+            } else if start < cursor {
+                replaced_cursor = Some(edited.len());
+            }
+        }
+        edited.push_str(&text[copied..]);
+
+        *self.contents_mut() = edited.split('\n').map(String::from).collect();
+        let (row, byte) = self.text_position(replaced_cursor.unwrap_or(cursor_after as usize));
+        self.cursor = (row, self.col_at_byte(row, byte));
+        self.fix();
+        Ok(())
+    }
+
+    /// The row and column of a byte offset in the whole text, for an edit
+    fn text_point(&self, offset: usize) -> (usize, usize) {
+        let (row, byte) = self.text_position(offset);
+        (row as usize, self.col_at_byte(row, byte) as usize)
+    }
+
+    /// The byte offset of a position in the whole text, where rows are joined by newlines
+    pub fn text_offset(&self, row: i32, col: i32) -> usize {
+        self.map_positions(|mapper| mapper.text_offset(row, col))
+    }
+
+    /// The row and byte offset in that row of a byte offset in the whole text
+    pub fn text_position(&self, offset: usize) -> (i32, usize) {
+        self.map_positions(|mapper| mapper.text_position(offset))
+    }
+
+    /// The number of cells a grapheme takes up when it's drawn at cell `x` of a row.
+    /// Tabs stretch to the next tab stop.
+    fn grapheme_width(&self, g: &str, x: usize) -> usize {
+        self.map_positions(|mapper| mapper.grapheme_width(g, x))
+    }
+
+    /// The number of cells on screen taken up by the first `col` graphemes of a row
+    pub fn display_col(&self, row: i32, col: i32) -> usize {
+        self.map_positions(|mapper| mapper.display_col(row, col))
+    }
+
+    /// The column of the grapheme drawn at cell `x` of a row
+    pub fn col_at_display(&self, row: i32, x: usize) -> i32 {
+        self.map_positions(|mapper| mapper.col_at_display(row, x))
+    }
+
+    /// Replace the tabs in some text with the spaces they're drawn as,
+    /// when the text starts at cell `x` of a row
+    fn expand_tabs(&self, text: &str, mut x: usize) -> String {
+        let mut result = String::new();
+        for g in text.graphemes(true) {
+            let width = self.grapheme_width(g, x);
+            if g == "\t" {
+                result.push_str(&" ".repeat(width));
+            } else {
+                result.push_str(g);
+            }
+            x += width;
+        }
+        result
+    }
+
+    /// Highlight a row that starts in `state`, with its tabs expanded to spaces,
+    /// and find the state it ends in. The last highlighting of the same text in
+    /// the same state is reused if there is one.
+    fn highlight_row(&self, row: i32, state: &LineState) -> (StyledString, LineState) {
+        let text = self.line(row);
+        if let Some(highlighted) = self.cached_highlight(&text, state, Clone::clone) {
+            return highlighted;
+        }
+
+        let (highlighted, end) = self.highlighter.highlight_line(&text, state);
+        let highlighted = apply_rules(&self.highlight_rules, &text, highlighted);
+        let highlighted = (self.expand_highlighted_tabs(&text, highlighted), end);
+        let mut cache = self.highlighted.borrow_mut();
+        // Forget rows that were edited away once there are many more than the buffer has
+        if cache.values().map(HashMap::len).sum::<usize>() > 2 * self.contents().len() + 1024 {
+            cache.clear();
+        }
+        cache
+            .entry(state.clone())
+            .or_default()
+            .insert(text.to_string(), highlighted.clone());
+        highlighted
+    }
+
+    fn cached_highlight<T>(
+        &self,
+        text: &str,
+        state: &LineState,
+        f: impl FnOnce(&(StyledString, LineState)) -> T,
+    ) -> Option<T> {
+        let cache = self.highlighted.borrow();
+        cache.get(state)?.get(text).map(f)
+    }
+
+    /// The state the highlighter is in at the start of each row before `end`,
+    /// which depends on every row above it. The states from the last draw are
+    /// kept down to the first row that changed since.
+    fn row_states(&self, end: usize) -> Ref<'_, [LineState]> {
+        let version = self.buffer().version;
+        let contents = self.contents();
+        let end = max(min(end, contents.len()), 1);
+        let mut cache = self.row_states.borrow_mut();
+        if cache.version != Some(version) {
+            let unchanged = cache
+                .rows
+                .iter()
+                .zip(contents.iter())
+                .take_while(|(old, new)| old == new)
+                .count();
+            cache.rows.truncate(unchanged);
+            cache.starts.truncate(unchanged + 1);
+            cache.version = Some(version);
+        }
+        if cache.starts.is_empty() {
+            cache.starts.push(LineState::Code);
+        }
+        while cache.starts.len() < end {
+            let row = cache.rows.len();
+            let state = cache
+                .starts
+                .last()
+                .expect("there's a state for the first row");
+            let text = &contents[row];
+            let next = match self.cached_highlight(text, state, |(_, end)| end.clone()) {
+                Some(next) => next,
+                None => self.highlight_row(row as i32, state).1,
+            };
+            cache.rows.push(text.clone());
+            cache.starts.push(next);
+        }
+        drop(cache);
+        Ref::map(self.row_states.borrow(), |cache| &cache.starts[..end])
+    }
+
+    fn expand_highlighted_tabs(&self, text: &str, highlighted: StyledString) -> StyledString {
+        if !text.contains('\t') {
+            return highlighted;
+        }
+
+        let mut expanded = StyledString::new();
+        let mut x = 0;
+        for span in highlighted.spans() {
+            let content = self.expand_tabs(span.content, x);
+            x += content.width();
+            expanded.append_styled(content, *span.attr);
+        }
+        expanded
+    }
+
+    /// Cuts the current line of the cursor
+    pub fn cut(&mut self) {
+        self.fix();
+
+        let (row, _) = self.cursor;
+        // Will be stored into clipboard
+        let mut result = String::new();
+
+        if let Some((top, bottom)) = self.selection_bounds() {
+            if top == bottom {
+                return;
+            }
+
+            let mut chars_between = 0;
+            let (top_row, top_col) = top;
+            let (bottom_row, bottom_col) = bottom;
+            for ln in top_row..bottom_row + 1 {
+                if ln == top_row && ln == bottom_row {
+                    chars_between += bottom_col - top_col - 1;
+                } else if ln == top_row {
+                    chars_between += self.row_len(ln) - top_col;
+                } else if top_row < ln && ln < bottom_row {
+                    chars_between += self.row_len(ln) + 1;
+                } else if ln == bottom_row {
+                    chars_between += bottom_col;
+                }
+            }
+
+            self.cursor = top;
+
+            for _ in 0..chars_between + 1 {
+                if let Some(g) = self.line(top_row).graphemes(true).nth(top_col as usize) {
+                    result.push_str(g);
+                } else {
+                    result.push('\n');
+                }
+                self.delete();
+            }
+
+            self.set_clipboard(result, ClipboardKind::Chars);
+        } else {
+            if self.contents().len() > 1 {
+                let result = self.contents_mut().remove(row as usize);
+                self.set_clipboard(result, ClipboardKind::Lines);
+                self.move_cursor_home();
+            }
+        }
+
+        self.fix();
+    }
+
+    pub fn copy(&mut self) {
+        let save_pos = self.cursor;
+        if self.is_selecting() {
+            self.cut();
+            self.paste();
+            self.cursor = save_pos;
+        } else {
+            let line = self.line(save_pos.0).to_string();
+            self.set_clipboard(line, ClipboardKind::Lines);
+        }
+
+        self.fix();
+    }
+
+    /// Put text on the clipboard, keeping what was there before in the history
+    fn set_clipboard(&mut self, text: String, kind: ClipboardKind) {
+        self.clipboard.borrow_mut().set(text, kind);
+    }
+
+    /// Recent cuts and copies, newest first
+    pub fn clipboard_history(&self) -> Vec<String> {
+        self.clipboard
+            .borrow()
+            .history()
+            .map(String::from)
+            .collect()
+    }
+
+    /// Paste an entry from the clipboard history, which goes back on the clipboard
+    pub fn paste_from_history(&mut self, index: usize) -> Option<Callback> {
+        if !self.clipboard.borrow_mut().restore(index) {
+            return None;
+        }
+        self.run_command(Command::Paste)
+    }
+
+    /// Paste the clipboard the way it was cut or copied: text at the cursor,
+    /// whole lines above the cursor's line, or a block onto the rows below
+    pub fn paste(&mut self) {
+        let (text, kind) = self.clipboard.borrow().contents();
+        match kind {
+            ClipboardKind::Chars => self.insert_str(&text),
+            ClipboardKind::Lines => self.paste_lines(&text),
+            ClipboardKind::Block => self.paste_block(&text),
+        }
+        self.fix();
+    }
+
+    /// The clipboard, if it's big enough to be pasted a chunk at a time
+    fn large_paste(&self) -> Option<String> {
+        let (text, kind) = self.clipboard.borrow().contents();
+        let is_large =
+            kind == ClipboardKind::Chars && text.matches('\n').nth(LARGE_PASTE).is_some();
+        Some(text).filter(|_| is_large)
+    }
+
+    /// Start pasting text a chunk at a time. The paste can be
+    /// cancelled with Esc, and is undone in one step when it's done.
+    fn start_paste(&mut self, text: String) -> Callback {
+        let before = self.contents().clone();
+        self.pending_paste = Some(PendingPaste {
+            text,
+            done: 0,
+            before,
+            cursor_before: self.cursor,
+        });
+        continue_paste()
+    }
+
+    /// Paste the next chunk of a large paste, and show how far along it is
+    fn paste_chunk(&mut self, was_modified: bool) -> Option<Callback> {
+        let paste = self.pending_paste.as_mut()?;
+        let rest = &paste.text[paste.done..];
+        let len = rest
+            .match_indices('\n')
+            .nth(PASTE_CHUNK)
+            .map(|(i, _)| i + 1)
+            .unwrap_or(rest.len());
+        let chunk = rest[..len].to_string();
+        paste.done += len;
+        let (done, total) = (paste.done, paste.text.len());
+        self.insert_str(chunk);
+        self.scroll_pending = true;
+        if done < total {
+            self.message = Some(format!(
+                "Pasting, {}% done (Esc to cancel)",
+                done * 100 / total
+            ));
+            return Some(continue_paste());
+        }
+
+        let PendingPaste {
+            before,
+            cursor_before,
+            ..
+        } = self.pending_paste.take()?;
+        self.message = None;
+        if self.record_change(&before, cursor_before) {
+            self.buffer_mut().modified = true;
+            self.last_edit = Instant::now();
+            self.autosaved = false;
+        }
+        self.notify_modified(was_modified, None)
+    }
+
+    /// Stop a large paste, and put the buffer back how it was before it
+    fn cancel_paste(&mut self) {
+        if let Some(paste) = self.pending_paste.take() {
+            *self.contents_mut() = paste.before;
+            self.cursor = paste.cursor_before;
+            self.scroll_pending = true;
+            self.message = Some(String::from("The paste was cancelled"));
+        }
+    }
+
+    /// Paste whole lines above the cursor's line, leaving the cursor on its line
+    fn paste_lines(&mut self, text: &str) {
+        let (row, col) = self.cursor;
+        let lines: Vec<String> = text.split('\n').map(String::from).collect();
+        let count = lines.len() as i32;
+        self.contents_mut()
+            .splice(row as usize..row as usize, lines);
+        self.cursor = (row + count, col);
+    }
+
+    pub fn copy_line_down(&mut self) {
+        let (row, _) = self.cursor;
+        let current_line = self.row(row).clone();
+        self.contents_mut().insert(row as usize, current_line);
+        self.move_cursor_down();
+        self.fix();
+    }
+
+    /// The string that starts a line comment. Files with only block
+    /// comments, like HTML, don't have one.
+    fn line_comment(&self) -> Option<String> {
+        match (&self.comment_prefix, self.comments) {
+            (Some(prefix), _) => Some(prefix.clone()),
+            (None, Some(style)) => style.line.map(String::from),
+            (None, None) => Some(String::from("// ")),
+        }
+    }
+
+    /// The strings that start and end a block comment, if the file type has them
+    fn block_comment(&self) -> Option<(&'static str, &'static str)> {
+        self.comments.and_then(|style| style.block)
+    }
+
+    /// Comments out the current line of the cursor if the line is not
+    /// already commented. If the line is commented, this will uncomment
+    /// the line. Languages without line comments wrap the line in a block comment.
+    pub fn comment_current_line(&mut self) {
+        let (row, col) = self.cursor;
+        let comment = match (self.line_comment(), self.block_comment()) {
+            (Some(comment), _) => comment,
+            (None, Some(block)) => return self.block_comment_line(block),
+            (None, None) => return,
+        };
+        let len = comment.graphemes(true).count();
+
+        self.cursor = (row, 0);
+
+        // Uncomment if the line is already commented
+        let should_do_comment = !self.line(row).starts_with(&comment);
+
+        if should_do_comment {
+            self.insert_str(comment);
+            self.cursor = (row, col + len as i32);
+        } else {
+            self.row(row).replace_range(..comment.len(), "");
+
+            if col <= len as i32 {
+                self.cursor = (row, 0);
+            } else {
+                self.cursor = (row, col - len as i32);
+            }
+        }
+
+        self.fix();
+    }
+
+    /// Wrap the text of the cursor's line after its indentation in a block
+    /// comment, or unwrap it if it's already wrapped
+    fn block_comment_line(&mut self, (open, close): (&str, &str)) {
+        let (row, col) = self.cursor;
+        let line = self.line(row).to_string();
+        let indent = line.len() - line.trim_start().len();
+        let text = &line[indent..];
+        let (changed, moved) = match block_comment_markers(text, (open, close)) {
+            Some((open_len, close_len)) => (
+                text[open_len..text.len() - close_len].to_string(),
+                -(text[..open_len].graphemes(true).count() as i32),
+            ),
+            None => (
+                format!("{}{}{}", open, text, close),
+                open.graphemes(true).count() as i32,
+            ),
+        };
+        self.row(row).replace_range(indent.., &changed);
+        let indent_col = self.col_at_byte(row, indent);
+        if col >= indent_col {
+            self.cursor = (row, max(col + moved, indent_col));
+        }
+        self.fix();
+    }
+
+    /// Comments out the selected lines of the cursor (if lines have been selected)
+    pub fn comment_selection(&mut self) {
+        let (init_row, init_col) = self.cursor;
+        if let Some((marker_row, _)) = self.selection_marker {
+            let begin_row = min(marker_row, init_row);
+            let end_row = max(marker_row, init_row);
+            let init_len = self.row_len(init_row);
+
+            for row in begin_row..end_row + 1 {
+                self.cursor = (row, 0);
+                self.comment_current_line();
+            }
+            let added = self.row_len(init_row) - init_len;
+            self.cursor = (init_row, max(init_col + added, 0));
+        }
+
+        self.fix();
+    }
+
+    /// Whether the selection starts or ends partway through the text of a line
+    fn is_selecting_mid_line(&self) -> bool {
+        let ((top_row, top_col), (bottom_row, bottom_col)) = match self.selection_bounds() {
+            Some(bounds) => bounds,
+            None => return false,
+        };
+        let top = self.line(top_row);
+        let bottom = self.line(bottom_row);
+        let indent = top.len() - top.trim_start().len();
+        let text_end = bottom.trim_end().len();
+        (top_row, top_col) != (bottom_row, bottom_col)
+            && (self.byte_offset(top_row, top_col) > indent
+                || (bottom_col > 0 && self.byte_offset(bottom_row, bottom_col) < text_end))
+    }
+
+    /// Wrap the selection in a block comment, or unwrap it if it's already wrapped,
+    /// keeping the selection on the same text
+    fn toggle_block_comment(&mut self, (open, close): (&str, &str)) {
+        let (top, bottom) = match self.selection_bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let start = self.byte_offset(top.0, top.1);
+        let end = self.byte_offset(bottom.0, bottom.1);
+        let selected = self.text_between(top, bottom);
+        // The end goes first, so the start stays where it is on the same row
+        let end = match block_comment_markers(&selected, (open, close)) {
+            Some((open_len, close_len)) => {
+                self.row(bottom.0).replace_range(end - close_len..end, "");
+                self.row(top.0).replace_range(start..start + open_len, "");
+                end - close_len - if top.0 == bottom.0 { open_len } else { 0 }
+            }
+            None => {
+                self.row(bottom.0).insert_str(end, close);
+                self.row(top.0).insert_str(start, open);
+                end + close.len() + if top.0 == bottom.0 { open.len() } else { 0 }
+            }
+        };
+        self.selection_marker = Some(top);
+        self.cursor = (bottom.0, self.col_at_byte(bottom.0, end));
+        self.fix();
+    }
+
+    /// The text inserted to indent a line by one level
+    fn indent_unit(&self) -> String {
+        if self.hard_tabs {
+            String::from("\t")
         } else {
-            self.clipboard = String::from("\n") + self.row(save_pos.0);
+            " ".repeat(self.tab_width)
         }
+    }
 
-        self.fix();
+    /// The rows covered by the selection. A selection ending at the start
+    /// of a row doesn't cover that row.
+    fn selected_rows(&self) -> Option<(i32, i32)> {
+        let ((top_row, _), (bottom_row, bottom_col)) = self.selection_bounds()?;
+        if bottom_col == 0 && bottom_row > top_row {
+            Some((top_row, bottom_row - 1))
+        } else {
+            Some((top_row, bottom_row))
+        }
     }
 
-    pub fn paste(&mut self) {
-        let content = self.clipboard.clone();
-        self.insert_str(&content);
-        self.fix();
+    /// The lines covered by the selection, or every line if nothing is
+    /// selected, and the line they start on, counting from 1
+    pub fn selected_lines(&self) -> (usize, String) {
+        let (top, bottom) = self
+            .selected_rows()
+            .unwrap_or((0, self.contents().len() as i32 - 1));
+        let lines = &self.contents()[top as usize..=bottom as usize];
+        (top as usize + 1, lines.join("\n"))
     }
 
-    pub fn copy_line_down(&mut self) {
-        let (row, _) = self.cursor;
-        let current_line = self.row(row).clone();
-        self.contents.insert(row as usize, current_line);
-        self.move_cursor_down();
+    /// Whether the selection covers more than one row
+    pub fn is_selecting_rows(&self) -> bool {
+        self.selection_marker
+            .map(|(marker_row, _)| marker_row != self.cursor.0)
+            .unwrap_or(false)
+    }
+
+    /// Indent a row by one level, and return the number of graphemes added
+    fn indent_row(&mut self, row: i32) -> i32 {
+        let unit = self.indent_unit();
+        self.row(row).insert_str(0, &unit);
+        unit.graphemes(true).count() as i32
+    }
+
+    /// Remove one level of indentation from a row,
+    /// and return the number of graphemes removed
+    fn dedent_row(&mut self, row: i32) -> i32 {
+        let line = self.line(row).to_string();
+        let removed = if line.starts_with('\t') {
+            1
+        } else {
+            line.chars()
+                .take(self.tab_width)
+                .take_while(|&ch| ch == ' ')
+                .count()
+        };
+        self.row(row).replace_range(..removed, "");
+        removed as i32
+    }
+
+    /// Indent the selected rows, or the current row, by one level,
+    /// keeping the selection on the same text
+    pub fn indent_selection(&mut self) {
+        self.reindent_selection(|area, row| area.indent_row(row));
+    }
+
+    /// Dedent the selected rows, or the current row, by one level,
+    /// keeping the selection on the same text
+    pub fn dedent_selection(&mut self) {
+        self.reindent_selection(|area, row| -area.dedent_row(row));
+    }
+
+    /// Change the indentation of the selected rows, or the current row, and
+    /// shift the cursor and selection marker by the number of graphemes changed
+    fn reindent_selection(&mut self, mut reindent: impl FnMut(&mut Self, i32) -> i32) {
+        let (top, bottom) = self
+            .selected_rows()
+            .unwrap_or((self.cursor.0, self.cursor.0));
+        for row in top..=bottom {
+            let change = reindent(self, row);
+            // Positions at the start of a row stay there, so that
+            // selections of whole rows still cover the whole rows
+            let positions = self
+                .selection_marker
+                .iter_mut()
+                .chain(Some(&mut self.cursor));
+            for (position_row, position_col) in positions {
+                if *position_row == row && *position_col > 0 {
+                    *position_col = max(*position_col + change, 0);
+                }
+            }
+        }
         self.fix();
     }
 
-    /// Comments out the current line of the cursor if the line is not
-    /// already commented. If the line is commented, this will uncomment
-    /// the line.
-    pub fn comment_current_line(&mut self) {
+    /// Group and sort the imports at the top of a Rust or Python file
+    pub fn organize_imports(&mut self) {
+        let (range, organized) = match self
+            .imports
+            .and_then(|style| style.organize(&self.contents()))
+        {
+            Some(organized) => organized,
+            None => return,
+        };
+        if self.contents()[range.clone()] == organized[..] {
+            return;
+        }
+
+        // Keep the cursor on the same text if it's below the imports
         let (row, col) = self.cursor;
-        let len = self.comment_prefix.len();
-        let comment = self.comment_prefix.clone();
+        let (start, end) = (range.start as i32, range.end as i32);
+        let added = organized.len() as i32 - (end - start);
+        self.contents_mut().splice(range, organized);
+        self.cursor = if row >= end {
+            (row + added, col)
+        } else if row >= start {
+            (start, 0)
+        } else {
+            (row, col)
+        };
+        self.fix();
+    }
 
-        self.cursor = (row, 0);
+    /// Replace the buffer with what the file type's formatter makes of it,
+    /// keeping the cursor on the same code. Returns `false` if the file type
+    /// has no formatter.
+    pub fn format_buffer(&mut self) -> io::Result<bool> {
+        let formatter = match &self.formatter {
+            Some(formatter) => formatter,
+            None => return Ok(false),
+        };
+        let formatted = formatter.format(&self.contents().join("\n"), &self.dir())?;
+        let lines: Vec<String> = formatted.split('\n').map(String::from).collect();
+        if lines == *self.contents() {
+            return Ok(true);
+        }
 
-        let should_do_comment;
-        if self.row(row).len() < len {
-            // Do the comment because its not possible to already
-            // have a comment but have a line shorter than a comment
-            should_do_comment = true;
-        } else if &self.row(row)[0..len] == comment {
-            // Uncomment because its already commented
-            should_do_comment = false;
-        } else {
-            should_do_comment = true;
+        // The cursor moves with its line, and with the line's indentation
+        let (row, col) = self.cursor;
+        let before = replace(&mut *self.contents_mut(), lines);
+        let new_row = self.follow_row(&before, row);
+        let indent = |line: &String| line.chars().take_while(|ch| ch.is_whitespace()).count();
+        let old_indent = before.get(row as usize).map_or(0, indent) as i32;
+        let new_indent = self.contents().get(new_row as usize).map_or(0, indent) as i32;
+        let moved = new_indent - old_indent;
+        self.cursor = (new_row, max(col + moved, 0));
+        self.fix();
+        Ok(true)
+    }
+
+    /// Run the selected text, or the whole buffer if nothing is selected, through
+    /// a shell command like `sort` or `jq .`, and replace it with what the command
+    /// prints. The output is left selected, and can be undone in one step.
+    pub fn filter_selection(&mut self, command: &str) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the buffer is read-only",
+            ));
+        }
+        self.fix();
+        let selection = self
+            .selection_bounds()
+            .filter(|(top, bottom)| top != bottom);
+        let input = match selection {
+            Some((top, bottom)) => self.text_between(top, bottom),
+            None => self.contents().join("\n"),
+        };
+        let mut shell = process::Command::new("sh");
+        shell.arg("-c").arg(command).current_dir(self.dir());
+        let output = pipe_through(&mut shell, input.as_bytes())?;
+        let mut output =
+            String::from_utf8(output).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        // Commands end their output with a newline even if the input didn't have one
+        if !input.ends_with('\n') && output.ends_with('\n') {
+            output.pop();
         }
 
-        if should_do_comment {
-            self.insert_str(comment);
-            self.cursor = (row, col + len as i32);
-        } else {
-            for _ in 0..len {
-                self.row(row).remove(0);
+        let before = self.contents().clone();
+        let cursor = self.cursor;
+        match selection {
+            Some((top, bottom)) => {
+                self.delete_range(top, bottom);
+                self.insert_str(&output);
+                self.selection_marker = Some(top);
+            }
+            None => {
+                *self.contents_mut() = output.split('\n').map(String::from).collect();
+                self.cursor.0 = self.follow_row(&before, cursor.0);
+                self.fix();
             }
+        }
+        if self.record_change(&before, cursor) {
+            self.buffer_mut().modified = true;
+            self.last_edit = Instant::now();
+            self.autosaved = false;
+        }
+        Ok(())
+    }
 
-            if col <= len as i32 {
-                self.cursor = (row, 0);
+    /// The directory the file is in, which commands run on it are run in
+    fn dir(&self) -> PathBuf {
+        file_dir(Path::new(&*self.filename())).to_path_buf()
+    }
+
+    /// Change the case of the selection, or the word under the cursor if
+    /// nothing is selected, keeping the selection on the changed text
+    pub fn transform_case(&mut self, transform: fn(&str) -> String) {
+        let ((top_row, top_col), (bottom_row, bottom_col)) = match self.selection_bounds() {
+            Some((top, bottom)) if top != bottom => (top, bottom),
+            _ => match self.word_at(self.cursor) {
+                Some((start, end)) => {
+                    let row = self.cursor.0;
+                    (
+                        (row, self.col_at_byte(row, start)),
+                        (row, self.col_at_byte(row, end)),
+                    )
+                }
+                None => return,
+            },
+        };
+        let mut end_byte = 0;
+        for row in top_row..=bottom_row {
+            let start = if row == top_row {
+                self.byte_offset(row, top_col)
             } else {
-                self.cursor = (row, col - len as i32);
-            }
+                0
+            };
+            let end = if row == bottom_row {
+                self.byte_offset(row, bottom_col)
+            } else {
+                self.line(row).len()
+            };
+            let changed = transform(&self.line(row)[start..end]);
+            end_byte = start + changed.len();
+            self.row(row).replace_range(start..end, &changed);
         }
 
+        // The case of some letters changes how many there are, like `ß` and `SS`
+        let end = (bottom_row, self.col_at_byte(bottom_row, end_byte));
+        if self.cursor == (bottom_row, bottom_col) {
+            self.cursor = end;
+        } else if self.selection_marker == Some((bottom_row, bottom_col)) {
+            self.selection_marker = Some(end);
+        }
         self.fix();
     }
 
-    /// Comments out the selected lines of the cursor (if lines have been selected)
-    pub fn comment_selection(&mut self) {
-        let (init_row, init_col) = self.cursor;
-        if let Some((marker_row, _)) = self.selection_marker {
-            let begin_row = min(marker_row, init_row);
-            let end_row = max(marker_row, init_row);
+    /// The first and last rows of text, leaving out the empty row
+    /// that `fix_newline` keeps at the end of the buffer
+    fn text_rows(&self) -> (i32, i32) {
+        let last = self.contents().len() as i32 - 1;
+        let at_end = self.narrowing.as_ref().is_none_or(|n| n.below.is_empty());
+        if at_end && last > 0 && self.contents()[last as usize].is_empty() {
+            (0, last - 1)
+        } else {
+            (0, last)
+        }
+    }
 
-            for row in begin_row..end_row + 1 {
-                self.cursor = (row, 0);
-                self.comment_current_line();
-            }
-            self.cursor = (init_row, init_col + self.comment_prefix.len() as i32);
+    /// Sort the selected lines, or every line if nothing is selected
+    pub fn sort_lines(&mut self, descending: bool) {
+        let (top, bottom) = self.selected_rows().unwrap_or_else(|| self.text_rows());
+        let range = top as usize..=bottom as usize;
+        self.contents_mut()[range.clone()].sort();
+        if descending {
+            self.contents_mut()[range].reverse();
         }
+        self.fix();
+    }
 
+    /// Remove the selected lines, or any lines if nothing is selected,
+    /// that are the same as a line before them
+    pub fn deduplicate_lines(&mut self) {
+        let (top, bottom) = self.selected_rows().unwrap_or_else(|| self.text_rows());
+        let range = top as usize..=bottom as usize;
+        let mut seen = HashSet::new();
+        let unique: Vec<String> = self.contents()[range.clone()]
+            .iter()
+            .filter(|line| seen.insert(line.as_str()))
+            .cloned()
+            .collect();
+        self.contents_mut().splice(range, unique);
         self.fix();
     }
 
+    /// Move the cursor's line, or the selected lines, up past the line above,
+    /// keeping the selection on the lines that moved
     pub fn move_line_up(&mut self) {
+        let (row, _) = self.cursor;
+        let (top, bottom) = self.selected_rows().unwrap_or((row, row));
+        if top == 0 {
+            return;
+        }
+        self.contents_mut()[top as usize - 1..=bottom as usize].rotate_left(1);
+        self.shift_selection_rows(-1);
+    }
+
+    /// Move the cursor's line, or the selected lines, down past the line below,
+    /// keeping the selection on the lines that moved
+    pub fn move_line_down(&mut self) {
+        let (row, _) = self.cursor;
+        let (top, bottom) = self.selected_rows().unwrap_or((row, row));
+        if bottom + 1 >= self.contents().len() as i32 {
+            return;
+        }
+        self.contents_mut()[top as usize..=bottom as usize + 1].rotate_right(1);
+        self.shift_selection_rows(1);
+    }
+
+    /// Swap the argument or list element at the cursor with the next one,
+    /// or the one before it. Arguments are separated by the commas between
+    /// the innermost brackets around the cursor, and the cursor moves with
+    /// its argument.
+    pub fn move_argument(&mut self, forward: bool) {
+        let text = self.contents().join("\n");
+        let mut mask = Vec::with_capacity(text.len());
+        for line in self.contents().iter() {
+            mask.extend(self.code_mask(line));
+            mask.push(true);
+        }
+        let cursor = self.text_offset(self.cursor.0, self.cursor.1);
+        let arguments = match arguments_around(&text, &mask, cursor) {
+            Some(arguments) => arguments,
+            None => return,
+        };
+        let i = match arguments.iter().position(|&(_, end)| cursor <= end) {
+            Some(i) => i,
+            None => return,
+        };
+        let j = match (forward, i) {
+            (true, _) if i + 1 < arguments.len() => i + 1,
+            (false, _) if i > 0 => i - 1,
+            _ => return,
+        };
+
+        // Only the text of the arguments moves, and not the spaces around them
+        let trimmed = |(start, end): (usize, usize)| {
+            let argument = &text[start..end];
+            let start = start + argument.len() - argument.trim_start().len();
+            (start, start + argument.trim().len())
+        };
+        let (first, second) = (trimmed(arguments[min(i, j)]), trimmed(arguments[max(i, j)]));
+        if first.0 == first.1 || second.0 == second.1 {
+            return;
+        }
+        let swap = |(start, end): (usize, usize), (other_start, other_end): (usize, usize)| {
+            TextEdit::replace(
+                self.text_point(start),
+                self.text_point(end),
+                &text[other_start..other_end],
+            )
+        };
+        let edits = [swap(first, second), swap(second, first)];
+
+        let (start, end) = trimmed(arguments[i]);
+        let into = min(cursor.saturating_sub(start), end - start);
+        let offset = if forward {
+            // The argument after this one is now before it, and may be longer
+            second.1 - (first.1 - first.0) + into
+        } else {
+            first.0 + into
+        };
+        if self.make_edits(&edits).is_err() {
+            return;
+        }
+        let (row, byte) = self.text_position(offset);
+        self.cursor = (row, self.col_at_byte(row, byte));
+        self.fix();
+    }
+
+    /// Move the cursor and the selection marker by a number of rows
+    fn shift_selection_rows(&mut self, rows: i32) {
+        self.cursor.0 += rows;
+        if let Some(marker) = &mut self.selection_marker {
+            marker.0 += rows;
+        }
+    }
+
+    pub fn move_cursor_home(&mut self) {
+        let (row, _) = self.cursor;
+        self.cursor = (row, 0);
+    }
+
+    /// Move the cursor to the start of the buffer
+    pub fn move_cursor_to_start(&mut self) {
+        self.cursor = (0, 0);
+    }
+
+    /// Move the cursor to the end of the buffer
+    pub fn move_cursor_to_end(&mut self) {
+        let last = self.contents().len() as i32 - 1;
+        self.cursor = (last, self.row_len(last));
+    }
+
+    /// Move the cursor to the start of a line, counting from 1,
+    /// and scroll it into view
+    pub fn goto_line(&mut self, line: usize) {
+        self.goto_position(line, 1);
+    }
+
+    /// Move the cursor to a line and column, both counting from 1,
+    /// and scroll it into view. Positions past the end are clamped.
+    pub fn goto_position(&mut self, line: usize, col: usize) {
+        let row = line.saturating_sub(1 + self.hidden_above()) as i32;
+        self.cursor = (row, col.saturating_sub(1) as i32);
+        self.forget_selection();
+        self.fix();
+        self.scroll_to_cursor();
+        // A file that was just opened hasn't been laid out yet
+        self.scroll_pending = true;
+    }
+
+    /// The line and column of the cursor, both counting from 1
+    pub fn cursor_position(&self) -> (usize, usize) {
+        let line = self.cursor.0 as usize + 1 + self.hidden_above();
+        (line, self.cursor.1 as usize + 1)
+    }
+
+    /// The word before the cursor, which completion finishes
+    fn word_before_cursor(&self) -> String {
         let (row, col) = self.cursor;
-        let current_line = self.row(row).clone();
-        let previous_line = self.row(row - 1).clone();
+        let line = self.line(row);
+        let line = &line[..self.byte_offset(row, col)];
+        match line.split_word_bound_indices().next_back() {
+            Some((i, word)) if is_word(word) => line[i..].to_string(),
+            _ => String::new(),
+        }
+    }
 
-        *self.row(row) = previous_line;
-        *self.row(row - 1) = current_line;
-        self.cursor = (max(row - 1, 0), col);
+    /// The words in the buffer that start with `prefix` and are longer than
+    /// it, nearest to the cursor first, without the word the cursor is in
+    fn words_starting_with(&self, prefix: &str) -> Vec<String> {
+        let (row, _) = self.cursor;
+        let here = self.word_at(self.cursor);
+        let contents = self.contents();
+        let mut found: Vec<(i32, &str)> = Vec::new();
+        for (i, line) in contents.iter().enumerate() {
+            let i = i as i32;
+            for (start, word) in line.split_word_bound_indices() {
+                let is_here = i == row && here.is_some_and(|(here, _)| here == start);
+                let completes = word.len() > prefix.len() && word.starts_with(prefix);
+                if is_word(word) && completes && !is_here {
+                    found.push(((i - row).abs(), word));
+                }
+            }
+        }
+        found.sort_by_key(|&(distance, _)| distance);
+        let mut seen = HashSet::new();
+        found
+            .into_iter()
+            .filter(|&(_, word)| seen.insert(word))
+            .map(|(_, word)| word.to_string())
+            .collect()
     }
 
-    pub fn move_line_down(&mut self) {
+    /// Offer the words that could finish the word before the cursor,
+    /// or finish it right away if only one word could
+    pub fn complete(&mut self) {
+        let prefix = self.word_before_cursor().to_string();
+        if prefix.is_empty() {
+            self.bell("There's no word before the cursor to complete");
+            return;
+        }
+        let words = self.words_starting_with(&prefix);
+        match words.len() {
+            0 => self.bell("No words in the buffer start with this one"),
+            1 => self.insert_completion(&prefix, &words[0]),
+            _ => {
+                self.completion = Some(Completion {
+                    prefix,
+                    words,
+                    selected: 0,
+                })
+            }
+        }
+    }
+
+    /// Type the rest of a word that completes the prefix before the cursor
+    fn insert_completion(&mut self, prefix: &str, word: &str) {
+        for ch in word[prefix.len()..].chars() {
+            self.run_command(Command::Type(ch));
+        }
+    }
+
+    /// Handle a key while the completion popup is open. Keys that don't
+    /// choose a word close the popup, and are handled as usual.
+    fn completion_event(&mut self, event: &Event) -> Option<EventResult> {
+        let completion = self.completion.as_mut()?;
+        let count = completion.words.len();
+        match event {
+            Event::Key(Key::Up) => completion.selected = (completion.selected + count - 1) % count,
+            Event::Key(Key::Down) => completion.selected = (completion.selected + 1) % count,
+            Event::Key(Key::Enter) | Event::Key(Key::Tab) => {
+                let Completion {
+                    prefix,
+                    words,
+                    selected,
+                } = self.completion.take()?;
+                self.insert_completion(&prefix, &words[selected]);
+            }
+            Event::Key(Key::Esc) => self.completion = None,
+            _ => {
+                self.completion = None;
+                return None;
+            }
+        }
+        Some(EventResult::Consumed(None))
+    }
+
+    /// Keep offering words while the word before the cursor is typed
+    fn refine_completion(&mut self) {
+        let prefix = self.word_before_cursor().to_string();
+        let words = self.words_starting_with(&prefix);
+        if !prefix.is_empty() && !words.is_empty() {
+            self.completion = Some(Completion {
+                prefix,
+                words,
+                selected: 0,
+            });
+        }
+    }
+
+    /// Draw the completion popup below the word it completes, or above it
+    /// if there's no room below
+    fn draw_completion(&self, printer: &Printer<'_, '_>) {
+        let completion = match &self.completion {
+            Some(completion) => completion,
+            None => return,
+        };
         let (row, col) = self.cursor;
-        let current_line = self.row(row).clone();
-        let next_line = self.row(row + 1).clone();
+        let start_col = col - completion.prefix.chars().count() as i32;
+        let (y, x) = self.cell_of((row, start_col));
+        let x = self.gutter_width() + x.saturating_sub(self.scroll_x);
+        let y = y.saturating_sub(self.scrollbase.start_line);
+        let rows = min(completion.words.len(), COMPLETION_ROWS);
+        let top = if y + 1 + rows <= printer.size.y {
+            y + 1
+        } else {
+            y.saturating_sub(rows)
+        };
+        // The words scroll to keep the selected one in view
+        let first = completion.selected.saturating_sub(rows - 1);
+        let widest = completion.words.iter().map(|word| word.width()).max();
+        let width = widest.unwrap_or(0) + 2;
+        for (i, word) in completion.words.iter().enumerate().skip(first).take(rows) {
+            let style = if i == completion.selected {
+                ColorStyle::highlight()
+            } else {
+                ColorStyle::primary()
+            };
+            printer.with_color(style, |printer| {
+                let y = top + i - first;
+                printer.print_hline((x, y), width, " ");
+                printer.print((x + 1, y), word);
+            });
+        }
+    }
+
+    /// Make a code area show the same buffer as this one, with a cursor and
+    /// scroll position of its own. `area` brings the settings, like the keymap
+    /// from the config. A narrowed buffer can't be shown twice.
+    pub fn second_view(&mut self, mut area: Self) -> Option<Self> {
+        if self.narrowing.is_some() {
+            return None;
+        }
+        area.detect_file_type(&self.filename());
+        area.buffer = self.buffer.clone();
+        area.seen_version = self.buffer().version;
+        area.read_only |= self.buffer().locked_elsewhere;
+        area.clipboard = self.clipboard.clone();
+        area.cursor = self.cursor;
+        area.scroll_pending = true;
+        Some(area)
+    }
+
+    /// Whether another code area shows the same buffer
+    fn is_shared(&self) -> bool {
+        Rc::strong_count(&self.buffer) > 1
+    }
+
+    /// Keep the cursor and selection in the buffer if it was changed by
+    /// another code area that shows it, or from outside of a command
+    fn follow_buffer(&mut self) {
+        let version = self.buffer().version;
+        if replace(&mut self.seen_version, version) != version {
+            self.forget_selection();
+            self.cursors.clear();
+            self.block = None;
+            self.fix_cursor();
+        }
+    }
+
+    /// Hide every line but the selected ones, until the buffer is widened again.
+    /// Edits made while it's narrowed are undone as one change after widening.
+    pub fn narrow(&mut self) {
+        if self.narrowing.is_some() {
+            self.bell("The buffer is already narrowed");
+            return;
+        }
+        if self.is_shared() {
+            self.bell("A buffer can't be narrowed while another view shows it");
+            return;
+        }
+        let ((top, _), (mut bottom, bottom_col)) = match self.selection_bounds() {
+            Some(bounds) if bounds.0 != bounds.1 => bounds,
+            _ => {
+                self.bell("Select the lines to narrow to");
+                return;
+            }
+        };
+        // A selection that ends at the start of a line doesn't include it
+        if bottom_col == 0 && bottom > top {
+            bottom -= 1;
+        }
+
+        let below = self.contents_mut().split_off(bottom as usize + 1);
+        let above: Vec<String> = self.contents_mut().drain(..top as usize).collect();
+        self.cursor.0 -= top;
+        self.forget_selection();
+        self.cursors.clear();
+        self.block = None;
+        let region = self.contents().clone();
+        let history = take(&mut self.buffer_mut().history);
+        self.narrowing = Some(Narrowing {
+            above,
+            below,
+            region,
+            cursor: self.cursor,
+            history,
+        });
+        self.fix_cursor();
+        self.scroll_to_cursor();
+        self.scroll_pending = true;
+    }
+
+    /// Show the whole buffer again after narrowing it, and return whether it was narrowed
+    pub fn widen(&mut self) -> bool {
+        let Narrowing {
+            above,
+            below,
+            region,
+            cursor,
+            history,
+        } = match self.narrowing.take() {
+            Some(narrowing) => narrowing,
+            None => return false,
+        };
+        let first = above.len() as i32;
+        let before = [&above[..], &region, &below].concat();
+        let contents = [&above[..], &self.contents(), &below].concat();
+        *self.contents_mut() = contents;
+        self.cursor.0 += first;
+        self.buffer_mut().history = history;
+        let cursor_before = (cursor.0 + first, cursor.1);
+        self.record_change(&before, cursor_before);
+        self.forget_selection();
+        self.cursors.clear();
+        self.block = None;
+        self.scroll_to_cursor();
+        self.scroll_pending = true;
+        true
+    }
+
+    /// Hide the line numbers and status line and center the text, or show them again
+    pub fn toggle_zen(&mut self) {
+        self.zen = !self.zen;
+        // The text column moves, so the view has to be laid out again
+        self.scroll_pending = true;
+    }
+
+    /// Whether the line numbers and status line are hidden
+    pub fn is_zen(&self) -> bool {
+        self.zen
+    }
+
+    /// How many columns are left of the text: the line numbers,
+    /// or the margin that centers the text when `zen` is on
+    fn gutter_width(&self) -> usize {
+        if self.zen {
+            self.width.saturating_sub(self.zen_width) / 2
+        } else {
+            self.blame_width() + LN_SPACE as usize
+        }
+    }
+
+    /// How many columns of text fit beside the line numbers, and the
+    /// scrollbar if it takes up a column
+    fn fit_text_width(&self, scroll_width: usize) -> usize {
+        let width = self
+            .width
+            .saturating_sub(self.gutter_width() + scroll_width);
+        if self.zen {
+            min(width, self.zen_width)
+        } else {
+            width
+        }
+    }
+
+    /// How many columns the blame takes up left of the line numbers
+    fn blame_width(&self) -> usize {
+        if self.blame.is_some() {
+            BLAME_SPACE
+        } else {
+            0
+        }
+    }
+
+    /// How many lines are hidden above the ones the buffer is narrowed to
+    fn hidden_above(&self) -> usize {
+        let narrowing = self.narrowing.as_ref();
+        narrowing.map_or(0, |narrowing| narrowing.above.len())
+    }
+
+    /// All the lines of the buffer, with the hidden ones if it's narrowed
+    fn whole_contents(&self) -> Vec<String> {
+        match &self.narrowing {
+            Some(narrowing) => [&narrowing.above[..], &self.contents(), &narrowing.below].concat(),
+            None => self.contents().clone(),
+        }
+    }
+
+    /// Keep the cursor's row in the middle of the view as it moves, or stop
+    pub fn toggle_typewriter(&mut self) {
+        self.typewriter = !self.typewriter;
+        self.centered_row = None;
+    }
+
+    /// Scroll long lines left, towards their start
+    pub fn scroll_left(&mut self, columns: usize) {
+        if self.soft_wrap {
+            return;
+        }
+        self.scroll_x = self.scroll_x.saturating_sub(columns);
+    }
+
+    /// Scroll long lines right, up to where the longest one ends
+    pub fn scroll_right(&mut self, columns: usize) {
+        if self.soft_wrap {
+            return;
+        }
+        let widest = (0..self.contents().len() as i32)
+            .map(|i| self.display_col(i, self.row_len(i)))
+            .max()
+            .unwrap_or(0);
+        let max = (widest + 1).saturating_sub(self.text_width);
+        self.scroll_x = (self.scroll_x + columns).min(max);
+    }
+
+    /// Scroll up or down just enough to show the cursor's row. Rows are
+    /// wrapped when the view is laid out, so with soft wrap on, the view
+    /// waits until then to scroll.
+    fn scroll_to_cursor(&mut self) {
+        if self.soft_wrap {
+            self.scroll_pending = true;
+        } else {
+            self.scrollbase.scroll_to(self.cursor.0 as usize);
+        }
+    }
+
+    /// Work out where each row wraps when soft wrap is on, leaving a cell
+    /// at the end of the text for the cursor
+    fn wrap_rows(&mut self) {
+        self.wrapped_rows.clear();
+        if !self.soft_wrap {
+            return;
+        }
+        let rows = self.contents().len() as i32;
+        let width = self.text_width.saturating_sub(1);
+        let wrapped = self.map_positions(|mapper| {
+            (0..rows)
+                .flat_map(|row| {
+                    let points = mapper.wrap_points(row, width);
+                    points.into_iter().map(move |col| (row, col))
+                })
+                .collect()
+        });
+        self.wrapped_rows = wrapped;
+    }
+
+    /// The row shown on a row of cells, counting from the top of the
+    /// buffer, and the columns of it that are shown there
+    fn wrapped_row(&self, y: usize) -> Option<(usize, i32, i32)> {
+        if !self.soft_wrap {
+            let row = y as i32;
+            return (y < self.contents().len()).then(|| (y, 0, self.row_len(row)));
+        }
+        // The rows are from the last layout, which can be before an edit
+        let &(row, start) = self.wrapped_rows.get(y)?;
+        if row as usize >= self.contents().len() {
+            return None;
+        }
+        let end = match self.wrapped_rows.get(y + 1) {
+            Some(&(next_row, next_start)) if next_row == row => next_start,
+            _ => self.row_len(row),
+        };
+        Some((row as usize, start, end))
+    }
+
+    /// The row of cells a position is shown on, counting from the top of the
+    /// buffer, and the cell in that row, counting from the start of the text
+    /// as if it wasn't scrolled sideways
+    fn cell_of(&self, (row, col): (i32, i32)) -> (usize, usize) {
+        let x = self.display_col(row, col);
+        if !self.soft_wrap || self.wrapped_rows.is_empty() {
+            return (max(row, 0) as usize, x);
+        }
+        let y = self
+            .wrapped_rows
+            .partition_point(|&start| start <= (row, col))
+            .saturating_sub(1);
+        let (_, start) = self.wrapped_rows[y];
+        (y, x.saturating_sub(self.display_col(row, start)))
+    }
+
+    /// The position shown at a cell of the view, counting from its corner,
+    /// or nothing if the cell is in the gutter or below the last row
+    fn position_at_cell(&self, x: usize, y: usize) -> Option<(i32, i32)> {
+        if !self.soft_wrap {
+            return self.map_positions(|mapper| mapper.position_at_cell(x, y));
+        }
+        let x = x.checked_sub(self.gutter_width())?;
+        let (row, start, end) = self.wrapped_row(self.scrollbase.start_line + y)?;
+        let row = row as i32;
+        Some((row, self.col_in_piece(row, start, end, x)))
+    }
+
+    /// The column shown at cell `x` of the piece of a wrapped row from `start`
+    /// to `end`. Cells past the end of a piece that isn't the last one are
+    /// on its last column, since its end is where the next one starts.
+    fn col_in_piece(&self, row: i32, start: i32, end: i32, x: usize) -> i32 {
+        let col = self.col_at_display(row, self.display_col(row, start) + x);
+        if end < self.row_len(row) {
+            min(col, max(end - 1, start))
+        } else {
+            min(col, end)
+        }
+    }
 
-        *self.row(row) = next_line;
-        *self.row(row + 1) = current_line;
-        self.cursor = (min(row + 1, (self.contents.len() - 1) as i32), col);
+    /// Where the cursor goes to move up or down a row of cells when soft wrap
+    /// is on, keeping it in the same column on screen, or nothing if there's
+    /// no row of cells there
+    fn wrapped_cursor(&self, down: bool) -> Option<(i32, i32)> {
+        if !self.soft_wrap || self.wrapped_rows.is_empty() {
+            return None;
+        }
+        let (y, x) = self.cell_of(self.cursor);
+        let y = if down { y + 1 } else { y.checked_sub(1)? };
+        let (row, start, end) = self.wrapped_row(y)?;
+        let row = row as i32;
+        Some((row, self.col_in_piece(row, start, end, x)))
     }
 
-    pub fn move_cursor_home(&mut self) {
-        let (row, _) = self.cursor;
-        self.cursor = (row, 0);
+    /// Scroll sideways just enough to show the cursor
+    fn scroll_to_cursor_x(&mut self) {
+        if self.soft_wrap {
+            return;
+        }
+        let (row, col) = self.cursor;
+        let x = self.display_col(row, col);
+        if x < self.scroll_x {
+            self.scroll_x = x;
+        } else if self.text_width > 0 && x >= self.scroll_x + self.text_width {
+            self.scroll_x = x + 1 - self.text_width;
+        }
     }
 
     pub fn move_cursor_end(&mut self) {
@@ -483,34 +4077,236 @@ where
         self.fix();
     }
 
-    /// Move the cursor down one character
+    /// The position of the start of the word before a position. At the
+    /// start of a row, this is the end of the row before it.
+    pub fn word_left_of(&self, (row, col): (i32, i32)) -> (i32, i32) {
+        if col == 0 {
+            return if row > 0 {
+                (row - 1, self.row_len(row - 1))
+            } else {
+                (0, 0)
+            };
+        }
+        let byte = self.byte_offset(row, col);
+        let start = self
+            .line(row)
+            .split_word_bound_indices()
+            .rev()
+            .find(|&(i, word)| i < byte && is_word(word))
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+        (row, self.col_at_byte(row, start))
+    }
+
+    /// The position of the end of the word after a position. At the
+    /// end of a row, this is the start of the row after it.
+    pub fn word_right_of(&self, (row, col): (i32, i32)) -> (i32, i32) {
+        if col >= self.row_len(row) {
+            return if row + 1 < self.contents().len() as i32 {
+                (row + 1, 0)
+            } else {
+                (row, col)
+            };
+        }
+        let byte = self.byte_offset(row, col);
+        let line = self.line(row);
+        let end = line
+            .split_word_bound_indices()
+            .find(|&(i, word)| i + word.len() > byte && is_word(word))
+            .map(|(i, word)| i + word.len())
+            .unwrap_or_else(|| line.len());
+        (row, self.col_at_byte(row, end))
+    }
+
+    /// The byte range of the word at a position, or just before it, in its row
+    fn word_at(&self, (row, col): (i32, i32)) -> Option<(usize, usize)> {
+        let byte = self.byte_offset(row, col);
+        self.line(row)
+            .split_word_bound_indices()
+            .find(|&(i, word)| i <= byte && byte <= i + word.len() && is_word(word))
+            .map(|(i, word)| (i, i + word.len()))
+    }
+
+    /// The extra cursors, besides the main one
+    pub fn cursors(&self) -> &[(i32, i32)] {
+        &self.cursors
+    }
+
+    /// Add a cursor, besides the main one
+    pub fn add_cursor(&mut self, (row, col): (i32, i32)) {
+        let last_row = self.contents().len() as i32 - 1;
+        let row = min(max(row, 0), last_row);
+        let cursor = (row, min(max(col, 0), self.row_len(row)));
+        if cursor != self.cursor && !self.cursors.contains(&cursor) {
+            self.cursors.push(cursor);
+        }
+    }
+
+    /// Add a cursor where the word under the cursor next appears after the
+    /// last cursor that was added, at the same place in the word. The search
+    /// wraps around to the start of the buffer.
+    pub fn add_cursor_at_next_match(&mut self) {
+        let (row, col) = self.cursor;
+        let (start, end) = match self.word_at(self.cursor) {
+            Some(word) => word,
+            None => return,
+        };
+        let word = self.line(row)[start..end].to_string();
+        let offset = self.byte_offset(row, col) - start;
+
+        let (from_row, from_col) = self.cursors.last().copied().unwrap_or(self.cursor);
+        let from = self.byte_offset(from_row, from_col).saturating_sub(offset);
+        let rows = self.contents().len();
+        for i in 0..=rows {
+            let row = (from_row as usize + i) % rows;
+            let matches: Vec<usize> = self.contents()[row]
+                .split_word_bound_indices()
+                .filter(|&(_, other)| other == word)
+                .map(|(byte, _)| byte)
+                .collect();
+            for byte in matches {
+                // Only after the last cursor on its own row, and only before it after wrapping around
+                if (i == 0 && byte <= from) || (i == rows && byte >= from) {
+                    continue;
+                }
+                let position = (row as i32, self.col_at_byte(row as i32, byte + offset));
+                if position != self.cursor && !self.cursors.contains(&position) {
+                    self.cursors.push(position);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Run a command at every cursor. The cursors are visited from the
+    /// end of the buffer back, so that each edit is before the cursors
+    /// already visited, and leaves their distance to the end unchanged.
+    fn execute_at_each_cursor(&mut self, command: Command) -> Option<Callback> {
+        let main = self.cursor;
+        let mut cursors = take(&mut self.cursors);
+        cursors.push(main);
+        cursors.sort_unstable();
+        cursors.dedup();
+
+        let mut callback = None;
+        // Each cursor is kept as its rows from the end of the buffer,
+        // and its columns from the end of its row
+        let mut from_end = Vec::new();
+        for &cursor in cursors.iter().rev() {
+            self.cursor = cursor;
+            callback = self.execute(command).or(callback);
+            self.fix();
+            let (row, col) = self.cursor;
+            let rows = self.contents().len() as i32 - 1 - row;
+            from_end.push((rows, self.row_len(row) - col, cursor == main));
+        }
+
+        let last_row = self.contents().len() as i32 - 1;
+        for (rows, cols, is_main) in from_end {
+            let row = max(last_row - rows, 0);
+            let cursor = (row, max(self.row_len(row) - cols, 0));
+            if is_main {
+                self.cursor = cursor;
+            } else {
+                self.cursors.push(cursor);
+            }
+        }
+        let main = self.cursor;
+        self.cursors.retain(|&cursor| cursor != main);
+        self.cursors.sort_unstable();
+        self.cursors.dedup();
+        callback
+    }
+
+    /// Move the cursor to the start of the word before it
+    pub fn move_cursor_word_left(&mut self) {
+        self.cursor = self.word_left_of(self.cursor);
+        self.fix();
+    }
+
+    /// Move the cursor to the end of the word after it
+    pub fn move_cursor_word_right(&mut self) {
+        self.cursor = self.word_right_of(self.cursor);
+        self.fix();
+    }
+
+    /// Move the cursor to the start of the next sentence, or the previous one
+    pub fn move_cursor_sentence(&mut self, forward: bool) {
+        let starts: Vec<(i32, i32)> = sentence_starts(&self.contents())
+            .into_iter()
+            .map(|(row, byte)| (row as i32, self.col_at_byte(row as i32, byte)))
+            .collect();
+        self.move_cursor_among(&starts, forward);
+    }
+
+    /// Move the cursor to the start of the next paragraph, or the previous one
+    pub fn move_cursor_paragraph(&mut self, forward: bool) {
+        let starts: Vec<(i32, i32)> = paragraph_starts(&self.contents())
+            .into_iter()
+            .map(|row| (row as i32, 0))
+            .collect();
+        self.move_cursor_among(&starts, forward);
+    }
+
+    /// Move the cursor to the first of `starts` after it, or the last one
+    /// before it. Past the last one is the end of the buffer, and before
+    /// the first one is its start.
+    fn move_cursor_among(&mut self, starts: &[(i32, i32)], forward: bool) {
+        let last = self.contents().len() as i32 - 1;
+        self.cursor = if forward {
+            let found = starts.iter().find(|&&start| start > self.cursor);
+            found.copied().unwrap_or((last, self.row_len(last)))
+        } else {
+            let found = starts.iter().rev().find(|&&start| start < self.cursor);
+            found.copied().unwrap_or((0, 0))
+        };
+        self.fix();
+    }
+
+    /// Move the cursor up one line, or one row of cells if rows are wrapped,
+    /// keeping it in the same column on screen
     pub fn move_cursor_up(&mut self) {
+        if let Some(cursor) = self.wrapped_cursor(false) {
+            self.cursor = cursor;
+            self.fix();
+            return;
+        }
         match self.cursor {
             // You cant move up!
             (0, _) => return,
-            (row, col) => self.cursor = (row - 1, col),
+            (row, col) => {
+                let x = self.display_col(row, col);
+                self.cursor = (row - 1, self.col_at_display(row - 1, x))
+            }
         }
 
         self.fix();
     }
 
-    /// Move the cursor down one character
+    /// Move the cursor down one line, or one row of cells if rows are wrapped,
+    /// keeping it in the same column on screen
     pub fn move_cursor_down(&mut self) {
+        if let Some(cursor) = self.wrapped_cursor(true) {
+            self.cursor = cursor;
+            self.fix();
+            return;
+        }
         let (row, col) = self.cursor;
-        self.cursor = (row + 1, col);
+        let x = self.display_col(row, col);
+        self.cursor = (row + 1, self.col_at_display(row + 1, x));
         self.fix();
     }
 
     /// Move cursor a page up
     pub fn move_page_up(&mut self) {
-        for _ in 0..8 {
+        for _ in 0..self.page_size {
             self.move_cursor_up();
         }
     }
 
     /// Move cursor a page down
     pub fn move_page_down(&mut self) {
-        for _ in 0..8 {
+        for _ in 0..self.page_size {
             self.move_cursor_down();
         }
     }
@@ -521,57 +4317,674 @@ where
 
         let (row, col) = self.cursor;
 
-        match (row, col) {
-            (row, col) if col >= self.row_len(row) && row < (self.contents.len() - 1) as i32 => {
-                let s = self.row(row + 1).clone();
-                *self.row(row) += &s;
-                self.contents.remove((row + 1) as usize);
-            }
-            (row, col) if row < (self.contents.len() - 1) as i32 => {
-                self.row(row).remove(col as usize);
-            }
-            _ => {}
-        }
+        match (row, col) {
+            (row, col) if col >= self.row_len(row) && row < (self.contents().len() - 1) as i32 => {
+                let s = self.row(row + 1).clone();
+                *self.row(row) += &s;
+                self.contents_mut().remove((row + 1) as usize);
+            }
+            (row, col) if row < (self.contents().len() - 1) as i32 => {
+                let start = self.byte_offset(row, col);
+                let end = self.byte_offset(row, col + 1);
+                self.row(row).replace_range(start..end, "");
+            }
+            _ => {}
+        }
+
+        self.fix();
+    }
+
+    /// Delete the text between two positions, where `from` comes before `to`
+    pub fn delete_range(&mut self, from: (i32, i32), to: (i32, i32)) {
+        let start = self.byte_offset(from.0, from.1);
+        let end = self.byte_offset(to.0, to.1);
+        let joined = format!("{}{}", &self.line(from.0)[..start], &self.line(to.0)[end..]);
+        *self.row(from.0) = joined;
+        if to.0 > from.0 {
+            self.contents_mut()
+                .drain(from.0 as usize + 1..=to.0 as usize);
+        }
+        self.cursor = from;
+        self.fix();
+    }
+
+    /// Delete from the start of the word before the cursor up to the cursor
+    pub fn delete_word_left(&mut self) {
+        let start = self.word_left_of(self.cursor);
+        self.delete_range(start, self.cursor);
+    }
+
+    /// Delete from the cursor to the end of the word after it
+    pub fn delete_word_right(&mut self) {
+        let end = self.word_right_of(self.cursor);
+        self.delete_range(self.cursor, end);
+    }
+
+    /// Delete from the cursor to the end of the row. At the
+    /// end of a row, this joins the next row onto it instead.
+    pub fn delete_to_end_of_line(&mut self) {
+        let (row, col) = self.cursor;
+        if col >= self.row_len(row) {
+            self.delete();
+        } else {
+            self.delete_range(self.cursor, (row, self.row_len(row)));
+        }
+    }
+
+    /// Delete the selected rows, or the cursor's row, without
+    /// putting them on the clipboard
+    pub fn delete_lines(&mut self) {
+        let (row, col) = self.cursor;
+        let (top, bottom) = self.selected_rows().unwrap_or((row, row));
+        self.contents_mut().drain(top as usize..=bottom as usize);
+        if self.contents().is_empty() {
+            self.contents_mut().push(String::new());
+        }
+        let last = self.contents().len() as i32 - 1;
+        self.cursor = (min(top, last), col);
+        self.fix();
+    }
+
+    /// Join the selected rows, or the next row onto the cursor's row, with
+    /// a single space where each line break was. The cursor goes to the last join.
+    pub fn join_lines(&mut self) {
+        let (row, _) = self.cursor;
+        let (top, bottom) = self.selected_rows().unwrap_or((row, row));
+        let bottom = min(max(bottom, top + 1), self.contents().len() as i32 - 1);
+        if top >= bottom {
+            return;
+        }
+        let mut joined = self.line(top).trim_end().to_string();
+        let mut col = 0;
+        for row in top + 1..=bottom {
+            let next = self.line(row);
+            let next = next.trim_start();
+            if !joined.is_empty() && !next.is_empty() {
+                joined.push(' ');
+            }
+            col = joined.graphemes(true).count() as i32;
+            joined.push_str(next);
+        }
+        self.contents_mut()
+            .splice(top as usize..=bottom as usize, Some(joined));
+        self.cursor = (top, col);
+    }
+
+    /// Cut from the cursor to the end of the row. At the end of
+    /// a row, the line break is cut, joining the next row onto it.
+    pub fn kill_to_end_of_line(&mut self) {
+        let (row, col) = self.cursor;
+        let end = if col >= self.row_len(row) && row + 1 < self.contents().len() as i32 {
+            (row + 1, 0)
+        } else {
+            (row, self.row_len(row))
+        };
+        self.kill_range(self.cursor, end);
+    }
+
+    /// Cut from the start of the row to the cursor. At the start of
+    /// a row, the line break is cut, joining the row onto the one before.
+    pub fn kill_to_start_of_line(&mut self) {
+        let (row, col) = self.cursor;
+        let start = if col == 0 && row > 0 {
+            (row - 1, self.row_len(row - 1))
+        } else {
+            (row, 0)
+        };
+        self.kill_range(start, self.cursor);
+    }
+
+    /// Delete the text between two positions and put it on the clipboard
+    fn kill_range(&mut self, from: (i32, i32), to: (i32, i32)) {
+        if from == to {
+            return;
+        }
+        let text = self.text_between(from, to);
+        self.set_clipboard(text, ClipboardKind::Chars);
+        self.delete_range(from, to);
+    }
+
+    /// The text from one position up to another, with rows joined by newlines
+    pub fn text_between(&self, from: (i32, i32), to: (i32, i32)) -> String {
+        let text = self.contents()[from.0 as usize..=to.0 as usize].join("\n");
+        let start = self.byte_offset(from.0, from.1);
+        let end = text.len() - (self.line(to.0).len() - self.byte_offset(to.0, to.1));
+        text[start..end].to_string()
+    }
+
+    /// Undo the last change
+    pub fn undo(&mut self) {
+        let cursor = self.buffer_mut().step(History::undo);
+        if let Some(cursor) = cursor {
+            self.cursor = cursor;
+        }
+        self.fix();
+    }
+
+    /// Redo the last change that was undone
+    pub fn redo(&mut self) {
+        let cursor = self.buffer_mut().step(History::redo);
+        if let Some(cursor) = cursor {
+            self.cursor = cursor;
+        }
+        self.fix();
+    }
+
+    /// Put the buffer back how it looked `duration` ago
+    pub fn undo_for(&mut self, duration: Duration) {
+        let cursor = self
+            .buffer_mut()
+            .step(|history, contents| history.undo_for(duration, contents));
+        if let Some(cursor) = cursor {
+            self.cursor = cursor;
+        }
+        self.fix();
+    }
+
+    /// Redo the changes made in the `duration` after the time the buffer was put back to
+    pub fn redo_for(&mut self, duration: Duration) {
+        let cursor = self
+            .buffer_mut()
+            .step(|history, contents| history.redo_for(duration, contents));
+        if let Some(cursor) = cursor {
+            self.cursor = cursor;
+        }
+        self.fix();
+    }
+
+    /// Move left and delete
+    pub fn backspace(&mut self) {
+        if self.cursor == (0, 0) {
+            return;
+        }
+        self.move_cursor_left();
+        self.delete();
+    }
+
+    /// Insert a character at the cursor
+    pub fn insert(&mut self, ch: char) {
+        let (row, col) = self.cursor;
+        match ch {
+            '\n' => {
+                let byte = self.byte_offset(row, col);
+                let before_cursor = String::from(&self.line(row)[..byte]);
+                let after_cursor = String::from(&self.line(row)[byte..]);
+
+                *self.row(row) = before_cursor;
+                self.contents_mut().insert((row + 1) as usize, after_cursor);
+                self.cursor = (row + 1, 0);
+            }
+            other => {
+                let byte = self.byte_offset(row, col);
+                self.row(row).insert(byte, other);
+                // A combining character joins the grapheme before it
+                // instead of starting a new one
+                self.cursor = (row, self.col_at_byte(row, byte + other.len_utf8()));
+            }
+        }
+        self.fix();
+    }
+
+    /// Split the line at the cursor. Between a pair of brackets, like `{}`,
+    /// the closing bracket goes on a line of its own, and the cursor goes on
+    /// an indented line between them.
+    pub fn new_line(&mut self) {
+        let (row, col) = self.cursor;
+        let byte = self.byte_offset(row, col);
+        let line = self.line(row).to_string();
+        let previous = line[..byte].chars().next_back();
+        let next = line[byte..].chars().next();
+        let between_brackets = BRACKETS
+            .iter()
+            .any(|&(open, close)| previous == Some(open) && next == Some(close));
+        if !self.auto_pairs || !between_brackets {
+            self.insert('\n');
+            return;
+        }
+
+        let indent: String = line.chars().take_while(|ch| ch.is_whitespace()).collect();
+        let inner = indent.clone() + &self.indent_unit();
+        let after_cursor = indent + &line[byte..];
+        self.row(row).truncate(byte);
+        self.contents_mut().insert(row as usize + 1, inner);
+        self.contents_mut().insert(row as usize + 2, after_cursor);
+        self.cursor = (row + 1, self.row_len(row + 1));
+        self.fix();
+    }
+
+    /// Open a new line below the cursor's row, without splitting the row,
+    /// indented like it or one level more after an opening bracket
+    pub fn insert_line_below(&mut self) {
+        let row = self.cursor.0;
+        let line = self.line(row).to_string();
+        let mut indent: String = line.chars().take_while(|ch| ch.is_whitespace()).collect();
+        let opens_bracket = |ch| BRACKETS.iter().any(|&(open, _)| open == ch);
+        if line.trim_end().ends_with(opens_bracket) {
+            indent.push_str(&self.indent_unit());
+        }
+        let col = indent.graphemes(true).count() as i32;
+        self.contents_mut().insert(row as usize + 1, indent);
+        self.cursor = (row + 1, col);
+    }
+
+    /// Open a new line above the cursor's row, indented like it
+    pub fn insert_line_above(&mut self) {
+        let row = self.cursor.0;
+        let indent: String = self
+            .line(row)
+            .chars()
+            .take_while(|ch| ch.is_whitespace())
+            .collect();
+        let col = indent.graphemes(true).count() as i32;
+        self.contents_mut().insert(row as usize, indent);
+        self.cursor = (row, col);
+    }
+
+    /// Insert a character typed by the user. Unlike `insert`, this
+    /// can trigger automatic edits, like completing a closing tag.
+    pub fn type_char(&mut self, ch: char) {
+        if self.auto_pairs && self.type_pair(ch) {
+            return;
+        }
+        let (row_before, col_before) = self.cursor;
+        self.insert(ch);
+
+        let (row, col) = self.cursor;
+        if ch == '/' && self.line(row)[..self.byte_offset(row, col)].ends_with("</") {
+            let offset = self.text_offset(row, col) - 2;
+            if let Some(name) = self.unclosed_tag_at(offset) {
+                self.insert_str(name + ">");
+            }
+        }
+
+        if self.cursor.0 == row_before {
+            self.shift_auto_closers(col_before, self.cursor.1 - col_before);
+        } else {
+            self.auto_closers.clear();
+        }
+    }
+
+    /// Type a bracket or quote with auto-pairing. Returns `false`
+    /// if the character should just be inserted normally instead.
+    fn type_pair(&mut self, ch: char) -> bool {
+        let (row, col) = self.cursor;
+        let byte = self.byte_offset(row, col);
+        let previous = self.line(row)[..byte].chars().next_back();
+        let next = self.line(row)[byte..].chars().next();
+
+        // Typing a closer that auto-pairing put there just steps over it
+        if next == Some(ch) && self.auto_closers.contains(&self.cursor) {
+            self.auto_closers.retain(|&closer| closer != (row, col));
+            self.move_cursor_right();
+            return true;
+        }
+
+        let close = match PAIRS.iter().find(|&&(open, _)| open == ch) {
+            Some(&(_, close)) => close,
+            None => return false,
+        };
+
+        // Wrap the selection in the pair
+        if let Some((top, bottom)) = self.selection_bounds() {
+            if top != bottom {
+                self.cursor = bottom;
+                self.insert(close);
+                self.cursor = top;
+                self.insert(ch);
+                self.cursor = if top.0 == bottom.0 {
+                    (bottom.0, bottom.1 + 1)
+                } else {
+                    bottom
+                };
+                return true;
+            }
+        }
+
+        // Don't pair a bracket typed right before a word,
+        // or a quote that's probably an apostrophe
+        let is_alphanumeric = |ch: Option<char>| ch.map(char::is_alphanumeric).unwrap_or(false);
+        if is_alphanumeric(next) || (ch == close && is_alphanumeric(previous)) {
+            return false;
+        }
+
+        self.insert(ch);
+        self.insert(close);
+        self.move_cursor_left();
+        self.shift_auto_closers(col, 2);
+        self.auto_closers.push(self.cursor);
+        true
+    }
+
+    /// Move the closers that auto-pairing inserted at or after a column
+    /// of the cursor's row, after text is inserted there
+    fn shift_auto_closers(&mut self, col: i32, by: i32) {
+        let row = self.cursor.0;
+        for closer in &mut self.auto_closers {
+            if closer.0 == row && closer.1 >= col {
+                closer.1 += by;
+            }
+        }
+    }
+
+    /// Whether each byte of a line is code, rather than part of a string
+    /// or comment. Brackets that aren't code are never matched up.
+    fn code_mask(&self, line: &str) -> Vec<bool> {
+        let quotes = self.language.map_or(&['"'][..], Language::quotes);
+        code_mask(line, self.comments, quotes)
+    }
+
+    /// The positions of the bracket at the cursor, or just before it,
+    /// and the bracket that matches it
+    pub fn matching_bracket(&self) -> Option<((i32, i32), (i32, i32))> {
+        let (row, col) = self.cursor;
+        let at = self.byte_offset(row, col);
+        let before = self.byte_offset(row, col - 1);
+        let (byte, found) = if let Some(found) = self.match_bracket_at(row, at) {
+            (at, found)
+        } else if col > 0 {
+            (before, self.match_bracket_at(row, before)?)
+        } else {
+            return None;
+        };
+        Some((
+            (row, self.col_at_byte(row, byte)),
+            (found.0, self.col_at_byte(found.0, found.1)),
+        ))
+    }
+
+    /// Move the cursor to the bracket that matches the one it's on
+    pub fn jump_to_matching_bracket(&mut self) {
+        if let Some((_, found)) = self.matching_bracket() {
+            self.cursor = found;
+        }
+    }
+
+    /// Find the row and byte offset of the bracket that matches
+    /// the one at a byte offset of a row, skipping strings and comments
+    fn match_bracket_at(&self, row: i32, byte: usize) -> Option<(i32, usize)> {
+        let ch = self.line(row)[byte..].chars().next()?;
+        let (open, close, forward) =
+            if let Some(&(open, close)) = BRACKETS.iter().find(|&&(open, _)| open == ch) {
+                (open, close, true)
+            } else if let Some(&(open, close)) = BRACKETS.iter().find(|&&(_, close)| close == ch) {
+                (close, open, false)
+            } else {
+                return None;
+            };
+        if !self.code_mask(&self.line(row))[byte] {
+            return None;
+        }
+
+        // `open` is the bracket we started on, which may be a closing
+        // bracket when searching backwards
+        let mut depth = 0;
+        let mut check = |r: i32, i: usize, c: char, mask: &[bool]| {
+            if mask[i] {
+                if c == open {
+                    depth += 1;
+                } else if c == close {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((r, i));
+                    }
+                }
+            }
+            None
+        };
+
+        if forward {
+            for r in row..self.contents().len() as i32 {
+                let line = self.line(r);
+                let mask = self.code_mask(&line);
+                let start = if r == row { byte } else { 0 };
+                for (i, c) in line[start..].char_indices() {
+                    if let Some(found) = check(r, start + i, c, &mask) {
+                        return Some(found);
+                    }
+                }
+            }
+        } else {
+            for r in (0..=row).rev() {
+                let line = self.line(r);
+                let mask = self.code_mask(&line);
+                let end = if r == row {
+                    byte + ch.len_utf8()
+                } else {
+                    line.len()
+                };
+                for (i, c) in line[..end].char_indices().rev() {
+                    if let Some(found) = check(r, i, c, &mask) {
+                        return Some(found);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Insert the closing tag for the innermost tag that's open at the cursor
+    pub fn close_tag(&mut self) {
+        let (row, col) = self.cursor;
+        if let Some(name) = self.unclosed_tag_at(self.text_offset(row, col)) {
+            self.insert_str(format!("</{}>", name));
+        }
+    }
+
+    /// Make an edit at the cursor, and if it renamed a markup tag,
+    /// rename the tag that matches it to the same thing
+    fn edit_paired_tag(&mut self, edit: impl FnOnce(&mut Self)) {
+        let (row, col) = self.cursor;
+        let offset = self.text_offset(row, col);
+        let pair = self.markup.and_then(|markup| {
+            let text = self.contents().join("\n");
+            let tags = scan_tags(&text, markup);
+            let index = tags.iter().position(|tag| {
+                tag.name_start <= offset && offset <= tag.name_start + tag.name.len()
+            })?;
+            let other = matching_tag(&tags, index, markup)?.clone();
+            Some((tags[index].clone(), other, text.len()))
+        });
+
+        edit(self);
+
+        let (tag, other, len_before) = match pair {
+            Some(pair) => pair,
+            None => return,
+        };
+        // Make sure the edit didn't change the start of the tag, like
+        // a backspace that deletes its `<`
+        let text = self.contents().join("\n");
+        let prefix = match tag.kind {
+            TagKind::Close => "</",
+            _ => "<",
+        };
+        if text.get(tag.start..tag.name_start) != Some(prefix) {
+            return;
+        }
+        let name_len = text[tag.name_start..]
+            .find(|ch: char| !is_name_char(ch))
+            .unwrap_or(text.len() - tag.name_start);
+        let name = &text[tag.name_start..tag.name_start + name_len];
+        if name.is_empty() || name == tag.name {
+            return;
+        }
+        // If the edit didn't all go into the name, like a space typed in the
+        // middle of it to start an attribute, the name was left rather than renamed
+        if (tag.name.len() + text.len()) as isize - len_before as isize != name_len as isize {
+            return;
+        }
+
+        let mut other_start = other.name_start;
+        if other_start > tag.name_start {
+            other_start = other_start + name_len - tag.name.len();
+        }
+        // The cursor stays in the name it's in
+        let rename = TextEdit::replace(
+            self.text_point(other_start),
+            self.text_point(other_start + other.name.len()),
+            name,
+        );
+        let _ = self.make_edits(&[rename]);
+    }
+
+    /// The name of the innermost markup tag that's open at a byte offset
+    fn unclosed_tag_at(&self, offset: usize) -> Option<String> {
+        let markup = self.markup?;
+        let tags = scan_tags(&self.contents().join("\n"), markup);
+        unclosed_tag(&tags, offset, markup).map(|tag| tag.name.clone())
+    }
+
+    /// Expand the Emmet abbreviation before the cursor, like `ul>li*3>a`.
+    /// Returns `false` if this isn't an HTML or CSS file, or there's
+    /// no abbreviation to expand.
+    pub fn expand_abbreviation(&mut self) -> bool {
+        let syntax = match self.emmet {
+            Some(syntax) => syntax,
+            None => return false,
+        };
+        let (row, col) = self.cursor;
+        let end = self.byte_offset(row, col);
+        let line = self.line(row).to_string();
+        let abbreviation = abbreviation_before(&line[..end]);
+        let start = end - abbreviation.len();
+        let indent: String = line.chars().take_while(|ch| ch.is_whitespace()).collect();
+        let tab = if self.hard_tabs {
+            String::from("\t")
+        } else {
+            " ".repeat(self.tab_width)
+        };
+
+        let expansion = match syntax.expand(abbreviation, &indent, &tab) {
+            Some(expansion) if !abbreviation.is_empty() => expansion,
+            _ => return false,
+        };
+        self.row(row).replace_range(start..end, "");
+        self.cursor = (row, self.col_at_byte(row, start));
 
-        self.fix();
+        // Leave the cursor at the first place that needs filling in
+        self.insert_str(&expansion.text[..expansion.cursor]);
+        let cursor = self.cursor;
+        self.insert_str(&expansion.text[expansion.cursor..]);
+        self.cursor = cursor;
+        true
     }
 
-    /// Move left and delete
-    pub fn backspace(&mut self) {
-        if self.cursor == (0, 0) {
-            return;
-        }
-        self.move_cursor_left();
-        self.delete();
+    /// Expand the snippet whose trigger is the word before the cursor,
+    /// leaving the cursor at its first stop. Returns `false` if there's
+    /// no snippet to expand.
+    pub fn expand_snippet(&mut self) -> bool {
+        let trigger = self.word_before_cursor();
+        let body = match self
+            .extension
+            .as_ref()
+            .and_then(|extension| self.languages.get(extension))
+            .and_then(|settings| settings.snippets.get(&trigger))
+        {
+            Some(body) => body.clone(),
+            None => return false,
+        };
+        let (row, col) = self.cursor;
+        let end = self.byte_offset(row, col);
+        let start = end - trigger.len();
+        let indent: String = self
+            .line(row)
+            .chars()
+            .take_while(|ch| ch.is_whitespace())
+            .collect();
+        let expansion = expand_snippet(&body, &indent, &self.indent_unit());
+
+        self.row(row).replace_range(start..end, "");
+        self.cursor = (row, self.col_at_byte(row, start));
+        let offset = self.text_offset(row, self.cursor.1);
+        self.insert_str(&expansion.text);
+        let stops = expansion
+            .stops
+            .iter()
+            .map(|stop| {
+                let (row, byte) = self.text_position(offset + stop);
+                (row, self.col_at_byte(row, byte))
+            })
+            .collect();
+        self.jump_to_snippet_stop(stops);
+        true
     }
 
-    /// Insert a character at the cursor
-    pub fn insert(&mut self, ch: char) {
-        let (row, col) = self.cursor;
-        match ch {
-            '\n' => {
-                let before_cursor = String::from(&self.row(row)[..col as usize]);
-                let after_cursor = String::from(&self.row(row)[col as usize..]);
+    /// Move the cursor to the first of a snippet's stops, keeping the rest
+    fn jump_to_snippet_stop(&mut self, mut stops: Vec<(i32, i32)>) {
+        let at = stops.remove(0);
+        self.cursor = at;
+        self.snippet_stops = if stops.is_empty() {
+            None
+        } else {
+            Some(SnippetStops {
+                stops,
+                at,
+                row_len: self.row_len(at.0),
+                rows: self.contents().len(),
+            })
+        };
+    }
 
-                *self.row(row) = before_cursor;
-                self.contents.insert((row + 1) as usize, after_cursor);
-                self.cursor = (row + 1, 0);
-            }
-            '\t' => self.insert_str("    "),
-            other => {
-                self.row(row).insert(col as usize, other);
-                self.move_cursor_right();
-            }
+    /// Jump to the next stop of the snippet that was expanded last.
+    /// The stops after the one the cursor was at move along with the
+    /// text after it, by what was typed there.
+    fn next_snippet_stop(&mut self) {
+        let snippet = match self.snippet_stops.take() {
+            Some(snippet) => snippet,
+            None => return,
+        };
+        let (at_row, at_col) = snippet.at;
+        let row_len = snippet.row_len;
+        let added_rows = self.contents().len() as i32 - snippet.rows as i32;
+        let stops = snippet
+            .stops
+            .into_iter()
+            .map(|(row, col)| {
+                if row > at_row {
+                    (row + added_rows, col)
+                } else if row == at_row && col >= at_col {
+                    let row = row + added_rows;
+                    (row, max(self.row_len(row) - (row_len - col), 0))
+                } else {
+                    (row, col)
+                }
+            })
+            .collect();
+        self.jump_to_snippet_stop(stops);
+    }
+
+    /// Insert a tab at the cursor. With soft tabs, this inserts
+    /// spaces up to the next tab stop instead.
+    pub fn insert_tab(&mut self) {
+        if self.hard_tabs {
+            self.insert('\t');
+        } else {
+            let (row, col) = self.cursor;
+            let x = self.display_col(row, col);
+            self.insert_str(" ".repeat(self.tab_width - x % self.tab_width));
         }
-        self.fix();
     }
 
-    /// Insert a string at the cursor
+    /// Insert a string at the cursor. The string is split into lines once,
+    /// and they're spliced into the buffer together, so that pasting or
+    /// opening a big file doesn't insert it one character at a time.
     pub fn insert_str(&mut self, s: impl ToString) {
-        for ch in s.to_string().chars() {
-            self.insert(ch);
-        }
+        let text = s.to_string();
+        let (row, col) = self.cursor;
+        let byte = self.byte_offset(row, col);
+        let after_cursor = self.row(row).split_off(byte);
 
+        let mut lines = text.split('\n');
+        self.row(row).push_str(lines.next().unwrap_or_default());
+        let rest: Vec<String> = lines.map(String::from).collect();
+        let last_row = row + rest.len() as i32;
+        let next_row = (row + 1) as usize;
+        self.contents_mut().splice(next_row..next_row, rest);
+
+        // The cursor goes after the inserted text, before what was after it
+        let end = self.line(last_row).len();
+        self.row(last_row).push_str(&after_cursor);
+        self.cursor = (last_row, self.col_at_byte(last_row, end));
         self.fix();
     }
 
@@ -579,8 +4992,8 @@ where
     pub fn fix_cursor(&mut self) {
         let (mut row, mut col) = self.cursor;
         // Check if the cursor is greater than the number of rows
-        if row >= self.contents.len() as i32 {
-            row = max((self.contents.len() - 1) as i32, 0);
+        if row >= self.contents().len() as i32 {
+            row = max((self.contents().len() - 1) as i32, 0);
             col = self.row_len(row)
         }
         if col > self.row_len(row) {
@@ -594,13 +5007,16 @@ where
     /// Also, confirm there is an extra line at the end of the file.
     pub fn fix_newline(&mut self) {
         // Get rid of any newlines (there shouldnt be any)
-        for line in &mut self.contents {
+        for line in &mut *self.contents_mut() {
             *line = line.replace("\n", "");
         }
 
         // If theres no empty line, add one!
-        if Some(&String::from("")) != self.contents.last() {
-            self.contents.push(String::from(""));
+        // Lines narrowed to before the end of the file don't need one.
+        let at_end = self.narrowing.as_ref().is_none_or(|n| n.below.is_empty());
+        if self.contents().is_empty() || at_end && Some(&String::from("")) != self.contents().last()
+        {
+            self.contents_mut().push(String::from(""));
         }
     }
 
@@ -610,6 +5026,482 @@ where
         self.fix_cursor();
         self.fix_newline();
     }
+
+    /// Run a command as if its key was pressed. Every command that changes
+    /// the buffer can be undone in one step, and none of them run if the
+    /// code area is read-only.
+    pub fn run_command(&mut self, command: Command) -> Option<Callback> {
+        self.message = None;
+        match command {
+            Command::RecordMacro => {
+                self.toggle_macro_recording();
+                return None;
+            }
+            Command::PlayMacro => return self.play_macro(1),
+            Command::ScrollLeft => {
+                self.scroll_left(5);
+                return None;
+            }
+            Command::ScrollRight => {
+                self.scroll_right(5);
+                return None;
+            }
+            Command::ToggleTypewriter => {
+                self.toggle_typewriter();
+                return None;
+            }
+            Command::ToggleBlame => {
+                self.toggle_blame();
+                return None;
+            }
+            Command::ToggleReadOnly => {
+                self.toggle_read_only();
+                return None;
+            }
+            // The rows of a snippet's stops change
+            Command::Narrow => {
+                self.snippet_stops = None;
+                self.narrow();
+                return None;
+            }
+            Command::Widen => {
+                self.snippet_stops = None;
+                if !self.widen() {
+                    self.bell("The buffer isn't narrowed");
+                }
+                return None;
+            }
+            _ => {}
+        }
+        if self.read_only && command.is_edit() {
+            self.bell("The buffer is read-only");
+            return None;
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.push(command);
+        }
+        let was_modified = self.buffer().modified;
+        // A burst of typing is undone all at once
+        let is_typing = |command| matches!(command, Command::Type(_) | Command::Backspace);
+        let continues_typing = is_typing(command)
+            && self.last_command.is_some_and(is_typing)
+            && self.last_edit.elapsed() < TYPING_PAUSE;
+        self.last_command = Some(command);
+
+        if command == Command::Paste {
+            // The desktop's clipboard is read once, not again for each cursor
+            self.clipboard.borrow_mut().read_system();
+            if self.cursors.is_empty() {
+                if let Some(text) = self.large_paste() {
+                    return Some(self.start_paste(text));
+                }
+            }
+        }
+
+        // Edits made outside of commands, like reloading, were recorded already
+        self.fix();
+        let cursor = self.cursor;
+        self.buffer_mut().take_change(cursor, cursor);
+
+        // Formatting is its own change, so it can be undone after saving
+        let formats = self.format_on_save && self.formatter.is_some() && !self.read_only;
+        if command == Command::Save && formats {
+            let cursor = self.cursor;
+            if let Err(e) = self.format_buffer() {
+                self.message = Some(format_error(&e));
+            }
+            self.fix();
+            let cursor_after = self.cursor;
+            let mut buffer = self.buffer_mut();
+            if let Some(change) = buffer.take_change(cursor, cursor_after) {
+                buffer.history.record(change);
+            }
+        }
+
+        let cursor_before = self.cursor;
+        let several_cursors = !self.cursors.is_empty();
+        let at_each_cursor = several_cursors && command.applies_to_each_cursor();
+        let callback = if at_each_cursor {
+            self.execute_at_each_cursor(command)
+        } else {
+            self.execute(command)
+        };
+        let cursor_after = self.cursor;
+        let change = self.buffer_mut().take_change(cursor_before, cursor_after);
+        // Other cursors can't follow other edits or jumps of the main cursor
+        if !at_each_cursor && (self.cursor != cursor_before || change.is_some()) {
+            self.cursors.clear();
+        }
+        // Typing at several cursors, or anything other than typing,
+        // makes it unclear which closers auto-pairing inserted
+        if several_cursors || !matches!(command, Command::Type(_)) {
+            self.auto_closers.clear();
+        }
+        if self.cursor == cursor_before && change.is_none() {
+            if let Some(message) = stuck_message(command) {
+                self.bell(message);
+            }
+        }
+
+        if let Some(change) = change {
+            self.buffer_mut().modified = true;
+            self.last_edit = Instant::now();
+            self.autosaved = false;
+            match command {
+                Command::Undo | Command::Redo | Command::Earlier(_) | Command::Later(_) => {}
+                _ if self.playing_macro => {}
+                _ if continues_typing => self.buffer_mut().history.record_grouped(change),
+                _ => self.buffer_mut().history.record(change),
+            }
+            // Undoing or redoing back to how the buffer was saved leaves nothing to save
+            if matches!(
+                command,
+                Command::Undo | Command::Redo | Command::Earlier(_) | Command::Later(_)
+            ) && self.buffer().history.is_saved()
+            {
+                self.buffer_mut().modified = false;
+            }
+        }
+
+        // Editing a block keeps it, so the next edit goes on every row too
+        let edits_block = self.block.is_some()
+            && matches!(
+                command,
+                Command::Type(_) | Command::Backspace | Command::Delete
+            );
+        if !command.keeps_selection() && !edits_block {
+            self.forget_selection()
+        }
+
+        self.scroll_to_cursor();
+        self.scroll_to_cursor_x();
+        self.notify_modified(was_modified, callback)
+    }
+
+    fn execute(&mut self, command: Command) -> Option<Callback> {
+        match command {
+            Command::Quit if self.on_quit.is_some() => return self.on_quit.clone(),
+            Command::Quit => return Some(self.quit_callback()),
+            Command::Save if self.is_scratch() && self.on_save_as.is_some() => {
+                return self.on_save_as.clone();
+            }
+            Command::Save => {
+                if let Err(e) = self.save_content() {
+                    let message = format!("Could not save {}: {}", self.filename(), e);
+                    return Some(Callback::from_fn(move |s| {
+                        s.add_layer(Dialog::info(message.clone()))
+                    }));
+                }
+            }
+            Command::Paste => self.paste(),
+            Command::Copy if self.block.is_some() => self.copy_block(),
+            Command::Copy => self.copy(),
+            Command::Cut if self.block.is_some() => self.cut_block(),
+            Command::Cut => self.cut(),
+            Command::ToggleComment => {
+                let block = self
+                    .block_comment()
+                    .filter(|_| self.is_selecting_mid_line());
+                if let Some(block) = block {
+                    self.toggle_block_comment(block)
+                } else if self.is_selecting() {
+                    self.comment_selection()
+                } else {
+                    self.comment_current_line()
+                }
+            }
+            Command::CopyLineDown => self.copy_line_down(),
+            Command::Undo => self.undo(),
+            Command::Redo => self.redo(),
+            Command::Earlier(duration) => self.undo_for(duration),
+            Command::Later(duration) => self.redo_for(duration),
+            Command::FileInfo => {
+                let info = self.file_info().to_string();
+                return Some(Callback::from_fn(move |s| {
+                    s.add_layer(Dialog::info(info.clone()).title("File info"))
+                }));
+            }
+            Command::ShowChanges => {
+                let title = format!("Unsaved changes to {}", self.filename());
+                let changes = self
+                    .unsaved_changes()
+                    .map_err(|e| format!("Could not read {}: {}", self.filename(), e));
+                let empty = "There are no unsaved changes";
+                return Some(changes_callback(title, changes, empty));
+            }
+            Command::ShowHeadChanges => {
+                let title = format!("Changes to {} since the last commit", self.filename());
+                let changes = self
+                    .head_changes()
+                    .map_err(|e| format!("Could not read {} from git: {}", self.filename(), e));
+                let empty = "There are no changes since the last commit";
+                return Some(changes_callback(title, changes, empty));
+            }
+            Command::ToggleLineEnding => self.set_line_ending(self.line_ending().toggled()),
+            Command::CloseTag => self.close_tag(),
+            Command::JumpToMatchingBracket => self.jump_to_matching_bracket(),
+            Command::NextChange => self.jump_to_head_change(true),
+            Command::PreviousChange => self.jump_to_head_change(false),
+            Command::NextDiagnostic => self.jump_to_diagnostic(true),
+            Command::PreviousDiagnostic => self.jump_to_diagnostic(false),
+            Command::OrganizeImports => self.organize_imports(),
+            Command::Format if self.formatter.is_none() && self.on_format.is_some() => {
+                return self.on_format.clone();
+            }
+            Command::Format => match self.format_buffer() {
+                Ok(true) => {}
+                Ok(false) => self.bell("There's no formatter for this file type"),
+                Err(e) => self.message = Some(format_error(&e)),
+            },
+            Command::Uppercase => self.transform_case(str::to_uppercase),
+            Command::Lowercase => self.transform_case(str::to_lowercase),
+            Command::ToggleCase => self.transform_case(toggle_case),
+            Command::SortLines => self.sort_lines(false),
+            Command::SortLinesDescending => self.sort_lines(true),
+            Command::DeduplicateLines => self.deduplicate_lines(),
+            Command::Complete => self.complete(),
+            Command::ExpandAbbreviation => {
+                self.expand_abbreviation();
+            }
+            Command::NewLine => self.new_line(),
+            Command::InsertLineBelow => self.insert_line_below(),
+            Command::InsertLineAbove => self.insert_line_above(),
+            Command::Delete if self.block.is_some() => self.delete_in_block(true),
+            Command::Delete => self.edit_paired_tag(Self::delete),
+            Command::Backspace if self.block.is_some() => self.delete_in_block(false),
+            Command::Backspace => self.edit_paired_tag(Self::backspace),
+            Command::DeleteWordLeft => self.delete_word_left(),
+            Command::DeleteWordRight => self.delete_word_right(),
+            Command::DeleteToEndOfLine => self.delete_to_end_of_line(),
+            Command::DeleteLine => self.delete_lines(),
+            Command::JoinLines => self.join_lines(),
+            Command::KillToEndOfLine => self.kill_to_end_of_line(),
+            Command::KillToStartOfLine => self.kill_to_start_of_line(),
+            Command::Indent if self.snippet_stops.is_some() => self.next_snippet_stop(),
+            Command::Indent if self.is_selecting_rows() => self.indent_selection(),
+            Command::Indent => {
+                if !self.expand_snippet() {
+                    self.insert_tab();
+                }
+                self.forget_selection();
+            }
+            Command::Dedent => self.dedent_selection(),
+            Command::Home => self.move_cursor_home(),
+            Command::End => self.move_cursor_end(),
+            Command::PageUp => self.move_page_up(),
+            Command::PageDown => self.move_page_down(),
+            Command::MoveLineUp => self.move_line_up(),
+            Command::MoveArgumentLeft => self.move_argument(false),
+            Command::MoveArgumentRight => self.move_argument(true),
+            Command::MoveLineDown => self.move_line_down(),
+            Command::Up => self.move_cursor_up(),
+            Command::Down => self.move_cursor_down(),
+            Command::Left => self.move_cursor_left(),
+            Command::Right => self.move_cursor_right(),
+            Command::WordLeft => self.move_cursor_word_left(),
+            Command::WordRight => self.move_cursor_word_right(),
+            Command::NextSentence => self.move_cursor_sentence(true),
+            Command::PreviousSentence => self.move_cursor_sentence(false),
+            Command::NextParagraph => self.move_cursor_paragraph(true),
+            Command::PreviousParagraph => self.move_cursor_paragraph(false),
+            Command::SelectPageUp
+            | Command::SelectPageDown
+            | Command::SelectUp
+            | Command::SelectDown
+            | Command::SelectLeft
+            | Command::SelectRight
+            | Command::SelectWordLeft
+            | Command::SelectWordRight
+            | Command::SelectHome
+            | Command::SelectEnd
+            | Command::SelectToStart
+            | Command::SelectToEnd => {
+                self.block = None;
+                self.continue_selection();
+                match command {
+                    Command::SelectPageUp => self.move_page_up(),
+                    Command::SelectPageDown => self.move_page_down(),
+                    Command::SelectUp => self.move_cursor_up(),
+                    Command::SelectDown => self.move_cursor_down(),
+                    Command::SelectLeft => self.move_cursor_left(),
+                    Command::SelectRight => self.move_cursor_right(),
+                    Command::SelectWordLeft => self.move_cursor_word_left(),
+                    Command::SelectHome => self.move_cursor_home(),
+                    Command::SelectEnd => self.move_cursor_end(),
+                    Command::SelectToStart => self.move_cursor_to_start(),
+                    Command::SelectToEnd => self.move_cursor_to_end(),
+                    _ => self.move_cursor_word_right(),
+                }
+            }
+            Command::SelectBlockUp => {
+                self.continue_block_selection();
+                self.move_block_corner(-1, 0);
+            }
+            Command::SelectBlockDown => {
+                self.continue_block_selection();
+                self.move_block_corner(1, 0);
+            }
+            Command::SelectBlockLeft => {
+                self.continue_block_selection();
+                self.move_block_corner(0, -1);
+            }
+            Command::SelectBlockRight => {
+                self.continue_block_selection();
+                self.move_block_corner(0, 1);
+            }
+            Command::AddCursorAtNextMatch => self.add_cursor_at_next_match(),
+            Command::ClearCursors => {
+                self.cursors.clear();
+                self.snippet_stops = None;
+            }
+            Command::SelectAll => self.select_all(),
+            Command::SelectWord => self.select_word(),
+            Command::SelectLine => self.select_line(),
+            // These are run by `run_command`, which records a played macro's changes together
+            Command::RecordMacro
+            | Command::PlayMacro
+            | Command::ScrollLeft
+            | Command::ScrollRight
+            | Command::ToggleTypewriter
+            | Command::ToggleBlame
+            | Command::ToggleReadOnly
+            | Command::Narrow
+            | Command::Widen => {}
+            Command::Type(ch) if self.block.is_some() => self.type_in_block(ch),
+            Command::Type(ch) => self.edit_paired_tag(|area| area.type_char(ch)),
+        }
+        None
+    }
+
+    /// Handle an event, like a key press, the way `on_event` does
+    fn handle_event(&mut self, event: Event) -> EventResult {
+        let was_modified = self.buffer().modified;
+        if self.pending_paste.is_some() {
+            return match event {
+                Event::Refresh => EventResult::Consumed(self.paste_chunk(was_modified)),
+                Event::Key(Key::Esc) => {
+                    self.cancel_paste();
+                    EventResult::Consumed(None)
+                }
+                // Nothing else can happen until the paste is done
+                _ => EventResult::Consumed(None),
+            };
+        }
+        if event == Event::Refresh && self.save_and_quit.replace(false) {
+            let callback = self.run_command(Command::Save);
+            if self.buffer().modified {
+                return EventResult::Consumed(callback);
+            }
+            let quit = Callback::from_fn_mut(|s| s.quit());
+            return EventResult::Consumed(chain(callback, Some(quit)));
+        }
+        if event == Event::Refresh {
+            self.check_spelling_if_idle();
+            let callback = match self.autosave_if_idle() {
+                Ok(()) => None,
+                Err(e) => {
+                    let message = format!("Could not autosave {}: {}", self.filename(), e);
+                    Some(Callback::from_fn(move |s| {
+                        s.add_layer(Dialog::info(message.clone()))
+                    }))
+                }
+            };
+            let callback = chain(callback, self.file_changed_callback());
+            return match self.notify_modified(was_modified, callback) {
+                Some(callback) => EventResult::Consumed(Some(callback)),
+                None => EventResult::Ignored,
+            };
+        }
+
+        // Typing or deleting the word being completed keeps the popup open
+        let completing = self.completion.is_some();
+        if let Some(result) = self.completion_event(&event) {
+            return result;
+        }
+        if let Some(command) = self.keymap.get(&event) {
+            let callback = self.run_command(command);
+            if completing && command == Command::Backspace {
+                self.refine_completion();
+            }
+            return EventResult::Consumed(callback);
+        }
+        match event {
+            Event::Char(ch) => {
+                let callback = self.run_command(Command::Type(ch));
+                if completing {
+                    self.refine_completion();
+                }
+                EventResult::Consumed(callback)
+            }
+            Event::Mouse {
+                event: MouseEvent::WheelUp,
+                ..
+            } if self.scrollbase.can_scroll_up() => {
+                self.scrollbase.scroll_up(5);
+                EventResult::Consumed(None)
+            }
+            Event::Mouse {
+                event: MouseEvent::WheelDown,
+                ..
+            } if self.scrollbase.can_scroll_down() => {
+                self.scrollbase.scroll_down(5);
+                EventResult::Consumed(None)
+            }
+            // Terminals don't send which modifier keys are held with clicks,
+            // so a middle click places another cursor
+            Event::Mouse {
+                event: MouseEvent::Press(MouseButton::Middle),
+                position,
+                offset,
+            } => {
+                let position = position.saturating_sub(offset);
+                let clicked = self.position_at_cell(position.x, position.y);
+                if let Some(clicked) = clicked {
+                    self.add_cursor(clicked);
+                }
+                EventResult::Consumed(None)
+            }
+            // Clicking the scrollbar above or below the thumb scrolls a page,
+            // and clicking the thumb grabs it
+            Event::Mouse {
+                event: MouseEvent::Press(MouseButton::Left),
+                position,
+                offset,
+            } if self.scrollbase.scrollable()
+                && position.saturating_sub(offset).x == self.scrollbase.scrollbar_x(self.width) =>
+            {
+                let position = position.saturating_sub(offset);
+                let height = self.scrollbase.scrollbar_thumb_height();
+                let thumb_y = self.scrollbase.scrollbar_thumb_y(height);
+                let page = self.scrollbase.view_height;
+                if position.y < thumb_y {
+                    self.scrollbase.scroll_up(page);
+                } else if position.y >= thumb_y + height {
+                    self.scrollbase.scroll_down(page);
+                } else {
+                    self.scrollbase.start_drag(position, self.width);
+                }
+                EventResult::Consumed(None)
+            }
+            Event::Mouse {
+                event: MouseEvent::Hold(MouseButton::Left),
+                position,
+                offset,
+            } if self.scrollbase.is_dragging() => {
+                let position = position.saturating_sub(offset);
+                self.scrollbase.drag(position);
+                EventResult::Consumed(None)
+            }
+            Event::Mouse {
+                event: MouseEvent::Release(MouseButton::Left),
+                ..
+            } if self.scrollbase.is_dragging() => {
+                self.scrollbase.release_grab();
+                EventResult::Consumed(None)
+            }
+            _ => EventResult::Ignored,
+        }
+    }
 }
 
 impl<H> View for CodeArea<H>
@@ -617,21 +5509,36 @@ where
     H: Highlighter,
 {
     fn required_size(&mut self, constraint: Vec2) -> Vec2 {
-        let scroll_width = if self.contents.len() > constraint.y {
+        // Content taller than the view scrolls, with a column for the scrollbar
+        let scroll_width = if self.contents().len() > constraint.y {
             1
         } else {
             0
         };
-        // Vec2::new(
-        //     scroll_width + 1 + self.contents.iter().map(|r| r.len()).max().unwrap_or(1),
-        //     self.contents.len()
-        // )
+        // The cursor can go one column past the end of the longest line
+        let width = (0..self.contents().len() as i32)
+            .map(|i| self.display_col(i, self.row_len(i)))
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let gutter_width = if self.zen {
+            0
+        } else {
+            self.blame_width() + LN_SPACE as usize
+        };
+        // Wrapped rows fit in whatever width there is
+        let width = if self.soft_wrap {
+            min(
+                width,
+                constraint.x.saturating_sub(gutter_width + scroll_width),
+            )
+        } else {
+            width
+        };
+        let status_height = if self.bottom_line().is_some() { 1 } else { 0 };
         Vec2::new(
-            max(
-                100,
-                scroll_width + 1 + self.contents.iter().map(|r| r.len()).max().unwrap_or(1),
-            ),
-            max(self.contents.len(), 256),
+            gutter_width + width + scroll_width,
+            self.contents().len() + status_height,
         )
     }
 
@@ -654,140 +5561,237 @@ where
                 }
             });
 
-            self.scrollbase.draw(printer, |printer, i| {
-                let text = &self.contents[i];
+            let brackets = self.matching_bracket();
+            let states = self.row_states(self.scrollbase.start_line + printer.size.y);
+            self.scrollbase.draw(printer, |gutter, y| {
+                // Typewriter mode leaves room below the last row
+                let (i, start, end) = match self.wrapped_row(y) {
+                    Some(piece) => piece,
+                    None => return,
+                };
+                let text = &self.contents()[i];
+                // Text is drawn beside the line numbers, scrolled sideways
+                let column = gutter.offset((self.gutter_width(), 0));
+                let column = if self.zen {
+                    column.cropped((self.text_width, 1))
+                } else {
+                    column
+                };
+                // A wrapped row is drawn a piece at a time, as if it was
+                // scrolled to the start of the piece and cut off at its end
+                let (scroll_x, column) = if self.soft_wrap {
+                    let x = self.display_col(i as i32, start);
+                    let column = if end < self.row_len(i as i32) {
+                        column.cropped((self.display_col(i as i32, end) - x, 1))
+                    } else {
+                        column
+                    };
+                    (x, column)
+                } else {
+                    (self.scroll_x, column)
+                };
+                let printer = &column.content_offset((scroll_x, 0));
 
                 let (row, col) = self.cursor;
                 printer.with_effect(effect, |printer| {
-                    printer.print_styled((LN_SPACE, 0), (&self.highlighter.highlight(&text)).into());
+                    let (highlighted, _) = self.highlight_row(i as i32, &states[i]);
+                    printer.print_styled((0, 0), (&highlighted).into());
                 });
+                // Words the spell checker doesn't know are underlined
+                if let Some((_, misspelled)) = &self.misspelled {
+                    for (byte, word) in text.split_word_bound_indices() {
+                        if misspelled.contains(word) {
+                            let x = self.display_col(i as i32, self.col_at_byte(i as i32, byte));
+                            printer.with_effect(effect, |printer| {
+                                printer.with_effect(Effect::Underline, |printer| {
+                                    printer.print((x, 0), word)
+                                });
+                            });
+                        }
+                    }
+                }
+                // The first diagnostic of the line goes after its text, in the
+                // text's own color so it doesn't stand out more than the code.
+                // The line number shows how severe it is.
+                let line = i + 1 + self.hidden_above();
+                if let Some(diagnostic) = self.buffer().diagnostics.iter().find(|d| d.line == line)
+                {
+                    let x = self.display_col(i as i32, self.row_len(i as i32)) + 2;
+                    let message = diagnostic.message.lines().next().unwrap_or_default();
+                    printer.with_effect(effect, |printer| {
+                        printer
+                            .with_effect(Effect::Italic, |printer| printer.print((x, 0), message));
+                    });
+                }
+                if let Some(((top_row, top_col), (bottom_row, bottom_col))) =
+                    self.selection_bounds()
+                {
+                    let ln = i as i32;
+                    if top_row <= ln && ln <= bottom_row {
+                        let start = if ln == top_row { top_col } else { 0 };
+                        let end = if ln == bottom_row {
+                            bottom_col
+                        } else {
+                            self.row_len(ln)
+                        };
+                        let x = self.display_col(ln, start);
+                        let (start, end) = (self.byte_offset(ln, start), self.byte_offset(ln, end));
+
+                        // A selection that continues onto the next line
+                        // also covers the line break
+                        let mut selected = self.expand_tabs(&text[start..end], x);
+                        if ln < bottom_row {
+                            selected.push(' ');
+                        }
+
+                        // Draw the selection with the opposite effect of the text around it
+                        let selection_effect = if effect == Effect::Reverse {
+                            Effect::Simple
+                        } else {
+                            Effect::Reverse
+                        };
+                        printer.with_effect(selection_effect, |printer| {
+                            printer.print((x, 0), &selected);
+                        });
+                    }
+                }
+                if let Some(block) = self.block {
+                    let ((top, bottom), (left, right)) = (block.rows(), block.cells());
+                    let ln = i as i32;
+                    // An empty block has nothing to highlight, so it's marked in the gutter
+                    if top <= ln && ln <= bottom && left != right {
+                        let (start, end) = self.row_cells(ln, left, right);
+                        // Rows that end before the block are padded out to it
+                        let x = if start == text.len() {
+                            left
+                        } else {
+                            self.display_col(ln, self.col_at_display(ln, left))
+                        };
+                        let mut selected = self.expand_tabs(&text[start..end], x);
+                        let width = x + selected.width();
+                        if width < right {
+                            selected.push_str(&" ".repeat(right - width));
+                        }
+                        let block_effect = if effect == Effect::Reverse {
+                            Effect::Simple
+                        } else {
+                            Effect::Reverse
+                        };
+                        printer.with_effect(block_effect, |printer| {
+                            printer.print((x, 0), &selected);
+                        });
+                    }
+                }
+                if let Some((bracket, found)) = brackets {
+                    for &(ln, bracket_col) in &[bracket, found] {
+                        if ln == i as i32 {
+                            let x = self.display_col(ln, bracket_col);
+                            let byte = self.byte_offset(ln, bracket_col);
+                            let bracket_byte = self.byte_offset(ln, bracket_col + 1);
+                            printer.with_color(ColorStyle::highlight(), |printer| {
+                                printer.print((x, 0), &text[byte..bracket_byte]);
+                            });
+                        }
+                    }
+                }
                 if printer.focused && i as i32 == row {
-                    printer.print_styled((col + LN_SPACE, 0), (&StyledString::from("_")).into());
+                    let x = self.display_col(row, col);
+                    printer.print_styled((x, 0), (&StyledString::from("_")).into());
                 }
-                if let Some((mrow, mcol)) = self.selection_marker {
-                    if printer.focused && i as i32 == mrow {
-                        printer.print_styled((mcol + LN_SPACE, 0), (&StyledString::from("_")).into());
+                // Rows too long to fit are cut off, with a mark at each edge of the view
+                let width = self.display_col(i as i32, self.row_len(i as i32));
+                gutter.with_color(ColorStyle::highlight(), |gutter| {
+                    if self.soft_wrap {
+                        return;
+                    }
+                    if self.scroll_x > 0 && width > 0 {
+                        gutter.print((self.gutter_width(), 0), "<");
+                    }
+                    if self.text_width > 0 && width > self.scroll_x + self.text_width {
+                        let right = if self.zen {
+                            self.gutter_width() + self.text_width
+                        } else {
+                            gutter.size.x
+                        };
+                        gutter.print((right - 1, 0), ">");
+                    }
+                });
+                if printer.focused {
+                    for &(cursor_row, cursor_col) in &self.cursors {
+                        if cursor_row == i as i32 {
+                            let x = self.display_col(cursor_row, cursor_col);
+                            printer.print_styled((x, 0), (&StyledString::from("_")).into());
+                        }
                     }
                 }
 
-                printer.with_effect(effect, |printer| {
-                    printer
-                        .print_styled((0, 0), (&StyledString::from(format!("{:<4}| ", i+1))).into());
+                // Only the first row of cells of a wrapped row has its number
+                if self.zen || start > 0 {
+                    return;
+                }
+                // Lines with diagnostics have their numbers in the color of the worst one
+                let mut gutter_color = ColorStyle::secondary();
+                if let Some(diagnostic) = self.worst_diagnostic(i as i32) {
+                    gutter_color.back = ColorType::Color(diagnostic.severity.color());
+                }
+                // Who last changed the line goes left of its number
+                if let Some(blame) = &self.blame {
+                    let annotation = blame.annotations.get(line - 1).map_or("", String::as_str);
+                    gutter.with_color(ColorStyle::secondary(), |gutter| {
+                        gutter.with_effect(effect, |printer| printer.print((0, 0), annotation));
+                    });
+                }
+                let gutter = &gutter.offset((self.blame_width(), 0));
+                gutter.with_color(gutter_color, |gutter| {
+                    gutter.with_effect(effect, |printer| {
+                        printer.print_styled(
+                            (0, 0),
+                            (&StyledString::from(format!("{:<4} | ", line))).into(),
+                        );
+                    });
                 });
+                // The rows of an empty block are marked beside their numbers
+                if let Some(block) = self.block {
+                    let ((top, bottom), (left, right)) = (block.rows(), block.cells());
+                    let ln = i as i32;
+                    if top <= ln && ln <= bottom && left == right && printer.focused {
+                        gutter.with_color(ColorStyle::highlight(), |gutter| {
+                            gutter.print((5, 0), "|");
+                        });
+                    }
+                }
+                // Rows that differ from the last git commit are marked beside their numbers
+                if let Some(change) = self.head_change(i as i32) {
+                    let (marker, color) = match change {
+                        LineChange::Added => ("+", Color::Dark(BaseColor::Green)),
+                        LineChange::Modified => ("~", Color::Dark(BaseColor::Blue)),
+                        LineChange::Removed => ("_", Color::Dark(BaseColor::Red)),
+                    };
+                    let mut marker_color = ColorStyle::secondary();
+                    marker_color.back = ColorType::Color(color);
+                    gutter.with_color(marker_color, |gutter| {
+                        gutter.with_effect(effect, |printer| printer.print((4, 0), marker));
+                    });
+                }
             });
+
+            if let Some(message) = self.bottom_line() {
+                let y = printer.size.y.saturating_sub(1);
+                printer.with_color(ColorStyle::highlight(), |printer| {
+                    printer.print_hline((0, y), printer.size.x, " ");
+                    printer.print((1, y), &message);
+                });
+            }
+            self.draw_completion(printer);
         });
     }
 
     fn on_event(&mut self, event: Event) -> EventResult {
-        self.fix();
-        let mut fix_scroll = true;
-        let mut is_shifting = false;
-        let mut quit = false;
-        match event {
-            // Event::CtrlChar('k') => self.cut_line(),
-            Event::CtrlChar('q') => quit = true,
-            Event::CtrlChar('s') => self.save_content(),
-            Event::CtrlChar('v') => self.paste(),
-            Event::CtrlChar('f') => self.copy(),
-            Event::CtrlChar('x') => self.cut(),
-            Event::CtrlChar('k') => {
-                if self.is_selecting() {
-                    self.comment_selection()
-                } else {
-                    self.comment_current_line()
-                }
-                is_shifting = true;
-            }
-            Event::CtrlChar('d') => self.copy_line_down(),
-            Event::Char(ch) => self.insert(ch),
-            Event::Key(Key::Enter) => self.insert('\n'),
-            Event::Key(Key::Del) => self.delete(),
-            Event::Key(Key::Backspace) => self.backspace(),
-            Event::Key(Key::Tab) => self.insert('\t'),
-            Event::Key(Key::Enter) => self.insert('\n'),
-            Event::Key(Key::Del) => self.delete(),
-            Event::Key(Key::Backspace) => self.backspace(),
-
-            Event::Key(Key::Home) => self.move_cursor_home(),
-            Event::Key(Key::End) => self.move_cursor_end(),
-            Event::Key(Key::PageUp) => self.move_page_up(),
-            Event::Shift(Key::PageUp) => {
-                self.continue_selection();
-                self.move_page_up();
-                is_shifting = true;
-            }
-            Event::Key(Key::PageDown) => self.move_page_down(),
-            Event::Shift(Key::PageDown) => {
-                self.continue_selection();
-                self.move_page_down();
-                is_shifting = true;
-            }
-            Event::Ctrl(Key::Up) => self.move_line_up(),
-            Event::Key(Key::Up) => self.move_cursor_up(),
-            Event::Shift(Key::Up) => {
-                self.continue_selection();
-                self.move_cursor_up();
-                is_shifting = true;
-            }
-            Event::Ctrl(Key::Down) => self.move_line_down(),
-            Event::Key(Key::Down) => self.move_cursor_down(),
-            Event::Shift(Key::Down) => {
-                self.continue_selection();
-                self.move_cursor_down();
-                is_shifting = true;
-            }
-            Event::Key(Key::Left) => self.move_cursor_left(),
-            Event::Shift(Key::Left) => {
-                self.continue_selection();
-                self.move_cursor_left();
-                is_shifting = true;
-            }
-            Event::Key(Key::Right) => self.move_cursor_right(),
-            Event::Shift(Key::Right) => {
-                self.continue_selection();
-                self.move_cursor_right();
-                is_shifting = true;
-            }
-            Event::Mouse {
-                event: MouseEvent::WheelUp,
-                ..
-            } if self.scrollbase.can_scroll_up() => {
-                fix_scroll = false;
-                self.scrollbase.scroll_up(5);
-            }
-            Event::Mouse {
-                event: MouseEvent::WheelDown,
-                ..
-            } if self.scrollbase.can_scroll_down() => {
-                fix_scroll = false;
-                self.scrollbase.scroll_down(5);
-            }
-            Event::Mouse {
-                event: MouseEvent::Hold(MouseButton::Left),
-                position,
-                offset,
-            } => {
-                fix_scroll = false;
-                let position = position.saturating_sub(offset);
-                self.scrollbase.drag(position);
-            }
-            _ => return EventResult::Ignored,
-        }
-
-        if !is_shifting {
-            self.forget_selection()
-        }
-
-        if fix_scroll {
-            let focus = self.cursor.0;
-            self.scrollbase.scroll_to(focus as usize);
-        }
-
-        if quit {
-            EventResult::Consumed(Some(Callback::from_fn_mut(|s| s.quit())))
-        } else {
-            EventResult::Consumed(None)
-        }
+        self.follow_buffer();
+        let result = self.handle_event(event);
+        let version = self.buffer().version;
+        self.seen_version = version;
+        result
     }
 
     fn take_focus(&mut self, _: Direction) -> bool {
@@ -795,13 +5799,48 @@ where
     }
 
     fn layout(&mut self, size: Vec2) {
-        self.scrollbase.set_heights(size.y, self.contents.len());
+        self.follow_buffer();
+        self.update_head_changes();
+        self.update_blame();
+        // The bottom row is left for a message, so it doesn't cover any text
+        let height = if self.bottom_line().is_some() {
+            size.y.saturating_sub(1)
+        } else {
+            size.y
+        };
+        // In typewriter mode, the last row can scroll up to the middle too
+        let room_below = if self.typewriter { height / 2 } else { 0 };
+        self.width = size.x;
+        let mut rows = self.contents().len();
+        // Rows are wrapped narrower to leave room for a scrollbar only if there is one
+        if self.soft_wrap {
+            self.text_width = self.fit_text_width(0);
+            self.wrap_rows();
+            if self.wrapped_rows.len() + room_below > height {
+                self.text_width = self.fit_text_width(1);
+                self.wrap_rows();
+            }
+            rows = self.wrapped_rows.len();
+        }
+        self.scrollbase.set_heights(height, rows + room_below);
+        let scroll_width = if self.scrollbase.scrollable() { 1 } else { 0 };
+        self.text_width = self.fit_text_width(scroll_width);
+        let (cursor_y, _) = self.cell_of(self.cursor);
+        if replace(&mut self.scroll_pending, false) {
+            self.scrollbase.scroll_to(cursor_y);
+            self.scroll_to_cursor_x();
+        }
+        // Only moving the cursor recenters it, so the wheel can still scroll
+        if self.typewriter && self.centered_row != Some(self.cursor.0) {
+            self.scrollbase.start_line = cursor_y.saturating_sub(height / 2);
+            self.centered_row = Some(self.cursor.0);
+        }
     }
 
     fn important_area(&self, _: Vec2) -> Rect {
         // The important area is a single character
-        let (row, col) = self.cursor;
-        Rect::from_size((col, row), (1, 1))
+        let (y, x) = self.cell_of(self.cursor);
+        Rect::from_size((x, y), (1, 1))
     }
 }
 
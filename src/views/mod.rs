@@ -1,5 +1,7 @@
 mod code;
-pub use code::{CodeArea, DefaultHighlighter, Highlighter};
+pub use code::{CodeArea, DefaultHighlighter, Highlighter, LineState};
+mod terminal;
+pub use terminal::TerminalView;
 
 // mod test;
 // pub use test::{CodeArea, DefaultHighlighter, Highlighter};
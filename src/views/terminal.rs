@@ -0,0 +1,195 @@
+use crate::locations::{FileLocation, LocationFinder};
+use crate::terminal::{Screen, Shell};
+use cursive::direction::Direction;
+use cursive::event::{Event, EventResult, Key, MouseButton, MouseEvent};
+use cursive::theme::Effect;
+use cursive::view::View;
+use cursive::{CbSink, Cursive, Printer, Vec2};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use unicode_width::UnicodeWidthStr;
+
+/// What's called when a place in a file is clicked
+type OnLocation = Rc<dyn Fn(&mut Cursive, &FileLocation)>;
+
+/// A view of a shell running in a terminal. Keys typed in it go to the
+/// shell, except for Alt keys and Ctrl+W, which are left for the editor.
+/// Places in files that are printed, like `src/main.rs:3:5`, are underlined,
+/// and can be clicked.
+pub struct TerminalView {
+    shell: Option<Shell>,
+    screen: Arc<Mutex<Screen>>,
+    locations: LocationFinder,
+    on_location: Option<OnLocation>,
+    /// The columns and rows of the terminal, to find the line that's
+    /// clicked and to tell when the view is resized
+    size: (usize, usize),
+}
+
+impl TerminalView {
+    /// Start a shell in a directory, in a terminal of a size. `sink` is used
+    /// to redraw the screen when the shell prints something.
+    pub fn new(dir: &Path, size: (usize, usize), sink: CbSink) -> Self {
+        let screen = Arc::new(Mutex::new(Screen::new(size)));
+        let output = screen.clone();
+        let started = Shell::spawn(dir, size, move |bytes| {
+            if let Ok(mut screen) = output.lock() {
+                match bytes {
+                    [] => screen.feed(b"\r\n[the shell exited]"),
+                    bytes => screen.feed(bytes),
+                }
+            }
+            let _ = sink.send(Box::new(Cursive::noop));
+        });
+        let shell = match started {
+            Ok(shell) => Some(shell),
+            Err(e) => {
+                if let Ok(mut screen) = screen.lock() {
+                    screen.feed(format!("could not start a shell: {}", e).as_bytes());
+                }
+                None
+            }
+        };
+        Self {
+            shell,
+            screen,
+            locations: LocationFinder::default(),
+            on_location: None,
+            size,
+        }
+    }
+
+    /// Call a function when a place in a file is clicked
+    pub fn on_location(mut self, f: impl Fn(&mut Cursive, &FileLocation) + 'static) -> Self {
+        self.on_location = Some(Rc::new(f));
+        self
+    }
+
+    /// Every line on the screen and in the scrollback, oldest first
+    pub fn lines(&self) -> Vec<String> {
+        match self.screen.lock() {
+            Ok(screen) => screen.lines(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// The place in a file printed at a position in the view, if any
+    fn location_at(&self, (x, y): (usize, usize)) -> Option<FileLocation> {
+        let lines = self.screen.lock().ok()?.last_lines(self.size.1);
+        let line = lines.get(y)?;
+        self.locations.find(line).into_iter().find(|location| {
+            let start = line[..location.start].width();
+            let end = line[..location.end].width();
+            (start..end).contains(&x)
+        })
+    }
+}
+
+/// What a terminal sends for a key
+fn key_bytes(event: &Event) -> Option<Vec<u8>> {
+    let bytes = match event {
+        Event::Char(ch) => ch.to_string().into_bytes(),
+        // Ctrl+A is 1, up to Ctrl+Z at 26
+        Event::CtrlChar(ch) if ch.is_ascii_lowercase() => vec![*ch as u8 - b'a' + 1],
+        Event::Key(Key::Enter) => b"\r".to_vec(),
+        Event::Key(Key::Backspace) => b"\x7f".to_vec(),
+        Event::Key(Key::Tab) => b"\t".to_vec(),
+        Event::Key(Key::Esc) => b"\x1b".to_vec(),
+        Event::Key(Key::Del) => b"\x1b[3~".to_vec(),
+        Event::Key(Key::Up) => b"\x1b[A".to_vec(),
+        Event::Key(Key::Down) => b"\x1b[B".to_vec(),
+        Event::Key(Key::Right) => b"\x1b[C".to_vec(),
+        Event::Key(Key::Left) => b"\x1b[D".to_vec(),
+        Event::Key(Key::Home) => b"\x1b[H".to_vec(),
+        Event::Key(Key::End) => b"\x1b[F".to_vec(),
+        _ => return None,
+    };
+    Some(bytes)
+}
+
+impl View for TerminalView {
+    fn draw(&self, printer: &Printer<'_, '_>) {
+        let screen = match self.screen.lock() {
+            Ok(screen) => screen,
+            Err(_) => return,
+        };
+        // The screen is the last lines, below the ones that scrolled off it
+        let shown = screen.last_lines(printer.size.y);
+        let first = screen.line_count() - shown.len();
+        for (y, line) in shown.iter().enumerate() {
+            printer.print((0, y), line);
+            for location in self.locations.find(line) {
+                let x = line[..location.start].width();
+                let text = &line[location.start..location.end];
+                printer.with_effect(Effect::Underline, |printer| printer.print((x, y), text));
+            }
+        }
+
+        if printer.focused && self.shell.is_some() {
+            let (row, col) = screen.cursor();
+            let y = row.saturating_sub(first);
+            let line = shown.get(y).map_or("", String::as_str);
+            let before: String = line.chars().take(col).collect();
+            let x = before.width() + col.saturating_sub(line.chars().count());
+            let under = line.chars().nth(col).unwrap_or(' ').to_string();
+            printer.with_effect(Effect::Reverse, |printer| printer.print((x, y), &under));
+        }
+    }
+
+    fn required_size(&mut self, constraint: Vec2) -> Vec2 {
+        constraint
+    }
+
+    fn layout(&mut self, size: Vec2) {
+        let size = (size.x, size.y);
+        if size == self.size {
+            return;
+        }
+        self.size = size;
+        if let Some(shell) = &self.shell {
+            let _ = shell.resize(size);
+        }
+        if let Ok(mut screen) = self.screen.lock() {
+            screen.resize(size);
+        }
+    }
+
+    fn on_event(&mut self, event: Event) -> EventResult {
+        if event == Event::CtrlChar('w') {
+            return EventResult::Ignored;
+        }
+        if let Event::Mouse {
+            offset,
+            position,
+            event: MouseEvent::Press(MouseButton::Left),
+        } = event
+        {
+            let clicked = position
+                .checked_sub(offset)
+                .and_then(|position| self.location_at((position.x, position.y)));
+            return match (clicked, &self.on_location) {
+                (Some(location), Some(on_location)) => {
+                    let on_location = on_location.clone();
+                    EventResult::with_cb(move |s| on_location(s, &location))
+                }
+                _ => EventResult::Ignored,
+            };
+        }
+        let shell = match &mut self.shell {
+            Some(shell) => shell,
+            None => return EventResult::Ignored,
+        };
+        match key_bytes(&event) {
+            Some(bytes) => {
+                let _ = shell.write(&bytes);
+                EventResult::Consumed(None)
+            }
+            None => EventResult::Ignored,
+        }
+    }
+
+    fn take_focus(&mut self, _: Direction) -> bool {
+        true
+    }
+}